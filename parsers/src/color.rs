@@ -0,0 +1,138 @@
+use anyhow::{anyhow, bail};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    /// `#rrggbb`.
+    pub fn to_hex(&self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
+/// Accepts `RRGGBB`, `RGB` (each digit doubled), either with a leading `#`, and
+/// `rgb(r, g, b)` with decimal components. Surrounding whitespace is ignored.
+impl FromStr for Color {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+
+        if let Some(components) = value.strip_prefix("rgb(").and_then(|rest| rest.strip_suffix(')')) {
+            return parse_decimal(components);
+        }
+
+        let hex = value.strip_prefix('#').unwrap_or(value);
+        if !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            bail!("{:?} is not a hex color", value);
+        }
+
+        // All ASCII from here, so byte offsets are character boundaries
+        let digit = |i: usize| u8::from_str_radix(&hex[i..i + 1], 16).unwrap();
+        let pair = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+        match hex.len() {
+            6 => Ok(Color {
+                r: pair(0),
+                g: pair(2),
+                b: pair(4),
+            }),
+            3 => Ok(Color {
+                r: digit(0) * 0x11,
+                g: digit(1) * 0x11,
+                b: digit(2) * 0x11,
+            }),
+            _ => bail!("{:?} needs 3 or 6 hex digits", value),
+        }
+    }
+}
+
+fn parse_decimal(components: &str) -> anyhow::Result<Color> {
+    let mut values = components.split(',').map(|component| {
+        component
+            .trim()
+            .parse::<u8>()
+            .map_err(|_| anyhow!("{:?} is not a component between 0 and 255", component.trim()))
+    });
+
+    let mut next = || values.next().ok_or_else(|| anyhow!("rgb() needs three components"));
+    let color = Color {
+        r: next()??,
+        g: next()??,
+        b: next()??,
+    };
+    if values.next().is_some() {
+        bail!("rgb() needs three components");
+    }
+
+    Ok(color)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Color;
+
+    fn parse(value: &str) -> Option<Color> {
+        value.parse().ok()
+    }
+
+    fn rgb(r: u8, g: u8, b: u8) -> Option<Color> {
+        Some(Color { r, g, b })
+    }
+
+    #[test]
+    fn six_digit_hex() {
+        assert_eq!(parse("ff8000"), rgb(255, 128, 0));
+        assert_eq!(parse("#FF8000"), rgb(255, 128, 0));
+        assert_eq!(parse(" 0a0B0c\n"), rgb(10, 11, 12));
+    }
+
+    #[test]
+    fn three_digit_hex() {
+        assert_eq!(parse("f80"), rgb(255, 136, 0));
+        assert_eq!(parse("#0Af"), rgb(0, 170, 255));
+    }
+
+    #[test]
+    fn decimal() {
+        assert_eq!(parse("rgb(255,128,0)"), rgb(255, 128, 0));
+        assert_eq!(parse("rgb( 1 , 2 , 3 )"), rgb(1, 2, 3));
+    }
+
+    #[test]
+    fn rejects_wrong_lengths() {
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("#"), None);
+        assert_eq!(parse("ff"), None);
+        assert_eq!(parse("ff80"), None);
+        assert_eq!(parse("ff80001"), None);
+    }
+
+    #[test]
+    fn rejects_non_hex() {
+        assert_eq!(parse("gg0000"), None);
+        assert_eq!(parse("+f+f+f"), None);
+        assert_eq!(parse("##fff"), None);
+    }
+
+    #[test]
+    fn rejects_multibyte_input_without_panicking() {
+        assert_eq!(parse("é0000"), None);
+        assert_eq!(parse("ffé"), None);
+        assert_eq!(parse("€€"), None);
+    }
+
+    #[test]
+    fn rejects_bad_decimal() {
+        assert_eq!(parse("rgb(256,0,0)"), None);
+        assert_eq!(parse("rgb(-1,0,0)"), None);
+        assert_eq!(parse("rgb(1,2)"), None);
+        assert_eq!(parse("rgb(1,2,3,4)"), None);
+        assert_eq!(parse("rgb(1,,3)"), None);
+        assert_eq!(parse("rgb(1,2,3"), None);
+    }
+}
@@ -0,0 +1,38 @@
+use std::fmt::Display;
+
+/// An error with the HTTP status to answer it with; see the firmware's `errors`
+/// module for how it becomes a response.
+#[derive(Debug)]
+pub struct HttpError {
+    pub status: u16,
+    pub message: String,
+}
+
+impl HttpError {
+    /// Already an [`anyhow::Error`], ready for `?` or `Err(..)` in a handler.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(status: u16, message: impl Display) -> anyhow::Error {
+        HttpError {
+            status,
+            message: message.to_string(),
+        }
+        .into()
+    }
+
+    /// For `map_err` on validation and parsing.
+    pub fn bad_request(message: impl Display) -> anyhow::Error {
+        Self::new(400, message)
+    }
+
+    pub fn not_found(message: impl Display) -> anyhow::Error {
+        Self::new(404, message)
+    }
+}
+
+impl std::fmt::Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for HttpError {}
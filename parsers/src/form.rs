@@ -0,0 +1,155 @@
+//! `application/x-www-form-urlencoded` data, which is also what a query string
+//! holds: `&`-separated `name=value` pairs, percent-encoded with `+` for a space.
+
+use anyhow::Result;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::HttpError;
+
+/// Decoded pairs in their original order; a name without `=` has an empty value.
+#[derive(Debug, Default)]
+pub struct Form {
+    pairs: Vec<(String, String)>,
+}
+
+impl Form {
+    pub fn parse(data: &str) -> Self {
+        let pairs = data
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (percent_decode(name), percent_decode(value))
+            })
+            .collect();
+        Form { pairs }
+    }
+
+    /// The first value of `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.pairs
+            .iter()
+            .find(|(other, _)| other == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// `name` parsed as a `T`, `None` when absent; a value that does not parse is a 400.
+    pub fn value<T>(&self, name: &str) -> Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.get(name)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|e| HttpError::bad_request(format!("Invalid {} parameter: {}", name, e)))
+            })
+            .transpose()
+    }
+
+    /// Like [`Form::value`], with a missing `name` a 400 as well.
+    pub fn required<T>(&self, name: &str) -> Result<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.value(name)?
+            .ok_or_else(|| HttpError::bad_request(format!("Missing {} parameter", name)))
+    }
+}
+
+/// The first value of `key` in `form`, decoded.
+pub fn value(form: &str, key: &str) -> Option<String> {
+    Form::parse(form).get(key).map(str::to_string)
+}
+
+pub fn percent_decode(value: &str) -> String {
+    decode(value, true)
+}
+
+/// For a path segment, where only `%XX` is an escape and `+` is itself.
+pub fn percent_decode_path(segment: &str) -> String {
+    decode(segment, false)
+}
+
+fn decode(value: &str, plus_is_space: bool) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' if plus_is_space => decoded.push(b' '),
+            b'%' => {
+                // from_str_radix alone would take a sign, as in `%+f`
+                let hex = bytes
+                    .get(i + 1..i + 3)
+                    .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                match hex {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        i += 2;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{percent_decode, percent_decode_path, Form};
+
+    #[test]
+    fn decodes_names_and_values() {
+        let form = Form::parse("color=%23ff0000&name=a+b&caf%C3%A9=1");
+        assert_eq!(form.get("color"), Some("#ff0000"));
+        assert_eq!(form.get("name"), Some("a b"));
+        assert_eq!(form.get("café"), Some("1"));
+        assert_eq!(form.get("missing"), None);
+    }
+
+    #[test]
+    fn first_value_wins_and_bare_names_are_empty() {
+        let form = Form::parse("r=1&r=2&flag&&g=");
+        assert_eq!(form.get("r"), Some("1"));
+        assert_eq!(form.get("flag"), Some(""));
+        assert_eq!(form.get("g"), Some(""));
+        assert!(Form::parse("").get("").is_none());
+    }
+
+    #[test]
+    fn typed_values() {
+        let form = Form::parse("r=255&g=-1&b=x");
+        assert_eq!(form.value::<u8>("r").unwrap(), Some(255));
+        assert_eq!(form.value::<u8>("missing").unwrap(), None);
+        assert!(form.value::<u8>("g").is_err());
+        assert!(form.value::<u8>("b").is_err());
+        assert_eq!(form.required::<u8>("r").unwrap(), 255);
+        assert!(form.required::<u8>("missing").is_err());
+    }
+
+    #[test]
+    fn keeps_malformed_escapes() {
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz"), "%zz");
+        assert_eq!(percent_decode("%4"), "%4");
+        assert_eq!(percent_decode("%+f"), "% f");
+        assert_eq!(percent_decode("%-1"), "%-1");
+    }
+
+    #[test]
+    fn paths_keep_plus() {
+        assert_eq!(percent_decode_path("a+b.txt"), "a+b.txt");
+        assert_eq!(percent_decode_path("a%20b%2Bc"), "a b+c");
+    }
+}
@@ -0,0 +1,12 @@
+//! Request parsing that needs nothing from ESP-IDF, kept out of the firmware
+//! crate so its tests run on the host:
+//!
+//! ```text
+//! cargo test -p parsers --target x86_64-unknown-linux-gnu
+//! ```
+
+pub mod color;
+pub mod error;
+pub mod form;
+
+pub use error::HttpError;
@@ -0,0 +1,214 @@
+use anyhow::Result;
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use esp_idf_svc::sys::{
+    esp_reset_reason, esp_reset_reason_t_ESP_RST_BROWNOUT, esp_reset_reason_t_ESP_RST_DEEPSLEEP,
+    esp_reset_reason_t_ESP_RST_EXT, esp_reset_reason_t_ESP_RST_INT_WDT, esp_reset_reason_t_ESP_RST_PANIC,
+    esp_reset_reason_t_ESP_RST_POWERON, esp_reset_reason_t_ESP_RST_SW, esp_reset_reason_t_ESP_RST_TASK_WDT,
+    esp_reset_reason_t_ESP_RST_WDT,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::events::{Event, EventBus};
+use crate::page::{Page, PageRequest};
+
+const NAMESPACE: &str = "alerts";
+const ALERTS_KEY: &str = "log";
+const MAX_ALERTS: usize = 32;
+/// Anything before this is an unsynchronised clock rather than a real date.
+pub const MIN_VALID_UNIX_TIME: u64 = 1_600_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Severity {
+    Info,
+    Warning,
+    /// Keeps the LED in alert state until acknowledged.
+    Critical,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Alert {
+    pub id: u32,
+    pub severity: Severity,
+    pub source: String,
+    pub message: String,
+    /// Unix time, when the clock was synchronised at the time the alert was raised.
+    pub raised_at: Option<u64>,
+    pub acknowledged: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct AlertState {
+    next_id: u32,
+    alerts: VecDeque<Alert>,
+}
+
+/// Alerts raised by the subsystems, persisted as a JSON blob in the `alerts` NVS
+/// namespace so they survive the reboot that usually follows them.
+pub struct AlertLog {
+    nvs: Option<Mutex<EspNvs<NvsDefault>>>,
+    state: Mutex<AlertState>,
+    events: Arc<EventBus>,
+}
+
+impl AlertLog {
+    pub fn new(partition: EspDefaultNvsPartition, events: Arc<EventBus>) -> Result<Self> {
+        let nvs = EspNvs::new(partition, NAMESPACE, true)?;
+
+        let state = match Self::load(&nvs) {
+            Ok(state) => state,
+            Err(e) => {
+                log::warn!("Failed to load stored alerts, starting empty: {}", e);
+                AlertState::default()
+            }
+        };
+
+        Ok(AlertLog {
+            nvs: Some(Mutex::new(nvs)),
+            state: Mutex::new(state),
+            events,
+        })
+    }
+
+    /// Store used when NVS is unavailable; alerts are lost on reboot.
+    pub fn in_memory(events: Arc<EventBus>) -> Self {
+        AlertLog {
+            nvs: None,
+            state: Mutex::new(AlertState::default()),
+            events,
+        }
+    }
+
+    fn load(nvs: &EspNvs<NvsDefault>) -> Result<AlertState> {
+        let Some(len) = nvs.blob_len(ALERTS_KEY)? else {
+            return Ok(AlertState::default());
+        };
+
+        let mut buffer = vec![0_u8; len];
+        match nvs.get_blob(ALERTS_KEY, &mut buffer)? {
+            Some(data) => Ok(serde_json::from_slice(data)?),
+            None => Ok(AlertState::default()),
+        }
+    }
+
+    pub fn raise(&self, severity: Severity, source: &str, message: String) -> u32 {
+        match severity {
+            Severity::Info => log::info!("Alert from {}: {}", source, message),
+            Severity::Warning => log::warn!("Alert from {}: {}", source, message),
+            Severity::Critical => log::error!("Critical alert from {}: {}", source, message),
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id = state.next_id.wrapping_add(1);
+
+        if state.alerts.len() == MAX_ALERTS {
+            state.alerts.pop_front();
+        }
+        state.alerts.push_back(Alert {
+            id,
+            severity,
+            source: source.to_string(),
+            message,
+            raised_at: unix_time(),
+            acknowledged: false,
+        });
+
+        self.persist(&state);
+        drop(state);
+
+        self.events.publish(Event::AlertRaised {
+            id,
+            severity,
+            source: source.to_string(),
+        });
+        id
+    }
+
+    pub fn list(&self) -> Vec<Alert> {
+        self.state.lock().unwrap().alerts.iter().cloned().collect()
+    }
+
+    pub fn page(&self, request: &PageRequest<u32>) -> Page<Alert, u32> {
+        request.select(self.state.lock().unwrap().alerts.iter().map(|alert| (alert.id, alert)))
+    }
+
+    /// Returns false when no alert with this id is stored.
+    pub fn acknowledge(&self, id: u32) -> bool {
+        let mut state = self.state.lock().unwrap();
+
+        let Some(alert) = state.alerts.iter_mut().find(|alert| alert.id == id) else {
+            return false;
+        };
+        alert.acknowledged = true;
+        log::info!("Alert {} acknowledged", id);
+
+        self.persist(&state);
+        true
+    }
+
+    pub fn has_unacknowledged_critical(&self) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .alerts
+            .iter()
+            .any(|alert| alert.severity == Severity::Critical && !alert.acknowledged)
+    }
+
+    /// Raises an alert when the previous boot ended in a panic, watchdog or brownout.
+    pub fn check_reset_reason(&self) {
+        let reason = unsafe { esp_reset_reason() };
+
+        #[allow(non_upper_case_globals)]
+        let message = match reason {
+            esp_reset_reason_t_ESP_RST_PANIC => "Previous boot ended in a panic",
+            esp_reset_reason_t_ESP_RST_INT_WDT
+            | esp_reset_reason_t_ESP_RST_TASK_WDT
+            | esp_reset_reason_t_ESP_RST_WDT => "Previous boot was reset by a watchdog",
+            esp_reset_reason_t_ESP_RST_BROWNOUT => "Previous boot ended in a brownout",
+            _ => return,
+        };
+
+        self.raise(Severity::Critical, "system", message.to_string());
+    }
+
+    fn persist(&self, state: &AlertState) {
+        let Some(nvs) = &self.nvs else {
+            return;
+        };
+
+        let result = serde_json::to_vec(state)
+            .map_err(anyhow::Error::from)
+            .and_then(|data| Ok(nvs.lock().unwrap().set_blob(ALERTS_KEY, &data)?));
+        if let Err(e) = result {
+            log::error!("Failed to persist alerts: {:?}", e);
+        }
+    }
+}
+
+/// Why the current boot happened.
+pub fn reset_reason() -> &'static str {
+    #[allow(non_upper_case_globals)]
+    match unsafe { esp_reset_reason() } {
+        esp_reset_reason_t_ESP_RST_POWERON => "power-on",
+        esp_reset_reason_t_ESP_RST_EXT => "external",
+        esp_reset_reason_t_ESP_RST_SW => "software",
+        esp_reset_reason_t_ESP_RST_PANIC => "panic",
+        esp_reset_reason_t_ESP_RST_INT_WDT => "interrupt-watchdog",
+        esp_reset_reason_t_ESP_RST_TASK_WDT => "task-watchdog",
+        esp_reset_reason_t_ESP_RST_WDT => "watchdog",
+        esp_reset_reason_t_ESP_RST_DEEPSLEEP => "deep-sleep",
+        esp_reset_reason_t_ESP_RST_BROWNOUT => "brownout",
+        _ => "unknown",
+    }
+}
+
+pub fn unix_time() -> Option<u64> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+    (now >= MIN_VALID_UNIX_TIME).then_some(now)
+}
@@ -0,0 +1,136 @@
+use anyhow::Result;
+use esp_idf_svc::eventloop::{EspSubscription, EspSystemEventLoop, System};
+use esp_idf_svc::sys::{esp, esp_wifi_ap_get_sta_list, wifi_sta_list_t};
+use esp_idf_svc::wifi::WifiEvent;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::alerts::unix_time;
+use crate::page::{Page, PageRequest};
+use crate::scan::{format_mac, MacAddress};
+
+struct ApClient {
+    mac: [u8; 6],
+    aid: u8,
+    joined_at: Instant,
+    joined_unix: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApClientInfo {
+    pub mac: String,
+    pub aid: u8,
+    pub rssi: Option<i8>,
+    pub connected_secs: u64,
+}
+
+/// A station as listed by the WiFi driver, for `/api/stations`.
+#[derive(Debug, Serialize)]
+pub struct StationInfo {
+    pub mac: String,
+    pub rssi: i8,
+    /// Seconds since association; `None` for a station that joined before the
+    /// event subscription, which the driver lists all the same.
+    pub associated_secs: Option<u64>,
+    /// Unix time of the association, when the clock was set.
+    pub associated_at: Option<u64>,
+}
+
+/// Stations currently associated with our soft AP, kept up to date from WiFi events.
+#[derive(Default)]
+pub struct ApClients {
+    clients: Mutex<Vec<ApClient>>,
+}
+
+impl ApClients {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe(self: &Arc<Self>, sys_loop: &EspSystemEventLoop) -> Result<EspSubscription<'static, System>> {
+        let clients = self.clone();
+
+        let subscription = sys_loop.subscribe::<WifiEvent, _>(move |event| match event {
+            WifiEvent::ApStaConnected(station) => clients.joined(station.mac(), station.aid()),
+            WifiEvent::ApStaDisconnected(station) => clients.left(station.mac()),
+            _ => {}
+        })?;
+
+        Ok(subscription)
+    }
+
+    pub fn list(&self) -> Vec<ApClientInfo> {
+        let rssi = station_rssi();
+
+        self.clients
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|client| ApClientInfo {
+                mac: format_mac(&client.mac),
+                aid: client.aid,
+                rssi: rssi.iter().find(|(mac, _)| *mac == client.mac).map(|(_, rssi)| *rssi),
+                connected_secs: client.joined_at.elapsed().as_secs(),
+            })
+            .collect()
+    }
+
+    /// The driver's station list by MAC address, with association times from
+    /// the WiFi events.
+    pub fn stations(&self, request: &PageRequest<MacAddress>) -> Page<StationInfo, MacAddress> {
+        let stations = station_rssi();
+        let page = request.select(stations.iter().map(|station| (MacAddress(station.0), station)));
+
+        let clients = self.clients.lock().unwrap();
+        page.map(|(mac, rssi)| {
+            let client = clients.iter().find(|client| client.mac == mac);
+            StationInfo {
+                mac: format_mac(&mac),
+                rssi,
+                associated_secs: client.map(|client| client.joined_at.elapsed().as_secs()),
+                associated_at: client.and_then(|client| client.joined_unix),
+            }
+        })
+    }
+
+    fn joined(&self, mac: [u8; 6], aid: u8) {
+        log::info!("AP client joined: {} (aid {})", format_mac(&mac), aid);
+
+        let mut clients = self.clients.lock().unwrap();
+        clients.retain(|client| client.mac != mac);
+        clients.push(ApClient {
+            mac,
+            aid,
+            joined_at: Instant::now(),
+            joined_unix: unix_time(),
+        });
+    }
+
+    fn left(&self, mac: [u8; 6]) {
+        let mut clients = self.clients.lock().unwrap();
+
+        if let Some(client) = clients.iter().find(|client| client.mac == mac) {
+            log::info!(
+                "AP client left: {} after {} s",
+                format_mac(&mac),
+                client.joined_at.elapsed().as_secs()
+            );
+        }
+        clients.retain(|client| client.mac != mac);
+    }
+}
+
+/// Signal strength of the associated stations, as reported by the WiFi driver.
+fn station_rssi() -> Vec<([u8; 6], i8)> {
+    let mut list: wifi_sta_list_t = Default::default();
+
+    if esp!(unsafe { esp_wifi_ap_get_sta_list(&mut list) }).is_err() {
+        return Vec::new();
+    }
+
+    list.sta[..list.num as usize]
+        .iter()
+        .map(|station| (station.mac, station.rssi))
+        .collect()
+}
@@ -0,0 +1,78 @@
+//! Per-channel congestion from the periodic scan, over the channels of the
+//! configured country.
+
+use serde::Serialize;
+
+use crate::scan::ScannedNetwork;
+
+/// Channel 14 is 802.11b only, so it is never recommended.
+const RECOMMENDABLE: u8 = 13;
+/// 20 MHz channels sit 5 MHz apart, so an access point bleeds into the four
+/// channels on either side, less the further away they are.
+const OVERLAP: u8 = 5;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChannelUsage {
+    pub channel: u8,
+    /// Access points on exactly this channel.
+    pub access_points: usize,
+    /// Their signals summed in milliwatts, in dBm; `None` when there are none.
+    pub aggregate_rssi: Option<f32>,
+    /// Signals of every access point close enough to overlap, weighted by how
+    /// much they overlap, in dBm. Lower is better.
+    pub interference: Option<f32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChannelSurvey {
+    pub channels: Vec<ChannelUsage>,
+    /// Least congested channel an access point may use, the lowest on a tie.
+    pub recommended: u8,
+}
+
+/// `max_channel` is the highest channel allowed in the configured country.
+pub fn survey(networks: &[ScannedNetwork], max_channel: u8) -> ChannelSurvey {
+    let channels: Vec<ChannelUsage> = (1..=max_channel)
+        .map(|channel| {
+            let mut access_points = 0;
+            let mut power = 0.0;
+            let mut overlapping = 0.0;
+            for network in networks {
+                let distance = network.channel.abs_diff(channel);
+                if distance >= OVERLAP {
+                    continue;
+                }
+
+                let milliwatts = dbm_to_mw(network.signal_strength);
+                if distance == 0 {
+                    access_points += 1;
+                    power += milliwatts;
+                }
+                overlapping += milliwatts * f32::from(OVERLAP - distance) / f32::from(OVERLAP);
+            }
+
+            ChannelUsage {
+                channel,
+                access_points,
+                aggregate_rssi: mw_to_dbm(power),
+                interference: mw_to_dbm(overlapping),
+            }
+        })
+        .collect();
+
+    let recommended = channels
+        .iter()
+        .take(max_channel.min(RECOMMENDABLE) as usize)
+        .min_by(|a, b| a.interference.unwrap_or(f32::MIN).total_cmp(&b.interference.unwrap_or(f32::MIN)))
+        .map_or(1, |usage| usage.channel);
+
+    ChannelSurvey { channels, recommended }
+}
+
+fn dbm_to_mw(dbm: i8) -> f32 {
+    10_f32.powf(f32::from(dbm) / 10.0)
+}
+
+fn mw_to_dbm(milliwatts: f32) -> Option<f32> {
+    (milliwatts > 0.0).then(|| 10.0 * milliwatts.log10())
+}
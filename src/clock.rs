@@ -0,0 +1,106 @@
+//! Where the wall-clock time comes from. SNTP sets the system clock once the
+//! network is up; offline, `POST /time` sets it by hand and it then runs from
+//! the uptime counter like any other clock. Alert and timeline stamps and the
+//! `time-between` rule condition all read the system clock, so they follow.
+
+use anyhow::{bail, Result};
+use esp_idf_svc::sys;
+use serde::Serialize;
+use std::sync::Mutex;
+
+use crate::alerts::{self, unix_time};
+use crate::honeypot::uptime_secs;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TimeSource {
+    /// Not set; only the uptime is known.
+    #[default]
+    None,
+    /// Set over HTTP, until SNTP takes over.
+    Manual,
+    Sntp,
+}
+
+impl TimeSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TimeSource::None => "none",
+            TimeSource::Manual => "manual",
+            TimeSource::Sntp => "sntp",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClockStatus {
+    pub source: TimeSource,
+    pub unix_time: Option<u64>,
+    pub uptime_secs: u64,
+    /// Uptime at which the source last set the clock.
+    pub set_uptime_secs: Option<u64>,
+}
+
+#[derive(Default)]
+struct State {
+    source: TimeSource,
+    set_uptime_secs: Option<u64>,
+}
+
+#[derive(Default)]
+pub struct Clock {
+    state: Mutex<State>,
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// SNTP sync callback.
+    pub fn sntp_synced(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.source != TimeSource::Sntp {
+            log::info!("Clock set by SNTP, was {}", state.source.as_str());
+        }
+        state.source = TimeSource::Sntp;
+        state.set_uptime_secs = Some(uptime_secs());
+    }
+
+    pub fn set_manual(&self, unix_time: u64) -> Result<()> {
+        if unix_time < alerts::MIN_VALID_UNIX_TIME {
+            bail!("unix_time must be at least {}", alerts::MIN_VALID_UNIX_TIME);
+        }
+        let time = sys::timeval {
+            tv_sec: unix_time as _,
+            tv_usec: 0,
+        };
+        if unsafe { sys::settimeofday(&time, std::ptr::null()) } != 0 {
+            bail!("Failed to set the system clock");
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.source = TimeSource::Manual;
+        state.set_uptime_secs = Some(uptime_secs());
+        Ok(())
+    }
+
+    pub fn status(&self) -> ClockStatus {
+        let state = self.state.lock().unwrap();
+        ClockStatus {
+            source: state.source,
+            unix_time: unix_time(),
+            uptime_secs: uptime_secs(),
+            set_uptime_secs: state.set_uptime_secs,
+        }
+    }
+
+    /// Lines for the plain-text `/status` report.
+    pub fn status_lines(&self) -> String {
+        let status = self.status();
+        match status.unix_time {
+            Some(unix_time) => format!("time_source: {} ({})\n", status.source.as_str(), unix_time),
+            None => format!("time_source: {}\n", status.source.as_str()),
+        }
+    }
+}
@@ -0,0 +1,184 @@
+//! Hex and base64 for API payloads. Encoders stream into any `Write` through a
+//! small stack buffer and decoders fill a caller-provided slice, so a large
+//! payload is never held twice in memory.
+
+use anyhow::{bail, Result};
+use embedded_svc::io::Write;
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const CHUNK: usize = 64;
+
+/// Writes `data` as lowercase hex.
+#[allow(dead_code)]
+pub fn write_hex<W: Write>(out: &mut W, data: &[u8]) -> Result<(), W::Error> {
+    let mut buffer = [0_u8; CHUNK];
+    for chunk in data.chunks(CHUNK / 2) {
+        for (i, byte) in chunk.iter().enumerate() {
+            buffer[2 * i] = HEX_DIGITS[(byte >> 4) as usize];
+            buffer[2 * i + 1] = HEX_DIGITS[(byte & 0x0f) as usize];
+        }
+        out.write_all(&buffer[..2 * chunk.len()])?;
+    }
+    Ok(())
+}
+
+pub fn hex_string(data: &[u8]) -> String {
+    let mut text = String::with_capacity(2 * data.len());
+    for byte in data {
+        text.push(HEX_DIGITS[(byte >> 4) as usize] as char);
+        text.push(HEX_DIGITS[(byte & 0x0f) as usize] as char);
+    }
+    text
+}
+
+/// Decodes hex of either case into `out` and returns the number of bytes written.
+#[allow(dead_code)]
+pub fn decode_hex(text: &[u8], out: &mut [u8]) -> Result<usize> {
+    if text.len() % 2 != 0 {
+        bail!("Hex input has an odd number of digits");
+    }
+    if out.len() < text.len() / 2 {
+        bail!("Hex input decodes to {} bytes, room for {}", text.len() / 2, out.len());
+    }
+
+    for (i, pair) in text.chunks_exact(2).enumerate() {
+        out[i] = hex_value(pair[0])? << 4 | hex_value(pair[1])?;
+    }
+    Ok(text.len() / 2)
+}
+
+fn hex_value(digit: u8) -> Result<u8> {
+    match digit {
+        b'0'..=b'9' => Ok(digit - b'0'),
+        b'a'..=b'f' => Ok(digit - b'a' + 10),
+        b'A'..=b'F' => Ok(digit - b'A' + 10),
+        _ => bail!("{:?} is not a hex digit", digit as char),
+    }
+}
+
+/// Padded standard base64 written incrementally: feed it with `write` as the data
+/// comes in, then `finish` to flush the last group.
+#[allow(dead_code)]
+pub struct Base64Writer<W: Write> {
+    out: W,
+    pending: [u8; 3],
+    pending_len: usize,
+}
+
+#[allow(dead_code)]
+impl<W: Write> Base64Writer<W> {
+    pub fn new(out: W) -> Self {
+        Base64Writer {
+            out,
+            pending: [0; 3],
+            pending_len: 0,
+        }
+    }
+
+    pub fn write(&mut self, mut data: &[u8]) -> Result<(), W::Error> {
+        // Complete a group left over from the previous call first
+        while self.pending_len > 0 && self.pending_len < 3 && !data.is_empty() {
+            self.pending[self.pending_len] = data[0];
+            self.pending_len += 1;
+            data = &data[1..];
+        }
+        match self.pending_len {
+            0 => {}
+            3 => {
+                let group = self.pending;
+                self.out.write_all(&encode_group(&group))?;
+                self.pending_len = 0;
+            }
+            _ => return Ok(()),
+        }
+
+        let mut buffer = [0_u8; CHUNK];
+        let mut groups = data.chunks_exact(3);
+        loop {
+            let mut len = 0;
+            for group in groups.by_ref().take(CHUNK / 4) {
+                buffer[len..len + 4].copy_from_slice(&encode_group(group));
+                len += 4;
+            }
+            if len == 0 {
+                break;
+            }
+            self.out.write_all(&buffer[..len])?;
+        }
+
+        let rest = groups.remainder();
+        self.pending[..rest.len()].copy_from_slice(rest);
+        self.pending_len = rest.len();
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<W, W::Error> {
+        if self.pending_len > 0 {
+            let mut group = [0_u8; 3];
+            group[..self.pending_len].copy_from_slice(&self.pending[..self.pending_len]);
+            let mut encoded = encode_group(&group);
+            for digit in &mut encoded[self.pending_len + 1..] {
+                *digit = b'=';
+            }
+            self.out.write_all(&encoded)?;
+        }
+        Ok(self.out)
+    }
+}
+
+fn encode_group(group: &[u8]) -> [u8; 4] {
+    let bits = (group[0] as u32) << 16 | (group[1] as u32) << 8 | group[2] as u32;
+    [
+        BASE64_ALPHABET[(bits >> 18) as usize & 0x3f],
+        BASE64_ALPHABET[(bits >> 12) as usize & 0x3f],
+        BASE64_ALPHABET[(bits >> 6) as usize & 0x3f],
+        BASE64_ALPHABET[bits as usize & 0x3f],
+    ]
+}
+
+/// Upper bound of the decoded size of `len` base64 characters.
+pub fn base64_decoded_len(len: usize) -> usize {
+    len / 4 * 3
+}
+
+/// Decodes padded standard base64 into `out` and returns the number of bytes written.
+pub fn decode_base64(text: &[u8], out: &mut [u8]) -> Result<usize> {
+    if text.len() % 4 != 0 {
+        bail!("Base64 input length is not a multiple of 4");
+    }
+
+    let mut len = 0;
+    let groups = text.len() / 4;
+    for (index, group) in text.chunks_exact(4).enumerate() {
+        let padding = group.iter().rev().take_while(|&&digit| digit == b'=').count();
+        if padding > 2 || (padding > 0 && index + 1 != groups) {
+            bail!("Misplaced base64 padding");
+        }
+
+        let mut bits = 0_u32;
+        for &digit in &group[..4 - padding] {
+            bits = bits << 6 | base64_value(digit)?;
+        }
+        bits <<= 6 * padding;
+
+        let bytes = 3 - padding;
+        if out.len() < len + bytes {
+            bail!("Base64 input does not fit in {} bytes", out.len());
+        }
+        out[len..len + bytes].copy_from_slice(&bits.to_be_bytes()[1..1 + bytes]);
+        len += bytes;
+    }
+    Ok(len)
+}
+
+fn base64_value(digit: u8) -> Result<u32> {
+    match digit {
+        b'A'..=b'Z' => Ok((digit - b'A') as u32),
+        b'a'..=b'z' => Ok((digit - b'a' + 26) as u32),
+        b'0'..=b'9' => Ok((digit - b'0' + 52) as u32),
+        b'+' => Ok(62),
+        b'/' => Ok(63),
+        _ => bail!("{:?} is not a base64 digit", digit as char),
+    }
+}
@@ -0,0 +1,1158 @@
+use anyhow::{bail, Result};
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use esp_idf_svc::sys::{self, esp};
+use serde::{Deserialize, Serialize};
+use std::ffi::CString;
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::sync::Mutex;
+
+use crate::alerts::Severity;
+use crate::rules::Rule;
+use crate::scan::parse_mac;
+use crate::storage;
+
+const NAMESPACE: &str = "config";
+const CONFIG_KEY: &str = "app";
+
+/// Debug output for a password or similar: whether it is set, never the value,
+/// so settings can be logged.
+pub struct Secret<'a>(pub &'a str);
+
+impl fmt::Debug for Secret<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.is_empty() {
+            true => f.write_str("\"\""),
+            false => f.write_str("<redacted>"),
+        }
+    }
+}
+
+/// Runtime on/off switches for the optional subsystems. A disabled subsystem stays idle.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SubsystemToggles {
+    pub scanner: bool,
+    pub animations: bool,
+    /// Promiscuous capture of management frames.
+    pub sniffer: bool,
+}
+
+impl Default for SubsystemToggles {
+    fn default() -> Self {
+        SubsystemToggles {
+            scanner: true,
+            animations: true,
+            sniffer: false,
+        }
+    }
+}
+
+/// Partial update for [`SubsystemToggles`], as accepted by `POST /subsystems`.
+#[derive(Debug, Default, Deserialize)]
+pub struct SubsystemTogglesPatch {
+    pub scanner: Option<bool>,
+    pub animations: Option<bool>,
+    pub sniffer: Option<bool>,
+}
+
+impl SubsystemToggles {
+    pub fn apply(&mut self, patch: &SubsystemTogglesPatch) {
+        if let Some(scanner) = patch.scanner {
+            self.scanner = scanner;
+        }
+        if let Some(animations) = patch.animations {
+            self.animations = animations;
+        }
+        if let Some(sniffer) = patch.sniffer {
+            self.sniffer = sniffer;
+        }
+    }
+}
+
+/// What the LED shows between two scans.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScanDisplay {
+    /// Slow red blinking while waiting for the next scan.
+    #[default]
+    Flash,
+    /// Hue sweep over channels 1-13, brightness following the per-channel AP count.
+    ChannelHeat,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WifiMode {
+    /// Join the configured network as a client.
+    #[default]
+    Station,
+    /// Run our own access point with a DHCP server.
+    AccessPoint,
+    /// Run a decoy access point that records associations and portal logins.
+    Honeypot,
+    /// Join the configured network and run our own access point for local control
+    /// at the same time. The access point follows the station's channel.
+    Mixed,
+}
+
+impl WifiMode {
+    /// Whether the station joins one of the known networks.
+    pub fn joins_network(self) -> bool {
+        matches!(self, WifiMode::Station | WifiMode::Mixed)
+    }
+}
+
+/// Modem power save of the station between beacons. Deeper modes cut the
+/// current a lot but add latency to incoming traffic.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WifiPowerSave {
+    /// Radio always on.
+    None,
+    /// Wake for every DTIM beacon.
+    #[default]
+    Min,
+    /// Wake for every `listen_interval` beacons.
+    Max,
+}
+
+/// A bundle of settings for one way of using the device, applied in one go at
+/// provisioning time instead of flipping each switch by hand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Profile {
+    /// Just the LED: no scanning.
+    Lamp,
+    /// Continuous scanning with the channel heat map on the LED.
+    Scanner,
+    /// Station mode with the scanner and animations off, leaving the radio to the gateway.
+    RadioGateway,
+    /// Scanner on, LED quiet, honeypot access point.
+    Pentest,
+}
+
+impl Profile {
+    /// Overwrites the settings the profile covers and keeps everything else.
+    pub fn apply(self, config: &mut AppConfig) {
+        let (scanner, animations, sniffer, wifi_mode) = match self {
+            Profile::Lamp => (false, true, false, WifiMode::Station),
+            Profile::Scanner => (true, true, false, WifiMode::Station),
+            Profile::RadioGateway => (false, false, false, WifiMode::Station),
+            Profile::Pentest => (true, false, true, WifiMode::Honeypot),
+        };
+
+        config.profile = Some(self);
+        config.subsystems = SubsystemToggles {
+            scanner,
+            animations,
+            sniffer,
+        };
+        config.wifi_mode = wifi_mode;
+        if self == Profile::Scanner {
+            config.scan_display = ScanDisplay::ChannelHeat;
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccessPointSettings {
+    pub ssid: String,
+    /// Leave empty for an open network.
+    pub password: String,
+    /// `0` picks the least crowded channel from a scan when the access point starts.
+    /// Ignored in mixed mode, where the station's network decides.
+    pub channel: u8,
+    pub max_connections: u16,
+}
+
+impl fmt::Debug for AccessPointSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AccessPointSettings")
+            .field("ssid", &self.ssid)
+            .field("password", &Secret(&self.password))
+            .field("channel", &self.channel)
+            .field("max_connections", &self.max_connections)
+            .finish()
+    }
+}
+
+impl Default for AccessPointSettings {
+    fn default() -> Self {
+        AccessPointSettings {
+            ssid: "esp32-lamp".to_string(),
+            password: String::new(),
+            channel: 0,
+            max_connections: 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HoneypotSettings {
+    #[serde(flatten)]
+    pub access_point: AccessPointSettings,
+}
+
+impl Default for HoneypotSettings {
+    fn default() -> Self {
+        HoneypotSettings {
+            access_point: AccessPointSettings {
+                ssid: "Free-WiFi".to_string(),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// A network the station may join. Higher priorities are tried first.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct KnownNetwork {
+    pub ssid: String,
+    /// Leave empty for an open network.
+    #[serde(default)]
+    pub password: String,
+    #[serde(default)]
+    pub priority: u8,
+    /// WPA2-Enterprise credentials; `password` is unused when set.
+    #[serde(default)]
+    pub enterprise: Option<EnterpriseCredentials>,
+}
+
+impl fmt::Debug for KnownNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KnownNetwork")
+            .field("ssid", &self.ssid)
+            .field("password", &Secret(&self.password))
+            .field("priority", &self.priority)
+            .field("enterprise", &self.enterprise)
+            .finish()
+    }
+}
+
+impl KnownNetwork {
+    pub fn validate(&self) -> Result<()> {
+        if self.ssid.is_empty() || self.ssid.len() > 32 {
+            bail!("SSID must be 1-32 bytes");
+        }
+        match &self.enterprise {
+            Some(enterprise) => enterprise.validate()?,
+            None if !self.password.is_empty() && !(8..=64).contains(&self.password.len()) => {
+                bail!("Password must be empty or 8-64 bytes")
+            }
+            None => {}
+        }
+
+        Ok(())
+    }
+}
+
+/// Inner authentication of EAP-TTLS. PEAP always uses MSCHAPv2.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TtlsPhase2 {
+    #[default]
+    Mschapv2,
+    Mschap,
+    Pap,
+    Chap,
+}
+
+/// Credentials for PEAP or EAP-TTLS; which one is used is negotiated with the server.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EnterpriseCredentials {
+    /// Outer identity, often `anonymous@realm`. Empty sends the username.
+    #[serde(default)]
+    pub identity: String,
+    pub username: String,
+    pub password: String,
+    /// PEM certificate of the CA that signed the RADIUS server's certificate. Without
+    /// it the server is not verified.
+    #[serde(default)]
+    pub ca_cert: Option<String>,
+    #[serde(default)]
+    pub ttls_phase2: TtlsPhase2,
+}
+
+impl fmt::Debug for EnterpriseCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EnterpriseCredentials")
+            .field("identity", &Secret(&self.identity))
+            .field("username", &self.username)
+            .field("password", &Secret(&self.password))
+            .field("ca_cert", &self.ca_cert.is_some())
+            .field("ttls_phase2", &self.ttls_phase2)
+            .finish()
+    }
+}
+
+impl EnterpriseCredentials {
+    pub fn validate(&self) -> Result<()> {
+        if self.username.is_empty() || self.password.is_empty() {
+            bail!("Enterprise networks need a username and a password");
+        }
+        if let Some(ca_cert) = &self.ca_cert {
+            if !ca_cert.trim_start().starts_with("-----BEGIN CERTIFICATE-----") {
+                bail!("The CA certificate must be PEM encoded");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// How the station interface gets its address.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", tag = "mode")]
+pub enum StaAddressing {
+    #[default]
+    Dhcp,
+    /// Fixed address for networks without a DHCP server.
+    Static {
+        ip: Ipv4Addr,
+        netmask: Ipv4Addr,
+        gateway: Ipv4Addr,
+        dns: Option<Ipv4Addr>,
+        secondary_dns: Option<Ipv4Addr>,
+    },
+}
+
+impl StaAddressing {
+    pub fn validate(&self) -> Result<()> {
+        let StaAddressing::Static { ip, netmask, gateway, .. } = *self else {
+            return Ok(());
+        };
+
+        let mask = u32::from(netmask);
+        if mask == 0 || mask.leading_ones() != mask.count_ones() {
+            bail!("{} is not a valid netmask", netmask);
+        }
+        if u32::from(ip) & mask != u32::from(gateway) & mask {
+            bail!("Gateway {} is outside {}/{}", gateway, ip, mask.count_ones());
+        }
+
+        Ok(())
+    }
+}
+
+/// MAC address the station shows to the networks it joins.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", tag = "mode")]
+pub enum StaMac {
+    /// The address from the eFuse.
+    #[default]
+    Factory,
+    /// A random locally administered address picked at boot and, with
+    /// `per_reconnect`, again before every reconnection.
+    Random { per_reconnect: bool },
+    Fixed { mac: String },
+}
+
+impl StaMac {
+    pub fn validate(&self) -> Result<()> {
+        let StaMac::Fixed { mac } = self else {
+            return Ok(());
+        };
+
+        let Some(bytes) = parse_mac(mac) else {
+            bail!("{} is not a valid MAC address", mac);
+        };
+        if bytes[0] & 0x01 != 0 {
+            bail!("{} is a multicast address", mac);
+        }
+
+        Ok(())
+    }
+}
+
+/// Name the station gives the DHCP server, which most routers show in their
+/// client list.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Hostname {
+    /// Empty keeps the ESP-IDF default, `espressif`.
+    pub name: String,
+}
+
+impl Hostname {
+    pub fn validate(&self) -> Result<()> {
+        let name = self.name.as_str();
+        if name.len() > 32 {
+            bail!("The hostname must be at most 32 characters");
+        }
+        if name.starts_with('-')
+            || name.ends_with('-')
+            || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            bail!("The hostname may only contain letters, digits and inner hyphens");
+        }
+
+        Ok(())
+    }
+}
+
+/// Tells devices of a fleet apart on the dashboard and in monitoring.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Identity {
+    pub name: String,
+    pub location: String,
+    pub tags: Vec<String>,
+}
+
+impl Identity {
+    pub fn validate(&self) -> Result<()> {
+        // Shown in HTML and Prometheus labels as they are
+        let plain = |text: &str| !text.chars().any(|c| c.is_control() || matches!(c, '<' | '>' | '&' | '"' | '\\'));
+        if self.name.len() > 32 || !plain(&self.name) {
+            bail!("name must be at most 32 characters, without <>&\"\\");
+        }
+        if self.location.len() > 64 || !plain(&self.location) {
+            bail!("location must be at most 64 characters, without <>&\"\\");
+        }
+        if self.tags.len() > 8 {
+            bail!("At most 8 tags");
+        }
+        for tag in &self.tags {
+            if tag.is_empty() || tag.len() > 24 || !plain(tag) || tag.contains(',') {
+                bail!("Tags must be 1-24 characters, without <>&\"\\ or commas");
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.name.is_empty() && self.location.is_empty() && self.tags.is_empty()
+    }
+}
+
+/// Per-channel gains (0.0-1.0) applied to every color before it reaches the LED, so
+/// boards with different LED parts show the same white.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LedCalibration {
+    pub red: f32,
+    pub green: f32,
+    pub blue: f32,
+}
+
+impl Default for LedCalibration {
+    fn default() -> Self {
+        LedCalibration {
+            red: 1.0,
+            green: 1.0,
+            blue: 1.0,
+        }
+    }
+}
+
+impl LedCalibration {
+    pub fn gains(&self) -> [f32; 3] {
+        [self.red, self.green, self.blue]
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.gains().iter().any(|gain| !(0.0..=1.0).contains(gain)) {
+            bail!("LED calibration gains must be between 0.0 and 1.0");
+        }
+        Ok(())
+    }
+}
+
+/// Limits for LED loads switched through MOSFETs, where a sudden full-white turn-on
+/// can brown out the board.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LedPowerLimits {
+    /// Maximum combined duty of the three channels, in percent of all three at full
+    /// duty. 100 disables the limit.
+    pub max_combined_percent: u8,
+    /// Maximum duty increase per channel and 10 ms step, in percent of full duty.
+    /// 100 disables the ramp. Decreases are always applied at once.
+    pub max_rise_percent: u8,
+}
+
+impl Default for LedPowerLimits {
+    fn default() -> Self {
+        LedPowerLimits {
+            max_combined_percent: 100,
+            max_rise_percent: 100,
+        }
+    }
+}
+
+impl LedPowerLimits {
+    pub fn validate(&self) -> Result<()> {
+        if !(1..=100).contains(&self.max_combined_percent) || !(1..=100).contains(&self.max_rise_percent) {
+            bail!("LED power limits must be between 1 and 100 percent");
+        }
+        Ok(())
+    }
+}
+
+/// What gives way when `POST /color` arrives while the scanner is busy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LedPriorityPolicy {
+    /// A running scan is aborted, and scans and animations wait `hold_ms`, so the
+    /// color shows at once and the next command is not stuck behind a scan.
+    #[default]
+    Latency,
+    /// Scans always complete; the color may wait for one and is overdrawn by the
+    /// next animation frame.
+    DataCollection,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LedPriority {
+    pub policy: LedPriorityPolicy,
+    pub hold_ms: u16,
+}
+
+impl Default for LedPriority {
+    fn default() -> Self {
+        LedPriority {
+            policy: LedPriorityPolicy::Latency,
+            hold_ms: 3000,
+        }
+    }
+}
+
+impl LedPriority {
+    pub fn validate(&self) -> Result<()> {
+        if !(100..=30000).contains(&self.hold_ms) {
+            bail!("hold_ms must be 100-30000");
+        }
+        Ok(())
+    }
+}
+
+/// GPIOs driving the LED, one per channel: three for RGB, one for a single-color LED.
+/// Read once at boot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LedOutput {
+    pub pins: Vec<u8>,
+}
+
+impl Default for LedOutput {
+    fn default() -> Self {
+        LedOutput { pins: vec![3, 4, 5] }
+    }
+}
+
+impl LedOutput {
+    /// Only GPIO0-10 are free for LEDC on the ESP32-C3: 11-17 belong to the flash,
+    /// 18/19 to USB and 20/21 to the console UART.
+    pub fn validate(&self, claimed: &[u8]) -> Result<()> {
+        if self.pins.is_empty() || self.pins.len() > 3 {
+            bail!("The LED needs 1 to 3 pins, got {}", self.pins.len());
+        }
+
+        for (i, pin) in self.pins.iter().enumerate() {
+            if *pin > 10 {
+                bail!("GPIO{} cannot drive the LED on the ESP32-C3", pin);
+            }
+            if claimed.contains(pin) {
+                bail!("GPIO{} is already used by another subsystem", pin);
+            }
+            if self.pins[..i].contains(pin) {
+                bail!("GPIO{} is listed twice", pin);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Limits that keep a few stalled browser tabs from holding every HTTP socket.
+/// Read once at boot.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpSettings {
+    pub max_open_sockets: u8,
+    /// Sockets one client IP may hold; its least recently used ones are closed beyond that.
+    pub max_sockets_per_client: u8,
+    /// Sockets without a request for this long are closed.
+    pub idle_timeout_secs: u16,
+    /// Serve HTTPS on 443 with the certificate from `POST /tls`; plain HTTP while none is installed.
+    pub https: bool,
+    /// With `https`, redirect plain HTTP requests on 80 there.
+    pub redirect_http: bool,
+    /// Log every request with its status and duration, not only slow ones.
+    pub access_log: bool,
+    /// Requests taking longer are logged as warnings and counted as slow.
+    pub slow_request_ms: u32,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        HttpSettings {
+            max_open_sockets: 7,
+            max_sockets_per_client: 2,
+            idle_timeout_secs: 30,
+            https: false,
+            redirect_http: false,
+            access_log: false,
+            slow_request_ms: 1000,
+        }
+    }
+}
+
+impl HttpSettings {
+    /// lwIP has 10 sockets and the HTTP server keeps 3 of them for itself; the
+    /// redirect server takes 4 more.
+    pub fn validate(&self) -> Result<()> {
+        if !(1..=7).contains(&self.max_open_sockets) {
+            bail!("max_open_sockets must be 1-7");
+        }
+        if self.https && self.redirect_http && self.max_open_sockets > 3 {
+            bail!("max_open_sockets must be 1-3 while redirecting HTTP");
+        }
+        if self.redirect_http && !self.https {
+            bail!("redirect_http needs https");
+        }
+        if !(1..=self.max_open_sockets).contains(&self.max_sockets_per_client) {
+            bail!("max_sockets_per_client must be 1-{}", self.max_open_sockets);
+        }
+        if !(5..=3600).contains(&self.idle_timeout_secs) {
+            bail!("idle_timeout_secs must be 5-3600");
+        }
+        if !(10..=60_000).contains(&self.slow_request_ms) {
+            bail!("slow_request_ms must be 10-60000");
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScannerSettings {
+    /// Pause between two background scans, while not tracking.
+    pub interval_secs: u16,
+    /// RSSI samples kept per BSSID; read once at boot.
+    pub history_samples: u8,
+}
+
+impl Default for ScannerSettings {
+    fn default() -> Self {
+        ScannerSettings {
+            interval_secs: 10,
+            history_samples: 16,
+        }
+    }
+}
+
+impl ScannerSettings {
+    pub fn validate(&self) -> Result<()> {
+        if !(5..=3600).contains(&self.interval_secs) {
+            bail!("interval_secs must be 5-3600");
+        }
+        if !(1..=64).contains(&self.history_samples) {
+            bail!("history_samples must be 1-64");
+        }
+
+        Ok(())
+    }
+}
+
+/// Current drawn in each mode, in mA, for the energy estimate. The defaults are
+/// typical ESP32-C3 figures; `calibration` scales them all, e.g. to match a
+/// reading from an INA219 or a bench supply.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EnergyModel {
+    /// Associated, with modem sleep between beacons.
+    pub station_ma: f32,
+    /// The radio never sleeps while serving an access point.
+    pub access_point_ma: f32,
+    pub honeypot_ma: f32,
+    /// Added while the background scanner is on.
+    pub scanning_ma: f32,
+    /// Station in automatic light sleep, waking for beacons and traffic. Replaces
+    /// `station_ma` while the idle policy has the device asleep.
+    pub light_sleep_ma: f32,
+    pub calibration: f32,
+}
+
+impl Default for EnergyModel {
+    fn default() -> Self {
+        EnergyModel {
+            station_ma: 35.0,
+            access_point_ma: 95.0,
+            honeypot_ma: 95.0,
+            scanning_ma: 25.0,
+            light_sleep_ma: 3.0,
+            calibration: 1.0,
+        }
+    }
+}
+
+impl EnergyModel {
+    pub fn validate(&self) -> Result<()> {
+        let currents = [
+            self.station_ma,
+            self.access_point_ma,
+            self.honeypot_ma,
+            self.scanning_ma,
+            self.light_sleep_ma,
+        ];
+        if !currents.iter().all(|current| (0.0..=1000.0).contains(current)) {
+            bail!("Currents must be 0-1000 mA");
+        }
+        if !(0.1..=10.0).contains(&self.calibration) {
+            bail!("calibration must be 0.1-10.0");
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeauthSettings {
+    /// Deauthentication and disassociation frames per second and BSSID that
+    /// count as an attack. A roaming client sends a handful at most.
+    pub frames_per_sec: u16,
+}
+
+impl Default for DeauthSettings {
+    fn default() -> Self {
+        DeauthSettings { frames_per_sec: 10 }
+    }
+}
+
+impl DeauthSettings {
+    pub fn validate(&self) -> Result<()> {
+        if !(1..=1000).contains(&self.frames_per_sec) {
+            bail!("frames_per_sec must be 1-1000");
+        }
+
+        Ok(())
+    }
+}
+
+/// Background pings of the gateway, and of a few more hosts if set, that decide
+/// the link health shown on `/status`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LatencyMonitorSettings {
+    pub enabled: bool,
+    pub interval_secs: u16,
+    /// Names or addresses pinged after the gateway. They do not affect the health.
+    pub extra_hosts: Vec<String>,
+    /// Gateway loss over the recent probes above which the link is degraded.
+    pub degraded_loss_percent: f32,
+    /// Gateway round trip above which the link is degraded.
+    pub degraded_rtt_ms: f32,
+}
+
+impl Default for LatencyMonitorSettings {
+    fn default() -> Self {
+        LatencyMonitorSettings {
+            enabled: true,
+            interval_secs: 30,
+            extra_hosts: Vec::new(),
+            degraded_loss_percent: 20.0,
+            degraded_rtt_ms: 200.0,
+        }
+    }
+}
+
+impl LatencyMonitorSettings {
+    pub fn validate(&self) -> Result<()> {
+        if !(5..=3600).contains(&self.interval_secs) {
+            bail!("interval_secs must be 5-3600");
+        }
+        if self.extra_hosts.len() > 4 {
+            bail!("At most 4 extra hosts");
+        }
+        if self.extra_hosts.iter().any(|host| host.is_empty() || host.len() > 253) {
+            bail!("Hosts must be 1-253 characters");
+        }
+        if !(0.0..=100.0).contains(&self.degraded_loss_percent) {
+            bail!("degraded_loss_percent must be 0-100");
+        }
+        if !(1.0..=10_000.0).contains(&self.degraded_rtt_ms) {
+            bail!("degraded_rtt_ms must be 1-10000");
+        }
+
+        Ok(())
+    }
+}
+
+/// Soak-test mode: heap samples logged at every interval, with the extremes
+/// tracked from the start of the test.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SoakTestSettings {
+    pub enabled: bool,
+    /// Between two heap samples, also outside a soak test.
+    pub interval_secs: u16,
+}
+
+impl Default for SoakTestSettings {
+    fn default() -> Self {
+        SoakTestSettings {
+            enabled: false,
+            interval_secs: 60,
+        }
+    }
+}
+
+impl SoakTestSettings {
+    pub fn validate(&self) -> Result<()> {
+        if !(10..=3600).contains(&self.interval_secs) {
+            bail!("interval_secs must be 10-3600");
+        }
+
+        Ok(())
+    }
+}
+
+/// Automatic light sleep once nothing has happened for a while. Only a station
+/// can sleep; an access point has to keep beaconing.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SleepSettings {
+    pub enabled: bool,
+    /// Minutes without HTTP requests or button presses before sleeping.
+    pub idle_minutes: u16,
+    /// Active-low button that wakes the device, the BOOT button by default. Read
+    /// once at boot.
+    pub wake_gpio: Option<u8>,
+}
+
+impl Default for SleepSettings {
+    fn default() -> Self {
+        SleepSettings {
+            enabled: false,
+            idle_minutes: 10,
+            wake_gpio: Some(9),
+        }
+    }
+}
+
+impl SleepSettings {
+    /// `led_pins` are driven by the LED and cannot double as the button.
+    pub fn validate(&self, led_pins: &[u8]) -> Result<()> {
+        if !(1..=1440).contains(&self.idle_minutes) {
+            bail!("idle_minutes must be 1-1440");
+        }
+        if let Some(gpio) = self.wake_gpio {
+            if gpio > 10 {
+                bail!("GPIO{} cannot be used for the wake button on the ESP32-C3", gpio);
+            }
+            if led_pins.contains(&gpio) {
+                bail!("GPIO{} drives the LED", gpio);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Regulatory domain the radio follows: the channels scans and the access point
+/// may use, and the transmit power limit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WifiCountry {
+    /// ISO 3166-1 alpha-2 code, or `01` for the world-wide safe domain, which only
+    /// transmits on channels 1-11.
+    pub code: String,
+}
+
+impl Default for WifiCountry {
+    fn default() -> Self {
+        WifiCountry { code: "01".to_string() }
+    }
+}
+
+impl WifiCountry {
+    pub fn validate(&self) -> Result<()> {
+        let code = self.code.as_bytes();
+        if self.code != "01" && !(code.len() == 2 && code.iter().all(u8::is_ascii_uppercase)) {
+            bail!("code must be two uppercase letters or 01");
+        }
+
+        Ok(())
+    }
+
+    /// Highest channel an access point may use. ESP-IDF has the full table; this
+    /// covers the domains that differ from the usual 1-13.
+    pub fn max_channel(&self) -> u8 {
+        match self.code.as_str() {
+            "01" | "US" | "CA" | "MX" | "TW" | "PR" => 11,
+            "JP" => 14,
+            _ => 13,
+        }
+    }
+}
+
+/// Alerting on access points that were never seen before. BSSIDs are learned
+/// silently while disabled, so turning it on does not flag the neighbours.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NewNetworkAlerts {
+    pub enabled: bool,
+    /// Critical keeps the LED flashing until the alert is acknowledged.
+    pub severity: Severity,
+    /// Gets a JSON POST for every new network; empty for none.
+    pub webhook_url: String,
+}
+
+impl fmt::Debug for NewNetworkAlerts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NewNetworkAlerts")
+            .field("enabled", &self.enabled)
+            .field("severity", &self.severity)
+            .field("webhook_url", &Secret(&self.webhook_url))
+            .finish()
+    }
+}
+
+impl Default for NewNetworkAlerts {
+    fn default() -> Self {
+        NewNetworkAlerts {
+            enabled: false,
+            severity: Severity::Critical,
+            webhook_url: String::new(),
+        }
+    }
+}
+
+impl NewNetworkAlerts {
+    pub fn validate(&self) -> Result<()> {
+        let url = self.webhook_url.as_str();
+        if !url.is_empty() && !url.starts_with("http://") && !url.starts_with("https://") {
+            bail!("webhook_url must be an http:// or https:// URL");
+        }
+
+        Ok(())
+    }
+}
+
+/// Lowest level forwarded to syslog. Levels below the console's are never logged
+/// in the first place.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SyslogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+}
+
+impl SyslogLevel {
+    pub fn filter(self) -> log::LevelFilter {
+        match self {
+            SyslogLevel::Error => log::LevelFilter::Error,
+            SyslogLevel::Warn => log::LevelFilter::Warn,
+            SyslogLevel::Info => log::LevelFilter::Info,
+            SyslogLevel::Debug => log::LevelFilter::Debug,
+        }
+    }
+}
+
+/// Log records also sent to a syslog server over UDP, as RFC 5424 messages.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyslogSettings {
+    pub enabled: bool,
+    /// Host name or IPv4 address.
+    pub server: String,
+    pub port: u16,
+    pub level: SyslogLevel,
+}
+
+impl Default for SyslogSettings {
+    fn default() -> Self {
+        SyslogSettings {
+            enabled: false,
+            server: String::new(),
+            port: 514,
+            level: SyslogLevel::Info,
+        }
+    }
+}
+
+impl SyslogSettings {
+    pub fn validate(&self) -> Result<()> {
+        if self.enabled && self.server.is_empty() {
+            bail!("server is required when syslog is enabled");
+        }
+        if self.server.len() > 64 || self.server.contains(|c: char| c.is_whitespace() || c == ':') {
+            bail!("server must be a host name or IPv4 address of at most 64 characters");
+        }
+        if self.port == 0 {
+            bail!("port must not be 0");
+        }
+
+        Ok(())
+    }
+}
+
+/// Periodic check of a JSON manifest `{"version": "1.2.3", "url": "https://..."}`
+/// for a firmware newer than the running one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OtaPullSettings {
+    pub enabled: bool,
+    pub manifest_url: String,
+    pub interval_hours: u16,
+}
+
+impl Default for OtaPullSettings {
+    fn default() -> Self {
+        OtaPullSettings {
+            enabled: false,
+            manifest_url: String::new(),
+            interval_hours: 24,
+        }
+    }
+}
+
+impl OtaPullSettings {
+    pub fn validate(&self) -> Result<()> {
+        if self.enabled && self.manifest_url.is_empty() {
+            bail!("manifest_url is required when pull updates are enabled");
+        }
+        if !self.manifest_url.is_empty() && !self.manifest_url.starts_with("https://") {
+            bail!("manifest_url must be an https:// URL");
+        }
+        if !(1..=720).contains(&self.interval_hours) {
+            bail!("interval_hours must be 1-720");
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AppConfig {
+    /// Last profile applied; single settings may have been changed since.
+    pub profile: Option<Profile>,
+    pub subsystems: SubsystemToggles,
+    pub scan_display: ScanDisplay,
+    pub wifi_mode: WifiMode,
+    /// Networks tried in station mode; empty falls back to the simulator's open network.
+    pub known_networks: Vec<KnownNetwork>,
+    pub sta_addressing: StaAddressing,
+    pub sta_mac: StaMac,
+    pub hostname: Hostname,
+    pub identity: Identity,
+    pub access_point: AccessPointSettings,
+    pub honeypot: HoneypotSettings,
+    pub led_calibration: LedCalibration,
+    pub led_power: LedPowerLimits,
+    pub led_output: LedOutput,
+    pub led_priority: LedPriority,
+    pub http: HttpSettings,
+    pub scanner: ScannerSettings,
+    pub new_network_alerts: NewNetworkAlerts,
+    pub energy: EnergyModel,
+    pub deauth: DeauthSettings,
+    pub rules: Vec<Rule>,
+    pub sleep: SleepSettings,
+    pub wifi_country: WifiCountry,
+    pub wifi_power_save: WifiPowerSave,
+    pub latency_monitor: LatencyMonitorSettings,
+    pub soak_test: SoakTestSettings,
+    pub syslog: SyslogSettings,
+    pub ota_pull: OtaPullSettings,
+}
+
+/// Application configuration, persisted as a JSON blob in the `config` NVS namespace.
+pub struct ConfigStore {
+    nvs: Option<Mutex<EspNvs<NvsDefault>>>,
+    config: Mutex<AppConfig>,
+}
+
+impl ConfigStore {
+    pub fn new(partition: EspDefaultNvsPartition) -> Result<Self> {
+        let nvs = EspNvs::new(partition, NAMESPACE, true)?;
+
+        let config = match Self::load(&nvs) {
+            Ok(config) => config,
+            Err(e) => {
+                log::warn!("Failed to load stored configuration, using defaults: {}", e);
+                AppConfig::default()
+            }
+        };
+        log::info!("Loaded configuration: {:?}", config);
+
+        Ok(ConfigStore {
+            nvs: Some(Mutex::new(nvs)),
+            config: Mutex::new(config),
+        })
+    }
+
+    /// Defaults-only store used when NVS is unavailable; updates are not persisted.
+    pub fn in_memory() -> Self {
+        ConfigStore {
+            nvs: None,
+            config: Mutex::new(AppConfig::default()),
+        }
+    }
+
+    fn load(nvs: &EspNvs<NvsDefault>) -> Result<AppConfig> {
+        match storage::read(nvs, CONFIG_KEY)? {
+            Some(data) => Ok(serde_json::from_slice(&data)?),
+            None => Ok(AppConfig::default()),
+        }
+    }
+
+    pub fn get(&self) -> AppConfig {
+        self.config.lock().unwrap().clone()
+    }
+
+    /// Applies `f` to a copy of the configuration and persists the result.
+    ///
+    /// The in-memory configuration is only replaced once the write to NVS succeeded.
+    pub fn update<F: FnOnce(&mut AppConfig)>(&self, f: F) -> Result<AppConfig> {
+        let mut config = self.config.lock().unwrap();
+
+        let mut updated = config.clone();
+        f(&mut updated);
+
+        match &self.nvs {
+            Some(nvs) => {
+                let data = serde_json::to_vec(&updated)?;
+                // Rules and CA certificates can outgrow a single entry
+                storage::write(&mut nvs.lock().unwrap(), CONFIG_KEY, &data)?;
+            }
+            None => log::warn!("NVS unavailable, configuration change will not survive a reboot"),
+        }
+
+        *config = updated.clone();
+        Ok(updated)
+    }
+
+    /// Erases the `config` namespace and goes back to the defaults. Pairing,
+    /// TLS and the other namespaces are kept.
+    pub fn factory_reset(&self) -> Result<()> {
+        let mut config = self.config.lock().unwrap();
+        if let Some(nvs) = &self.nvs {
+            // Held so no update writes the old configuration back in between
+            let _nvs = nvs.lock().unwrap();
+            let namespace = CString::new(NAMESPACE)?;
+            let mut handle = 0;
+            unsafe {
+                esp!(sys::nvs_open(namespace.as_ptr(), sys::nvs_open_mode_t_NVS_READWRITE, &mut handle))?;
+                let result = esp!(sys::nvs_erase_all(handle)).and_then(|()| esp!(sys::nvs_commit(handle)));
+                sys::nvs_close(handle);
+                result?;
+            }
+        }
+
+        *config = AppConfig::default();
+        Ok(())
+    }
+}
@@ -0,0 +1,63 @@
+//! Single-use tokens a destructive request has to echo back, so a stray or
+//! replayed POST cannot reboot or wipe a deployed board. A request without a
+//! token is answered with one; repeating it with the token within
+//! [`TOKEN_LIFETIME`] goes through.
+
+use esp_idf_svc::sys;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::codec;
+use crate::pairing::constant_time_eq;
+
+pub const TOKEN_LIFETIME: Duration = Duration::from_secs(60);
+const TOKEN_BYTES: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Reboot,
+    FactoryReset,
+}
+
+struct Pending {
+    action: Action,
+    token: String,
+    issued: Instant,
+}
+
+/// At most one token per action; issuing another replaces it.
+#[derive(Default)]
+pub struct Confirmations {
+    pending: Mutex<Vec<Pending>>,
+}
+
+impl Confirmations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn issue(&self, action: Action) -> String {
+        let mut random = [0_u8; TOKEN_BYTES];
+        unsafe { sys::esp_fill_random(random.as_mut_ptr() as *mut _, random.len()) };
+        let token = codec::hex_string(&random);
+
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|pending| pending.action != action);
+        pending.push(Pending {
+            action,
+            token: token.clone(),
+            issued: Instant::now(),
+        });
+        token
+    }
+
+    /// Whether `token` is the live one for `action`; it is used up either way.
+    pub fn confirm(&self, action: Action, token: &str) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        let Some(index) = pending.iter().position(|pending| pending.action == action) else {
+            return false;
+        };
+        let issued = pending.remove(index);
+        issued.issued.elapsed() <= TOKEN_LIFETIME && constant_time_eq(issued.token.as_bytes(), token.as_bytes())
+    }
+}
@@ -0,0 +1,139 @@
+//! Per-task CPU usage from the FreeRTOS run time counters.
+//!
+//! Needs `CONFIG_FREERTOS_USE_TRACE_FACILITY` and
+//! `CONFIG_FREERTOS_GENERATE_RUN_TIME_STATS`, see `sdkconfig.defaults`.
+
+use esp_idf_hal::task::thread::ThreadSpawnConfiguration;
+use esp_idf_svc::sys::{uxTaskGetNumberOfTasks, uxTaskGetSystemState, TaskStatus_t};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::ffi::CStr;
+use std::fmt::Write as _;
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskUsage {
+    pub name: String,
+    /// Share of the CPU over the last sampling period.
+    pub percent: f32,
+    pub priority: u32,
+    /// Least free stack the task ever had, in bytes.
+    pub stack_free_min: u32,
+}
+
+#[derive(Default)]
+struct Samples {
+    /// Run time counter of each task, by task number, at the previous sample.
+    run_time: BTreeMap<u32, u64>,
+    total_run_time: u64,
+    usage: Vec<TaskUsage>,
+}
+
+/// CPU share of each task between the two most recent samples.
+#[derive(Default)]
+pub struct CpuMonitor {
+    samples: Mutex<Samples>,
+}
+
+impl CpuMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn sample(&self) {
+        // Room for tasks created while the snapshot is taken
+        let capacity = unsafe { uxTaskGetNumberOfTasks() } as usize + 4;
+        let mut tasks: Vec<TaskStatus_t> = Vec::with_capacity(capacity);
+        let mut total_run_time = 0;
+        let count = unsafe { uxTaskGetSystemState(tasks.as_mut_ptr(), capacity as _, &mut total_run_time) };
+        // Safety: the kernel filled in `count` entries
+        unsafe { tasks.set_len(count as usize) };
+
+        let mut samples = self.samples.lock().unwrap();
+        let total_run_time = total_run_time as u64;
+        let elapsed = total_run_time.saturating_sub(samples.total_run_time).max(1);
+
+        let mut run_time = BTreeMap::new();
+        let mut usage: Vec<TaskUsage> = tasks
+            .iter()
+            .map(|task| {
+                let number = task.xTaskNumber as u32;
+                let counter = task.ulRunTimeCounter as u64;
+                let previous = samples.run_time.get(&number).copied().unwrap_or(0);
+                run_time.insert(number, counter);
+
+                TaskUsage {
+                    name: unsafe { CStr::from_ptr(task.pcTaskName) }.to_string_lossy().into_owned(),
+                    percent: (counter.saturating_sub(previous) * 100) as f32 / elapsed as f32,
+                    priority: task.uxCurrentPriority as u32,
+                    stack_free_min: task.usStackHighWaterMark as u32,
+                }
+            })
+            .collect();
+        usage.sort_by(|a, b| b.percent.total_cmp(&a.percent));
+
+        *samples = Samples {
+            run_time,
+            total_run_time,
+            usage,
+        };
+    }
+
+    /// Busiest first.
+    pub fn usage(&self) -> Vec<TaskUsage> {
+        self.samples.lock().unwrap().usage.clone()
+    }
+
+    /// Lines for the plain-text `/status` report.
+    pub fn status_lines(&self) -> String {
+        let mut lines = String::new();
+        for task in self.usage() {
+            let _ = writeln!(lines, "cpu: {} {:.1}% (priority {})", task.name, task.percent, task.priority);
+        }
+        lines
+    }
+
+    /// Prometheus text exposition for `/metrics`.
+    pub fn metrics(&self) -> String {
+        let usage = self.usage();
+        let mut metrics = String::new();
+
+        let _ = writeln!(metrics, "# TYPE task_cpu_percent gauge");
+        for task in &usage {
+            let _ = writeln!(metrics, "task_cpu_percent{{task=\"{}\"}} {:.1}", task.name, task.percent);
+        }
+        let _ = writeln!(metrics, "# TYPE task_stack_free_min_bytes gauge");
+        for task in &usage {
+            let _ = writeln!(metrics, "task_stack_free_min_bytes{{task=\"{}\"}} {}", task.name, task.stack_free_min);
+        }
+        metrics
+    }
+}
+
+/// Spawns a thread whose FreeRTOS task is called `name` instead of "pthread", so
+/// it can be told apart in the CPU report.
+pub fn spawn<F, T>(name: &'static CStr, stack_size: Option<usize>, f: F) -> Option<JoinHandle<T>>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let named = ThreadSpawnConfiguration {
+        name: Some(name.to_bytes_with_nul()),
+        ..Default::default()
+    };
+    if let Err(e) = named.set() {
+        log::warn!("Failed to name thread {}: {:?}", name.to_string_lossy(), e);
+    }
+
+    let mut builder = std::thread::Builder::new();
+    if let Some(stack_size) = stack_size {
+        builder = builder.stack_size(stack_size);
+    }
+    let handle = builder.spawn(f);
+    let _ = ThreadSpawnConfiguration::default().set();
+
+    handle
+        .map_err(|e| log::error!("Failed to start thread {}: {:?}", name.to_string_lossy(), e))
+        .ok()
+}
@@ -0,0 +1,82 @@
+use std::collections::BTreeMap;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::alerts::{AlertLog, Severity};
+use crate::config::ConfigStore;
+use crate::events::{Event, EventBus};
+use crate::scan::format_mac;
+use crate::sniffer::{ManagementFrame, Subtype};
+
+/// Queue between the sniffer and the detector; deauth floods are bursty.
+pub const FRAME_QUEUE: usize = 64;
+const WINDOW: Duration = Duration::from_secs(1);
+/// A BSSID that raised an alarm stays quiet this long, however long the flood lasts.
+const COOLDOWN: Duration = Duration::from_secs(60);
+
+/// Counts deauthentication and disassociation frames per BSSID each second and
+/// raises a critical alert, which turns the LED to the red alert pattern, when
+/// one goes over the configured rate.
+pub struct DeauthDetector {
+    config: Arc<ConfigStore>,
+    events: Arc<EventBus>,
+    alerts: Arc<AlertLog>,
+    counts: BTreeMap<[u8; 6], u32>,
+    last_alarm: BTreeMap<[u8; 6], Instant>,
+}
+
+impl DeauthDetector {
+    pub fn new(config: Arc<ConfigStore>, events: Arc<EventBus>, alerts: Arc<AlertLog>) -> Self {
+        DeauthDetector {
+            config,
+            events,
+            alerts,
+            counts: BTreeMap::new(),
+            last_alarm: BTreeMap::new(),
+        }
+    }
+
+    /// Consumes sniffed frames until the sniffer goes away.
+    pub fn run(mut self, frames: Receiver<ManagementFrame>) {
+        let mut window_start = Instant::now();
+
+        loop {
+            match frames.recv_timeout(WINDOW / 4) {
+                Ok(frame) => {
+                    if matches!(frame.subtype, Subtype::Deauthentication | Subtype::Disassociation) {
+                        *self.counts.entry(frame.bssid).or_default() += 1;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+
+            if window_start.elapsed() >= WINDOW {
+                self.close_window();
+                window_start = Instant::now();
+            }
+        }
+    }
+
+    fn close_window(&mut self) {
+        let threshold = self.config.get().deauth.frames_per_sec;
+        let now = Instant::now();
+
+        self.last_alarm.retain(|_, at| now - *at < COOLDOWN);
+        for (bssid, frames) in std::mem::take(&mut self.counts) {
+            if frames < threshold as u32 || self.last_alarm.contains_key(&bssid) {
+                continue;
+            }
+            self.last_alarm.insert(bssid, now);
+
+            let bssid = format_mac(&bssid);
+            self.alerts.raise(
+                Severity::Critical,
+                "deauth",
+                format!("{} deauthentication frames/s for {}", frames, bssid),
+            );
+            self.events.publish(Event::DeauthFlood { bssid, frames });
+        }
+    }
+}
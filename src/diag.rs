@@ -0,0 +1,181 @@
+use anyhow::{anyhow, Result};
+use esp_idf_svc::ipv4::IpInfo;
+use esp_idf_svc::ping::{self, EspPing, Reply};
+use serde::Serialize;
+use std::net::{Ipv4Addr, SocketAddr, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+pub const MAX_PING_COUNT: u32 = 10;
+/// What one request may spend pinging, since it holds the single HTTP worker.
+/// Resolving a host name comes on top.
+pub const MAX_REQUEST_TIME: Duration = Duration::from_secs(4);
+/// Per echo when pinging for a request: a reply slower than this counts as lost.
+const REQUEST_PING_TIMEOUT: Duration = Duration::from_millis(500);
+const REQUEST_PING_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Serialize)]
+pub struct PingReport {
+    pub host: String,
+    pub ip: String,
+    pub transmitted: u32,
+    pub received: u32,
+    pub loss_percent: f32,
+    pub min_ms: Option<f32>,
+    pub avg_ms: Option<f32>,
+    pub max_ms: Option<f32>,
+}
+
+/// One stage of a path check.
+#[derive(Debug, Serialize)]
+pub struct Hop {
+    pub name: &'static str,
+    pub report: Option<PingReport>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DnsReport {
+    pub name: String,
+    pub resolver: Option<String>,
+    pub elapsed_ms: f32,
+    pub a: Vec<String>,
+    pub aaaa: Vec<String>,
+    pub error: Option<String>,
+}
+
+/// Resolves `name` through lwIP's resolver. A failed lookup is reported in the
+/// result rather than as an error, since that is the point of the diagnostic.
+pub fn lookup(name: &str, resolver: Option<Ipv4Addr>) -> DnsReport {
+    let started = Instant::now();
+    let result = (name, 0).to_socket_addrs();
+    let elapsed_ms = started.elapsed().as_secs_f32() * 1000.0;
+
+    let mut report = DnsReport {
+        name: name.to_string(),
+        resolver: resolver.map(|resolver| resolver.to_string()),
+        elapsed_ms,
+        a: Vec::new(),
+        aaaa: Vec::new(),
+        error: None,
+    };
+
+    match result {
+        Ok(addrs) => {
+            for addr in addrs {
+                match addr {
+                    SocketAddr::V4(addr) => report.a.push(addr.ip().to_string()),
+                    SocketAddr::V6(addr) => report.aaaa.push(addr.ip().to_string()),
+                }
+            }
+        }
+        Err(e) => report.error = Some(e.to_string()),
+    }
+
+    report
+}
+
+/// Accepts a dotted IPv4 address or a host name, resolved with the configured DNS.
+pub fn resolve(host: &str) -> Result<Ipv4Addr> {
+    if let Ok(ip) = host.parse() {
+        return Ok(ip);
+    }
+
+    (host, 0)
+        .to_socket_addrs()?
+        .find_map(|addr| match addr {
+            SocketAddr::V4(addr) => Some(*addr.ip()),
+            SocketAddr::V6(_) => None,
+        })
+        .ok_or_else(|| anyhow!("{} has no IPv4 address", host))
+}
+
+pub fn ping(host: &str, count: u32) -> Result<PingReport> {
+    let configuration = ping::Configuration {
+        count: count.clamp(1, MAX_PING_COUNT),
+        ..Default::default()
+    };
+    ping_with(host, &configuration)
+}
+
+/// Like [`ping`] with a short timeout and as many of the `count` echoes as fit
+/// in `budget`, at least one.
+pub fn ping_within(host: &str, count: u32, budget: Duration) -> Result<PingReport> {
+    let fits = (budget.as_millis() / REQUEST_PING_TIMEOUT.as_millis()) as u32;
+    let configuration = ping::Configuration {
+        count: count.clamp(1, MAX_PING_COUNT).min(fits.max(1)),
+        interval: REQUEST_PING_INTERVAL,
+        timeout: REQUEST_PING_TIMEOUT,
+        ..Default::default()
+    };
+    ping_with(host, &configuration)
+}
+
+fn ping_with(host: &str, configuration: &ping::Configuration) -> Result<PingReport> {
+    let ip = resolve(host)?;
+
+    let mut round_trips: Vec<Duration> = Vec::new();
+    let summary = EspPing::default().ping_details(ip, configuration, |_, reply| {
+        if let Reply::Success(info) = reply {
+            round_trips.push(info.elapsed_time);
+        }
+    })?;
+
+    let millis = |duration: &Duration| duration.as_secs_f32() * 1000.0;
+    let loss_percent = match summary.transmitted {
+        0 => 100.0,
+        transmitted => (transmitted - summary.received) as f32 * 100.0 / transmitted as f32,
+    };
+
+    Ok(PingReport {
+        host: host.to_string(),
+        ip: ip.to_string(),
+        transmitted: summary.transmitted,
+        received: summary.received,
+        loss_percent,
+        min_ms: round_trips.iter().min().map(millis),
+        avg_ms: (!round_trips.is_empty())
+            .then(|| millis(&round_trips.iter().sum::<Duration>()) / round_trips.len() as f32),
+        max_ms: round_trips.iter().max().map(millis),
+    })
+}
+
+/// Traceroute-lite: the ping wrapper cannot set the TTL, so instead of real hops this
+/// pings the gateway, the DNS server and the target in turn, which is usually enough
+/// to tell a local link problem from an upstream one.
+///
+/// The stages share [`MAX_REQUEST_TIME`]; one that no longer fits is reported
+/// as an error instead of pinged.
+pub fn path_check(host: &str, count: u32, ip_info: Option<&IpInfo>) -> Vec<Hop> {
+    let deadline = Instant::now() + MAX_REQUEST_TIME;
+    let mut stages = Vec::new();
+    if let Some(ip_info) = ip_info {
+        stages.push(("gateway", ip_info.subnet.gateway.to_string()));
+        if let Some(dns) = ip_info.dns {
+            stages.push(("dns", dns.to_string()));
+        }
+    }
+    stages.push(("target", host.to_string()));
+
+    let total = stages.len();
+    let mut hops = Vec::new();
+    for (index, (name, host)) in stages.into_iter().enumerate() {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let result = match remaining < REQUEST_PING_TIMEOUT {
+            true => Err(anyhow!("Skipped, out of time")),
+            false => ping_within(&host, count, remaining / (total - index) as u32),
+        };
+        hops.push(match result {
+            Ok(report) => Hop {
+                name,
+                report: Some(report),
+                error: None,
+            },
+            Err(e) => Hop {
+                name,
+                report: None,
+                error: Some(e.to_string()),
+            },
+        });
+    }
+    hops
+}
@@ -0,0 +1,194 @@
+use anyhow::Result;
+#[cfg(not(feature = "sim"))]
+use anyhow::anyhow;
+#[cfg(not(feature = "sim"))]
+use embedded_graphics::mono_font::{ascii::FONT_6X10, MonoTextStyle};
+#[cfg(not(feature = "sim"))]
+use embedded_graphics::pixelcolor::BinaryColor;
+#[cfg(not(feature = "sim"))]
+use embedded_graphics::prelude::*;
+#[cfg(not(feature = "sim"))]
+use embedded_graphics::text::{Baseline, Text};
+#[cfg(not(feature = "sim"))]
+use esp_idf_hal::gpio::{PinDriver, Pull};
+use esp_idf_hal::gpio::{InputPin, OutputPin};
+#[cfg(not(feature = "sim"))]
+use esp_idf_hal::i2c::{I2cConfig, I2cDriver};
+use esp_idf_hal::i2c::I2c;
+use esp_idf_hal::peripheral::Peripheral;
+#[cfg(not(feature = "sim"))]
+use esp_idf_hal::units::*;
+use esp_idf_svc::sys::{esp, esp_wifi_sta_get_rssi};
+#[cfg(not(feature = "sim"))]
+use ssd1306::{prelude::*, I2CDisplayInterface, Ssd1306};
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+#[cfg(not(feature = "sim"))]
+use std::time::Instant;
+
+use crate::config::ConfigStore;
+use crate::scan::LatestScan;
+
+#[cfg(not(feature = "sim"))]
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+#[cfg(not(feature = "sim"))]
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+#[cfg(not(feature = "sim"))]
+const LINE_HEIGHT: i32 = 12;
+#[cfg(feature = "sim")]
+const SIM_PAGE_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy)]
+enum Page {
+    Network,
+    Mode,
+    Scan,
+}
+
+impl Page {
+    fn next(self) -> Self {
+        match self {
+            Page::Network => Page::Mode,
+            Page::Mode => Page::Scan,
+            Page::Scan => Page::Network,
+        }
+    }
+}
+
+/// Drives a 128x64 SSD1306 over I2C, showing one status page at a time.
+/// Each press of the button (active low) moves to the next page.
+#[cfg(not(feature = "sim"))]
+pub fn run_display<I2C: I2c>(
+    i2c: impl Peripheral<P = I2C> + 'static,
+    sda: impl Peripheral<P = impl InputPin + OutputPin> + 'static,
+    scl: impl Peripheral<P = impl InputPin + OutputPin> + 'static,
+    button: impl Peripheral<P = impl InputPin + OutputPin> + 'static,
+    ip: Option<Ipv4Addr>,
+    latest_scan: LatestScan,
+    config: Arc<ConfigStore>,
+) -> Result<()> {
+    let i2c = I2cDriver::new(i2c, sda, scl, &I2cConfig::new().baudrate(400.kHz().into()))?;
+    let interface = I2CDisplayInterface::new(i2c);
+    let mut display = Ssd1306::new(interface, DisplaySize128x64, DisplayRotation::Rotate0)
+        .into_buffered_graphics_mode();
+    display.init().map_err(|e| anyhow!("Display init failed: {:?}", e))?;
+
+    let mut button = PinDriver::input(button)?;
+    button.set_pull(Pull::Up)?;
+
+    log::info!("Display initialized");
+
+    let mut page = Page::Network;
+    let mut was_pressed = false;
+    let mut last_refresh: Option<Instant> = None;
+
+    loop {
+        let pressed = button.is_low();
+        let page_changed = pressed && !was_pressed;
+        was_pressed = pressed;
+
+        if page_changed {
+            page = page.next();
+        }
+
+        if page_changed || last_refresh.map_or(true, |at| at.elapsed() >= REFRESH_INTERVAL) {
+            let lines = page_lines(page, ip, &latest_scan, &config);
+
+            display.clear_buffer();
+            let style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+            for (i, line) in lines.iter().enumerate() {
+                Text::with_baseline(line, Point::new(0, i as i32 * LINE_HEIGHT), style, Baseline::Top)
+                    .draw(&mut display)
+                    .map_err(|e| anyhow!("Display draw failed: {:?}", e))?;
+            }
+            display.flush().map_err(|e| anyhow!("Display flush failed: {:?}", e))?;
+
+            last_refresh = Some(Instant::now());
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Simulation stand-in for the SSD1306: cycles through the pages on a fixed
+/// period and logs them, leaving the I2C bus and button untouched.
+#[cfg(feature = "sim")]
+pub fn run_display<I2C: I2c>(
+    _i2c: impl Peripheral<P = I2C> + 'static,
+    _sda: impl Peripheral<P = impl InputPin + OutputPin> + 'static,
+    _scl: impl Peripheral<P = impl InputPin + OutputPin> + 'static,
+    _button: impl Peripheral<P = impl InputPin + OutputPin> + 'static,
+    ip: Option<Ipv4Addr>,
+    latest_scan: LatestScan,
+    config: Arc<ConfigStore>,
+) -> Result<()> {
+    log::info!("Simulated display: pages are logged instead of drawn");
+
+    let mut page = Page::Network;
+    loop {
+        for line in page_lines(page, ip, &latest_scan, &config) {
+            log::info!("[display] {}", line);
+        }
+
+        page = page.next();
+        thread::sleep(SIM_PAGE_INTERVAL);
+    }
+}
+
+fn page_lines(
+    page: Page,
+    ip: Option<Ipv4Addr>,
+    latest_scan: &LatestScan,
+    config: &ConfigStore,
+) -> Vec<String> {
+    match page {
+        Page::Network => vec![
+            "Network".to_string(),
+            match ip {
+                Some(ip) => format!("IP: {}", ip),
+                None => "IP: not connected".to_string(),
+            },
+            match current_rssi() {
+                Some(rssi) => format!("RSSI: {} dBm", rssi),
+                None => "RSSI: n/a".to_string(),
+            },
+        ],
+        Page::Mode => {
+            let config = config.get();
+            vec![
+                "Mode".to_string(),
+                format!("Scanner: {}", on_off(config.subsystems.scanner)),
+                format!("Animations: {}", on_off(config.subsystems.animations)),
+                format!("LED: {:?}", config.scan_display),
+            ]
+        }
+        Page::Scan => {
+            let networks = latest_scan.lock().unwrap();
+            let mut lines = vec![
+                "Scan".to_string(),
+                format!("{} networks", networks.len()),
+            ];
+            if let Some(best) = networks.iter().max_by_key(|network| network.signal_strength) {
+                lines.push(format!("Best: {}", best.ssid));
+                lines.push(format!("{} dBm ch {}", best.signal_strength, best.channel));
+            }
+            lines
+        }
+    }
+}
+
+fn current_rssi() -> Option<i32> {
+    let mut rssi: core::ffi::c_int = 0;
+    esp!(unsafe { esp_wifi_sta_get_rssi(&mut rssi) }).ok()?;
+    Some(rssi)
+}
+
+fn on_off(enabled: bool) -> &'static str {
+    if enabled {
+        "on"
+    } else {
+        "off"
+    }
+}
@@ -0,0 +1,121 @@
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::config::{AppConfig, WifiMode};
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct EnergyReport {
+    pub consumed_mah: f64,
+    pub station_secs: u64,
+    pub access_point_secs: u64,
+    pub honeypot_secs: u64,
+    /// Part of the time above with the background scanner on.
+    pub scanning_secs: u64,
+    /// Part of the station time spent in idle light sleep.
+    pub light_sleep_secs: u64,
+}
+
+struct Totals {
+    last_sample: Instant,
+    consumed_mah: f64,
+    station_ms: u64,
+    access_point_ms: u64,
+    honeypot_ms: u64,
+    scanning_ms: u64,
+    light_sleep_ms: u64,
+}
+
+/// Estimated charge drawn since boot, integrated from the time spent in each mode
+/// and the currents of the [`EnergyModel`](crate::config::EnergyModel).
+pub struct EnergyMeter {
+    /// The WiFi mode only changes on reboot.
+    wifi_mode: WifiMode,
+    totals: Mutex<Totals>,
+}
+
+impl EnergyMeter {
+    pub fn new(wifi_mode: WifiMode) -> Self {
+        EnergyMeter {
+            wifi_mode,
+            totals: Mutex::new(Totals {
+                last_sample: Instant::now(),
+                consumed_mah: 0.0,
+                station_ms: 0,
+                access_point_ms: 0,
+                honeypot_ms: 0,
+                scanning_ms: 0,
+                light_sleep_ms: 0,
+            }),
+        }
+    }
+
+    /// Accounts for the time since the previous sample as spent in the current mode.
+    pub fn sample(&self, config: &AppConfig, asleep: bool) {
+        let model = &config.energy;
+        let scanning = config.subsystems.scanner;
+
+        let mut totals = self.totals.lock().unwrap();
+        let now = Instant::now();
+        let elapsed = now - totals.last_sample;
+        totals.last_sample = now;
+        let elapsed_ms = elapsed.as_millis() as u64;
+
+        let mut current_ma = match self.wifi_mode {
+            WifiMode::Station if asleep => {
+                totals.station_ms += elapsed_ms;
+                totals.light_sleep_ms += elapsed_ms;
+                model.light_sleep_ma
+            }
+            WifiMode::Station => {
+                totals.station_ms += elapsed_ms;
+                model.station_ma
+            }
+            WifiMode::AccessPoint | WifiMode::Mixed => {
+                totals.access_point_ms += elapsed_ms;
+                model.access_point_ma
+            }
+            WifiMode::Honeypot => {
+                totals.honeypot_ms += elapsed_ms;
+                model.honeypot_ma
+            }
+        };
+        if scanning {
+            totals.scanning_ms += elapsed_ms;
+            current_ma += model.scanning_ma;
+        }
+
+        totals.consumed_mah += (current_ma * model.calibration) as f64 * elapsed.as_secs_f64() / 3600.0;
+    }
+
+    pub fn report(&self) -> EnergyReport {
+        let totals = self.totals.lock().unwrap();
+        EnergyReport {
+            consumed_mah: totals.consumed_mah,
+            station_secs: totals.station_ms / 1000,
+            access_point_secs: totals.access_point_ms / 1000,
+            honeypot_secs: totals.honeypot_ms / 1000,
+            scanning_secs: totals.scanning_ms / 1000,
+            light_sleep_secs: totals.light_sleep_ms / 1000,
+        }
+    }
+
+    /// Lines for the plain-text `/status` report.
+    pub fn status_lines(&self) -> String {
+        let report = self.report();
+        let mut lines = String::new();
+
+        let _ = writeln!(lines, "energy: {:.1} mAh (estimated)", report.consumed_mah);
+        let _ = writeln!(
+            lines,
+            "energy_time: station {} s, access point {} s, honeypot {} s, scanning {} s, light sleep {} s",
+            report.station_secs,
+            report.access_point_secs,
+            report.honeypot_secs,
+            report.scanning_secs,
+            report.light_sleep_secs
+        );
+        lines
+    }
+}
@@ -0,0 +1,45 @@
+//! Handler errors as HTTP responses. A handler returns an [`HttpError`] for a
+//! status of its choosing; malformed JSON, UTF-8 or numbers are 400s and
+//! anything else a 500. The body is always `{"status": n, "error": "..."}`.
+
+use anyhow::Result;
+use esp_idf_svc::http::server::EspHttpConnection;
+use serde::Serialize;
+use std::num::{ParseFloatError, ParseIntError};
+use std::str::Utf8Error;
+
+pub use parsers::HttpError;
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    status: u16,
+    error: &'a str,
+}
+
+pub fn status(error: &anyhow::Error) -> u16 {
+    if let Some(error) = error.downcast_ref::<HttpError>() {
+        return error.status;
+    }
+    if error.is::<serde_json::Error>()
+        || error.is::<Utf8Error>()
+        || error.is::<ParseIntError>()
+        || error.is::<ParseFloatError>()
+    {
+        return 400;
+    }
+    500
+}
+
+pub fn write_error(
+    connection: &mut EspHttpConnection,
+    status: u16,
+    message: &str,
+    headers: &[(&str, &str)],
+) -> Result<()> {
+    let body = serde_json::to_vec(&ErrorBody { status, error: message })?;
+    let mut all_headers = vec![("Content-Type", "application/json")];
+    all_headers.extend_from_slice(headers);
+    connection.initiate_response(status, None, &all_headers)?;
+    connection.write_all(&body)?;
+    Ok(())
+}
@@ -0,0 +1,236 @@
+//! ESP-NOW next to the WiFi link, so two boards can exchange data without an
+//! access point in between.
+//!
+//! Frames go out on the channel the radio is already on; a peer has to sit on the
+//! same channel, which boards joined to the same network do.
+
+use anyhow::{bail, Result};
+use esp_idf_svc::espnow::{EspNow, PeerInfo, SendStatus, BROADCAST};
+use esp_idf_svc::sys::{wifi_interface_t, wifi_interface_t_WIFI_IF_AP, wifi_interface_t_WIFI_IF_STA};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+
+use crate::codec;
+use crate::config::WifiMode;
+use crate::events::{Event, EventBus};
+use crate::honeypot::uptime_secs;
+use crate::scan::format_mac;
+
+/// Largest ESP-NOW payload.
+pub const MAX_PAYLOAD: usize = 250;
+/// Queue between the receive callback and the link's own consumer.
+pub const MESSAGE_QUEUE: usize = 16;
+const MAX_RECENT: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct EspNowMessage {
+    pub from: [u8; 6],
+    /// Sent to the broadcast address rather than to us.
+    pub broadcast: bool,
+    /// Inline, so receiving does not allocate.
+    pub data: heapless::Vec<u8, MAX_PAYLOAD>,
+    pub uptime_secs: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MessageInfo {
+    pub from: String,
+    pub broadcast: bool,
+    pub data_hex: String,
+    /// The payload, when it is UTF-8.
+    pub text: Option<String>,
+    pub uptime_secs: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EspNowStatus {
+    pub peers: Vec<String>,
+    pub sent: u32,
+    pub send_failures: u32,
+    pub received: u32,
+    /// Messages no subscriber had room for.
+    pub dropped: u32,
+    /// Oldest first.
+    pub recent: Vec<MessageInfo>,
+}
+
+#[derive(Default)]
+struct Counters {
+    sent: AtomicU32,
+    send_failures: AtomicU32,
+    received: AtomicU32,
+    dropped: AtomicU32,
+}
+
+pub struct EspNowLink {
+    espnow: EspNow<'static>,
+    interface: wifi_interface_t,
+    subscribers: Arc<Mutex<Vec<SyncSender<EspNowMessage>>>>,
+    counters: Arc<Counters>,
+    recent: Mutex<VecDeque<EspNowMessage>>,
+}
+
+impl EspNowLink {
+    /// Needs the WiFi driver to be started.
+    pub fn new(wifi_mode: WifiMode) -> Result<Self> {
+        let espnow = EspNow::take()?;
+        let interface = match wifi_mode {
+            WifiMode::Station | WifiMode::Mixed => wifi_interface_t_WIFI_IF_STA,
+            WifiMode::AccessPoint | WifiMode::Honeypot => wifi_interface_t_WIFI_IF_AP,
+        };
+
+        let subscribers: Arc<Mutex<Vec<SyncSender<EspNowMessage>>>> = Arc::new(Mutex::new(Vec::new()));
+        let counters = Arc::new(Counters::default());
+
+        // Runs on the WiFi task: hand over and get out
+        let receive_subscribers = subscribers.clone();
+        let receive_counters = counters.clone();
+        espnow.register_recv_cb(move |info, data| {
+            receive_counters.received.fetch_add(1, Ordering::Relaxed);
+            let Ok(data) = heapless::Vec::from_slice(data) else {
+                receive_counters.dropped.fetch_add(1, Ordering::Relaxed);
+                return;
+            };
+            let message = EspNowMessage {
+                from: *info.src_addr,
+                broadcast: *info.dst_addr == BROADCAST,
+                data,
+                uptime_secs: uptime_secs(),
+            };
+
+            let Ok(mut subscribers) = receive_subscribers.try_lock() else {
+                receive_counters.dropped.fetch_add(1, Ordering::Relaxed);
+                return;
+            };
+            subscribers.retain(|subscriber| match subscriber.try_send(message.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    receive_counters.dropped.fetch_add(1, Ordering::Relaxed);
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            });
+        })?;
+
+        let send_counters = counters.clone();
+        espnow.register_send_cb(move |_, status| match status {
+            SendStatus::SUCCESS => {
+                send_counters.sent.fetch_add(1, Ordering::Relaxed);
+            }
+            SendStatus::FAIL => {
+                send_counters.send_failures.fetch_add(1, Ordering::Relaxed);
+            }
+        })?;
+
+        let link = EspNowLink {
+            espnow,
+            interface,
+            subscribers,
+            counters,
+            recent: Mutex::new(VecDeque::new()),
+        };
+        link.add_peer(BROADCAST)?;
+
+        log::info!("ESP-NOW started");
+        Ok(link)
+    }
+
+    /// Received messages for one consumer. Like the event bus, a consumer that
+    /// falls behind loses messages rather than stalling the WiFi task.
+    pub fn subscribe(&self, capacity: usize) -> Receiver<EspNowMessage> {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Keeps the most recent messages and announces each one on the event bus,
+    /// until the link goes away.
+    pub fn run(&self, messages: Receiver<EspNowMessage>, events: &EventBus) {
+        for message in messages {
+            events.publish(Event::EspNowReceived {
+                from: format_mac(&message.from),
+                len: message.data.len(),
+            });
+
+            let mut recent = self.recent.lock().unwrap();
+            if recent.len() == MAX_RECENT {
+                recent.pop_front();
+            }
+            recent.push_back(message);
+        }
+    }
+
+    pub fn add_peer(&self, mac: [u8; 6]) -> Result<()> {
+        if self.espnow.peer_exists(mac)? {
+            return Ok(());
+        }
+
+        self.espnow.add_peer(PeerInfo {
+            peer_addr: mac,
+            // The current channel
+            channel: 0,
+            ifidx: self.interface,
+            encrypt: false,
+            ..Default::default()
+        })?;
+        Ok(())
+    }
+
+    /// Returns whether the peer was known.
+    pub fn remove_peer(&self, mac: [u8; 6]) -> Result<bool> {
+        if mac == BROADCAST || !self.espnow.peer_exists(mac)? {
+            return Ok(false);
+        }
+
+        self.espnow.del_peer(mac)?;
+        Ok(true)
+    }
+
+    /// `to` must be a registered peer; `None` broadcasts. Delivery is reported
+    /// asynchronously in the counters.
+    pub fn send(&self, to: Option<[u8; 6]>, data: &[u8]) -> Result<()> {
+        if data.len() > MAX_PAYLOAD {
+            bail!("ESP-NOW payloads are at most {} bytes", MAX_PAYLOAD);
+        }
+
+        self.espnow.send(to.unwrap_or(BROADCAST), data)?;
+        Ok(())
+    }
+
+    pub fn status(&self) -> EspNowStatus {
+        let mut peers = Vec::new();
+        let mut peer = self.espnow.fetch_peer(true);
+        while let Ok(info) = peer {
+            if info.peer_addr != BROADCAST {
+                peers.push(format_mac(&info.peer_addr));
+            }
+            peer = self.espnow.fetch_peer(false);
+        }
+
+        let recent = self
+            .recent
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|message| MessageInfo {
+                from: format_mac(&message.from),
+                broadcast: message.broadcast,
+                data_hex: codec::hex_string(&message.data),
+                text: std::str::from_utf8(&message.data).ok().map(str::to_owned),
+                uptime_secs: message.uptime_secs,
+            })
+            .collect();
+
+        EspNowStatus {
+            peers,
+            sent: self.counters.sent.load(Ordering::Relaxed),
+            send_failures: self.counters.send_failures.load(Ordering::Relaxed),
+            received: self.counters.received.load(Ordering::Relaxed),
+            dropped: self.counters.dropped.load(Ordering::Relaxed),
+            recent,
+        }
+    }
+}
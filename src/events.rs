@@ -0,0 +1,101 @@
+use serde::Serialize;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Mutex;
+
+use crate::alerts::Severity;
+use crate::latency::LinkHealth;
+
+/// Events published on the [`EventBus`].
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub enum Event {
+    ApAppeared {
+        ssid: String,
+        bssid: String,
+        channel: u8,
+        rssi: i8,
+    },
+    ApDisappeared {
+        ssid: String,
+        bssid: String,
+    },
+    ApChannelChanged {
+        ssid: String,
+        bssid: String,
+        from: u8,
+        to: u8,
+    },
+    TrackedRssi {
+        bssid: String,
+        rssi: Option<i8>,
+        smoothed_rssi: Option<f32>,
+    },
+    ScanCompleted {
+        networks: usize,
+    },
+    /// More deauthentication or disassociation frames than the threshold in one second.
+    DeauthFlood {
+        bssid: String,
+        frames: u32,
+    },
+    EspNowReceived {
+        from: String,
+        len: usize,
+    },
+    AlertRaised {
+        id: u32,
+        severity: Severity,
+        source: String,
+    },
+    /// The latency monitor's view of the station link changed.
+    LinkHealthChanged {
+        health: LinkHealth,
+    },
+    /// The LED was set to a fixed color over HTTP or by a rule, as `#rrggbb`.
+    LedChanged {
+        color: String,
+        source: String,
+    },
+    /// A setting was changed at runtime, e.g. over HTTP.
+    SettingChanged {
+        setting: String,
+        value: String,
+    },
+}
+
+/// In-process publish/subscribe bus.
+///
+/// Every subscriber gets its own bounded queue; when a subscriber falls behind its
+/// events are dropped rather than blocking the publisher.
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: Mutex<Vec<SyncSender<Event>>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe(&self, capacity: usize) -> Receiver<Event> {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    pub fn publish(&self, event: Event) {
+        log::trace!("Event: {:?}", event);
+
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| match subscriber.try_send(event.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    log::warn!("Event subscriber is lagging, dropping event");
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            });
+    }
+}
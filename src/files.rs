@@ -0,0 +1,134 @@
+//! Files in the `storage` SPIFFS partition of `partitions.csv`, mounted at
+//! [`BASE_PATH`] so `std::fs` works on them.
+//!
+//! SPIFFS has no directories, so names are flat. A partition that does not
+//! mount is formatted once; an image flashed without the partition leaves the
+//! store unavailable.
+
+use anyhow::{bail, Result};
+use esp_idf_svc::sys::{self, esp};
+use serde::Serialize;
+use std::ffi::CStr;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read, Write};
+
+pub const BASE_PATH: &str = "/files";
+const MOUNT_POINT: &CStr = c"/files";
+const PARTITION: &CStr = c"storage";
+/// Files open at once.
+const MAX_OPEN_FILES: usize = 4;
+/// `CONFIG_SPIFFS_OBJ_NAME_LEN` is 32 with the terminating NUL and the leading `/`.
+const MAX_NAME_LEN: usize = 30;
+
+#[derive(Debug, Serialize)]
+pub struct FileInfo {
+    pub name: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FilesUsage {
+    pub total_bytes: usize,
+    pub used_bytes: usize,
+    pub files: Vec<FileInfo>,
+}
+
+pub struct FileStore;
+
+impl FileStore {
+    pub fn mount() -> Result<Self> {
+        let conf = sys::esp_vfs_spiffs_conf_t {
+            base_path: MOUNT_POINT.as_ptr(),
+            partition_label: PARTITION.as_ptr(),
+            max_files: MAX_OPEN_FILES,
+            format_if_mount_failed: true,
+        };
+        esp!(unsafe { sys::esp_vfs_spiffs_register(&conf) })?;
+        log::info!("Mounted the {:?} partition at {}", PARTITION, BASE_PATH);
+        Ok(FileStore)
+    }
+
+    pub fn usage(&self) -> Result<FilesUsage> {
+        let (mut total_bytes, mut used_bytes) = (0, 0);
+        esp!(unsafe { sys::esp_spiffs_info(PARTITION.as_ptr(), &mut total_bytes, &mut used_bytes) })?;
+
+        let mut files = Vec::new();
+        for entry in fs::read_dir(BASE_PATH)? {
+            let entry = entry?;
+            files.push(FileInfo {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                size: entry.metadata()?.len(),
+            });
+        }
+        files.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(FilesUsage {
+            total_bytes,
+            used_bytes,
+            files,
+        })
+    }
+
+    /// `None` when there is no such file.
+    pub fn open(&self, name: &str) -> Result<Option<File>> {
+        match File::open(path(name)?) {
+            Ok(file) => Ok(Some(file)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn read(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let Some(mut file) = self.open(name)? else {
+            return Ok(None);
+        };
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        Ok(Some(data))
+    }
+
+    /// Replaces `name` with what `fill` writes; a failed write leaves no file behind.
+    pub fn write<F>(&self, name: &str, fill: F) -> Result<u64>
+    where
+        F: FnOnce(&mut File) -> Result<u64>,
+    {
+        let path = path(name)?;
+        let mut file = File::create(&path)?;
+        match fill(&mut file).and_then(|len| Ok(file.flush().map(|()| len)?)) {
+            Ok(len) => Ok(len),
+            Err(e) => {
+                drop(file);
+                let _ = fs::remove_file(&path);
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns whether the file existed.
+    pub fn remove(&self, name: &str) -> Result<bool> {
+        match fs::remove_file(path(name)?) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+fn path(name: &str) -> Result<String> {
+    check_name(name)?;
+    Ok(format!("{}/{}", BASE_PATH, name))
+}
+
+pub fn check_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        bail!("File names are 1-{} characters", MAX_NAME_LEN);
+    }
+    if name.starts_with('.')
+        || !name
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'.' | b'-' | b'_'))
+    {
+        bail!("File names are letters, digits, '.', '-' and '_', not starting with '.'");
+    }
+    Ok(())
+}
@@ -0,0 +1,81 @@
+//! Frames the sniffer hears per minute, by type and channel, over the last hour,
+//! exported as CSV or JSON so interference on a site can be studied without a
+//! laptop and monitor-mode hardware.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use crate::alerts::unix_time;
+use crate::honeypot::uptime_secs;
+use crate::sniffer::{self, ChannelFrames};
+
+pub const MAX_MINUTES: usize = 60;
+const MINUTE: Duration = Duration::from_secs(60);
+pub const CSV_HEADER: &str = "uptime_secs,unix_time,channel,management,control,data";
+
+/// One minute of counts, stamped at its end.
+#[derive(Debug, Clone, Serialize)]
+pub struct MinuteFrames {
+    pub uptime_secs: u64,
+    /// `None` until the clock has been set, see [`crate::clock`].
+    pub unix_time: Option<u64>,
+    /// Only the channels anything was heard on.
+    pub channels: Vec<ChannelFrames>,
+}
+
+impl MinuteFrames {
+    /// One CSV line per channel, each ending in a newline.
+    pub fn csv_lines(&self) -> String {
+        let unix_time = self.unix_time.map(|time| time.to_string()).unwrap_or_default();
+        self.channels
+            .iter()
+            .map(|frames| {
+                format!(
+                    "{},{},{},{},{},{}\n",
+                    self.uptime_secs, unix_time, frames.channel, frames.management, frames.control, frames.data
+                )
+            })
+            .collect()
+    }
+}
+
+#[derive(Default)]
+pub struct FrameStats {
+    minutes: Mutex<VecDeque<MinuteFrames>>,
+}
+
+impl FrameStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Closes a minute every minute, forever. Minutes the sniffer was off for
+    /// are not kept.
+    pub fn run(&self) {
+        loop {
+            thread::sleep(MINUTE);
+            let channels = sniffer::take_frame_counts();
+            if channels.is_empty() && !sniffer::status().running {
+                continue;
+            }
+
+            let mut minutes = self.minutes.lock().unwrap();
+            if minutes.len() == MAX_MINUTES {
+                minutes.pop_front();
+            }
+            minutes.push_back(MinuteFrames {
+                uptime_secs: uptime_secs(),
+                unix_time: unix_time(),
+                channels,
+            });
+        }
+    }
+
+    /// Oldest first.
+    pub fn minutes(&self) -> Vec<MinuteFrames> {
+        self.minutes.lock().unwrap().iter().cloned().collect()
+    }
+}
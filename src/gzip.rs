@@ -0,0 +1,207 @@
+//! Streaming gzip small enough for the HTTP task: LZ77 over a 4 KiB window with
+//! the fixed Huffman codes of deflate. It compresses less than zlib would, but
+//! needs about 12 KiB of heap where a full deflate implementation wants well over
+//! 100 KiB.
+
+use anyhow::Result;
+use embedded_svc::io::Write;
+
+/// Bytes already compressed that matches may still point back into.
+const WINDOW: usize = 4096;
+/// Input compressed per deflate block.
+const BLOCK: usize = 2048;
+const HASH_BITS: u32 = 11;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const END_OF_BLOCK: u16 = 256;
+
+const HEADER: [u8; 10] = [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227,
+    258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097,
+    6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+
+pub struct GzipWriter<W: Write> {
+    inner: W,
+    /// Up to [`WINDOW`] bytes of history followed by the input not compressed yet.
+    data: Vec<u8>,
+    pending: usize,
+    /// Position + 1 in `data` of the last 3-byte sequence with each hash, 0 for none.
+    head: Vec<u16>,
+    bits: u64,
+    bit_count: u32,
+    out: Vec<u8>,
+    crc: u32,
+    size: u32,
+}
+
+impl<W: Write> GzipWriter<W>
+where
+    anyhow::Error: From<W::Error>,
+{
+    pub fn new(mut inner: W) -> Result<Self> {
+        inner.write_all(&HEADER)?;
+        Ok(GzipWriter {
+            inner,
+            data: Vec::with_capacity(WINDOW + BLOCK),
+            pending: 0,
+            head: vec![0; 1 << HASH_BITS],
+            bits: 0,
+            bit_count: 0,
+            out: Vec::with_capacity(BLOCK + BLOCK / 4),
+            crc: !0,
+            size: 0,
+        })
+    }
+
+    pub fn write_all(&mut self, mut data: &[u8]) -> Result<()> {
+        self.crc = crc32(self.crc, data);
+        self.size = self.size.wrapping_add(data.len() as u32);
+
+        while !data.is_empty() {
+            let room = self.pending + BLOCK - self.data.len();
+            let (now, rest) = data.split_at(room.min(data.len()));
+            self.data.extend_from_slice(now);
+            data = rest;
+
+            if self.data.len() == self.pending + BLOCK {
+                self.compress_block(false)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Compresses what is left and writes the gzip trailer.
+    pub fn finish(mut self) -> Result<W> {
+        self.compress_block(true)?;
+        if self.bit_count > 0 {
+            self.out.push(self.bits as u8);
+        }
+        self.out.extend_from_slice(&(!self.crc).to_le_bytes());
+        self.out.extend_from_slice(&self.size.to_le_bytes());
+        self.inner.write_all(&self.out)?;
+        Ok(self.inner)
+    }
+
+    fn compress_block(&mut self, last: bool) -> Result<()> {
+        self.put_bits(last as u32, 1);
+        // Fixed Huffman codes
+        self.put_bits(1, 2);
+
+        let mut i = self.pending;
+        while i < self.data.len() {
+            let length = self.longest_match(i);
+            if length >= MIN_MATCH {
+                let distance = i - (self.head[hash(&self.data[i..])] as usize - 1);
+                for position in i..i + length {
+                    self.insert(position);
+                }
+                self.put_length(length as u16);
+                self.put_distance(distance as u16);
+                i += length;
+            } else {
+                self.insert(i);
+                self.put_symbol(self.data[i] as u16);
+                i += 1;
+            }
+        }
+        self.put_symbol(END_OF_BLOCK);
+
+        // Keep the tail as history for the next block
+        let drop = self.data.len().saturating_sub(WINDOW);
+        self.data.drain(..drop);
+        for entry in self.head.iter_mut() {
+            *entry = entry.saturating_sub(drop as u16);
+        }
+        self.pending = self.data.len();
+
+        self.inner.write_all(&self.out)?;
+        self.out.clear();
+        Ok(())
+    }
+
+    /// Length of the match at `position` against the last occurrence of its first
+    /// three bytes, 0 when there is none.
+    fn longest_match(&self, position: usize) -> usize {
+        let data = &self.data;
+        if position + MIN_MATCH > data.len() {
+            return 0;
+        }
+        let Some(candidate) = (self.head[hash(&data[position..])] as usize).checked_sub(1) else {
+            return 0;
+        };
+
+        let limit = (data.len() - position).min(MAX_MATCH);
+        (0..limit)
+            .take_while(|k| data[candidate + k] == data[position + k])
+            .count()
+    }
+
+    fn insert(&mut self, position: usize) {
+        if position + MIN_MATCH <= self.data.len() {
+            self.head[hash(&self.data[position..])] = position as u16 + 1;
+        }
+    }
+
+    fn put_bits(&mut self, value: u32, count: u32) {
+        self.bits |= (value as u64) << self.bit_count;
+        self.bit_count += count;
+        while self.bit_count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    /// Huffman codes go out most significant bit first.
+    fn put_code(&mut self, code: u16, length: u32) {
+        self.put_bits((code.reverse_bits() >> (16 - length)) as u32, length);
+    }
+
+    fn put_symbol(&mut self, symbol: u16) {
+        match symbol {
+            0..=143 => self.put_code(0x30 + symbol, 8),
+            144..=255 => self.put_code(0x190 + symbol - 144, 9),
+            256..=279 => self.put_code(symbol - 256, 7),
+            _ => self.put_code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn put_length(&mut self, length: u16) {
+        let index = LENGTH_BASE.iter().rposition(|&base| base <= length).unwrap();
+        self.put_symbol(257 + index as u16);
+        self.put_bits((length - LENGTH_BASE[index]) as u32, LENGTH_EXTRA[index] as u32);
+    }
+
+    fn put_distance(&mut self, distance: u16) {
+        let index = DISTANCE_BASE.iter().rposition(|&base| base <= distance).unwrap();
+        self.put_code(index as u16, 5);
+        self.put_bits((distance - DISTANCE_BASE[index]) as u32, DISTANCE_EXTRA[index] as u32);
+    }
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let key = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+    (key.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+}
+
+/// Continues a CRC32 (IEEE) over `data`; start from `!0` and invert the result.
+pub fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    crc
+}
@@ -0,0 +1,1869 @@
+use anyhow::Result;
+use embedded_svc::io::{Read, Write};
+use esp_idf_svc::http::server::{EspHttpConnection, Request};
+use esp_idf_svc::ipv4::IpInfo;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::alerts::{self, unix_time, AlertLog};
+use crate::ap::ApClients;
+use crate::channels;
+use crate::clock::Clock;
+use crate::color::Color;
+use crate::cpu::CpuMonitor;
+use crate::diag;
+use crate::energy::EnergyMeter;
+use crate::espnow::EspNowLink;
+use crate::events::{Event, EventBus};
+use crate::gzip::GzipWriter;
+use crate::heap::HeapMonitor;
+use crate::config::{ConfigStore, DeauthSettings, EnergyModel, Hostname, HttpSettings, Identity, KnownNetwork, LatencyMonitorSettings, OtaPullSettings, SoakTestSettings, SyslogSettings, LedCalibration, LedOutput, LedPowerLimits, LedPriority, LedPriorityPolicy, NewNetworkAlerts, Profile, ScanDisplay, ScannerSettings, SleepSettings, StaAddressing, StaMac, SubsystemTogglesPatch, WifiCountry, WifiMode, WifiPowerSave};
+use crate::confirm::{self, Action, Confirmations};
+use crate::history::ScanHistory;
+use crate::errors::HttpError;
+use crate::files::{self, FileStore};
+use crate::form::{self, Form};
+use crate::framestats::{self, FrameStats};
+use crate::honeypot::uptime_secs;
+use crate::honeypot::{portal_html, Honeypot};
+use crate::httpstats::HttpStats;
+use crate::i18n::{self, Strings};
+use crate::latency::{LatencyMonitor, LatencyReport};
+use crate::led::RgbLed;
+use crate::logs;
+use crate::netstats::NetworkStats;
+use crate::ota::{self, OtaPuller};
+use crate::page::{Page, PageRequest};
+use crate::pairing::{PairError, Pairing, Role};
+use crate::probes::ProbeLog;
+use crate::routes::{self, active_routes, method_name, RouteInfo};
+use crate::rules::{self, Rule};
+use crate::safemode::{self, BootGuard};
+use crate::scan::{self, format_mac, parse_mac, LatestScan, MacAddress, ScanResult};
+use crate::sessions::HttpSessions;
+use crate::sleep::IdleSleep;
+use crate::pcap;
+use crate::pintest::{self, PinAction, PinPull};
+use crate::sniffer::{self, ManagementFrame, Subtype};
+use crate::startup::Startup;
+use crate::sysinfo;
+use crate::timeline::Timeline;
+use crate::tls::{self, TlsStore};
+use crate::txlog::TxLog;
+use crate::tracker::BssidTracker;
+use crate::twins::TwinDetector;
+use crate::version;
+use crate::wifi::{self, sta_mac_address, LinkState, StaAddress, StaApInfo, WifiLink};
+
+pub type HttpRequest<'r, 'c> = Request<&'r mut EspHttpConnection<'c>>;
+
+/// The HTTP server has a single worker, so a capture holds up every other request.
+const MAX_PCAP_SECS: u64 = 10;
+/// Same worker: an event stream ends after a few seconds and the browser
+/// reconnects after [`EVENT_STREAM_RETRY_MS`], leaving the worker free in between.
+const MAX_EVENT_STREAM_SECS: u64 = 5;
+const EVENT_STREAM_RETRY_MS: u64 = 10_000;
+const EVENT_STREAM_POLL: Duration = Duration::from_millis(250);
+
+/// Everything the HTTP handlers can reach.
+pub struct AppState {
+    pub startup: Arc<Startup>,
+    pub boot: BootGuard,
+    pub config: Arc<ConfigStore>,
+    pub ap_clients: Arc<ApClients>,
+    pub honeypot: Arc<Honeypot>,
+    pub tracker: Arc<BssidTracker>,
+    pub alerts: Arc<AlertLog>,
+    pub provisioning_svg: Option<String>,
+    pub led: Arc<RgbLed>,
+    /// GPIOs the LED was set up on at boot.
+    pub led_pins: Vec<u8>,
+    /// Station addressing as obtained at boot, `None` when not connected as a station.
+    pub ip_info: Option<IpInfo>,
+    /// Channel the access point came up on, `None` in station mode.
+    pub ap_channel: Option<u8>,
+    pub network_stats: Arc<NetworkStats>,
+    pub wifi_link: Arc<WifiLink>,
+    pub latency: Arc<LatencyMonitor>,
+    pub sessions: Arc<HttpSessions>,
+    pub http_stats: Arc<HttpStats>,
+    pub pairing: Arc<Pairing>,
+    /// `None` when the WiFi driver did not come up.
+    pub espnow: Option<Arc<EspNowLink>>,
+    /// `None` when the storage partition did not mount.
+    pub files: Option<Arc<FileStore>>,
+    pub sleep: Arc<IdleSleep>,
+    pub energy: Arc<EnergyMeter>,
+    pub cpu: Arc<CpuMonitor>,
+    pub heap: Arc<HeapMonitor>,
+    pub clock: Arc<Clock>,
+    pub tls: Arc<TlsStore>,
+    pub tx_log: Arc<TxLog>,
+    pub ota_puller: Arc<OtaPuller>,
+    pub confirmations: Arc<Confirmations>,
+    pub events: Arc<EventBus>,
+    pub timeline: Arc<Timeline>,
+    pub latest_scan: LatestScan,
+    pub scan_history: Arc<ScanHistory>,
+    pub twins: Arc<TwinDetector>,
+    pub probes: Arc<ProbeLog>,
+    pub frame_stats: Arc<FrameStats>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PairRequest {
+    code: String,
+}
+
+#[derive(Debug, Serialize)]
+struct PairingStatus {
+    paired: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct PairResponse {
+    api_key: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PinTest {
+    pin: u8,
+    #[serde(flatten)]
+    action: PinAction,
+}
+
+#[derive(Debug, Deserialize)]
+struct SpiPins {
+    sclk: u8,
+    mosi: u8,
+    miso: u8,
+}
+
+#[derive(Debug, Deserialize)]
+struct SetTime {
+    unix_time: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct NewUser {
+    name: String,
+    role: Role,
+}
+
+#[derive(Debug, Deserialize)]
+struct EspNowPeer {
+    mac: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct EspNowSend {
+    /// Broadcast when unset.
+    #[serde(default)]
+    to: Option<String>,
+    data: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct AlertAck {
+    id: u32,
+}
+
+/// The dashboard is a single page that only talks to the JSON routes and `/ws`.
+/// A `dashboard.html` uploaded to the file store replaces the built-in one.
+const DASHBOARD_HTML: &str = include_str!("../assets/dashboard.html");
+const DASHBOARD_FILE: &str = "dashboard.html";
+
+pub fn dashboard(req: HttpRequest, state: &AppState) -> Result<()> {
+    let uploaded = match &state.files {
+        Some(files) => files.read(DASHBOARD_FILE).unwrap_or_else(|e| {
+            log::warn!("Failed to read {}, serving the built-in dashboard: {:?}", DASHBOARD_FILE, e);
+            None
+        }),
+        None => None,
+    };
+    match uploaded {
+        Some(html) => {
+            let mut response = req.into_response(200, None, &[("Content-Type", "text/html; charset=utf-8")])?;
+            response.write_all(&html)?;
+            Ok(())
+        }
+        None => write_html(req, 200, DASHBOARD_HTML),
+    }
+}
+
+pub fn route_index(req: HttpRequest, state: &AppState) -> Result<()> {
+    let strings = strings(&req);
+    let identity = state.config.get().identity;
+    // Identity fields are validated free of HTML special characters
+    let (title, heading) = match identity.name.as_str() {
+        "" => (strings.index_title.to_string(), strings.index_heading.to_string()),
+        name => (format!("{} - {}", name, strings.index_title), format!("{} - {}", name, strings.index_heading)),
+    };
+    let details: Vec<String> = [identity.location.clone(), identity.tags.join(", ")]
+        .into_iter()
+        .filter(|detail| !detail.is_empty())
+        .collect();
+    let details = if details.is_empty() {
+        String::new()
+    } else {
+        format!("    <p><small>{}</small></p>\n", details.join(" | "))
+    };
+    let mut html = format!(
+        r#"
+<!DOCTYPE html>
+<html lang="{}">
+<head><title>{}</title><meta charset="utf-8"></head>
+<body>
+    <h1>{}</h1>
+{}    <p>{}</p>
+    <p>{}</p>
+"#,
+        strings.code, title, heading, details, strings.index_scanner, strings.index_api
+    );
+
+    for route in active_routes(&state.config.get()) {
+        if route.path == "/routes" {
+            continue;
+        }
+        let stats = match state.http_stats.get(method_name(route.method), route.path) {
+            Some(hour) if hour.requests > 0 => format!(
+                " <small>[{} {}, {} {}, p50 {} ms, p95 {} ms]</small>",
+                hour.requests,
+                strings.index_requests,
+                hour.errors,
+                strings.index_errors,
+                latency(hour.p50_ms),
+                latency(hour.p95_ms)
+            ),
+            _ => String::new(),
+        };
+        let line = match route.method {
+            embedded_svc::http::Method::Get => {
+                format!("    <p><a href=\"{0}\">{0}</a>: {1}{2}</p>\n", route.path, route.description, stats)
+            }
+            method => format!(
+                "    <p>{} {}: {} ({}){}</p>\n",
+                method_name(method),
+                route.path,
+                route.description,
+                route.body.unwrap_or(strings.no_body),
+                stats
+            ),
+        };
+        html.push_str(&line);
+    }
+    html.push_str("</body>\n</html>\n");
+
+    write_html(req, 200, &html)
+}
+
+/// Percentiles are bin bounds; the last bin has none.
+fn latency(ms: Option<u32>) -> String {
+    match ms {
+        Some(u32::MAX) => ">5000".to_string(),
+        Some(ms) => format!("≤{}", ms),
+        None => "-".to_string(),
+    }
+}
+
+pub fn api_index(req: HttpRequest, state: &AppState) -> Result<()> {
+    let routes: Vec<RouteInfo> = active_routes(&state.config.get())
+        .into_iter()
+        .map(RouteInfo::from)
+        .collect();
+    write_json(req, &routes)
+}
+
+pub fn status(req: HttpRequest, state: &AppState) -> Result<()> {
+    let mut report = String::new();
+    if state.boot.safe_mode() {
+        report.push_str(&format!(
+            "safe_mode: after {} crash resets in a row, POST /api/reboot to leave\n",
+            state.boot.consecutive_crashes()
+        ));
+    }
+    report.push_str(&state.startup.report());
+    if let Some(mac) = sta_mac_address() {
+        report.push_str(&format!("sta_mac: {}\n", format_mac(&mac)));
+    }
+    report.push_str(&state.clock.status_lines());
+    report.push_str(&state.latency.status_lines());
+    report.push_str(&state.energy.status_lines());
+    report.push_str(&state.cpu.status_lines());
+
+    let mut response = req.into_ok_response()?;
+    response.write_all(report.as_bytes())?;
+    Ok(())
+}
+
+/// A software reset behind a confirmation token, which also clears the crash
+/// count that triggers safe mode.
+pub fn confirmed_reboot(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    if !confirmed(&mut req, state, Action::Reboot)? {
+        return write_confirmation(req, state, Action::Reboot);
+    }
+    log::warn!("Rebooting on confirmed request");
+    restart(req, "Rebooting")
+}
+
+/// Erases the stored configuration and reboots into the defaults.
+pub fn factory_reset(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    if !confirmed(&mut req, state, Action::FactoryReset)? {
+        return write_confirmation(req, state, Action::FactoryReset);
+    }
+    log::warn!("Factory reset on confirmed request");
+    state.config.factory_reset()?;
+    restart(req, "Configuration erased, rebooting")
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ConfirmRequest {
+    confirm: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ConfirmChallenge {
+    confirm: String,
+    expires_in_secs: u64,
+}
+
+/// `false` when the body carries no token; a wrong or expired one is a 403.
+fn confirmed(req: &mut HttpRequest, state: &AppState, action: Action) -> Result<bool> {
+    let body = read_body(req, 128)?;
+    let request: ConfirmRequest = if body.is_empty() {
+        ConfirmRequest::default()
+    } else {
+        serde_json::from_slice(&body)?
+    };
+    match request.confirm {
+        None => Ok(false),
+        Some(token) if state.confirmations.confirm(action, &token) => Ok(true),
+        Some(_) => Err(HttpError::new(403, "Wrong or expired confirmation token, request a new one")),
+    }
+}
+
+/// Answers with a fresh token to repeat the request with.
+fn write_confirmation(req: HttpRequest, state: &AppState, action: Action) -> Result<()> {
+    let challenge = ConfirmChallenge {
+        confirm: state.confirmations.issue(action),
+        expires_in_secs: confirm::TOKEN_LIFETIME.as_secs(),
+    };
+    let body = serde_json::to_vec(&challenge)?;
+    let mut response = req.into_response(202, None, &[("Content-Type", "application/json")])?;
+    response.write_all(&body)?;
+    Ok(())
+}
+
+fn restart(req: HttpRequest, message: &str) -> Result<()> {
+    let mut response = req.into_ok_response()?;
+    response.write_all(message.as_bytes())?;
+    drop(response);
+
+    // Let the response go out first
+    std::thread::sleep(safemode::REBOOT_DELAY);
+    unsafe { esp_idf_svc::sys::esp_restart() }
+}
+
+pub fn system_info(req: HttpRequest, _state: &AppState) -> Result<()> {
+    write_json(req, &sysinfo::snapshot())
+}
+
+pub fn build_info(req: HttpRequest, _state: &AppState) -> Result<()> {
+    write_json(req, &version::info())
+}
+
+pub fn ota_status(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &ota::status(&state.ota_puller)?)
+}
+
+pub fn get_ota_pull(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.config.get().ota_pull)
+}
+
+/// Read by the updater before each check.
+pub fn set_ota_pull(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 512)?;
+    let settings: OtaPullSettings = serde_json::from_slice(&body)?;
+    settings.validate().map_err(HttpError::bad_request)?;
+    log::info!("Firmware pull updates set to {:?}", settings);
+    state.config.update(|config| config.ota_pull = settings)?;
+
+    let mut response = req.into_ok_response()?;
+    response.write_all("Firmware pull settings updated".as_bytes())?;
+    Ok(())
+}
+
+/// Streams the body into the update slot, then reboots into it.
+pub fn ota_upload(mut req: HttpRequest, _state: &AppState) -> Result<()> {
+    log::info!("Firmware upload started");
+    let len = ota::update(|buffer| Ok(req.read(buffer)?)).map_err(HttpError::bad_request)?;
+    log::info!("Firmware of {} bytes written, rebooting into it", len);
+
+    let mut response = req.into_ok_response()?;
+    response.write_all(format!("Wrote {} bytes, rebooting", len).as_bytes())?;
+    drop(response);
+
+    // Let the response go out first
+    std::thread::sleep(safemode::REBOOT_DELAY);
+    unsafe { esp_idf_svc::sys::esp_restart() }
+}
+
+pub fn metrics(req: HttpRequest, state: &AppState) -> Result<()> {
+    let identity = state.config.get().identity;
+    let mut response = req.into_response(200, None, &[("Content-Type", "text/plain; version=0.0.4")])?;
+    // Always 1, for joining the other series on the identity labels
+    let device_info = format!(
+        "# TYPE device_info gauge\ndevice_info{{name=\"{}\",location=\"{}\",tags=\"{}\"}} 1\n",
+        identity.name,
+        identity.location,
+        identity.tags.join(",")
+    );
+    response.write_all(device_info.as_bytes())?;
+    response.write_all(state.cpu.metrics().as_bytes())?;
+    response.write_all(state.heap.metrics().as_bytes())?;
+    response.write_all(state.http_stats.metrics().as_bytes())?;
+    response.write_all(state.network_stats.metrics().as_bytes())?;
+    response.write_all(scan::metrics(&state.latest_scan).as_bytes())?;
+    Ok(())
+}
+
+/// Values under these keys are replaced in the support report's copy of the
+/// configuration and in what the open routes return of it. `url` covers the
+/// webhooks of rules, which often embed a token.
+const REDACTED_KEYS: &[&str] = &["password", "identity", "webhook_url", "url"];
+
+#[derive(Debug, Serialize)]
+struct FirmwareInfo {
+    #[serde(flatten)]
+    build: version::BuildInfo,
+    uptime_secs: u64,
+    unix_time: Option<u64>,
+    reset_reason: &'static str,
+    free_heap: u32,
+    min_free_heap: u32,
+}
+
+/// Everything useful in a bug report as one gzipped JSON document, compressed
+/// section by section as it is sent so the whole report never sits in memory.
+pub fn support_report(req: HttpRequest, state: &AppState) -> Result<()> {
+    let firmware = FirmwareInfo {
+        build: version::info(),
+        uptime_secs: uptime_secs(),
+        unix_time: unix_time(),
+        reset_reason: alerts::reset_reason(),
+        free_heap: unsafe { esp_idf_svc::sys::esp_get_free_heap_size() },
+        min_free_heap: unsafe { esp_idf_svc::sys::esp_get_minimum_free_heap_size() },
+    };
+    let mut config = serde_json::to_value(state.config.get())?;
+    redact(&mut config);
+
+    let response = req.into_response(
+        200,
+        None,
+        &[
+            ("Content-Type", "application/gzip"),
+            ("Content-Disposition", "attachment; filename=\"report.json.gz\""),
+        ],
+    )?;
+    let mut gzip = GzipWriter::new(response)?;
+
+    gzip.write_all(b"{\"firmware\":")?;
+    gzip.write_all(&serde_json::to_vec(&firmware)?)?;
+    gzip.write_all(b",\"self_test\":")?;
+    gzip.write_all(&serde_json::to_vec(&state.startup.subsystems())?)?;
+    gzip.write_all(b",\"config\":")?;
+    gzip.write_all(&serde_json::to_vec(&config)?)?;
+    drop(config);
+    gzip.write_all(b",\"alerts\":")?;
+    gzip.write_all(&serde_json::to_vec(&state.alerts.list())?)?;
+    gzip.write_all(b",\"network_stats\":")?;
+    gzip.write_all(&serde_json::to_vec(&state.network_stats.all())?)?;
+    gzip.write_all(b",\"energy\":")?;
+    gzip.write_all(&serde_json::to_vec(&state.energy.report())?)?;
+    gzip.write_all(b",\"tasks\":")?;
+    gzip.write_all(&serde_json::to_vec(&state.cpu.usage())?)?;
+    gzip.write_all(b",\"logs\":[")?;
+    for (i, line) in logs::recent().iter().enumerate() {
+        if i > 0 {
+            gzip.write_all(b",")?;
+        }
+        gzip.write_all(&serde_json::to_vec(line)?)?;
+    }
+    gzip.write_all(b"]}")?;
+    gzip.finish()?;
+    Ok(())
+}
+
+/// For settings viewers can read that hold a webhook.
+fn write_redacted_json<T: Serialize>(req: HttpRequest, value: &T) -> Result<()> {
+    let mut value = serde_json::to_value(value)?;
+    redact(&mut value);
+    write_json(req, &value)
+}
+
+fn redact(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                if REDACTED_KEYS.contains(&key.as_str()) && value.as_str().is_some_and(|value| !value.is_empty()) {
+                    *value = serde_json::Value::from("<redacted>");
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+pub fn energy(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.energy.report())
+}
+
+pub fn get_energy_model(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.config.get().energy)
+}
+
+pub fn set_energy_model(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 256)?;
+    let model: EnergyModel = serde_json::from_slice(&body)?;
+    model.validate().map_err(HttpError::bad_request)?;
+    log::info!("Energy model set to {:?}", model);
+    state.config.update(|config| config.energy = model)?;
+
+    write_json(req, &model)
+}
+
+pub fn espnow_status(req: HttpRequest, state: &AppState) -> Result<()> {
+    let Some(espnow) = &state.espnow else {
+        return Err(espnow_unavailable());
+    };
+    write_json(req, &espnow.status())
+}
+
+pub fn add_espnow_peer(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let Some(espnow) = &state.espnow else {
+        return Err(espnow_unavailable());
+    };
+    let body = read_body(&mut req, 64)?;
+    let peer: EspNowPeer = serde_json::from_slice(&body)?;
+    let mac = parse_mac(&peer.mac).ok_or_else(|| HttpError::bad_request(format!("Invalid MAC address: {}", peer.mac)))?;
+    espnow.add_peer(mac)?;
+    log::info!("ESP-NOW peer {} added", peer.mac);
+
+    let mut response = req.into_ok_response()?;
+    response.write_all("Peer added".as_bytes())?;
+    Ok(())
+}
+
+pub fn remove_espnow_peer(req: HttpRequest, state: &AppState) -> Result<()> {
+    let Some(espnow) = &state.espnow else {
+        return Err(espnow_unavailable());
+    };
+    let mac = query_param(&req, "mac").ok_or_else(|| HttpError::bad_request("Missing mac parameter"))?;
+    let peer = parse_mac(&mac).ok_or_else(|| HttpError::bad_request(format!("Invalid MAC address: {}", mac)))?;
+
+    if !espnow.remove_peer(peer)? {
+        return Err(HttpError::not_found("No such peer"));
+    }
+    log::info!("ESP-NOW peer {} removed", mac);
+    let mut response = req.into_ok_response()?;
+    response.write_all("Peer removed".as_bytes())?;
+    Ok(())
+}
+
+pub fn espnow_send(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let Some(espnow) = &state.espnow else {
+        return Err(espnow_unavailable());
+    };
+    let body = read_body(&mut req, 512)?;
+    let message: EspNowSend = serde_json::from_slice(&body)?;
+    let to = match &message.to {
+        Some(to) => Some(parse_mac(to).ok_or_else(|| HttpError::bad_request(format!("Invalid MAC address: {}", to)))?),
+        None => None,
+    };
+    espnow.send(to, message.data.as_bytes())?;
+    state.tx_log.record_espnow(message.data.len());
+
+    let mut response = req.into_ok_response()?;
+    response.write_all("Sent".as_bytes())?;
+    Ok(())
+}
+
+fn espnow_unavailable() -> anyhow::Error {
+    HttpError::not_found("ESP-NOW is not running")
+}
+
+pub fn sleep_status(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.sleep.status(&state.config.get()))
+}
+
+pub fn get_sleep_settings(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.config.get().sleep)
+}
+
+pub fn set_sleep_settings(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 128)?;
+    let settings: SleepSettings = serde_json::from_slice(&body)?;
+    settings.validate(&state.config.get().led_output.pins)?;
+    log::info!("Idle sleep set to {:?}", settings);
+    state.config.update(|config| config.sleep = settings)?;
+
+    write_json(req, &settings)
+}
+
+pub fn get_wifi_country(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.config.get().wifi_country)
+}
+
+/// Takes effect at once; an access point already up keeps its channel.
+pub fn set_wifi_country(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 64)?;
+    let country: WifiCountry = serde_json::from_slice(&body)?;
+    country.validate().map_err(HttpError::bad_request)?;
+    wifi::set_country(&country)?;
+    let updated = state.config.update(|config| config.wifi_country = country)?;
+
+    write_json(req, &updated.wifi_country)
+}
+
+pub fn get_power_save(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.config.get().wifi_power_save)
+}
+
+/// Takes effect at once, or when the device wakes if it is in light sleep.
+pub fn set_power_save(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 64)?;
+    let mode: WifiPowerSave = serde_json::from_slice(&body)?;
+    if !state.sleep.is_asleep() {
+        wifi::set_power_save(mode)?;
+    }
+    state.config.update(|config| config.wifi_power_save = mode)?;
+    log::info!("WiFi power save set to {:?}", mode);
+    setting_changed(state, "wifi power save", format!("{:?}", mode));
+
+    write_json(req, &mode)
+}
+
+pub fn sniffer_status(req: HttpRequest, _state: &AppState) -> Result<()> {
+    write_json(req, &sniffer::status())
+}
+
+/// Frame counts per minute, type and channel for the last hour, as JSON or, with
+/// `format=csv`, as a CSV download. Written minute by minute.
+pub fn sniffer_counters(req: HttpRequest, state: &AppState) -> Result<()> {
+    let minutes = state.frame_stats.minutes();
+
+    match query_param(&req, "format").as_deref() {
+        Some("csv") => {
+            let mut response = req.into_response(
+                200,
+                None,
+                &[
+                    ("Content-Type", "text/csv"),
+                    ("Content-Disposition", "attachment; filename=\"frames.csv\""),
+                ],
+            )?;
+            response.write_all(framestats::CSV_HEADER.as_bytes())?;
+            response.write_all(b"\n")?;
+            for minute in &minutes {
+                response.write_all(minute.csv_lines().as_bytes())?;
+            }
+        }
+        None | Some("json") => {
+            let mut response = req.into_response(200, None, &[("Content-Type", "application/json")])?;
+            response.write_all(b"[")?;
+            for (i, minute) in minutes.iter().enumerate() {
+                if i > 0 {
+                    response.write_all(b",")?;
+                }
+                response.write_all(&serde_json::to_vec(minute)?)?;
+            }
+            response.write_all(b"]")?;
+        }
+        Some(format) => return Err(HttpError::bad_request(format!("Unknown format {}, csv or json", format))),
+    }
+    Ok(())
+}
+
+/// Streams sniffed frames as a pcap file for `seconds` or until `count` frames were
+/// sent, starting the sniffer for the length of the capture if it is off. Safe
+/// mode keeps the sniffer off, so there is nothing to capture.
+pub fn sniffer_pcap(req: HttpRequest, state: &AppState) -> Result<()> {
+    if state.boot.safe_mode() {
+        return Err(HttpError::new(503, "No captures in safe mode"));
+    }
+    let params = query(&req);
+    let seconds: u64 = params.value("seconds")?.unwrap_or(5);
+    if !(1..=MAX_PCAP_SECS).contains(&seconds) {
+        return Err(HttpError::bad_request(format!("seconds must be 1-{}", MAX_PCAP_SECS)));
+    }
+    let count: Option<usize> = params.value("count")?;
+    let subtype: Option<Subtype> = query_param(&req, "subtype")
+        .map(|subtype| serde_json::from_value(serde_json::Value::String(subtype)))
+        .transpose()?;
+    let mac = query_param(&req, "mac")
+        .map(|mac| parse_mac(&mac).ok_or_else(|| HttpError::bad_request(format!("Invalid MAC: {}", mac))))
+        .transpose()?;
+
+    let started = !sniffer::status().running;
+    if started {
+        sniffer::start()?;
+    }
+    let frames = sniffer::subscribe(32);
+
+    let deadline = Instant::now() + Duration::from_secs(seconds);
+    let result = write_pcap(req, &frames, deadline, count, subtype, mac);
+
+    if started {
+        if let Err(e) = sniffer::stop() {
+            log::error!("Failed to stop the sniffer after a capture: {:?}", e);
+        }
+    }
+    let sent = result?;
+    log::info!("Captured {} frames to pcap", sent);
+    Ok(())
+}
+
+fn write_pcap(
+    req: HttpRequest,
+    frames: &Receiver<ManagementFrame>,
+    deadline: Instant,
+    count: Option<usize>,
+    subtype: Option<Subtype>,
+    mac: Option<[u8; 6]>,
+) -> Result<usize> {
+    let mut response = req.into_response(
+        200,
+        None,
+        &[
+            ("Content-Type", "application/vnd.tcpdump.pcap"),
+            ("Content-Disposition", "attachment; filename=\"capture.pcap\""),
+        ],
+    )?;
+    response.write_all(&pcap::file_header())?;
+
+    let mut sent = 0;
+    while count != Some(sent) {
+        let Some(timeout) = deadline.checked_duration_since(Instant::now()) else {
+            break;
+        };
+        let Ok(frame) = frames.recv_timeout(timeout) else {
+            break;
+        };
+        if subtype.is_some_and(|subtype| subtype != frame.subtype)
+            || mac.is_some_and(|mac| ![frame.receiver, frame.transmitter, frame.bssid].contains(&mac))
+        {
+            continue;
+        }
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        response.write_all(&pcap::record_header(frame.data.len(), frame.original_len, timestamp))?;
+        response.write_all(&frame.data)?;
+        sent += 1;
+    }
+
+    Ok(sent)
+}
+
+pub fn get_syslog(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.config.get().syslog)
+}
+
+/// Applies at once.
+pub fn set_syslog(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 256)?;
+    let settings: SyslogSettings = serde_json::from_slice(&body)?;
+    settings.validate().map_err(HttpError::bad_request)?;
+    log::info!("Syslog set to {:?}", settings);
+    let config = state.config.update(|config| config.syslog = settings.clone())?;
+    logs::set_syslog(&settings, &config.hostname.name);
+
+    let mut response = req.into_ok_response()?;
+    response.write_all("Syslog settings updated".as_bytes())?;
+    Ok(())
+}
+
+/// The last `lines` log lines, oldest first.
+pub fn api_logs(req: HttpRequest, _state: &AppState) -> Result<()> {
+    let lines: usize = query(&req).value("lines")?.unwrap_or(logs::MAX_LINES);
+    if !(1..=logs::MAX_LINES).contains(&lines) {
+        return Err(HttpError::bad_request(format!("lines must be 1-{}", logs::MAX_LINES)));
+    }
+
+    let recent = logs::recent();
+    write_json(req, &recent[recent.len().saturating_sub(lines)..])
+}
+
+/// Server-sent events, one per log line, with the line's sequence number as the
+/// event id. A client reconnecting with `Last-Event-ID` gets the lines it missed
+/// that are still buffered; a new one gets the whole buffer first.
+pub fn log_stream(req: HttpRequest, _state: &AppState) -> Result<()> {
+    write_event_stream(req, |next| {
+        let (first, lines) = logs::since(next);
+        // Multi-line messages take one data field per line
+        (first..).zip(lines.iter().map(|line| line.replace('\n', "\ndata: "))).collect()
+    })
+}
+
+/// Each response only lasts a few seconds, so following a source is a series of
+/// short streams stitched together by `Last-Event-ID`. `poll` gets the first id
+/// still wanted and returns what is there from it on, with ids.
+fn write_event_stream(req: HttpRequest, poll: impl Fn(u64) -> Vec<(u64, String)>) -> Result<()> {
+    let seconds: u64 = query(&req).value("seconds")?.unwrap_or(MAX_EVENT_STREAM_SECS);
+    if !(1..=MAX_EVENT_STREAM_SECS).contains(&seconds) {
+        return Err(HttpError::bad_request(format!("seconds must be 1-{}", MAX_EVENT_STREAM_SECS)));
+    }
+    let mut next = match req.header("Last-Event-ID").map(|id| id.trim().parse::<u64>()) {
+        Some(Ok(id)) => id.saturating_add(1),
+        _ => 0,
+    };
+
+    let mut response = req.into_response(
+        200,
+        None,
+        &[("Content-Type", "text/event-stream"), ("Cache-Control", "no-cache")],
+    )?;
+    response.write_all(format!("retry: {}\n\n", EVENT_STREAM_RETRY_MS).as_bytes())?;
+
+    let deadline = Instant::now() + Duration::from_secs(seconds);
+    while Instant::now() < deadline {
+        for (id, data) in poll(next) {
+            response.write_all(format!("id: {}\ndata: {}\n\n", id, data).as_bytes())?;
+            next = id.saturating_add(1);
+        }
+        response.flush()?;
+        std::thread::sleep(EVENT_STREAM_POLL);
+    }
+    Ok(())
+}
+
+pub fn get_time(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.clock.status())
+}
+
+/// For offline deployments; SNTP takes over again on its next sync.
+pub fn set_time(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 64)?;
+    let time: SetTime = serde_json::from_slice(&body)?;
+    state.clock.set_manual(time.unix_time).map_err(HttpError::bad_request)?;
+    log::info!("Clock set by hand to {}", time.unix_time);
+
+    write_json(req, &state.clock.status())
+}
+
+pub fn heap(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.heap.report())
+}
+
+pub fn get_soak_test(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.config.get().soak_test)
+}
+
+/// Applies from the next sample.
+pub fn set_soak_test(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 64)?;
+    let settings: SoakTestSettings = serde_json::from_slice(&body)?;
+    settings.validate().map_err(HttpError::bad_request)?;
+    log::info!("Soak test set to {:?}", settings);
+    state.config.update(|config| config.soak_test = settings)?;
+
+    write_json(req, &settings)
+}
+
+pub fn get_deauth_settings(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.config.get().deauth)
+}
+
+pub fn set_deauth_settings(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 64)?;
+    let settings: DeauthSettings = serde_json::from_slice(&body)?;
+    settings.validate().map_err(HttpError::bad_request)?;
+    log::info!("Deauthentication alarm set to {:?}", settings);
+    state.config.update(|config| config.deauth = settings)?;
+
+    write_json(req, &settings)
+}
+
+pub fn get_rules(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_redacted_json(req, &state.config.get().rules)
+}
+
+/// Replaces the whole rule list.
+pub fn set_rules(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 4096)?;
+    let rules: Vec<Rule> = serde_json::from_slice(&body)?;
+    if rules.len() > rules::MAX_RULES {
+        return Err(HttpError::bad_request(format!("At most {} rules", rules::MAX_RULES)));
+    }
+    for rule in &rules {
+        rule.validate().map_err(HttpError::bad_request)?;
+    }
+    log::info!("{} rules set", rules.len());
+    state.config.update(|config| config.rules = rules)?;
+
+    write_json(req, &state.config.get().rules)
+}
+
+pub fn get_subsystems(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.config.get().subsystems)
+}
+
+pub fn set_subsystems(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 256)?;
+    let patch: SubsystemTogglesPatch = serde_json::from_slice(&body)?;
+    let updated = state.config.update(|config| config.subsystems.apply(&patch))?;
+    log::info!("Subsystems updated: {:?}", updated.subsystems);
+    apply_sniffer(state, updated.subsystems.sniffer);
+    setting_changed(state, "subsystems", format!("{:?}", updated.subsystems));
+
+    write_json(req, &updated.subsystems)
+}
+
+pub fn get_profile(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.config.get().profile)
+}
+
+pub fn set_profile(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 64)?;
+    let profile: Profile = serde_json::from_slice(&body)?;
+    let previous_mode = state.config.get().wifi_mode;
+    let updated = state.config.update(|config| profile.apply(config))?;
+    log::info!("Profile {:?} applied: {:?}", profile, updated.subsystems);
+    apply_sniffer(state, updated.subsystems.sniffer);
+    setting_changed(state, "profile", format!("{:?}", profile));
+
+    let mut response = req.into_ok_response()?;
+    if updated.wifi_mode == previous_mode {
+        response.write_all("Profile applied".as_bytes())?;
+    } else {
+        response.write_all("Profile applied, reboot to switch the WiFi mode".as_bytes())?;
+    }
+    Ok(())
+}
+
+pub fn get_scan_display(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.config.get().scan_display)
+}
+
+pub fn set_scan_display(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 64)?;
+    let display: ScanDisplay = serde_json::from_slice(&body)?;
+    state.config.update(|config| config.scan_display = display)?;
+    log::info!("Scan display set to {:?}", display);
+    setting_changed(state, "scan display", format!("{:?}", display));
+
+    let mut response = req.into_ok_response()?;
+    response.write_all("Scan display updated".as_bytes())?;
+    Ok(())
+}
+
+pub fn get_wifi_mode(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.config.get().wifi_mode)
+}
+
+pub fn set_wifi_mode(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 64)?;
+    let mode: WifiMode = serde_json::from_slice(&body)?;
+    state.config.update(|config| config.wifi_mode = mode)?;
+    log::info!("WiFi mode set to {:?}, applied on next boot", mode);
+    setting_changed(state, "wifi mode", format!("{:?}", mode));
+
+    let mut response = req.into_ok_response()?;
+    response.write_all("WiFi mode updated, reboot to apply".as_bytes())?;
+    Ok(())
+}
+
+/// Results of the scanner's last pass, by BSSID.
+pub fn api_scan(req: HttpRequest, state: &AppState) -> Result<()> {
+    let request = page_request(&req)?;
+    let page = request.select(
+        state
+            .latest_scan
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|network| (MacAddress(network.bssid), network)),
+    );
+
+    write_json_page(req, &page.map(|network| ScanResult::from(&network)))
+}
+
+#[derive(Debug, Serialize)]
+struct NetworkInfo {
+    link: LinkState,
+    /// `None` while not associated.
+    access_point: Option<StaApInfo>,
+    address: Option<StaAddress>,
+    latency: LatencyReport,
+    reconnects: u32,
+}
+
+pub fn api_network(req: HttpRequest, state: &AppState) -> Result<()> {
+    let info = NetworkInfo {
+        link: state.wifi_link.state(),
+        access_point: wifi::sta_ap_info(),
+        address: wifi::sta_address(),
+        latency: state.latency.report(),
+        reconnects: state.wifi_link.reconnects(),
+    };
+    write_json(req, &info)
+}
+
+pub fn api_channels(req: HttpRequest, state: &AppState) -> Result<()> {
+    let max_channel = state.config.get().wifi_country.max_channel();
+    let survey = channels::survey(&state.latest_scan.lock().unwrap(), max_channel);
+    write_json(req, &survey)
+}
+
+pub fn scan_history(req: HttpRequest, state: &AppState) -> Result<()> {
+    let Some(bssid) = query_param(&req, "bssid") else {
+        let page = state.scan_history.page(&page_request(&req)?);
+        return write_json_page(req, &page);
+    };
+
+    let mac = parse_mac(&bssid).ok_or_else(|| HttpError::bad_request(format!("Invalid BSSID: {}", bssid)))?;
+    match state.scan_history.get(&mac) {
+        Some(history) => write_json(req, &history),
+        None => Err(HttpError::not_found("BSSID not seen")),
+    }
+}
+
+pub fn api_probes(req: HttpRequest, state: &AppState) -> Result<()> {
+    let page = state.probes.page(&page_request(&req)?);
+    write_json_page(req, &page)
+}
+
+pub fn evil_twins(req: HttpRequest, state: &AppState) -> Result<()> {
+    let page = state.twins.page(&page_request(&req)?);
+    write_json_page(req, &page)
+}
+
+pub fn get_scanner_settings(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.config.get().scanner)
+}
+
+pub fn set_scanner_settings(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 128)?;
+    let settings: ScannerSettings = serde_json::from_slice(&body)?;
+    settings.validate().map_err(HttpError::bad_request)?;
+    log::info!("Scanner settings set to {:?}", settings);
+    state.config.update(|config| config.scanner = settings)?;
+    setting_changed(state, "scan_interval", format!("{}s", settings.interval_secs));
+
+    let mut response = req.into_ok_response()?;
+    response.write_all("Scanner settings updated, history size applies after reboot".as_bytes())?;
+    Ok(())
+}
+
+pub fn get_new_network_alerts(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_redacted_json(req, &state.config.get().new_network_alerts)
+}
+
+pub fn set_new_network_alerts(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 512)?;
+    let settings: NewNetworkAlerts = serde_json::from_slice(&body)?;
+    settings.validate().map_err(HttpError::bad_request)?;
+    log::info!("New network alerts set to {:?}", settings);
+    let enabled = settings.enabled;
+    state.config.update(|config| config.new_network_alerts = settings)?;
+    setting_changed(state, "new_network_alerts", enabled.to_string());
+
+    write_json(req, &state.config.get().new_network_alerts)
+}
+
+pub fn wifi_stats(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.network_stats.all())
+}
+
+pub fn get_sta_addressing(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.config.get().sta_addressing)
+}
+
+pub fn set_sta_addressing(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 256)?;
+    let addressing: StaAddressing = serde_json::from_slice(&body)?;
+    addressing.validate().map_err(HttpError::bad_request)?;
+    state.config.update(|config| config.sta_addressing = addressing)?;
+    log::info!("Station addressing set to {:?}, applied on next boot", addressing);
+
+    let mut response = req.into_ok_response()?;
+    response.write_all("Addressing updated, reboot to apply".as_bytes())?;
+    Ok(())
+}
+
+pub fn get_sta_mac(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.config.get().sta_mac)
+}
+
+pub fn set_sta_mac(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 128)?;
+    let sta_mac: StaMac = serde_json::from_slice(&body)?;
+    sta_mac.validate().map_err(HttpError::bad_request)?;
+    log::info!("Station MAC set to {:?}, applied on next boot", sta_mac);
+    state.config.update(|config| config.sta_mac = sta_mac)?;
+
+    let mut response = req.into_ok_response()?;
+    response.write_all("MAC setting updated, reboot to apply".as_bytes())?;
+    Ok(())
+}
+
+pub fn get_identity(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.config.get().identity)
+}
+
+pub fn set_identity(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 1024)?;
+    let identity: Identity = serde_json::from_slice(&body)?;
+    identity.validate().map_err(HttpError::bad_request)?;
+    log::info!("Identity set to {:?}", identity);
+    state.config.update(|config| config.identity = identity)?;
+
+    let mut response = req.into_ok_response()?;
+    response.write_all("Identity updated".as_bytes())?;
+    Ok(())
+}
+
+pub fn get_hostname(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.config.get().hostname)
+}
+
+pub fn set_hostname(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 128)?;
+    let hostname: Hostname = serde_json::from_slice(&body)?;
+    hostname.validate().map_err(HttpError::bad_request)?;
+    log::info!("Hostname set to {:?}, applied on next boot", hostname.name);
+    state.config.update(|config| config.hostname = hostname)?;
+
+    let mut response = req.into_ok_response()?;
+    response.write_all("Hostname updated, reboot to apply".as_bytes())?;
+    Ok(())
+}
+
+pub fn wifi_link(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.wifi_link.state())
+}
+
+pub fn wifi_latency(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.latency.report())
+}
+
+pub fn get_latency_settings(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.config.get().latency_monitor)
+}
+
+pub fn set_latency_settings(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 1024)?;
+    let settings: LatencyMonitorSettings = serde_json::from_slice(&body)?;
+    settings.validate().map_err(HttpError::bad_request)?;
+    log::info!("Latency monitor set to {:?}", settings);
+    let updated = state.config.update(|config| config.latency_monitor = settings)?;
+
+    write_json(req, &updated.latency_monitor)
+}
+
+/// A known network as listed over HTTP; the password never leaves the device.
+#[derive(Serialize)]
+struct KnownNetworkInfo {
+    ssid: String,
+    priority: u8,
+    open: bool,
+    enterprise: bool,
+}
+
+pub fn get_known_networks(req: HttpRequest, state: &AppState) -> Result<()> {
+    let networks: Vec<KnownNetworkInfo> = state
+        .config
+        .get()
+        .known_networks
+        .into_iter()
+        .map(|network| KnownNetworkInfo {
+            open: network.password.is_empty() && network.enterprise.is_none(),
+            enterprise: network.enterprise.is_some(),
+            ssid: network.ssid,
+            priority: network.priority,
+        })
+        .collect();
+
+    write_json(req, &networks)
+}
+
+/// Adds a network, replacing any saved network with the same SSID.
+pub fn add_known_network(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    // Room for an enterprise network's CA certificate
+    let body = read_body(&mut req, 4096)?;
+    let network: KnownNetwork = serde_json::from_slice(&body)?;
+    network.validate().map_err(HttpError::bad_request)?;
+    log::info!("Saving network {} with priority {}", network.ssid, network.priority);
+    state.config.update(|config| {
+        config.known_networks.retain(|known| known.ssid != network.ssid);
+        config.known_networks.push(network);
+    })?;
+
+    let mut response = req.into_ok_response()?;
+    response.write_all("Network saved, used on next connect".as_bytes())?;
+    Ok(())
+}
+
+pub fn remove_known_network(req: HttpRequest, state: &AppState) -> Result<()> {
+    let ssid = query_param(&req, "ssid").ok_or_else(|| HttpError::bad_request("Missing ssid parameter"))?;
+
+    let mut removed = false;
+    state.config.update(|config| {
+        let before = config.known_networks.len();
+        config.known_networks.retain(|known| known.ssid != ssid);
+        removed = config.known_networks.len() != before;
+    })?;
+
+    if !removed {
+        return Err(HttpError::not_found("No such network"));
+    }
+    log::info!("Removed network {}", ssid);
+    let mut response = req.into_ok_response()?;
+    response.write_all("Network removed".as_bytes())?;
+    Ok(())
+}
+
+pub fn pairing_status(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(
+        req,
+        &PairingStatus {
+            paired: state.pairing.is_paired(),
+        },
+    )
+}
+
+pub fn pair(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 128)?;
+    let request: PairRequest = serde_json::from_slice(&body)?;
+
+    match state.pairing.pair(&request.code) {
+        Ok(api_key) => {
+            log::info!("Paired, API key issued");
+            write_json(req, &PairResponse { api_key })
+        }
+        Err(e) => {
+            log::warn!("Pairing refused: {}", e);
+            let status = match e {
+                PairError::AlreadyPaired => 409,
+                PairError::WrongCode => 403,
+                PairError::LockedOut(_) => 429,
+            };
+            Err(HttpError::new(status, e))
+        }
+    }
+}
+
+pub fn unpair(req: HttpRequest, state: &AppState) -> Result<()> {
+    let code = state.pairing.unpair()?;
+    log::info!("API keys revoked");
+    log::info!(target: logs::CONSOLE_ONLY, "New pairing code: {}", code);
+
+    let mut response = req.into_ok_response()?;
+    response.write_all("API keys revoked, the new pairing code is on the serial console".as_bytes())?;
+    Ok(())
+}
+
+pub fn users(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.pairing.users())
+}
+
+pub fn add_user(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 256)?;
+    let user: NewUser = serde_json::from_slice(&body)?;
+    let api_key = state.pairing.add_user(&user.name, user.role).map_err(HttpError::bad_request)?;
+    log::info!("API key issued to {} with the {} role", user.name, user.role.as_str());
+    write_json(req, &PairResponse { api_key })
+}
+
+pub fn remove_user(req: HttpRequest, state: &AppState) -> Result<()> {
+    let name = query_param(&req, "name").ok_or_else(|| HttpError::bad_request("Missing name parameter"))?;
+
+    if !state.pairing.remove_user(&name)? {
+        return Err(HttpError::not_found("No such user"));
+    }
+    log::info!("API key of {} revoked", name);
+    let mut response = req.into_ok_response()?;
+    response.write_all("User removed".as_bytes())?;
+    Ok(())
+}
+
+pub fn ap_clients(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.ap_clients.list())
+}
+
+pub fn api_stations(req: HttpRequest, state: &AppState) -> Result<()> {
+    // The configured mode may already differ from the one running until reboot
+    if state.ap_channel.is_none() {
+        return Err(HttpError::not_found("No access point running"));
+    }
+
+    let page = state.ap_clients.stations(&page_request(&req)?);
+    write_json_page(req, &page)
+}
+
+pub fn portal(req: HttpRequest, state: &AppState) -> Result<()> {
+    let html = portal_html(strings(&req), state.ap_channel);
+    write_html(req, 200, &html)
+}
+
+pub fn portal_login(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let client_ip = req
+        .connection()
+        .raw_connection()
+        .and_then(|connection| connection.source_ipv4())
+        .map(|ip| ip.to_string())
+        .unwrap_or_default();
+    let body = read_body(&mut req, 512)?;
+    state.honeypot.record_login(client_ip, &String::from_utf8_lossy(&body));
+
+    let rejected = strings(&req).portal_rejected;
+    write_html(req, 401, rejected)
+}
+
+pub fn honeypot_findings(req: HttpRequest, state: &AppState) -> Result<()> {
+    let page = state.honeypot.page(&page_request(&req)?);
+    write_json_page(req, &page)
+}
+
+/// Server-sent events, one per finding as JSON, with the finding's id as the
+/// event id; a new client gets the stored findings first.
+pub fn honeypot_stream(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_event_stream(req, |next| {
+        state
+            .honeypot
+            .since(next)
+            .into_iter()
+            .filter_map(|record| Some((record.id, serde_json::to_string(&record).ok()?)))
+            .collect()
+    })
+}
+
+pub fn get_tracking(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.tracker.status())
+}
+
+pub fn set_tracking(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 64)?;
+    let body = std::str::from_utf8(&body)?.trim();
+    let bssid = match body {
+        "" | "none" => None,
+        bssid => Some(parse_mac(bssid).ok_or_else(|| HttpError::bad_request(format!("Invalid BSSID: {}", bssid)))?),
+    };
+    state.tracker.track(bssid);
+    setting_changed(state, "tracking", body.to_string());
+
+    write_json(req, &state.tracker.status())
+}
+
+pub fn provisioning_qr(req: HttpRequest, state: &AppState) -> Result<()> {
+    let Some(svg) = &state.provisioning_svg else {
+        return Err(HttpError::not_found("No provisioning QR code in this mode"));
+    };
+    let mut response = req.into_response(200, None, &[("Content-Type", "image/svg+xml")])?;
+    response.write_all(svg.as_bytes())?;
+    Ok(())
+}
+
+pub fn get_alerts(req: HttpRequest, state: &AppState) -> Result<()> {
+    let page = state.alerts.page(&page_request(&req)?);
+    write_json_page(req, &page)
+}
+
+pub fn acknowledge_alert(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 64)?;
+    let ack: AlertAck = serde_json::from_slice(&body)?;
+
+    if !state.alerts.acknowledge(ack.id) {
+        return Err(HttpError::not_found("No such alert"));
+    }
+    let mut response = req.into_ok_response()?;
+    response.write_all("Alert acknowledged".as_bytes())?;
+    Ok(())
+}
+
+pub fn get_led_calibration(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.led.calibration())
+}
+
+pub fn set_led_calibration(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 128)?;
+    let calibration: LedCalibration = serde_json::from_slice(&body)?;
+    calibration.validate().map_err(HttpError::bad_request)?;
+    state.config.update(|config| config.led_calibration = calibration)?;
+    state.led.set_calibration(calibration)?;
+    log::info!("LED calibration set to {:?}", calibration);
+
+    let mut response = req.into_ok_response()?;
+    response.write_all("LED calibration updated".as_bytes())?;
+    Ok(())
+}
+
+pub fn get_led_power(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.led.power_limits())
+}
+
+pub fn set_led_power(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 128)?;
+    let limits: LedPowerLimits = serde_json::from_slice(&body)?;
+    limits.validate().map_err(HttpError::bad_request)?;
+    state.config.update(|config| config.led_power = limits)?;
+    state.led.set_power_limits(limits)?;
+    log::info!("LED power limits set to {:?}", limits);
+
+    let mut response = req.into_ok_response()?;
+    response.write_all("LED power limits updated".as_bytes())?;
+    Ok(())
+}
+
+pub fn get_led_priority(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.config.get().led_priority)
+}
+
+pub fn set_led_priority(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 128)?;
+    let priority: LedPriority = serde_json::from_slice(&body)?;
+    priority.validate().map_err(HttpError::bad_request)?;
+    log::info!("LED priority set to {:?}", priority);
+    state.config.update(|config| config.led_priority = priority)?;
+
+    let mut response = req.into_ok_response()?;
+    response.write_all("LED priority updated".as_bytes())?;
+    Ok(())
+}
+
+pub fn get_led_output(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.config.get().led_output)
+}
+
+pub fn set_led_output(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 64)?;
+    let output: LedOutput = serde_json::from_slice(&body)?;
+    output.validate(crate::CLAIMED_PINS)?;
+    log::info!("LED pins set to {:?}, applied on next boot", output.pins);
+    state.config.update(|config| config.led_output = output)?;
+
+    let mut response = req.into_ok_response()?;
+    response.write_all("LED pins updated, reboot to apply".as_bytes())?;
+    Ok(())
+}
+
+pub fn http_sessions(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.sessions.list())
+}
+
+pub fn get_http_settings(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.config.get().http)
+}
+
+pub fn set_http_settings(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 256)?;
+    let settings: HttpSettings = serde_json::from_slice(&body)?;
+    settings.validate().map_err(HttpError::bad_request)?;
+    log::info!("HTTP limits set to {:?}, applied on next boot", settings);
+    state.config.update(|config| config.http = settings)?;
+
+    let mut response = req.into_ok_response()?;
+    response.write_all("HTTP limits updated, reboot to apply".as_bytes())?;
+    Ok(())
+}
+
+fn file_store(state: &AppState) -> Result<&FileStore> {
+    state
+        .files
+        .as_deref()
+        .ok_or_else(|| HttpError::new(503, "No file store, flash with partitions.csv"))
+}
+
+/// The `{name}` path parameter, checked to be a valid file name.
+fn file_name(req: &HttpRequest) -> Result<String> {
+    let name = routes::path_param(req, "name").unwrap_or_default();
+    files::check_name(&name).map_err(HttpError::bad_request)?;
+    Ok(name)
+}
+
+fn content_type(name: &str) -> &'static str {
+    match name.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("js") => "text/javascript",
+        Some("css") => "text/css",
+        Some("json") => "application/json",
+        Some("csv") => "text/csv",
+        Some("txt") | Some("log") => "text/plain; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        _ => "application/octet-stream",
+    }
+}
+
+pub fn list_files(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &file_store(state)?.usage()?)
+}
+
+pub fn get_file(req: HttpRequest, state: &AppState) -> Result<()> {
+    let name = file_name(&req)?;
+    let Some(mut file) = file_store(state)?.open(&name)? else {
+        return Err(HttpError::not_found("No such file"));
+    };
+
+    let mut response = req.into_response(200, None, &[("Content-Type", content_type(&name))])?;
+    let mut buffer = [0_u8; 512];
+    loop {
+        let len = std::io::Read::read(&mut file, &mut buffer)?;
+        if len == 0 {
+            break;
+        }
+        response.write_all(&buffer[..len])?;
+    }
+    Ok(())
+}
+
+/// Streams the body into the file, up to the free space of the partition.
+pub fn put_file(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let name = file_name(&req)?;
+    let files = file_store(state)?;
+    let usage = files.usage()?;
+    let existing = usage.files.iter().find(|file| file.name == name).map_or(0, |file| file.size);
+    let limit = usage.total_bytes.saturating_sub(usage.used_bytes) as u64 + existing;
+
+    let len = files.write(&name, |file| {
+        let mut buffer = [0_u8; 512];
+        let mut written = 0_u64;
+        loop {
+            let len = req.read(&mut buffer)?;
+            if len == 0 {
+                return Ok(written);
+            }
+            written += len as u64;
+            if written > limit {
+                return Err(HttpError::new(413, format!("Only {} bytes free", limit)));
+            }
+            std::io::Write::write_all(file, &buffer[..len])?;
+        }
+    })?;
+    log::info!("Wrote {} bytes to {}", len, name);
+
+    let mut response = req.into_ok_response()?;
+    response.write_all(format!("Wrote {} bytes", len).as_bytes())?;
+    Ok(())
+}
+
+pub fn delete_file(req: HttpRequest, state: &AppState) -> Result<()> {
+    let name = file_name(&req)?;
+    if !file_store(state)?.remove(&name)? {
+        return Err(HttpError::not_found("No such file"));
+    }
+    log::info!("Deleted {}", name);
+
+    let mut response = req.into_ok_response()?;
+    response.write_all("File deleted".as_bytes())?;
+    Ok(())
+}
+
+pub fn tx_log(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.tx_log.info())
+}
+
+pub fn tx_log_csv(req: HttpRequest, state: &AppState) -> Result<()> {
+    let csv = state
+        .tx_log
+        .csv(&state.config.get().identity.name, state.pairing.admin_key_sha256().as_deref())?;
+    let mut response = req.into_response(
+        200,
+        None,
+        &[
+            ("Content-Type", "text/csv"),
+            ("Content-Disposition", "attachment; filename=\"txlog.csv\""),
+        ],
+    )?;
+    response.write_all(csv.as_bytes())?;
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+struct TlsUpload {
+    certificate: String,
+    private_key: String,
+}
+
+pub fn tls_info(req: HttpRequest, state: &AppState) -> Result<()> {
+    write_json(req, &state.tls.info()?)
+}
+
+pub fn install_tls(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 2 * tls::MAX_PEM_LEN + 256)?;
+    let upload: TlsUpload = serde_json::from_slice(&body)?;
+    state.tls.install(&upload.certificate, &upload.private_key).map_err(HttpError::bad_request)?;
+    log::info!("HTTPS certificate installed, applied on next boot");
+
+    let mut response = req.into_ok_response()?;
+    let message = if state.config.get().http.https {
+        "Certificate installed, reboot to apply"
+    } else {
+        "Certificate installed, set https in /http/settings and reboot to apply"
+    };
+    response.write_all(message.as_bytes())?;
+    Ok(())
+}
+
+pub fn clear_tls(req: HttpRequest, state: &AppState) -> Result<()> {
+    state.tls.clear()?;
+    log::info!("HTTPS certificate removed, applied on next boot");
+
+    let mut response = req.into_ok_response()?;
+    response.write_all("Certificate removed, reboot to apply".as_bytes())?;
+    Ok(())
+}
+
+/// The color comes as the raw body, or as a form, in the body or the query
+/// string, with either `color` or the `r`, `g` and `b` components.
+pub fn set_color(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let is_form = req
+        .header("Content-Type")
+        .is_some_and(|content_type| content_type.starts_with("application/x-www-form-urlencoded"));
+    let body = read_body(&mut req, 64)?;
+    let body = std::str::from_utf8(&body)?;
+    let color = match (is_form, body.trim().is_empty()) {
+        (false, false) => body.parse::<Color>().map_err(HttpError::bad_request)?,
+        (true, _) => form_color(&Form::parse(body))?,
+        (false, true) => form_color(&query(&req))?,
+    };
+    log::info!("Setting color: {:?}", color);
+
+    let mut response = req.into_ok_response()?;
+    response.write_all("Color set successfully".as_bytes())?;
+
+    show_color(state, color)
+}
+
+fn form_color(form: &Form) -> Result<Color> {
+    if let Some(color) = form.get("color") {
+        return color.parse::<Color>().map_err(HttpError::bad_request);
+    }
+    Ok(Color {
+        r: form.required("r")?,
+        g: form.required("g")?,
+        b: form.required("b")?,
+    })
+}
+
+#[derive(Debug, Serialize)]
+struct LedChannel {
+    channel: String,
+    value: u8,
+}
+
+/// Index of the `{channel}` path parameter in an RGB triple.
+fn led_channel_index(req: &HttpRequest) -> Result<(String, usize)> {
+    let channel = routes::path_param(req, "channel").unwrap_or_default();
+    let index = match channel.as_str() {
+        "red" => 0,
+        "green" => 1,
+        "blue" => 2,
+        _ => return Err(HttpError::not_found(format!("No LED channel {:?}, use red, green or blue", channel))),
+    };
+    Ok((channel, index))
+}
+
+pub fn get_led_channel(req: HttpRequest, state: &AppState) -> Result<()> {
+    let (channel, index) = led_channel_index(&req)?;
+    let value = state.led.color()[index];
+    write_json(req, &LedChannel { channel, value })
+}
+
+pub fn set_led_channel(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let (channel, index) = led_channel_index(&req)?;
+    let body = read_body(&mut req, 8)?;
+    let value: u8 = std::str::from_utf8(&body)?.trim().parse()?;
+    let mut rgb = state.led.color();
+    rgb[index] = value;
+    let color = Color {
+        r: rgb[0],
+        g: rgb[1],
+        b: rgb[2],
+    };
+    log::info!("Setting LED {} to {}: {:?}", channel, value, color);
+
+    let mut response = req.into_ok_response()?;
+    response.write_all("Channel set".as_bytes())?;
+
+    show_color(state, color)
+}
+
+/// Shows a color requested over HTTP, ahead of the scanner's animations when the
+/// LED priority asks for it.
+fn show_color(state: &AppState, color: Color) -> Result<()> {
+    let priority = state.config.get().led_priority;
+    if priority.policy == LedPriorityPolicy::Latency {
+        state.led.hold(Duration::from_millis(priority.hold_ms as u64));
+        scan::preempt_scan();
+    }
+
+    state.led.set(color.r, color.g, color.b)?;
+    state.events.publish(Event::LedChanged {
+        color: color.to_hex(),
+        source: "http".to_string(),
+    });
+
+    Ok(())
+}
+
+/// GPIOs a pin test must leave alone.
+fn pins_in_use(state: &AppState) -> Vec<u8> {
+    let mut pins = crate::CLAIMED_PINS.to_vec();
+    pins.extend(&state.led_pins);
+    pins.extend(state.config.get().sleep.wake_gpio);
+    pins
+}
+
+pub fn diag_gpio_read(req: HttpRequest, state: &AppState) -> Result<()> {
+    let pin: u8 = query(&req).required("pin")?;
+    let pull: PinPull = query_param(&req, "pull")
+        .map(|pull| serde_json::from_value(serde_json::Value::String(pull)))
+        .transpose()?
+        .unwrap_or_default();
+
+    write_json(req, &pintest::read(pin, pull, &pins_in_use(state))?)
+}
+
+pub fn diag_gpio_drive(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 128)?;
+    let test: PinTest = serde_json::from_slice(&body)?;
+    log::info!("Pin test on GPIO{}: {:?}", test.pin, test.action);
+    pintest::drive(test.pin, test.action, &pins_in_use(state))?;
+
+    let mut response = req.into_ok_response()?;
+    response.write_all("Pin test done, the pin is released".as_bytes())?;
+    Ok(())
+}
+
+pub fn diag_spi_loopback(mut req: HttpRequest, state: &AppState) -> Result<()> {
+    let body = read_body(&mut req, 128)?;
+    let pins: SpiPins = serde_json::from_slice(&body)?;
+    let result = pintest::spi_loopback(pins.sclk, pins.mosi, pins.miso, &pins_in_use(state))?;
+    log::info!("SPI loopback on {:?}: {} mismatched bytes", pins, result.mismatched_bytes);
+
+    write_json(req, &result)
+}
+
+pub fn diag_ping(req: HttpRequest, state: &AppState) -> Result<()> {
+    let host = query_param(&req, "host").ok_or_else(|| HttpError::bad_request("Missing host parameter"))?;
+    let count = query(&req).value("count")?.unwrap_or(4);
+
+    match query_param(&req, "mode").as_deref() {
+        Some("trace") => write_json(req, &diag::path_check(&host, count, state.ip_info.as_ref())),
+        _ => write_json(req, &diag::ping_within(&host, count, diag::MAX_REQUEST_TIME)?),
+    }
+}
+
+pub fn diag_dns(req: HttpRequest, state: &AppState) -> Result<()> {
+    let name = query_param(&req, "name").ok_or_else(|| HttpError::bad_request("Missing name parameter"))?;
+    let resolver = state.ip_info.as_ref().and_then(|ip_info| ip_info.dns);
+
+    write_json(req, &diag::lookup(&name, resolver))
+}
+
+pub fn timeline(req: HttpRequest, state: &AppState) -> Result<()> {
+    let since = query(&req).value("since")?.unwrap_or(0);
+    let page = state.timeline.page(since, &page_request(&req)?);
+
+    write_json_page(req, &page)
+}
+
+/// Starts or stops the sniffer to match its toggle. In safe mode it stays off,
+/// the toggle applies from the next normal boot.
+fn apply_sniffer(state: &AppState, enabled: bool) {
+    if state.boot.safe_mode() || enabled == sniffer::status().running {
+        return;
+    }
+
+    let result = if enabled { sniffer::start() } else { sniffer::stop() };
+    if let Err(e) = result {
+        log::error!("Failed to switch the sniffer: {:?}", e);
+    }
+}
+
+fn setting_changed(state: &AppState, setting: &str, value: String) {
+    state.events.publish(Event::SettingChanged {
+        setting: setting.to_string(),
+        value,
+    });
+}
+
+/// Language of the web pages, from `?lang=` or `Accept-Language`.
+fn strings(req: &HttpRequest) -> &'static Strings {
+    i18n::negotiate(query_param(req, "lang").as_deref(), req.header("Accept-Language"))
+}
+
+fn query_param(req: &HttpRequest, name: &str) -> Option<String> {
+    let (_, query) = req.uri().split_once('?')?;
+    form::value(query, name)
+}
+
+/// The query string, for typed parameters.
+fn query(req: &HttpRequest) -> Form {
+    req.uri().split_once('?').map(|(_, query)| Form::parse(query)).unwrap_or_default()
+}
+
+fn write_html(req: HttpRequest, status: u16, html: &str) -> Result<()> {
+    let mut response = req.into_response(status, None, &[("Content-Type", "text/html; charset=utf-8")])?;
+    response.write_all(html.as_bytes())?;
+    Ok(())
+}
+
+fn write_json<T: Serialize>(req: HttpRequest, value: &T) -> Result<()> {
+    let body = serde_json::to_vec(value)?;
+    let mut response = req.into_response(200, None, &[("Content-Type", "application/json")])?;
+    response.write_all(&body)?;
+    Ok(())
+}
+
+/// Writes `page` as `{"items": [...], "next_cursor": ...}`, serializing item by
+/// item so the whole page never has to sit in one buffer.
+fn write_json_page<T: Serialize, K: Serialize>(req: HttpRequest, page: &Page<T, K>) -> Result<()> {
+    let mut response = req.into_response(200, None, &[("Content-Type", "application/json")])?;
+    response.write_all(b"{\"items\":[")?;
+    for (i, item) in page.items.iter().enumerate() {
+        if i > 0 {
+            response.write_all(b",")?;
+        }
+        response.write_all(&serde_json::to_vec(item)?)?;
+    }
+    response.write_all(b"],\"next_cursor\":")?;
+    response.write_all(&serde_json::to_vec(&page.next_cursor)?)?;
+    response.write_all(b"}")?;
+    Ok(())
+}
+
+/// `cursor` and `limit` from the query string.
+fn page_request<K>(req: &HttpRequest) -> Result<PageRequest<K>>
+where
+    K: Ord + Clone + FromStr,
+    K::Err: Display,
+{
+    PageRequest::parse(&query(req))
+}
+
+pub fn read_body(req: &mut HttpRequest, limit: usize) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    let mut buffer = [0_u8; 128];
+
+    loop {
+        let len = req.read(&mut buffer)?;
+        if len == 0 {
+            break;
+        }
+        if body.len() + len > limit {
+            return Err(HttpError::new(413, format!("Request body exceeds {} bytes", limit)));
+        }
+        body.extend_from_slice(&buffer[..len]);
+    }
+
+    Ok(body)
+}
@@ -0,0 +1,149 @@
+//! Heap usage and fragmentation, sampled in the background. In soak-test mode
+//! every sample is logged as well, to watch a long run for the largest free
+//! block shrinking while the free total stays put.
+
+use esp_idf_svc::sys;
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::config::{ConfigStore, SoakTestSettings};
+use crate::honeypot::uptime_secs;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct HeapSample {
+    pub uptime_secs: u64,
+    pub free: u32,
+    pub min_free: u32,
+    pub largest_free_block: u32,
+    /// Share of the free heap outside the largest block.
+    pub fragmentation_percent: f32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HeapReport {
+    pub current: HeapSample,
+    /// Since the soak test was started, or since boot.
+    pub smallest_largest_free_block: u32,
+    pub worst_fragmentation_percent: f32,
+    pub soak_test: bool,
+    pub soak_started_uptime_secs: Option<u64>,
+}
+
+struct State {
+    smallest_largest_free_block: u32,
+    worst_fragmentation_percent: f32,
+    soak_started: Option<u64>,
+}
+
+pub struct HeapMonitor {
+    config: Arc<ConfigStore>,
+    state: Mutex<State>,
+}
+
+impl HeapMonitor {
+    pub fn new(config: Arc<ConfigStore>) -> Self {
+        HeapMonitor {
+            config,
+            state: Mutex::new(State {
+                smallest_largest_free_block: u32::MAX,
+                worst_fragmentation_percent: 0.0,
+                soak_started: None,
+            }),
+        }
+    }
+
+    /// Samples forever.
+    pub fn run(&self) {
+        loop {
+            let settings = match self.config.get().soak_test {
+                settings if settings.validate().is_ok() => settings,
+                _ => SoakTestSettings::default(),
+            };
+            let sample = self.sample(settings.enabled);
+            if settings.enabled {
+                log::info!(
+                    "Soak: free {} B, min free {} B, largest block {} B, fragmentation {:.1}%",
+                    sample.free,
+                    sample.min_free,
+                    sample.largest_free_block,
+                    sample.fragmentation_percent
+                );
+            }
+            thread::sleep(Duration::from_secs(settings.interval_secs as u64));
+        }
+    }
+
+    pub fn report(&self) -> HeapReport {
+        let current = sample();
+        let state = self.state.lock().unwrap();
+        HeapReport {
+            current,
+            smallest_largest_free_block: state.smallest_largest_free_block.min(current.largest_free_block),
+            worst_fragmentation_percent: state.worst_fragmentation_percent.max(current.fragmentation_percent),
+            soak_test: state.soak_started.is_some(),
+            soak_started_uptime_secs: state.soak_started,
+        }
+    }
+
+    /// Prometheus text exposition for `/metrics`.
+    pub fn metrics(&self) -> String {
+        let report = self.report();
+        let mut metrics = String::new();
+        let _ = writeln!(metrics, "# TYPE heap_free_bytes gauge");
+        let _ = writeln!(metrics, "heap_free_bytes {}", report.current.free);
+        let _ = writeln!(metrics, "# TYPE heap_min_free_bytes gauge");
+        let _ = writeln!(metrics, "heap_min_free_bytes {}", report.current.min_free);
+        let _ = writeln!(metrics, "# TYPE heap_largest_free_block_bytes gauge");
+        let _ = writeln!(metrics, "heap_largest_free_block_bytes {}", report.current.largest_free_block);
+        let _ = writeln!(metrics, "# TYPE heap_fragmentation_percent gauge");
+        let _ = writeln!(metrics, "heap_fragmentation_percent {:.1}", report.current.fragmentation_percent);
+        metrics
+    }
+
+    /// Starting a soak test resets the extremes, so they cover the test only.
+    fn sample(&self, soak: bool) -> HeapSample {
+        let sample = sample();
+        let mut state = self.state.lock().unwrap();
+        match (soak, state.soak_started) {
+            (true, None) => {
+                log::info!("Soak test started");
+                state.soak_started = Some(sample.uptime_secs);
+                state.smallest_largest_free_block = u32::MAX;
+                state.worst_fragmentation_percent = 0.0;
+            }
+            (false, Some(_)) => {
+                log::info!("Soak test stopped");
+                state.soak_started = None;
+            }
+            _ => {}
+        }
+        state.smallest_largest_free_block = state.smallest_largest_free_block.min(sample.largest_free_block);
+        state.worst_fragmentation_percent = state.worst_fragmentation_percent.max(sample.fragmentation_percent);
+        sample
+    }
+}
+
+pub fn sample() -> HeapSample {
+    let (free, min_free, largest_free_block) = unsafe {
+        (
+            sys::heap_caps_get_free_size(sys::MALLOC_CAP_DEFAULT) as u32,
+            sys::esp_get_minimum_free_heap_size(),
+            sys::heap_caps_get_largest_free_block(sys::MALLOC_CAP_DEFAULT) as u32,
+        )
+    };
+    let fragmentation_percent = match free {
+        0 => 0.0,
+        free => (free - largest_free_block.min(free)) as f32 * 100.0 / free as f32,
+    };
+
+    HeapSample {
+        uptime_secs: uptime_secs(),
+        free,
+        min_free,
+        largest_free_block,
+        fragmentation_percent,
+    }
+}
@@ -0,0 +1,94 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+
+use crate::honeypot::uptime_secs;
+use crate::page::{Page, PageRequest};
+use crate::scan::{format_mac, MacAddress, ScannedNetwork};
+
+/// Beyond this many access points the one seen least recently is forgotten.
+const MAX_BSSIDS: usize = 64;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct RssiSample {
+    pub uptime_secs: u64,
+    pub rssi: i8,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BssidHistory {
+    #[serde(skip)]
+    pub mac: [u8; 6],
+    pub bssid: String,
+    pub ssid: String,
+    pub channel: u8,
+    pub first_seen: u64,
+    pub last_seen: u64,
+    /// Oldest first.
+    pub samples: VecDeque<RssiSample>,
+}
+
+/// Signal strength over time of every access point the scanner has seen, in
+/// seconds of uptime.
+pub struct ScanHistory {
+    samples_per_bssid: usize,
+    bssids: Mutex<BTreeMap<[u8; 6], BssidHistory>>,
+}
+
+impl ScanHistory {
+    pub fn new(samples_per_bssid: usize) -> Self {
+        ScanHistory {
+            samples_per_bssid,
+            bssids: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn record(&self, networks: &[ScannedNetwork]) {
+        let now = uptime_secs();
+        let mut bssids = self.bssids.lock().unwrap();
+
+        for network in networks {
+            let history = bssids.entry(network.bssid).or_insert_with(|| BssidHistory {
+                mac: network.bssid,
+                bssid: format_mac(&network.bssid),
+                ssid: network.ssid.clone(),
+                channel: network.channel,
+                first_seen: now,
+                last_seen: now,
+                samples: VecDeque::with_capacity(self.samples_per_bssid),
+            });
+
+            history.ssid.clone_from(&network.ssid);
+            history.channel = network.channel;
+            history.last_seen = now;
+            if history.samples.len() == self.samples_per_bssid {
+                history.samples.pop_front();
+            }
+            history.samples.push_back(RssiSample {
+                uptime_secs: now,
+                rssi: network.signal_strength,
+            });
+        }
+
+        while bssids.len() > MAX_BSSIDS {
+            let Some(stalest) = bssids
+                .iter()
+                .min_by_key(|(_, history)| history.last_seen)
+                .map(|(bssid, _)| *bssid)
+            else {
+                break;
+            };
+            bssids.remove(&stalest);
+        }
+    }
+
+    pub fn get(&self, bssid: &[u8; 6]) -> Option<BssidHistory> {
+        self.bssids.lock().unwrap().get(bssid).cloned()
+    }
+
+    /// By BSSID.
+    pub fn page(&self, request: &PageRequest<MacAddress>) -> Page<BssidHistory, MacAddress> {
+        let bssids = self.bssids.lock().unwrap();
+        request.select(bssids.iter().map(|(bssid, history)| (MacAddress(*bssid), history)))
+    }
+}
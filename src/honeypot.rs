@@ -0,0 +1,208 @@
+use anyhow::Result;
+use esp_idf_svc::eventloop::{EspSubscription, EspSystemEventLoop, System};
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use esp_idf_svc::sys::esp_timer_get_time;
+use esp_idf_svc::wifi::WifiEvent;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use crate::alerts::unix_time;
+use crate::codec;
+use crate::form;
+use crate::i18n::Strings;
+use crate::page::{Page, PageRequest};
+use crate::scan::format_mac;
+use crate::storage;
+
+const NAMESPACE: &str = "honeypot";
+const FINDINGS_KEY: &str = "findings";
+const MAX_FINDINGS: usize = 64;
+
+pub fn portal_html(strings: &Strings, channel: Option<u8>) -> String {
+    let channel = channel
+        .map(|channel| format!("<p><small>{} {}</small></p>", strings.portal_channel, channel))
+        .unwrap_or_default();
+
+    format!(
+        r#"
+<!DOCTYPE html>
+<html lang="{code}">
+<head><title>{title}</title><meta charset="utf-8"><meta name="viewport" content="width=device-width"></head>
+<body>
+    <h1>{heading}</h1>
+    <form method="post" action="/portal">
+        <p><input name="username" placeholder="{username}"></p>
+        <p><input name="password" type="password" placeholder="{password}"></p>
+        <p><button type="submit">{submit}</button></p>
+    </form>
+    {channel}
+</body>
+</html>
+"#,
+        code = strings.code,
+        title = strings.portal_title,
+        heading = strings.portal_heading,
+        username = strings.portal_username,
+        password = strings.portal_password,
+        submit = strings.portal_submit,
+        channel = channel,
+    )
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Finding {
+    Association {
+        mac: String,
+        uptime_secs: u64,
+    },
+    /// Passwords are never kept in clear: only their length and a SHA-256
+    /// fingerprint, enough to spot reused or dictionary credentials.
+    PortalLogin {
+        client_ip: String,
+        username: String,
+        password_length: usize,
+        password_sha256: String,
+        uptime_secs: u64,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    /// Increasing across reboots, the event id of the findings stream.
+    pub id: u64,
+    /// `None` until the clock has been set, see [`crate::clock`].
+    pub unix_time: Option<u64>,
+    #[serde(flatten)]
+    pub finding: Finding,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct FindingState {
+    next_id: u64,
+    findings: VecDeque<Record>,
+}
+
+/// Findings of the honeypot AP: who associated and what was submitted to the
+/// portal, persisted as JSON in the `honeypot` NVS namespace so a session's
+/// results survive a reboot or power loss.
+///
+/// Only meant for networks you are authorized to test.
+pub struct Honeypot {
+    nvs: Option<Mutex<EspNvs<NvsDefault>>>,
+    state: Mutex<FindingState>,
+}
+
+impl Honeypot {
+    pub fn new(partition: EspDefaultNvsPartition) -> Result<Self> {
+        let nvs = EspNvs::new(partition, NAMESPACE, true)?;
+
+        let state = match Self::load(&nvs) {
+            Ok(state) => state,
+            Err(e) => {
+                log::warn!("Failed to load stored honeypot findings, starting empty: {}", e);
+                FindingState::default()
+            }
+        };
+
+        Ok(Honeypot {
+            nvs: Some(Mutex::new(nvs)),
+            state: Mutex::new(state),
+        })
+    }
+
+    /// Store used when NVS is unavailable; findings are lost on reboot.
+    pub fn in_memory() -> Self {
+        Honeypot {
+            nvs: None,
+            state: Mutex::new(FindingState::default()),
+        }
+    }
+
+    fn load(nvs: &EspNvs<NvsDefault>) -> Result<FindingState> {
+        match storage::read(nvs, FINDINGS_KEY)? {
+            Some(data) => Ok(serde_json::from_slice(&data)?),
+            None => Ok(FindingState::default()),
+        }
+    }
+
+    pub fn subscribe(self: &Arc<Self>, sys_loop: &EspSystemEventLoop) -> Result<EspSubscription<'static, System>> {
+        let honeypot = self.clone();
+
+        let subscription = sys_loop.subscribe::<WifiEvent, _>(move |event| {
+            if let WifiEvent::ApStaConnected(station) = event {
+                honeypot.record(Finding::Association {
+                    mac: format_mac(&station.mac()),
+                    uptime_secs: uptime_secs(),
+                });
+            }
+        })?;
+
+        Ok(subscription)
+    }
+
+    pub fn record_login(&self, client_ip: String, form: &str) {
+        let username = form::value(form, "username").unwrap_or_default();
+        let password = form::value(form, "password").unwrap_or_default();
+
+        let digest = Sha256::digest(password.as_bytes());
+        let password_sha256 = codec::hex_string(&digest);
+
+        self.record(Finding::PortalLogin {
+            client_ip,
+            username,
+            password_length: password.chars().count(),
+            password_sha256,
+            uptime_secs: uptime_secs(),
+        });
+    }
+
+    pub fn page(&self, request: &PageRequest<u64>) -> Page<Record, u64> {
+        request.select(self.state.lock().unwrap().findings.iter().map(|record| (record.id, record)))
+    }
+
+    /// The stored findings from id `first` on, oldest first.
+    pub fn since(&self, first: u64) -> Vec<Record> {
+        let state = self.state.lock().unwrap();
+        state.findings.iter().filter(|record| record.id >= first).cloned().collect()
+    }
+
+    fn record(&self, finding: Finding) {
+        log::warn!("Honeypot finding: {:?}", finding);
+
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+
+        if state.findings.len() == MAX_FINDINGS {
+            state.findings.pop_front();
+        }
+        state.findings.push_back(Record {
+            id,
+            unix_time: unix_time(),
+            finding,
+        });
+
+        self.persist(&state);
+    }
+
+    fn persist(&self, state: &FindingState) {
+        let Some(nvs) = &self.nvs else {
+            return;
+        };
+
+        let result = serde_json::to_vec(state)
+            .map_err(anyhow::Error::from)
+            .and_then(|data| storage::write(&mut nvs.lock().unwrap(), FINDINGS_KEY, &data));
+        if let Err(e) = result {
+            log::error!("Failed to persist honeypot findings: {:?}", e);
+        }
+    }
+}
+
+pub fn uptime_secs() -> u64 {
+    (unsafe { esp_timer_get_time() } / 1_000_000) as u64
+}
@@ -0,0 +1,217 @@
+//! Request counts, errors and latency per route over the last minute and hour,
+//! to notice when a new handler slows the single HTTP worker down. Slow
+//! requests are logged as they finish, and with the access log on every one is.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::config::HttpSettings;
+use crate::honeypot::uptime_secs;
+
+/// Upper bounds of the latency histogram bins, in milliseconds; the last bin
+/// takes everything slower. Percentiles are reported as the bound of their bin.
+const LATENCY_BOUNDS_MS: [u32; 12] = [1, 2, 5, 10, 20, 50, 100, 200, 500, 1000, 2000, 5000];
+const BINS: usize = LATENCY_BOUNDS_MS.len() + 1;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Bucket {
+    /// Which bucket period this holds, so stale slots are recognised.
+    period: u64,
+    requests: u32,
+    errors: u32,
+    slow: u32,
+    latency: [u32; BINS],
+}
+
+/// A ring of buckets sliding with uptime.
+#[derive(Debug, Clone)]
+struct Window {
+    bucket_secs: u64,
+    buckets: Vec<Bucket>,
+}
+
+impl Window {
+    fn new(bucket_secs: u64, buckets: usize) -> Self {
+        Window {
+            bucket_secs,
+            buckets: vec![Bucket::default(); buckets],
+        }
+    }
+
+    fn record(&mut self, now: u64, bin: usize, error: bool, slow: bool) {
+        let period = now / self.bucket_secs;
+        let slot = period as usize % self.buckets.len();
+        let bucket = &mut self.buckets[slot];
+        if bucket.period != period {
+            *bucket = Bucket {
+                period,
+                ..Default::default()
+            };
+        }
+
+        bucket.requests += 1;
+        bucket.errors += error as u32;
+        bucket.slow += slow as u32;
+        bucket.latency[bin] += 1;
+    }
+
+    fn summary(&self, now: u64) -> WindowStats {
+        let period = now / self.bucket_secs;
+        let oldest = period.saturating_sub(self.buckets.len() as u64 - 1);
+
+        let mut total = Bucket::default();
+        for bucket in self.buckets.iter().filter(|bucket| (oldest..=period).contains(&bucket.period)) {
+            total.requests += bucket.requests;
+            total.errors += bucket.errors;
+            total.slow += bucket.slow;
+            for (sum, count) in total.latency.iter_mut().zip(bucket.latency) {
+                *sum += count;
+            }
+        }
+
+        WindowStats {
+            requests: total.requests,
+            errors: total.errors,
+            slow: total.slow,
+            p50_ms: percentile(&total.latency, total.requests, 50),
+            p95_ms: percentile(&total.latency, total.requests, 95),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct WindowStats {
+    pub requests: u32,
+    pub errors: u32,
+    /// Over `slow_request_ms`.
+    pub slow: u32,
+    /// `None` without requests; `u32::MAX` when slower than the last bin.
+    pub p50_ms: Option<u32>,
+    pub p95_ms: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RouteStats {
+    pub method: &'static str,
+    pub path: &'static str,
+    pub last_minute: WindowStats,
+    pub last_hour: WindowStats,
+}
+
+struct Windows {
+    minute: Window,
+    hour: Window,
+}
+
+pub struct HttpStats {
+    /// Read once at boot, like the other HTTP settings.
+    access_log: bool,
+    slow_request_ms: u32,
+    routes: Mutex<BTreeMap<(&'static str, &'static str), Windows>>,
+}
+
+impl HttpStats {
+    pub fn new(settings: &HttpSettings) -> Self {
+        HttpStats {
+            access_log: settings.access_log,
+            slow_request_ms: settings.slow_request_ms,
+            routes: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Counts a request to the route `method` `path` and logs it. A status of
+    /// 400 and up counts as an error, which includes rejected credentials.
+    pub fn record(&self, method: &'static str, path: &'static str, uri: &str, status: u16, latency: Duration) {
+        let latency_ms = latency.as_millis().min(u32::MAX as u128) as u32;
+        let (error, slow) = (status >= 400, latency_ms > self.slow_request_ms);
+        if slow {
+            log::warn!("Slow request: {} {} {} in {} ms", method, uri, status, latency_ms);
+        } else if self.access_log {
+            log::info!("{} {} {} in {} ms", method, uri, status, latency_ms);
+        }
+
+        let bin = LATENCY_BOUNDS_MS
+            .iter()
+            .position(|bound| latency_ms <= *bound)
+            .unwrap_or(BINS - 1);
+        let now = uptime_secs();
+
+        let mut routes = self.routes.lock().unwrap();
+        let windows = routes.entry((path, method)).or_insert_with(|| Windows {
+            // Six 10 s buckets and twelve 5 min ones: the windows slide in steps
+            minute: Window::new(10, 6),
+            hour: Window::new(300, 12),
+        });
+        windows.minute.record(now, bin, error, slow);
+        windows.hour.record(now, bin, error, slow);
+    }
+
+    /// Routes that were requested since boot, by path.
+    pub fn all(&self) -> Vec<RouteStats> {
+        let now = uptime_secs();
+        self.routes
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&(path, method), windows)| RouteStats {
+                method,
+                path,
+                last_minute: windows.minute.summary(now),
+                last_hour: windows.hour.summary(now),
+            })
+            .collect()
+    }
+
+    pub fn get(&self, method: &'static str, path: &'static str) -> Option<WindowStats> {
+        let routes = self.routes.lock().unwrap();
+        routes.get(&(path, method)).map(|windows| windows.hour.summary(uptime_secs()))
+    }
+
+    /// Prometheus text exposition for `/metrics`.
+    pub fn metrics(&self) -> String {
+        let stats = self.all();
+        let mut metrics = String::new();
+
+        let series: [(&str, fn(&WindowStats) -> Option<u32>); 5] = [
+            ("http_requests", |window| Some(window.requests)),
+            ("http_errors", |window| Some(window.errors)),
+            ("http_slow_requests", |window| Some(window.slow)),
+            ("http_latency_p50_ms", |window| window.p50_ms),
+            ("http_latency_p95_ms", |window| window.p95_ms),
+        ];
+        for (name, value) in series {
+            let _ = writeln!(metrics, "# TYPE {} gauge", name);
+            for route in &stats {
+                for (window, label) in [(&route.last_minute, "1m"), (&route.last_hour, "1h")] {
+                    if let Some(value) = value(window) {
+                        let _ = writeln!(
+                            metrics,
+                            "{}{{method=\"{}\",route=\"{}\",window=\"{}\"}} {}",
+                            name, route.method, route.path, label, value
+                        );
+                    }
+                }
+            }
+        }
+        metrics
+    }
+}
+
+fn percentile(histogram: &[u32; BINS], total: u32, percent: u32) -> Option<u32> {
+    if total == 0 {
+        return None;
+    }
+
+    let rank = (total * percent).div_ceil(100);
+    let mut seen = 0;
+    for (bin, count) in histogram.iter().enumerate() {
+        seen += count;
+        if seen >= rank {
+            return Some(LATENCY_BOUNDS_MS.get(bin).copied().unwrap_or(u32::MAX));
+        }
+    }
+    None
+}
@@ -0,0 +1,100 @@
+//! Strings of the web pages in each supported language.
+//!
+//! The tables are `'static` and stay in flash; they are small enough that
+//! compressing them would cost more in decompressor code than it saves.
+//! Route descriptions are API documentation and stay in English.
+
+pub struct Strings {
+    /// BCP 47 primary tag, used for `<html lang>`.
+    pub code: &'static str,
+    pub index_title: &'static str,
+    pub index_heading: &'static str,
+    pub index_scanner: &'static str,
+    pub index_api: &'static str,
+    pub no_body: &'static str,
+    /// Requests to a route in the last hour, after the count.
+    pub index_requests: &'static str,
+    pub index_errors: &'static str,
+    pub portal_title: &'static str,
+    pub portal_heading: &'static str,
+    pub portal_username: &'static str,
+    pub portal_password: &'static str,
+    pub portal_submit: &'static str,
+    pub portal_rejected: &'static str,
+    pub portal_channel: &'static str,
+}
+
+pub const EN: Strings = Strings {
+    code: "en",
+    index_title: "ESP32-C3 WiFi Scanner & LED Controller",
+    index_heading: "ESP32-C3 Services",
+    index_scanner: "WiFi Scanner running in background thread",
+    index_api: "HTTP API ready with LED control",
+    no_body: "no body",
+    index_requests: "requests in the last hour",
+    index_errors: "errors",
+    portal_title: "WiFi Login",
+    portal_heading: "Sign in to continue",
+    portal_username: "Username",
+    portal_password: "Password",
+    portal_submit: "Connect",
+    portal_rejected: "Invalid credentials, please try again",
+    portal_channel: "Access point on channel",
+};
+
+pub const FR: Strings = Strings {
+    code: "fr",
+    index_title: "ESP32-C3 Scanner WiFi et contrôleur LED",
+    index_heading: "Services ESP32-C3",
+    index_scanner: "Scanner WiFi actif en tâche de fond",
+    index_api: "API HTTP prête, avec contrôle de la LED",
+    no_body: "pas de corps",
+    index_requests: "requêtes dans la dernière heure",
+    index_errors: "erreurs",
+    portal_title: "Connexion WiFi",
+    portal_heading: "Connectez-vous pour continuer",
+    portal_username: "Identifiant",
+    portal_password: "Mot de passe",
+    portal_submit: "Se connecter",
+    portal_rejected: "Identifiants invalides, veuillez réessayer",
+    portal_channel: "Point d'accès sur le canal",
+};
+
+const LANGUAGES: &[&Strings] = &[&EN, &FR];
+
+/// Picks the language from an explicit `lang` query parameter, then from the
+/// `Accept-Language` header by quality, falling back to English.
+pub fn negotiate(lang: Option<&str>, accept_language: Option<&str>) -> &'static Strings {
+    if let Some(strings) = lang.and_then(find) {
+        return strings;
+    }
+
+    let mut best: Option<(&'static Strings, f32)> = None;
+    for range in accept_language.unwrap_or_default().split(',') {
+        let mut parts = range.split(';');
+        let tag = parts.next().unwrap_or_default().trim();
+        let quality = parts
+            .find_map(|param| param.trim().strip_prefix("q="))
+            .and_then(|quality| quality.parse().ok())
+            .unwrap_or(1.0);
+
+        let Some(strings) = find(tag).filter(|_| quality > 0.0) else {
+            continue;
+        };
+        match best {
+            Some((_, best_quality)) if best_quality >= quality => {}
+            _ => best = Some((strings, quality)),
+        }
+    }
+
+    best.map(|(strings, _)| strings).unwrap_or(&EN)
+}
+
+/// Matches on the primary subtag, so `fr-CA` gets French.
+fn find(tag: &str) -> Option<&'static Strings> {
+    let primary = tag.split('-').next().unwrap_or_default();
+    LANGUAGES
+        .iter()
+        .copied()
+        .find(|strings| strings.code.eq_ignore_ascii_case(primary))
+}
@@ -0,0 +1,272 @@
+//! Gateway latency monitor. Pings the gateway, then any extra hosts, every
+//! `interval_secs` and keeps the recent probes of each, from which the link
+//! health is derived. Health changes go out on the event bus, so rules can show
+//! them on the LED.
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::config::{ConfigStore, LatencyMonitorSettings};
+use crate::diag;
+use crate::events::{Event, EventBus};
+use crate::honeypot::uptime_secs;
+use crate::wifi::{self, WifiLink};
+
+/// Probes kept per host.
+const MAX_PROBES: usize = 20;
+const PINGS_PER_PROBE: u32 = 3;
+/// How often a disabled monitor looks at its settings again.
+const DISABLED_POLL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LinkHealth {
+    /// Not probed yet, or the monitor is off.
+    Unknown,
+    Good,
+    /// Losing packets or slow, see the thresholds in the settings.
+    Degraded,
+    /// Disconnected, or the gateway did not answer the last probe.
+    Down,
+}
+
+impl LinkHealth {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LinkHealth::Unknown => "unknown",
+            LinkHealth::Good => "good",
+            LinkHealth::Degraded => "degraded",
+            LinkHealth::Down => "down",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Probe {
+    transmitted: u32,
+    received: u32,
+    avg_ms: Option<f32>,
+}
+
+struct HostProbes {
+    host: String,
+    probes: VecDeque<Probe>,
+    last_error: Option<String>,
+    last_uptime_secs: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HostStats {
+    pub host: String,
+    pub probes: usize,
+    pub loss_percent: Option<f32>,
+    pub avg_ms: Option<f32>,
+    pub min_ms: Option<f32>,
+    pub max_ms: Option<f32>,
+    pub last_ms: Option<f32>,
+    /// Why the last probe could not be sent, e.g. a name that does not resolve.
+    pub last_error: Option<String>,
+    pub last_uptime_secs: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LatencyReport {
+    pub health: LinkHealth,
+    /// The gateway first.
+    pub hosts: Vec<HostStats>,
+}
+
+struct State {
+    health: LinkHealth,
+    gateway: Option<HostProbes>,
+    extra: Vec<HostProbes>,
+}
+
+pub struct LatencyMonitor {
+    config: Arc<ConfigStore>,
+    wifi_link: Arc<WifiLink>,
+    events: Arc<EventBus>,
+    state: Mutex<State>,
+}
+
+impl LatencyMonitor {
+    pub fn new(config: Arc<ConfigStore>, wifi_link: Arc<WifiLink>, events: Arc<EventBus>) -> Self {
+        LatencyMonitor {
+            config,
+            wifi_link,
+            events,
+            state: Mutex::new(State {
+                health: LinkHealth::Unknown,
+                gateway: None,
+                extra: Vec::new(),
+            }),
+        }
+    }
+
+    /// Probes forever.
+    pub fn run(&self) {
+        loop {
+            let settings = self.config.get().latency_monitor;
+            if !settings.enabled || settings.validate().is_err() {
+                self.set_health(LinkHealth::Unknown);
+                thread::sleep(DISABLED_POLL);
+                continue;
+            }
+
+            self.probe(&settings);
+            thread::sleep(Duration::from_secs(settings.interval_secs as u64));
+        }
+    }
+
+    pub fn report(&self) -> LatencyReport {
+        let state = self.state.lock().unwrap();
+        LatencyReport {
+            health: state.health,
+            hosts: state.gateway.iter().chain(&state.extra).map(HostProbes::stats).collect(),
+        }
+    }
+
+    /// Lines for the plain-text `/status` report.
+    pub fn status_lines(&self) -> String {
+        let report = self.report();
+        let mut line = format!("link_health: {}", report.health.as_str());
+        if let Some(gateway) = report.hosts.first() {
+            if let (Some(avg_ms), Some(loss_percent)) = (gateway.avg_ms, gateway.loss_percent) {
+                line.push_str(&format!(" (gateway {:.1} ms, {:.0}% loss)", avg_ms, loss_percent));
+            }
+        }
+        line.push('\n');
+        line
+    }
+
+    fn probe(&self, settings: &LatencyMonitorSettings) {
+        let gateway = wifi::sta_gateway().filter(|_| self.wifi_link.is_connected());
+        let gateway_probe = gateway.map(|gateway| (gateway.to_string(), ping(&gateway.to_string())));
+        // Pinging takes a while, so the state is not held meanwhile
+        let extra_probes: Vec<(String, Result<Probe, String>)> = settings
+            .extra_hosts
+            .iter()
+            .map(|host| (host.clone(), ping(host)))
+            .collect();
+
+        let mut state = self.state.lock().unwrap();
+        match gateway_probe {
+            Some((host, probe)) => {
+                if state.gateway.as_ref().map(|gateway| &gateway.host) != Some(&host) {
+                    state.gateway = Some(HostProbes::new(host));
+                }
+                if let Some(gateway) = state.gateway.as_mut() {
+                    gateway.record(probe);
+                }
+            }
+            None => state.gateway = None,
+        }
+
+        state.extra.retain(|probes| settings.extra_hosts.contains(&probes.host));
+        for (host, probe) in extra_probes {
+            let index = match state.extra.iter().position(|probes| probes.host == host) {
+                Some(index) => index,
+                None => {
+                    state.extra.push(HostProbes::new(host));
+                    state.extra.len() - 1
+                }
+            };
+            state.extra[index].record(probe);
+        }
+
+        let health = match &state.gateway {
+            None => LinkHealth::Down,
+            Some(gateway) => gateway.health(settings),
+        };
+        drop(state);
+        self.set_health(health);
+    }
+
+    fn set_health(&self, health: LinkHealth) {
+        let mut state = self.state.lock().unwrap();
+        if state.health == health {
+            return;
+        }
+        log::info!("Link health {} -> {}", state.health.as_str(), health.as_str());
+        state.health = health;
+        drop(state);
+
+        self.events.publish(Event::LinkHealthChanged { health });
+    }
+}
+
+impl HostProbes {
+    fn new(host: String) -> Self {
+        HostProbes {
+            host,
+            probes: VecDeque::new(),
+            last_error: None,
+            last_uptime_secs: 0,
+        }
+    }
+
+    fn record(&mut self, probe: Result<Probe, String>) {
+        self.last_uptime_secs = uptime_secs();
+        match probe {
+            Ok(probe) => {
+                self.last_error = None;
+                if self.probes.len() == MAX_PROBES {
+                    self.probes.pop_front();
+                }
+                self.probes.push_back(probe);
+            }
+            Err(e) => self.last_error = Some(e),
+        }
+    }
+
+    fn loss_percent(&self) -> Option<f32> {
+        let transmitted: u32 = self.probes.iter().map(|probe| probe.transmitted).sum();
+        let received: u32 = self.probes.iter().map(|probe| probe.received).sum();
+        (transmitted > 0).then(|| (transmitted - received) as f32 * 100.0 / transmitted as f32)
+    }
+
+    fn health(&self, settings: &LatencyMonitorSettings) -> LinkHealth {
+        let Some(last) = self.probes.back().filter(|_| self.last_error.is_none()) else {
+            return LinkHealth::Down;
+        };
+        if last.received == 0 {
+            return LinkHealth::Down;
+        }
+
+        let slow = last.avg_ms.is_some_and(|avg_ms| avg_ms > settings.degraded_rtt_ms);
+        let lossy = self.loss_percent().is_some_and(|loss| loss > settings.degraded_loss_percent);
+        if slow || lossy {
+            LinkHealth::Degraded
+        } else {
+            LinkHealth::Good
+        }
+    }
+
+    fn stats(&self) -> HostStats {
+        let round_trips: Vec<f32> = self.probes.iter().filter_map(|probe| probe.avg_ms).collect();
+        HostStats {
+            host: self.host.clone(),
+            probes: self.probes.len(),
+            loss_percent: self.loss_percent(),
+            avg_ms: (!round_trips.is_empty()).then(|| round_trips.iter().sum::<f32>() / round_trips.len() as f32),
+            min_ms: round_trips.iter().copied().reduce(f32::min),
+            max_ms: round_trips.iter().copied().reduce(f32::max),
+            last_ms: self.probes.back().and_then(|probe| probe.avg_ms),
+            last_error: self.last_error.clone(),
+            last_uptime_secs: self.last_uptime_secs,
+        }
+    }
+}
+
+fn ping(host: &str) -> Result<Probe, String> {
+    diag::ping(host, PINGS_PER_PROBE)
+        .map(|report| Probe {
+            transmitted: report.transmitted,
+            received: report.received,
+            avg_ms: report.avg_ms,
+        })
+        .map_err(|e| e.to_string())
+}
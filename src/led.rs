@@ -0,0 +1,153 @@
+use anyhow::Result;
+use esp_idf_hal::ledc::LedcDriver;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::{LedCalibration, LedPowerLimits};
+
+const RAMP_STEP: Duration = Duration::from_millis(10);
+
+struct LedState {
+    /// Red, green and blue, or a single channel for a one-color LED.
+    channels: Vec<LedcDriver<'static>>,
+    /// Last requested color, before calibration, so a new calibration can be re-applied.
+    color: [u8; 3],
+    calibration: LedCalibration,
+    limits: LedPowerLimits,
+}
+
+/// The LED on one to three LEDC channels.
+///
+/// Colors are 8 bits per channel; the calibration gains and the power limits are
+/// applied before the conversion to a duty cycle. A single-channel LED shows the
+/// brightest component of the color, with the red gain.
+pub struct RgbLed {
+    state: Mutex<LedState>,
+    held_until: Mutex<Option<Instant>>,
+}
+
+impl RgbLed {
+    pub fn new(channels: Vec<LedcDriver<'static>>, calibration: LedCalibration, limits: LedPowerLimits) -> Self {
+        RgbLed {
+            state: Mutex::new(LedState {
+                channels,
+                color: [0, 0, 0],
+                calibration,
+                limits,
+            }),
+            held_until: Mutex::new(None),
+        }
+    }
+
+    pub fn set(&self, red: u8, green: u8, blue: u8) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.color = [red, green, blue];
+        state.apply()
+    }
+
+    /// Last color set, before calibration and power limits.
+    pub fn color(&self) -> [u8; 3] {
+        self.state.lock().unwrap().color
+    }
+
+    /// An animation frame, dropped while the LED is held.
+    pub fn animate(&self, red: u8, green: u8, blue: u8) -> Result<()> {
+        if self.is_held() {
+            return Ok(());
+        }
+        self.set(red, green, blue)
+    }
+
+    /// Keeps animations off the LED for `duration`, so a requested color is not
+    /// overdrawn by the next frame.
+    pub fn hold(&self, duration: Duration) {
+        *self.held_until.lock().unwrap() = Some(Instant::now() + duration);
+    }
+
+    pub fn is_held(&self) -> bool {
+        self.held_until.lock().unwrap().is_some_and(|until| Instant::now() < until)
+    }
+
+    pub fn calibration(&self) -> LedCalibration {
+        self.state.lock().unwrap().calibration
+    }
+
+    pub fn set_calibration(&self, calibration: LedCalibration) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.calibration = calibration;
+        state.apply()
+    }
+
+    pub fn power_limits(&self) -> LedPowerLimits {
+        self.state.lock().unwrap().limits
+    }
+
+    pub fn set_power_limits(&self, limits: LedPowerLimits) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.limits = limits;
+        state.apply()
+    }
+}
+
+impl LedState {
+    fn apply(&mut self) -> Result<()> {
+        let targets = self.target_duties();
+
+        // Dimming never draws more current, so decreases go out at once
+        for (channel, target) in self.channels.iter_mut().zip(targets.iter().copied()) {
+            if target < channel.get_duty() {
+                channel.set_duty(target)?;
+            }
+        }
+
+        loop {
+            let mut rising = false;
+
+            for (channel, target) in self.channels.iter_mut().zip(targets.iter().copied()) {
+                let duty = channel.get_duty();
+                if duty < target {
+                    let max_rise = (channel.get_max_duty() * self.limits.max_rise_percent as u32 / 100).max(1);
+                    channel.set_duty(target.min(duty + max_rise))?;
+                    rising |= duty + max_rise < target;
+                }
+            }
+
+            if !rising {
+                return Ok(());
+            }
+            thread::sleep(RAMP_STEP);
+        }
+    }
+
+    /// Calibrated duties, scaled down together when they exceed the power budget so
+    /// the hue is kept.
+    fn target_duties(&self) -> Vec<u32> {
+        let gains = self.calibration.gains();
+        let levels = match self.channels.len() {
+            1 => vec![self.color.iter().copied().max().unwrap_or(0)],
+            _ => self.color.to_vec(),
+        };
+
+        let mut duties = Vec::with_capacity(self.channels.len());
+        let mut total = 0.0;
+        let mut total_max = 0.0;
+
+        for ((channel, level), gain) in self.channels.iter().zip(levels).zip(gains) {
+            let max_duty = channel.get_max_duty() as f32;
+            let duty = level as f32 * gain * max_duty / 255.0;
+            duties.push(duty);
+            total += duty;
+            total_max += max_duty;
+        }
+
+        let budget = total_max * self.limits.max_combined_percent as f32 / 100.0;
+        let scale = if total > budget { budget / total } else { 1.0 };
+
+        self.channels
+            .iter()
+            .zip(duties)
+            .map(|(channel, duty)| ((duty * scale).round() as u32).min(channel.get_max_duty()))
+            .collect()
+    }
+}
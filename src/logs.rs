@@ -0,0 +1,171 @@
+//! Console logger that also keeps the most recent lines for the support report
+//! and the log stream, and optionally forwards records to a syslog server.
+
+use esp_idf_svc::log::EspLogger;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::collections::VecDeque;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::SyslogSettings;
+use crate::honeypot::uptime_secs;
+
+pub const MAX_LINES: usize = 100;
+/// Records with this target are written to the serial console only, never to
+/// the buffer or syslog: `log::info!(target: logs::CONSOLE_ONLY, ...)`. For
+/// secrets such as the pairing code.
+pub const CONSOLE_ONLY: &str = "console";
+/// Longer lines are cut, so the buffer stays bounded whatever gets logged.
+const MAX_LINE_LEN: usize = 200;
+/// Between two attempts to resolve a syslog server that did not resolve.
+const RESOLVE_RETRY: Duration = Duration::from_secs(30);
+/// `local0`.
+const SYSLOG_FACILITY: u8 = 16;
+const SYSLOG_APP_NAME: &str = "esp32";
+
+static SYSLOG: Mutex<Option<Syslog>> = Mutex::new(None);
+
+static LOGGER: RecentLogs = RecentLogs {
+    console: EspLogger::new(),
+    lines: Mutex::new(Lines {
+        next_seq: 0,
+        lines: VecDeque::new(),
+    }),
+};
+
+struct Lines {
+    /// Sequence number of the next line logged.
+    next_seq: u64,
+    lines: VecDeque<String>,
+}
+
+struct RecentLogs {
+    console: EspLogger,
+    lines: Mutex<Lines>,
+}
+
+struct Syslog {
+    socket: UdpSocket,
+    server: String,
+    port: u16,
+    address: Option<SocketAddr>,
+    next_resolve: Instant,
+    level: LevelFilter,
+    hostname: String,
+}
+
+impl Syslog {
+    /// Resolved on first use, since the network may not be up yet.
+    fn address(&mut self) -> Option<SocketAddr> {
+        if self.address.is_none() && Instant::now() >= self.next_resolve {
+            self.address = (self.server.as_str(), self.port)
+                .to_socket_addrs()
+                .ok()
+                .and_then(|mut addresses| addresses.find(SocketAddr::is_ipv4));
+            self.next_resolve = Instant::now() + RESOLVE_RETRY;
+        }
+        self.address
+    }
+
+    fn send(&mut self, record: &Record) {
+        let Some(address) = self.address() else {
+            return;
+        };
+        let severity = match record.level() {
+            Level::Error => 3,
+            Level::Warn => 4,
+            Level::Info => 6,
+            Level::Debug | Level::Trace => 7,
+        };
+        // No timestamp, the server stamps messages on receipt
+        let message = format!(
+            "<{}>1 - {} {} - {} - {}",
+            SYSLOG_FACILITY * 8 + severity,
+            self.hostname,
+            SYSLOG_APP_NAME,
+            record.target().replace(' ', "_"),
+            record.args()
+        );
+        // Nothing is logged on failure, that would come straight back here
+        let _ = self.socket.send_to(message.as_bytes(), address);
+    }
+}
+
+/// Starts, reconfigures or stops forwarding to syslog. An empty `hostname` is
+/// sent as the nil value.
+pub fn set_syslog(settings: &SyslogSettings, hostname: &str) {
+    let syslog = match settings.enabled {
+        false => None,
+        true => match UdpSocket::bind("0.0.0.0:0") {
+            Ok(socket) => Some(Syslog {
+                socket,
+                server: settings.server.clone(),
+                port: settings.port,
+                address: None,
+                next_resolve: Instant::now(),
+                level: settings.level.filter(),
+                hostname: if hostname.is_empty() { "-".to_string() } else { hostname.to_string() },
+            }),
+            Err(e) => {
+                log::error!("Failed to open the syslog socket: {:?}", e);
+                None
+            }
+        },
+    };
+    *SYSLOG.lock().unwrap() = syslog;
+}
+
+/// Replaces `EspLogger::initialize_default`.
+pub fn init() {
+    log::set_logger(&LOGGER).map(|()| LOGGER.console.initialize()).unwrap();
+}
+
+/// Oldest first.
+pub fn recent() -> Vec<String> {
+    LOGGER.lines.lock().unwrap().lines.iter().cloned().collect()
+}
+
+/// Lines with a sequence number of `first` and up still in the buffer, oldest
+/// first, with the sequence number of the first one.
+pub fn since(first: u64) -> (u64, Vec<String>) {
+    let lines = LOGGER.lines.lock().unwrap();
+    let oldest = lines.next_seq - lines.lines.len() as u64;
+    let first = first.clamp(oldest, lines.next_seq);
+    (first, lines.lines.iter().skip((first - oldest) as usize).cloned().collect())
+}
+
+impl Log for RecentLogs {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.console.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        self.console.log(record);
+        if !self.enabled(record.metadata()) || record.target() == CONSOLE_ONLY {
+            return;
+        }
+
+        if let Some(syslog) = SYSLOG.lock().unwrap().as_mut().filter(|syslog| record.level() <= syslog.level) {
+            syslog.send(record);
+        }
+
+        let mut line = format!("{} {} {}: {}", uptime_secs(), record.level(), record.target(), record.args());
+        if line.len() > MAX_LINE_LEN {
+            let mut end = MAX_LINE_LEN;
+            while !line.is_char_boundary(end) {
+                end -= 1;
+            }
+            line.truncate(end);
+        }
+
+        let mut lines = self.lines.lock().unwrap();
+        if lines.lines.len() == MAX_LINES {
+            lines.lines.pop_front();
+        }
+        lines.lines.push_back(line);
+        lines.next_seq += 1;
+    }
+
+    fn flush(&self) {}
+}
@@ -1,200 +1,644 @@
+mod alerts;
+mod ap;
+mod clock;
+mod codec;
+mod channels;
+mod config;
+mod confirm;
+mod cpu;
+mod deauth;
+mod diag;
+mod energy;
+mod errors;
+mod espnow;
+mod files;
+mod framestats;
+#[cfg(any(feature = "display", feature = "sim"))]
+mod display;
+mod events;
+mod gzip;
+mod handlers;
+mod heap;
+mod history;
+mod honeypot;
+mod httpstats;
+mod i18n;
+mod latency;
+mod led;
+mod logs;
+mod netstats;
+mod ota;
+mod page;
+mod pairing;
+mod pcap;
+mod pintest;
+mod probes;
+mod provisioning;
+mod routes;
+mod rules;
+mod safemode;
 mod scan;
-
+mod sessions;
+mod sleep;
+mod sniffer;
+mod startup;
+mod storage;
+mod sysinfo;
+mod timeline;
+mod tls;
+mod txlog;
+mod tracker;
+mod twins;
+mod version;
+mod watch;
+mod webhook;
+mod wifi;
+mod ws;
+
+use parsers::{color, form};
 use std::time::Duration;
-use embedded_svc::wifi::{Configuration, AuthMethod};
-use esp_idf_svc::wifi::AsyncWifi;
-use esp_idf_svc::wifi::EspWifi;
 use log::info;
-use esp_idf_hal::peripheral::Peripheral;
-use esp_idf_svc::timer::{EspTimerService, Task};
 use esp_idf_svc::eventloop::EspSystemEventLoop;
-use esp_idf_svc::ping::EspPing;
-use anyhow::Result;
+use esp_idf_svc::sntp::EspSntp;
+use anyhow::{Context, Result};
 use esp_idf_hal::{prelude::Peripherals};
 use esp_idf_svc::{timer::EspTaskTimerService, nvs::EspDefaultNvsPartition};
-use esp_idf_svc::nvs::EspNvsPartition;
-use esp_idf_svc::nvs::NvsDefault;
-use embedded_svc::wifi::{ClientConfiguration};
-use esp_idf_svc::{http::server::EspHttpServer};
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::handle::RawHandle;
+use esp_idf_svc::wifi::Configuration;
 use std::sync::{Arc, Mutex};
-use esp_idf_hal::gpio::PinDriver;
-use embedded_svc::{ http::Method::Post, io::Read};
+use esp_idf_hal::gpio::{AnyOutputPin, PinDriver};
+use embedded_svc::http::Method::Post;
 use esp_idf_hal::units::*;
 use esp_idf_hal::{ledc::{LedcTimerDriver, config::TimerConfig, LedcDriver}};
 
-use crate::scan::{scan_wifi_with_resources, scan_networks_continuously};
-
-
-
-#[derive(Debug, Clone)]
-struct Color {
-    r: u8,
-    g: u8,
-    b: u8,
-}
-
-impl TryFrom<&str> for Color {
-    type Error = anyhow::Error;
-
-    fn try_from(value: &str) -> Result<Self, Self::Error> {
-        Ok(Color {
-            r: u8::from_str_radix(value.get(0..2).unwrap(), 16)?,
-            g: u8::from_str_radix(value.get(2..4).unwrap(), 16)?,
-            b: u8::from_str_radix(value.get(4..6).unwrap(), 16)?,
-        })
-    }
-}
-
+use crate::alerts::AlertLog;
+use crate::ap::ApClients;
+use crate::deauth::DeauthDetector;
+use crate::energy::EnergyMeter;
+use crate::espnow::EspNowLink;
+use crate::files::FileStore;
+use crate::framestats::FrameStats;
+use crate::events::EventBus;
+use crate::handlers::AppState;
+use crate::heap::HeapMonitor;
+use crate::history::ScanHistory;
+use crate::honeypot::Honeypot;
+use crate::httpstats::HttpStats;
+use crate::clock::Clock;
+use crate::cpu::CpuMonitor;
+use crate::config::{ConfigStore, Hostname, HttpSettings, LedOutput, ScannerSettings, StaAddressing, WifiCountry, WifiMode};
+use crate::confirm::Confirmations;
+use crate::latency::LatencyMonitor;
+use crate::led::RgbLed;
+use crate::netstats::NetworkStats;
+use crate::pairing::Pairing;
+use crate::scan::{scan_wifi_with_resources, scan_networks_continuously, LatestScan};
+use crate::probes::ProbeLog;
+use crate::rules::RulesEngine;
+use crate::safemode::BootGuard;
+use crate::sessions::HttpSessions;
+use crate::sleep::IdleSleep;
+use crate::startup::Startup;
+use crate::timeline::Timeline;
+use crate::ota::OtaPuller;
+use crate::tls::TlsStore;
+use crate::txlog::TxLog;
+use crate::twins::TwinDetector;
+use crate::watch::NetworkWatch;
+use crate::tracker::BssidTracker;
+use crate::wifi::{connect, wifi, SharedWifi, WifiLink};
+use crate::ws::WsClients;
+
+/// GPIOs owned by other subsystems, which the LED must not be configured on.
+#[cfg(any(feature = "display", feature = "sim"))]
+const CLAIMED_PINS: &[u8] = &[6, 7, 9];
+#[cfg(not(any(feature = "display", feature = "sim")))]
+const CLAIMED_PINS: &[u8] = &[];
 
 fn main() {
     // It is necessary to call this function once. Otherwise some patches to the runtime
     // implemented by esp-idf-sys might not link properly. See https://github.com/esp-rs/esp-idf-template/issues/71
     esp_idf_svc::sys::link_patches();
 
-    esp_idf_svc::log::EspLogger::initialize_default();
+    logs::init();
 
     log::info!("Starting dual-service ESP32 application...");
 
     log::info!("Taking peripherals in main...");
     let peripherals = Peripherals::take().unwrap();
     log::info!("Peripherals taken successfully");
-    
-    log::info!("Taking NVS partition in main...");
-    let nvs = EspDefaultNvsPartition::take().unwrap();
-    log::info!("NVS partition taken successfully");
-    
-    log::info!("Taking system event loop in main...");
-    let sys_loop = EspSystemEventLoop::take().unwrap();
-    log::info!("System event loop taken successfully");
-    
+
+    let startup = Arc::new(Startup::new());
+
+    let nvs = startup.run("nvs", &[], 3, || Ok(EspDefaultNvsPartition::take()?));
+
+    let boot = match &nvs {
+        Some(nvs) => BootGuard::new(nvs.clone()).unwrap_or_else(|e| {
+            log::error!("Failed to count crash resets: {:?}", e);
+            BootGuard::in_memory()
+        }),
+        None => BootGuard::in_memory(),
+    };
+    log::info!("{}", boot.banner());
+
+    let config = Arc::new(match &nvs {
+        Some(nvs) => ConfigStore::new(nvs.clone()).unwrap_or_else(|e| {
+            log::error!("Failed to open configuration store: {:?}", e);
+            ConfigStore::in_memory()
+        }),
+        None => ConfigStore::in_memory(),
+    });
+    match config.get().syslog {
+        syslog if syslog.validate().is_ok() => logs::set_syslog(&syslog, &config.get().hostname.name),
+        syslog => log::error!("Invalid syslog settings {:?}, not forwarding logs", syslog),
+    }
+
+    let files = startup.run("files", &[], 1, FileStore::mount).map(Arc::new);
+
+    let events = Arc::new(EventBus::new());
+
+    let timeline = Arc::new(Timeline::new());
+    let timeline_events = events.subscribe(timeline::EVENT_QUEUE);
+    let timeline_recorder = timeline.clone();
+    let _timeline_thread = cpu::spawn(c"timeline", None, move || timeline_recorder.run(timeline_events));
+
+    let alerts = Arc::new(match &nvs {
+        Some(nvs) => AlertLog::new(nvs.clone(), events.clone()).unwrap_or_else(|e| {
+            log::error!("Failed to open alert log: {:?}", e);
+            AlertLog::in_memory(events.clone())
+        }),
+        None => AlertLog::in_memory(events.clone()),
+    });
+    alerts.check_reset_reason();
+
+    let pairing = Arc::new(match &nvs {
+        Some(nvs) => Pairing::new(nvs.clone()).unwrap_or_else(|e| {
+            log::error!("Failed to open pairing state: {:?}", e);
+            Pairing::in_memory()
+        }),
+        None => Pairing::in_memory(),
+    });
+    if let Some(code) = pairing.code() {
+        log::info!(target: logs::CONSOLE_ONLY, "Not paired yet, pairing code: {}", code);
+    }
+
+    let network_stats = Arc::new(match &nvs {
+        Some(nvs) => NetworkStats::new(nvs.clone()).unwrap_or_else(|e| {
+            log::error!("Failed to open network statistics: {:?}", e);
+            NetworkStats::in_memory()
+        }),
+        None => NetworkStats::in_memory(),
+    });
+
+    let sys_loop = startup.run("event_loop", &[], 3, || Ok(EspSystemEventLoop::take()?));
+
     let timer_service = EspTaskTimerService::new().unwrap();
 
+    let tracker = Arc::new(BssidTracker::new());
+
+    let ap_clients = Arc::new(ApClients::new());
+    let _ap_clients_subscription = sys_loop.as_ref().and_then(|sys_loop| {
+        ap_clients
+            .subscribe(sys_loop)
+            .map_err(|e| log::error!("Failed to track AP clients: {:?}", e))
+            .ok()
+    });
+
+    let _network_stats_subscription = sys_loop.as_ref().and_then(|sys_loop| {
+        network_stats
+            .subscribe(sys_loop)
+            .map_err(|e| log::error!("Failed to track network statistics: {:?}", e))
+            .ok()
+    });
+
+    let honeypot = Arc::new(match &nvs {
+        Some(nvs) => Honeypot::new(nvs.clone()).unwrap_or_else(|e| {
+            log::error!("Failed to open honeypot findings: {:?}", e);
+            Honeypot::in_memory()
+        }),
+        None => Honeypot::in_memory(),
+    });
+    let _honeypot_subscription = match (config.get().wifi_mode, sys_loop.as_ref()) {
+        (WifiMode::Honeypot, Some(sys_loop)) => {
+            log::warn!("Honeypot mode enabled: recording AP associations and portal logins");
+            honeypot
+                .subscribe(sys_loop)
+                .map_err(|e| log::error!("Failed to start honeypot logging: {:?}", e))
+                .ok()
+        }
+        _ => None,
+    };
+
     log::info!("Setting up LED hardware...");
     let led_timer = peripherals.ledc.timer0;
     let led_timer_driver = LedcTimerDriver::new(led_timer, &TimerConfig::new().frequency(1000.Hz())).unwrap();
     
-    let red_channel = Arc::new(Mutex::new(LedcDriver::new(peripherals.ledc.channel0, &led_timer_driver, peripherals.pins.gpio3).unwrap()));
-    let green_channel = Arc::new(Mutex::new(LedcDriver::new(peripherals.ledc.channel1, &led_timer_driver, peripherals.pins.gpio4).unwrap()));
-    let blue_channel = Arc::new(Mutex::new(LedcDriver::new(peripherals.ledc.channel2, &led_timer_driver, peripherals.pins.gpio5).unwrap()));
+    let led_config = config.get();
+    let led_output = match led_config.led_output.validate(CLAIMED_PINS) {
+        Ok(()) => led_config.led_output.clone(),
+        Err(e) => {
+            log::error!("Invalid LED pins {:?}, using the defaults: {}", led_config.led_output.pins, e);
+            LedOutput::default()
+        }
+    };
+    log::info!("LED on GPIO {:?}", led_output.pins);
+
+    let mut ledc_channel0 = Some(peripherals.ledc.channel0);
+    let mut ledc_channel1 = Some(peripherals.ledc.channel1);
+    let mut ledc_channel2 = Some(peripherals.ledc.channel2);
+    let mut led_channels = Vec::new();
+    for (index, pin) in led_output.pins.iter().enumerate() {
+        // Safety: validate() only lets through free GPIOs that no other driver claims
+        let pin = unsafe { AnyOutputPin::new(*pin as i32) };
+        let channel = match index {
+            0 => LedcDriver::new(ledc_channel0.take().unwrap(), &led_timer_driver, pin),
+            1 => LedcDriver::new(ledc_channel1.take().unwrap(), &led_timer_driver, pin),
+            _ => LedcDriver::new(ledc_channel2.take().unwrap(), &led_timer_driver, pin),
+        };
+        led_channels.push(channel.unwrap());
+    }
+    let led = Arc::new(RgbLed::new(led_channels, led_config.led_calibration, led_config.led_power));
+    if boot.safe_mode() {
+        let (red, green, blue) = safemode::LED_COLOR;
+        if let Err(e) = led.set(red, green, blue) {
+            log::error!("Failed to show safe mode on the LED: {:?}", e);
+        }
+    }
 
     log::info!("Setting up WiFi connection for API...");
-    let _wifi_for_api = wifi(peripherals.modem, sys_loop.clone(), Some(nvs), timer_service).unwrap();
-
-    let sys_loop_clone = sys_loop.clone();
-    let red_channel_scanner = red_channel.clone();
-    let green_channel_scanner = green_channel.clone();
-    
-    let _scanner_thread = std::thread::spawn(move || {
-        log::info!("Starting WiFi scanner thread...");
-        scan_networks_continuously(sys_loop_clone, red_channel_scanner, green_channel_scanner);
+    let sta_addressing = match config.get().sta_addressing {
+        addressing if addressing.validate().is_ok() => addressing,
+        addressing => {
+            log::error!("Invalid station addressing {:?}, using DHCP", addressing);
+            StaAddressing::Dhcp
+        }
+    };
+    let hostname = match config.get().hostname {
+        hostname if hostname.validate().is_ok() => hostname,
+        hostname => {
+            log::error!("Invalid hostname {:?}, using the default", hostname);
+            Hostname::default()
+        }
+    };
+    let wifi_country = match config.get().wifi_country {
+        country if country.validate().is_ok() => country,
+        country => {
+            log::error!("Invalid WiFi country {:?}, using the world-wide safe domain", country);
+            WifiCountry::default()
+        }
+    };
+    let mut modem = Some(peripherals.modem);
+    let mut wifi_for_api = startup.run("wifi", &["event_loop"], 1, || {
+        let modem = modem.take().context("modem already claimed")?;
+        let sys_loop = sys_loop.clone().context("system event loop unavailable")?;
+        wifi(modem, sys_loop, nvs.clone(), timer_service.clone(), &sta_addressing, &hostname, &wifi_country)
     });
 
-    log::info!("Setting up HTTP server...");
-    let mut server = EspHttpServer::new(&Default::default()).unwrap();
-
-    server.fn_handler("/", embedded_svc::http::Method::Get, |req| {
-        let mut response = req.into_ok_response().unwrap();
-        let html = r#"
-<!DOCTYPE html>
-<html>
-<head><title>ESP32-C3 WiFi Scanner & LED Controller</title></head>
-<body>
-    <h1>ESP32-C3 Services</h1>
-    <p>WiFi Scanner running in background thread</p>
-    <p>HTTP API ready with LED control</p>
-    <p><a href="/status">Status</a></p>
-    <p>POST to /color with 6-byte hex color (e.g., FF0000 for red)</p>
-</body>
-</html>
-        "#;
-        response.write(html.as_bytes()).unwrap();
-        Ok::<_, anyhow::Error>(())
-    }).unwrap();
-
-    server.fn_handler("/status", embedded_svc::http::Method::Get, |req| {
-        let mut response = req.into_ok_response().unwrap();
-        let status = "WiFi Scanner: Active\nHTTP API: Active\nLED Controller: Ready";
-        response.write(status.as_bytes()).unwrap();
-        Ok::<_, anyhow::Error>(())
-    }).unwrap();
-
-    server.fn_handler("/color", embedded_svc::http::Method::Post, move |mut req| {
-        let mut buffer = [0_u8; 6];
-        req.read_exact(&mut buffer)?;
-        let color: Color = std::str::from_utf8(&buffer)?.try_into()?;
-        log::info!("Setting color: {:?}", color);
-        
-        let mut response = req.into_ok_response()?;
-        response.write("Color set successfully".as_bytes())?;
-        
-        red_channel.lock().unwrap().set_duty(color.r as u32).unwrap();
-        green_channel.lock().unwrap().set_duty(color.g as u32).unwrap();
-        blue_channel.lock().unwrap().set_duty(color.b as u32).unwrap();
-        
-        Ok::<_, anyhow::Error>(())
-    }).unwrap();
-
-    log::info!("HTTP server started successfully");
-    log::info!("Both WiFi scanner and HTTP API are now running in parallel");
-
-    loop {
-        std::thread::sleep(Duration::from_secs(5));
-        log::info!("Main thread alive - services running");
+    if let Some(wifi) = wifi_for_api.as_mut() {
+        startup.run("wifi_link", &["wifi"], 5, || connect(wifi, &config.get(), &network_stats));
+    }
+    if startup.is_up("wifi_link") && config.get().wifi_mode.joins_network() {
+        if let Err(e) = wifi::set_power_save(config.get().wifi_power_save) {
+            log::error!("Failed to set the WiFi power save mode: {:?}", e);
+        }
     }
-}
-
 
-pub fn wifi(
-    modem: impl Peripheral<P = esp_idf_hal::modem::Modem> + 'static,
-    sysloop: EspSystemEventLoop,
-    nvs: Option<EspNvsPartition<NvsDefault>>,
-    timer_service: EspTimerService<Task>,
-) -> Result<AsyncWifi<EspWifi<'static>>> {
-    use futures::executor::block_on;
+    // Safe mode runs WiFi and HTTP only: every optional thread below stays down
+    let probes = Arc::new(ProbeLog::new());
+    let frame_stats = Arc::new(FrameStats::new());
+    if !boot.safe_mode() {
+        let deauth_detector = DeauthDetector::new(config.clone(), events.clone(), alerts.clone());
+        let deauth_frames = sniffer::subscribe(deauth::FRAME_QUEUE);
+        let _deauth_thread = cpu::spawn(c"deauth", None, move || deauth_detector.run(deauth_frames));
 
-    let mut wifi = AsyncWifi::wrap(
-        EspWifi::new(modem, sysloop.clone(), nvs)?,
-        sysloop,
-        timer_service.clone(),
-    )?;
+        let probe_frames = sniffer::subscribe(probes::FRAME_QUEUE);
+        let probe_logger = probes.clone();
+        let _probes_thread = cpu::spawn(c"probes", None, move || probe_logger.run(probe_frames));
 
-    block_on(connect_wifi(&mut wifi))?;
+        let frame_counter = frame_stats.clone();
+        let _frames_thread = cpu::spawn(c"frames", None, move || frame_counter.run());
+    }
 
-    let ip_info = wifi.wifi().sta_netif().get_ip_info()?;
+    if startup.is_up("wifi_link") && config.get().subsystems.sniffer && !boot.safe_mode() {
+        if let Err(e) = sniffer::start() {
+            log::error!("Failed to start the sniffer: {:?}", e);
+        }
+    }
 
-    println!("Wifi DHCP info: {:?}", ip_info);
-    
-    EspPing::default().ping(ip_info.subnet.gateway, &esp_idf_svc::ping::Configuration::default())?;
-    Ok(wifi)
+    let espnow = match boot.safe_mode() {
+        true => None,
+        false => startup
+            .run("espnow", &["wifi_link"], 1, || EspNowLink::new(config.get().wifi_mode))
+            .map(Arc::new),
+    };
+    if let Some(espnow) = &espnow {
+        let espnow_messages = espnow.subscribe(espnow::MESSAGE_QUEUE);
+        let espnow_link = espnow.clone();
+        let espnow_events = events.clone();
+        let _espnow_thread = cpu::spawn(c"espnow", None, move || {
+            espnow_link.run(espnow_messages, &espnow_events)
+        });
+    }
 
-}
+    let wifi_link = Arc::new(WifiLink::new(startup.is_up("wifi_link")));
+    let _wifi_link_subscription = sys_loop.as_ref().and_then(|sys_loop| {
+        wifi_link
+            .subscribe(sys_loop)
+            .map_err(|e| log::error!("Failed to watch the WiFi link: {:?}", e))
+            .ok()
+    });
 
-async fn connect_wifi(wifi: &mut AsyncWifi<EspWifi<'static>>) -> anyhow::Result<()> {
+    let latency = Arc::new(LatencyMonitor::new(config.clone(), wifi_link.clone(), events.clone()));
+    if config.get().wifi_mode.joins_network() && startup.is_up("wifi_link") && !boot.safe_mode() {
+        let latency_monitor = latency.clone();
+        let _latency_thread = cpu::spawn(c"latency", None, move || latency_monitor.run());
+    }
 
-    const SSID: &str = "Wokwi-GUEST";
-    const PASS: &str = "";
+    let sta_ip_info = wifi_for_api
+        .as_ref()
+        .filter(|_| startup.is_up("wifi_link") && config.get().wifi_mode.joins_network())
+        .and_then(|wifi| wifi.wifi().sta_netif().get_ip_info().ok());
+
+    let ap_channel = wifi_for_api
+        .as_ref()
+        .filter(|_| startup.is_up("wifi_link"))
+        .and_then(|wifi| match wifi.get_configuration() {
+            Ok(Configuration::AccessPoint(access_point)) | Ok(Configuration::Mixed(_, access_point)) => {
+                Some(access_point.channel)
+            }
+            _ => None,
+        });
+
+    let provisioning_ip = wifi_for_api.as_ref().filter(|_| startup.is_up("wifi_link")).map(|wifi| {
+        match config.get().wifi_mode {
+            WifiMode::Station | WifiMode::Mixed => wifi.wifi().sta_netif().get_ip_info().ok(),
+            _ => wifi.wifi().ap_netif().get_ip_info().ok(),
+        }
+        .map(|ip_info| ip_info.ip)
+    });
+    let provisioning_qr = |pairing_code: Option<&str>| {
+        let payload = provisioning::payload(&config.get(), provisioning_ip?, pairing_code)?;
+        provisioning::encode(&payload)
+            .map_err(|e| log::error!("Failed to generate provisioning QR code: {:?}", e))
+            .ok()
+    };
+    // Only the console gets the pairing code; the SVG is served to anyone on the network
+    if let Some(qr) = provisioning_qr(pairing.code().as_deref()) {
+        log::info!(target: logs::CONSOLE_ONLY, "Scan to connect:\n{}", provisioning::to_ascii(&qr));
+    }
+    let provisioning_svg = provisioning_qr(None).map(|qr| provisioning::to_svg(&qr));
 
-    let wifi_configuration: Configuration = Configuration::Client(ClientConfiguration {
-        ssid: SSID.try_into().unwrap(),
-        bssid: None,
-        auth_method: AuthMethod::None, // Real AuthMethod will be WPA2Personal
-        password: PASS.try_into().unwrap(),
-        channel: None,
-        ..Default::default()
+    let clock = Arc::new(Clock::new());
+    let _sntp = startup.run("time", &["wifi_link"], 3, || {
+        let sntp_clock = clock.clone();
+        Ok(EspSntp::new_with_callback(&Default::default(), move |_| sntp_clock.sntp_synced())?)
     });
 
-    info!("Wifi configuration: {:?}", wifi_configuration);
+    let latest_scan: LatestScan = Arc::new(Mutex::new(Vec::new()));
+    let scanner_settings = match config.get().scanner {
+        settings if settings.validate().is_ok() => settings,
+        settings => {
+            log::error!("Invalid scanner settings {:?}, using the defaults", settings);
+            ScannerSettings::default()
+        }
+    };
+    let scan_history = Arc::new(ScanHistory::new(scanner_settings.history_samples as usize));
+
+    if !boot.safe_mode() {
+        let network_watch = match &nvs {
+            Some(nvs) => NetworkWatch::new(nvs.clone(), scan_history.clone(), config.clone(), alerts.clone())
+                .unwrap_or_else(|e| {
+                    log::error!("Failed to open known BSSIDs: {:?}", e);
+                    NetworkWatch::in_memory(scan_history.clone(), config.clone(), alerts.clone())
+                }),
+            None => NetworkWatch::in_memory(scan_history.clone(), config.clone(), alerts.clone()),
+        };
+        let watch_events = events.subscribe(watch::EVENT_QUEUE);
+
+        let _watch_thread = cpu::spawn(c"watch", Some(webhook::THREAD_STACK_SIZE), move || {
+            network_watch.run(watch_events)
+        });
+    }
+
+    let twins = Arc::new(TwinDetector::new(latest_scan.clone(), alerts.clone()));
+    if !boot.safe_mode() {
+        let twins_events = events.subscribe(twins::EVENT_QUEUE);
+        let twins_detector = twins.clone();
+        let _twins_thread = cpu::spawn(c"twins", None, move || twins_detector.run(twins_events));
+    }
 
-    wifi.set_configuration(&wifi_configuration)?;
+    let tx_log = Arc::new(match &nvs {
+        Some(nvs) => TxLog::new(nvs.clone(), config.clone()).unwrap_or_else(|e| {
+            log::error!("Failed to open the transmit log: {:?}", e);
+            TxLog::in_memory(config.clone())
+        }),
+        None => TxLog::in_memory(config.clone()),
+    });
+    let ota_puller = Arc::new(OtaPuller::new(config.clone()));
+    if !boot.safe_mode() {
+        let tx_log_events = events.subscribe(txlog::EVENT_QUEUE);
+        let tx_log_recorder = tx_log.clone();
+        let _tx_log_thread = cpu::spawn(c"txlog", None, move || tx_log_recorder.run(tx_log_events));
+
+        let ota_pull_checker = ota_puller.clone();
+        let _ota_thread = cpu::spawn(c"ota", Some(webhook::THREAD_STACK_SIZE), move || ota_pull_checker.run());
+
+        let rules_engine = RulesEngine::new(config.clone(), led.clone(), alerts.clone(), events.clone());
+        let rules_events = events.subscribe(rules::EVENT_QUEUE);
+        let _rules_thread = cpu::spawn(c"rules", Some(webhook::THREAD_STACK_SIZE), move || {
+            rules_engine.run(rules_events)
+        });
+    }
 
-    wifi.start().await?;
-    info!("Wifi started");
+    let shared_wifi: Option<SharedWifi> = wifi_for_api.map(|wifi| Arc::new(Mutex::new(wifi)));
+
+    // The scanner also drives the animations
+    if let Some(wifi) = shared_wifi.clone().filter(|_| !boot.safe_mode()) {
+        let led_scanner = led.clone();
+        let config_scanner = config.clone();
+        let latest_scan_scanner = latest_scan.clone();
+        let events_scanner = events.clone();
+        let tracker_scanner = tracker.clone();
+        let alerts_scanner = alerts.clone();
+        let history_scanner = scan_history.clone();
+
+        let _scanner_thread = cpu::spawn(c"scanner", None, move || {
+            log::info!("Starting WiFi scanner thread...");
+            scan_networks_continuously(
+                wifi,
+                led_scanner,
+                config_scanner,
+                latest_scan_scanner,
+                events_scanner,
+                tracker_scanner,
+                alerts_scanner,
+                history_scanner,
+            );
+        });
+    }
 
-    wifi.connect().await?;
-    info!("Wifi connected");
+    #[cfg(any(feature = "display", feature = "sim"))]
+    if !boot.safe_mode() {
+        let ip = shared_wifi
+            .as_ref()
+            .and_then(|wifi| wifi.lock().unwrap().wifi().sta_netif().get_ip_info().ok())
+            .map(|ip_info| ip_info.ip);
+        let latest_scan_display = latest_scan.clone();
+        let config_display = config.clone();
+        let i2c = peripherals.i2c0;
+        let sda = peripherals.pins.gpio6;
+        let scl = peripherals.pins.gpio7;
+        let button = peripherals.pins.gpio9;
+
+        let _display_thread = cpu::spawn(c"display", None, move || {
+            log::info!("Starting display thread...");
+            if let Err(e) = display::run_display(i2c, sda, scl, button, ip, latest_scan_display, config_display) {
+                log::error!("Display stopped: {:?}", e);
+            }
+        });
+    }
+
+    if config.get().wifi_mode.joins_network() {
+        if let Some(wifi) = shared_wifi.clone() {
+            let link = wifi_link.clone();
+            let config_link = config.clone();
+            let network_stats_link = network_stats.clone();
+
+            let _reconnect_thread = cpu::spawn(c"reconnect", None, move || {
+                log::info!("Starting WiFi reconnect thread...");
+                link.run(wifi, config_link, network_stats_link);
+            });
+        }
+    }
 
-    wifi.wait_netif_up().await?;
-    info!("Wifi netif up");
+    log::info!("Setting up HTTP server...");
+    let http_settings = match config.get().http {
+        settings if settings.validate().is_ok() => settings,
+        settings => {
+            log::error!("Invalid HTTP limits {:?}, using the defaults", settings);
+            HttpSettings::default()
+        }
+    };
+    let tls = Arc::new(match &nvs {
+        Some(nvs) => TlsStore::new(nvs.clone()).unwrap_or_else(|e| {
+            log::error!("Failed to open the TLS store: {:?}", e);
+            TlsStore::in_memory()
+        }),
+        None => TlsStore::in_memory(),
+    });
+    let certificate = match (http_settings.https, config.get().wifi_mode) {
+        (false, _) => None,
+        (true, WifiMode::Honeypot) => {
+            log::warn!("HTTPS disabled in honeypot mode, the captive portal needs plain HTTP");
+            None
+        }
+        (true, _) => match tls.load() {
+            Ok(Some(certificate)) => Some(certificate),
+            Ok(None) => {
+                log::error!("HTTPS enabled but no certificate installed, serving plain HTTP");
+                None
+            }
+            Err(e) => {
+                log::error!("Failed to load the HTTPS certificate, serving plain HTTP: {:?}", e);
+                None
+            }
+        },
+    };
+    let sessions = Arc::new(HttpSessions::new(http_settings));
+    let idle_sleep = Arc::new(IdleSleep::new(config.clone(), sessions.clone()));
+    let ws_clients = Arc::new(WsClients::new(latest_scan.clone(), pairing.clone(), sessions.clone()));
+    let ws_events = events.subscribe(ws::EVENT_QUEUE);
+    let ws_pusher = ws_clients.clone();
+    let _ws_thread = cpu::spawn(c"ws", None, move || ws_pusher.run(ws_events));
+
+    if !boot.safe_mode() {
+        let sleep_policy = idle_sleep.clone();
+        let _sleep_thread = cpu::spawn(c"sleep", None, move || sleep_policy.run());
+    }
+    let energy = Arc::new(EnergyMeter::new(config.get().wifi_mode));
+    let cpu = Arc::new(CpuMonitor::new());
+    let heap = Arc::new(HeapMonitor::new(config.clone()));
+    let heap_monitor = heap.clone();
+    let _heap_thread = cpu::spawn(c"heap", None, move || heap_monitor.run());
+    let app_state = Arc::new(AppState {
+        startup: startup.clone(),
+        boot,
+        config: config.clone(),
+        ap_clients: ap_clients.clone(),
+        honeypot: honeypot.clone(),
+        tracker: tracker.clone(),
+        alerts: alerts.clone(),
+        provisioning_svg,
+        led: led.clone(),
+        led_pins: led_output.pins.clone(),
+        ip_info: sta_ip_info,
+        ap_channel,
+        network_stats: network_stats.clone(),
+        wifi_link: wifi_link.clone(),
+        latency: latency.clone(),
+        sessions: sessions.clone(),
+        http_stats: Arc::new(HttpStats::new(&http_settings)),
+        pairing: pairing.clone(),
+        espnow: espnow.clone(),
+        files: files.clone(),
+        sleep: idle_sleep.clone(),
+        energy: energy.clone(),
+        cpu: cpu.clone(),
+        heap: heap.clone(),
+        clock: clock.clone(),
+        tls: tls.clone(),
+        tx_log: tx_log.clone(),
+        ota_puller: ota_puller.clone(),
+        confirmations: Arc::new(Confirmations::new()),
+        events: events.clone(),
+        timeline: timeline.clone(),
+        latest_scan: latest_scan.clone(),
+        scan_history: scan_history.clone(),
+        twins: twins.clone(),
+        probes: probes.clone(),
+        frame_stats: frame_stats.clone(),
+    });
+    let mut certificate = certificate;
+    let server = startup.run("http", &["wifi"], 3, || {
+        let mut server = match EspHttpServer::new(&routes::server_configuration(&http_settings, certificate)) {
+            // A certificate the server cannot use must not lock everyone out
+            Err(e) if certificate.is_some() => {
+                log::error!("Failed to start the HTTPS server, serving plain HTTP: {:?}", e);
+                certificate = None;
+                EspHttpServer::new(&routes::server_configuration(&http_settings, None))?
+            }
+            server => server?,
+        };
+        routes::register(&mut server, app_state.clone())?;
+        ws_clients.register(&mut server)?;
+        Ok(server)
+    });
+    if server.is_some() && certificate.is_some() {
+        log::info!("Serving HTTPS on port 443");
+        tls.set_active();
+    }
+    let _redirect_server = match (&server, certificate, http_settings.redirect_http) {
+        (Some(_), Some(_), true) => startup.run("http_redirect", &["http"], 1, tls::redirect_server),
+        _ => None,
+    };
+    ota::confirm(&[
+        ("not in safe mode", !app_state.boot.safe_mode()),
+        ("wifi", startup.is_up("wifi")),
+        ("wifi_link", startup.is_up("wifi_link")),
+        ("http", server.is_some()),
+    ]);
+
+    log::info!("Startup finished:\n{}", startup.report());
 
-    Ok(())
-}
\ No newline at end of file
+    loop {
+        std::thread::sleep(Duration::from_secs(5));
+        log::debug!("Main thread alive - services running");
+        network_stats.sample_rssi();
+        energy.sample(&config.get(), idle_sleep.is_asleep());
+        cpu.sample();
+        if let Some(server) = &server {
+            sessions.evict_idle(server.handle());
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use anyhow::Result;
+use esp_idf_svc::eventloop::{EspSubscription, EspSystemEventLoop, System};
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use esp_idf_svc::sys::{esp, esp_wifi_sta_get_rssi};
+use esp_idf_svc::wifi::WifiEvent;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const NAMESPACE: &str = "netstats";
+const STATS_KEY: &str = "ssids";
+/// RSSI samples are folded in memory and only written out every this many samples.
+const RSSI_SAMPLES_PER_WRITE: u32 = 60;
+
+/// Connection quality of one network, accumulated over all boots.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SsidStats {
+    pub connects: u32,
+    pub failures: u32,
+    pub disconnects: u32,
+    pub avg_connect_ms: u32,
+    pub avg_dhcp_ms: u32,
+    pub avg_rssi: Option<f32>,
+    pub rssi_samples: u32,
+}
+
+#[derive(Default)]
+struct StatsState {
+    ssids: BTreeMap<String, SsidStats>,
+    current: Option<String>,
+    unsaved_samples: u32,
+}
+
+/// Per-SSID connection statistics, persisted as a JSON blob in the `netstats` NVS namespace.
+pub struct NetworkStats {
+    nvs: Option<Mutex<EspNvs<NvsDefault>>>,
+    state: Mutex<StatsState>,
+}
+
+impl NetworkStats {
+    pub fn new(partition: EspDefaultNvsPartition) -> Result<Self> {
+        let nvs = EspNvs::new(partition, NAMESPACE, true)?;
+
+        let ssids = match Self::load(&nvs) {
+            Ok(ssids) => ssids,
+            Err(e) => {
+                log::warn!("Failed to load network statistics, starting empty: {}", e);
+                BTreeMap::new()
+            }
+        };
+
+        Ok(NetworkStats {
+            nvs: Some(Mutex::new(nvs)),
+            state: Mutex::new(StatsState {
+                ssids,
+                ..Default::default()
+            }),
+        })
+    }
+
+    /// Store used when NVS is unavailable; statistics only cover the current boot.
+    pub fn in_memory() -> Self {
+        NetworkStats {
+            nvs: None,
+            state: Mutex::new(StatsState::default()),
+        }
+    }
+
+    fn load(nvs: &EspNvs<NvsDefault>) -> Result<BTreeMap<String, SsidStats>> {
+        let Some(len) = nvs.blob_len(STATS_KEY)? else {
+            return Ok(BTreeMap::new());
+        };
+
+        let mut buffer = vec![0_u8; len];
+        match nvs.get_blob(STATS_KEY, &mut buffer)? {
+            Some(data) => Ok(serde_json::from_slice(data)?),
+            None => Ok(BTreeMap::new()),
+        }
+    }
+
+    /// Counts disconnects of the network we are currently connected to.
+    pub fn subscribe(self: &Arc<Self>, sys_loop: &EspSystemEventLoop) -> Result<EspSubscription<'static, System>> {
+        let stats = self.clone();
+
+        let subscription = sys_loop.subscribe::<WifiEvent, _>(move |event| {
+            if let WifiEvent::StaDisconnected(_) = event {
+                stats.record_disconnect();
+            }
+        })?;
+
+        Ok(subscription)
+    }
+
+    pub fn record_connect(&self, ssid: &str, connect_time: Duration, dhcp_time: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let stats = state.ssids.entry(ssid.to_string()).or_default();
+
+        stats.connects += 1;
+        stats.avg_connect_ms = running_average(stats.avg_connect_ms, connect_time, stats.connects);
+        stats.avg_dhcp_ms = running_average(stats.avg_dhcp_ms, dhcp_time, stats.connects);
+        state.current = Some(ssid.to_string());
+
+        self.persist(&mut state);
+    }
+
+    pub fn record_failure(&self, ssid: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.ssids.entry(ssid.to_string()).or_default().failures += 1;
+
+        self.persist(&mut state);
+    }
+
+    fn record_disconnect(&self) {
+        let mut state = self.state.lock().unwrap();
+        let Some(ssid) = state.current.take() else {
+            return;
+        };
+        log::info!("Disconnected from {}", ssid);
+        state.ssids.entry(ssid).or_default().disconnects += 1;
+
+        self.persist(&mut state);
+    }
+
+    /// Folds the current station RSSI into the average of the connected network.
+    pub fn sample_rssi(&self) {
+        let Some(rssi) = station_rssi() else {
+            return;
+        };
+
+        let mut state = self.state.lock().unwrap();
+        let Some(ssid) = state.current.clone() else {
+            return;
+        };
+        let stats = state.ssids.entry(ssid).or_default();
+        stats.rssi_samples += 1;
+        let average = stats.avg_rssi.unwrap_or(rssi as f32);
+        stats.avg_rssi = Some(average + (rssi as f32 - average) / stats.rssi_samples as f32);
+
+        state.unsaved_samples += 1;
+        if state.unsaved_samples >= RSSI_SAMPLES_PER_WRITE {
+            self.persist(&mut state);
+        }
+    }
+
+    pub fn all(&self) -> BTreeMap<String, SsidStats> {
+        self.state.lock().unwrap().ssids.clone()
+    }
+
+    /// Prometheus text exposition for `/metrics`: the live RSSI of the connected
+    /// network, and connection counts of every network since the first boot.
+    pub fn metrics(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let mut metrics = String::new();
+        let _ = writeln!(metrics, "# TYPE wifi_rssi_dbm gauge");
+        if let (Some(ssid), Some(rssi)) = (&state.current, station_rssi()) {
+            let _ = writeln!(metrics, "wifi_rssi_dbm{{ssid=\"{}\"}} {}", ssid, rssi);
+        }
+        let series: [(&str, fn(&SsidStats) -> u32); 3] = [
+            ("wifi_connects_total", |stats| stats.connects),
+            ("wifi_connect_failures_total", |stats| stats.failures),
+            ("wifi_disconnects_total", |stats| stats.disconnects),
+        ];
+        for (name, value) in series {
+            let _ = writeln!(metrics, "# TYPE {} counter", name);
+            for (ssid, stats) in &state.ssids {
+                let _ = writeln!(metrics, "{}{{ssid=\"{}\"}} {}", name, ssid, value(stats));
+            }
+        }
+        metrics
+    }
+
+    fn persist(&self, state: &mut StatsState) {
+        state.unsaved_samples = 0;
+
+        let Some(nvs) = &self.nvs else {
+            return;
+        };
+
+        let result = serde_json::to_vec(&state.ssids)
+            .map_err(anyhow::Error::from)
+            .and_then(|data| Ok(nvs.lock().unwrap().set_blob(STATS_KEY, &data)?));
+        if let Err(e) = result {
+            log::error!("Failed to persist network statistics: {:?}", e);
+        }
+    }
+}
+
+fn running_average(average_ms: u32, sample: Duration, count: u32) -> u32 {
+    let sample_ms = sample.as_millis() as i64;
+    let average_ms = average_ms as i64;
+    (average_ms + (sample_ms - average_ms) / count as i64) as u32
+}
+
+/// `None` when the station is not connected.
+fn station_rssi() -> Option<i32> {
+    let mut rssi: core::ffi::c_int = 0;
+    esp!(unsafe { esp_wifi_sta_get_rssi(&mut rssi) }).ok()?;
+    Some(rssi)
+}
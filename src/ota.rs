@@ -0,0 +1,415 @@
+//! Firmware updates into the inactive OTA slot of `partitions.csv`, uploaded
+//! over HTTP or pulled by [`OtaPuller`] from the URL of a manifest.
+//!
+//! A pulled image that stops downloading midway is picked up where it stopped
+//! with a Range request, re-reading a few bytes before that point to check the
+//! server still has the same image. With a `sha256` in the manifest, the whole
+//! image is checked against it before the boot slot is switched.
+//!
+//! The bootloader rolls an update back unless the new image marks itself valid.
+//! It does so from [`confirm`] once its boot health checks pass; a failed check
+//! rolls back at once, and an image that crashes first is undone by the reset.
+
+use anyhow::{anyhow, bail, Context, Result};
+use embedded_svc::http::client::Client;
+use embedded_svc::http::Method;
+use embedded_svc::io::Read;
+use embedded_svc::ota::{Slot, SlotState};
+use esp_idf_svc::http::client::{Configuration, EspHttpConnection};
+use esp_idf_svc::ota::EspOta;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cmp::Ordering;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::alerts::unix_time;
+use crate::codec;
+use crate::config::{ConfigStore, OtaPullSettings};
+use crate::safemode;
+use crate::version::VERSION;
+
+/// First byte of an ESP-IDF app image.
+const IMAGE_MAGIC: u8 = 0xe9;
+/// First check after boot, once the network is likely up.
+const FIRST_CHECK_DELAY: Duration = Duration::from_secs(60);
+const DISABLED_POLL: Duration = Duration::from_secs(60);
+const MAX_MANIFEST_LEN: usize = 1024;
+/// Times one pull may pick a broken download up again.
+const MAX_RESUMES: u32 = 5;
+const RESUME_DELAY: Duration = Duration::from_secs(5);
+/// Bytes before the resume point fetched again and compared with what was written.
+const RESUME_OVERLAP: usize = 256;
+
+#[derive(Debug, Serialize)]
+pub struct SlotInfo {
+    pub label: String,
+    pub state: &'static str,
+    pub version: Option<String>,
+}
+
+impl From<Slot> for SlotInfo {
+    fn from(slot: Slot) -> Self {
+        SlotInfo {
+            label: slot.label.to_string(),
+            state: match slot.state {
+                SlotState::Factory => "factory",
+                SlotState::Valid => "valid",
+                SlotState::Invalid => "invalid",
+                SlotState::Unverified => "unverified",
+                SlotState::Unknown => "unknown",
+            },
+            version: slot.firmware.map(|firmware| firmware.version.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct OtaStatus {
+    pub version: &'static str,
+    pub running: SlotInfo,
+    /// Where the next update goes.
+    pub update: SlotInfo,
+    /// Set when an update was rolled back.
+    pub last_invalid: Option<SlotInfo>,
+    pub pull: PullStatus,
+}
+
+pub fn status(puller: &OtaPuller) -> Result<OtaStatus> {
+    let ota = EspOta::new()?;
+    Ok(OtaStatus {
+        version: VERSION,
+        running: ota.get_running_slot()?.into(),
+        update: ota.get_update_slot()?.into(),
+        last_invalid: ota.get_last_invalid_slot()?.map(SlotInfo::from),
+        pull: puller.status(),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    version: String,
+    url: String,
+    /// Hex SHA-256 of the image.
+    sha256: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PullStatus {
+    pub last_check: Option<u64>,
+    /// What the last check found or why it failed.
+    pub last_result: Option<String>,
+}
+
+/// Checks the manifest every `interval_hours` and installs a newer firmware.
+pub struct OtaPuller {
+    config: Arc<ConfigStore>,
+    status: Mutex<PullStatus>,
+}
+
+impl OtaPuller {
+    pub fn new(config: Arc<ConfigStore>) -> Self {
+        OtaPuller {
+            config,
+            status: Mutex::new(PullStatus::default()),
+        }
+    }
+
+    pub fn run(&self) {
+        thread::sleep(FIRST_CHECK_DELAY);
+        loop {
+            let settings = self.config.get().ota_pull;
+            if !settings.enabled || settings.validate().is_err() {
+                thread::sleep(DISABLED_POLL);
+                continue;
+            }
+
+            let result = match self.check(&settings) {
+                Ok(result) => result,
+                Err(e) => {
+                    log::warn!("Firmware update check failed: {:?}", e);
+                    format!("failed: {:#}", e)
+                }
+            };
+            *self.status.lock().unwrap() = PullStatus {
+                last_check: unix_time(),
+                last_result: Some(result),
+            };
+            thread::sleep(Duration::from_secs(settings.interval_hours as u64 * 3600));
+        }
+    }
+
+    pub fn status(&self) -> PullStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Installs and reboots into a newer firmware, or says why not.
+    fn check(&self, settings: &OtaPullSettings) -> Result<String> {
+        let manifest: Manifest = {
+            let mut client = client()?;
+            let mut response = client.get(&settings.manifest_url)?.submit()?;
+            if response.status() != 200 {
+                bail!("manifest answered with status {}", response.status());
+            }
+            let mut body = vec![0_u8; MAX_MANIFEST_LEN];
+            let mut len = 0;
+            while len < body.len() {
+                match response.read(&mut body[len..])? {
+                    0 => break,
+                    read => len += read,
+                }
+            }
+            serde_json::from_slice(&body[..len]).context("manifest")?
+        };
+
+        if compare_versions(&manifest.version, VERSION)? != Ordering::Greater {
+            return Ok(format!("up to date, manifest has {}", manifest.version));
+        }
+        let rolled_back = EspOta::new()?
+            .get_last_invalid_slot()?
+            .and_then(|slot| slot.firmware)
+            .is_some_and(|firmware| firmware.version.as_str() == manifest.version);
+        if rolled_back {
+            return Ok(format!("{} was rolled back before, not installing it again", manifest.version));
+        }
+        if !manifest.url.starts_with("https://") {
+            bail!("image url must be an https:// URL");
+        }
+
+        log::info!("Updating firmware from {} to {}", VERSION, manifest.version);
+        let mut download = Download::start(&manifest.url, manifest.sha256)?;
+        update(|buffer| download.read(buffer))?;
+
+        log::info!("Firmware {} installed, rebooting into it", manifest.version);
+        thread::sleep(safemode::REBOOT_DELAY);
+        unsafe { esp_idf_svc::sys::esp_restart() }
+    }
+}
+
+/// A pulled image, read across dropped connections.
+struct Download {
+    url: String,
+    connection: EspHttpConnection,
+    total: Option<usize>,
+    received: usize,
+    resumes: u32,
+    /// The last bytes handed out, to compare a resumed response with.
+    tail: Vec<u8>,
+    hasher: Sha256,
+    sha256: Option<String>,
+    logged_percent: usize,
+}
+
+impl Download {
+    fn start(url: &str, sha256: Option<String>) -> Result<Self> {
+        let mut connection = connection()?;
+        connection.initiate_request(Method::Get, url, &[])?;
+        connection.initiate_response()?;
+        if connection.status() != 200 {
+            bail!("image answered with status {}", connection.status());
+        }
+        let total = connection.header("Content-Length").and_then(|len| len.parse().ok());
+
+        Ok(Download {
+            url: url.to_string(),
+            connection,
+            total,
+            received: 0,
+            resumes: 0,
+            tail: Vec::with_capacity(RESUME_OVERLAP),
+            hasher: Sha256::new(),
+            sha256,
+            logged_percent: 0,
+        })
+    }
+
+    /// Like [`Read::read`], resuming on errors and on a connection closed before
+    /// `Content-Length`. At the end, the image is checked against the manifest.
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        loop {
+            let result = match self.connection.read(buffer) {
+                Ok(0) if self.total.is_some_and(|total| self.received < total) => {
+                    Err(anyhow!("connection closed early"))
+                }
+                result => result.map_err(anyhow::Error::from),
+            };
+            let e = match result {
+                Ok(0) => {
+                    self.verify()?;
+                    return Ok(0);
+                }
+                Ok(len) => {
+                    self.accept(&buffer[..len]);
+                    return Ok(len);
+                }
+                Err(e) => e,
+            };
+
+            if self.resumes == MAX_RESUMES {
+                return Err(e.context(format!("firmware download failed after {} resumes", MAX_RESUMES)));
+            }
+            self.resumes += 1;
+            log::warn!("Firmware download interrupted at {} bytes: {:#}", self.received, e);
+            thread::sleep(RESUME_DELAY);
+            // Left on the broken connection when it fails, so the next read fails too
+            if let Err(e) = self.resume() {
+                log::warn!("Failed to resume the firmware download: {:#}", e);
+            }
+        }
+    }
+
+    fn accept(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+        self.received += data.len();
+        self.tail.extend_from_slice(data);
+        let excess = self.tail.len().saturating_sub(RESUME_OVERLAP);
+        self.tail.drain(..excess);
+
+        // Progress goes to the log, and with it to /api/logs/stream
+        if let Some(total) = self.total.filter(|&total| total > 0) {
+            let percent = self.received * 100 / total;
+            if percent >= self.logged_percent + 10 {
+                self.logged_percent = percent / 10 * 10;
+                log::info!("Firmware download {}% ({} of {} bytes)", self.logged_percent, self.received, total);
+            }
+        }
+    }
+
+    /// Asks for the rest from a little before where the download stopped, and
+    /// checks that overlap matches what was already written.
+    fn resume(&mut self) -> Result<()> {
+        let from = self.received - self.tail.len();
+        let range = format!("bytes={}-", from);
+        let mut connection = connection()?;
+        connection.initiate_request(Method::Get, &self.url, &[("Range", &range)])?;
+        connection.initiate_response()?;
+        if connection.status() != 206 {
+            bail!("image answered a range request with status {}", connection.status());
+        }
+        let content_range = connection.header("Content-Range").unwrap_or_default();
+        if !content_range.starts_with(&format!("bytes {}-", from)) {
+            bail!("image answered a range request with Content-Range {:?}", content_range);
+        }
+
+        let mut overlap = vec![0_u8; self.tail.len()];
+        let mut len = 0;
+        while len < overlap.len() {
+            match connection.read(&mut overlap[len..])? {
+                0 => bail!("image ended within the resume overlap"),
+                read => len += read,
+            }
+        }
+        if overlap != self.tail {
+            bail!("image changed on the server during the download");
+        }
+
+        self.connection = connection;
+        log::info!("Firmware download resumed at {} bytes", self.received);
+        Ok(())
+    }
+
+    fn verify(&mut self) -> Result<()> {
+        let Some(expected) = &self.sha256 else {
+            return Ok(());
+        };
+        let actual = codec::hex_string(&std::mem::take(&mut self.hasher).finalize());
+        if !actual.eq_ignore_ascii_case(expected.trim()) {
+            bail!("image SHA-256 is {}, the manifest says {}", actual, expected);
+        }
+        Ok(())
+    }
+}
+
+fn client() -> Result<Client<EspHttpConnection>> {
+    Ok(Client::wrap(connection()?))
+}
+
+fn connection() -> Result<EspHttpConnection> {
+    Ok(EspHttpConnection::new(&Configuration {
+        timeout: Some(Duration::from_secs(30)),
+        crt_bundle_attach: Some(esp_idf_svc::sys::esp_crt_bundle_attach),
+        ..Default::default()
+    })?)
+}
+
+/// Compares dotted numeric versions such as `1.10.2`; missing parts count as 0.
+fn compare_versions(a: &str, b: &str) -> Result<Ordering> {
+    let parse = |version: &str| -> Result<Vec<u32>> {
+        version
+            .split('.')
+            .map(|part| part.parse().with_context(|| format!("invalid version {:?}", version)))
+            .collect()
+    };
+    let (mut a, mut b) = (parse(a)?, parse(b)?);
+    let len = a.len().max(b.len());
+    a.resize(len, 0);
+    b.resize(len, 0);
+    Ok(a.cmp(&b))
+}
+
+/// Settles a freshly updated image at the end of boot: marks it valid when all
+/// `checks` passed, or reboots into the previous image when one failed. Images
+/// already valid, and factory images, are left alone.
+pub fn confirm(checks: &[(&str, bool)]) {
+    let mut ota = match EspOta::new() {
+        Ok(ota) => ota,
+        Err(e) => {
+            log::error!("Failed to open OTA to confirm the running firmware: {:?}", e);
+            return;
+        }
+    };
+    match ota.get_running_slot() {
+        Ok(slot) if slot.state == SlotState::Unverified => {}
+        Ok(_) => return,
+        Err(e) => {
+            log::error!("Failed to read the running firmware slot: {:?}", e);
+            return;
+        }
+    }
+
+    let failed: Vec<&str> = checks.iter().filter(|(_, passed)| !passed).map(|(name, _)| *name).collect();
+    if failed.is_empty() {
+        match ota.mark_running_slot_valid() {
+            Ok(()) => log::info!("Firmware {} passed its boot checks, marked valid", VERSION),
+            Err(e) => log::error!("Failed to mark the running firmware valid: {:?}", e),
+        }
+        return;
+    }
+
+    log::error!("Firmware {} failed its boot checks ({}), rolling back", VERSION, failed.join(", "));
+    thread::sleep(safemode::REBOOT_DELAY);
+    // Only returns on failure; still unverified, the bootloader rolls back on the next reset
+    let e = ota.mark_running_slot_invalid_and_reboot();
+    log::error!("Failed to roll back the running firmware: {:?}", e);
+}
+
+/// Writes the image `read` returns chunk by chunk, until it returns 0, into the
+/// update slot and makes it the boot slot. Nothing changes if any step fails.
+pub fn update<F>(mut read: F) -> Result<usize>
+where
+    F: FnMut(&mut [u8]) -> Result<usize>,
+{
+    let mut ota = EspOta::new()?;
+    let mut update = ota.initiate_update()?;
+    let mut buffer = [0_u8; 1024];
+    let mut written = 0;
+    loop {
+        let len = read(&mut buffer)?;
+        if len == 0 {
+            break;
+        }
+        if written == 0 && buffer[0] != IMAGE_MAGIC {
+            bail!("Not an ESP-IDF app image");
+        }
+        update.write(&buffer[..len])?;
+        written += len;
+    }
+    if written == 0 {
+        bail!("Empty image");
+    }
+
+    // Verifies the image before switching the boot slot
+    update.complete()?;
+    Ok(written)
+}
@@ -0,0 +1,76 @@
+//! Cursor pagination for the list routes. A page holds the items whose key comes
+//! after `cursor`, in key order, and `next_cursor` is the key of its last item,
+//! so a page stays put when items are added or dropped between requests.
+//!
+//! Keys must be unique: a sequence id, or a MAC address for lists of devices.
+
+use anyhow::Result;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::form::Form;
+
+pub const DEFAULT_SIZE: usize = 20;
+pub const MAX_SIZE: usize = 50;
+
+/// `cursor` and `limit` from the query string.
+pub struct PageRequest<K> {
+    after: Option<K>,
+    limit: usize,
+}
+
+pub struct Page<T, K> {
+    pub items: Vec<T>,
+    /// `None` on the last page.
+    pub next_cursor: Option<K>,
+}
+
+impl<K: Ord + Clone> PageRequest<K> {
+    pub fn parse(query: &Form) -> Result<Self>
+    where
+        K: FromStr,
+        K::Err: Display,
+    {
+        Ok(PageRequest {
+            after: query.value("cursor")?,
+            limit: query
+                .value::<usize>("limit")?
+                .map_or(DEFAULT_SIZE, |limit| limit.clamp(1, MAX_SIZE)),
+        })
+    }
+
+    /// Picks the page from keyed `items`, which may come in any order. Only
+    /// references are held while choosing, and only the items on the page are cloned.
+    pub fn select<'a, T: Clone + 'a>(&self, items: impl IntoIterator<Item = (K, &'a T)>) -> Page<T, K> {
+        // The lowest keys after the cursor, sorted, one more than a page to tell
+        // whether there is a next one
+        let mut lowest: Vec<(K, &T)> = Vec::with_capacity(self.limit + 1);
+        for (key, item) in items {
+            if self.after.as_ref().is_some_and(|after| key <= *after) {
+                continue;
+            }
+            if lowest.len() > self.limit && key >= lowest[self.limit].0 {
+                continue;
+            }
+            let at = lowest.partition_point(|(other, _)| *other < key);
+            lowest.insert(at, (key, item));
+            lowest.truncate(self.limit + 1);
+        }
+
+        let more = lowest.len() > self.limit;
+        lowest.truncate(self.limit);
+        Page {
+            next_cursor: lowest.last().filter(|_| more).map(|(key, _)| key.clone()),
+            items: lowest.into_iter().map(|(_, item)| item.clone()).collect(),
+        }
+    }
+}
+
+impl<T, K> Page<T, K> {
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U, K> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            next_cursor: self.next_cursor,
+        }
+    }
+}
@@ -0,0 +1,351 @@
+//! First-time pairing: until a client has exchanged the one-time pairing code for
+//! an API key, no route that needs credentials can be used, so an unprovisioned
+//! device on an open network cannot be reconfigured by whoever finds it first.
+//!
+//! The code is printed on the serial console and, in station mode, carried in the
+//! provisioning QR code. Only a SHA-256 of the API key is stored.
+//!
+//! Keys are sent as `Authorization: Bearer <key>`, or with basic auth as the
+//! password, for browsers and tools that only do that.
+//!
+//! The paired key has the admin role. With it, further named keys can be issued
+//! with a lesser [`Role`]; unpairing revokes those as well.
+
+use anyhow::{bail, Result};
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use esp_idf_svc::sys;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::Mutex;
+
+use crate::codec;
+use crate::honeypot::uptime_secs;
+
+const NAMESPACE: &str = "pairing";
+const CODE_KEY: &str = "code";
+const API_KEY_HASH_KEY: &str = "key_sha256";
+const USERS_KEY: &str = "users";
+const MAX_USERS: usize = 8;
+const MAX_NAME_LEN: usize = 16;
+/// Basic auth user name of the key issued by pairing.
+const ADMIN_NAME: &str = "admin";
+/// Wrong codes in a row before pairing is refused for [`LOCKOUT_SECS`].
+const MAX_FAILURES: u32 = 5;
+const LOCKOUT_SECS: u64 = 60;
+const API_KEY_BYTES: usize = 16;
+
+#[derive(Debug)]
+pub enum PairError {
+    AlreadyPaired,
+    WrongCode,
+    /// Too many wrong codes, retry after this many seconds.
+    LockedOut(u64),
+}
+
+impl std::fmt::Display for PairError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PairError::AlreadyPaired => write!(f, "Already paired"),
+            PairError::WrongCode => write!(f, "Wrong pairing code"),
+            PairError::LockedOut(secs) => write!(f, "Too many wrong codes, retry in {} s", secs),
+        }
+    }
+}
+
+/// What an API key may do. A viewer key reads the status, scan and settings
+/// routes; only a handful of pages stay open without a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    Viewer,
+    /// The LED, alerts and tracking, nothing that touches the radio or WiFi.
+    Operator,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Operator => "operator",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct User {
+    name: String,
+    role: Role,
+    key_sha256: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserInfo {
+    pub name: String,
+    pub role: Role,
+}
+
+#[derive(Default)]
+struct State {
+    /// Set while the device is unpaired.
+    code: Option<String>,
+    /// The admin key issued by pairing.
+    api_key_sha256: Option<String>,
+    users: Vec<User>,
+    failures: u32,
+    locked_until: u64,
+}
+
+pub struct Pairing {
+    nvs: Option<Mutex<EspNvs<NvsDefault>>>,
+    state: Mutex<State>,
+}
+
+impl Pairing {
+    /// Loads the stored key, or the pending code; a device with neither gets a
+    /// fresh code, kept across reboots until it is used.
+    pub fn new(partition: EspDefaultNvsPartition) -> Result<Self> {
+        let mut nvs = EspNvs::new(partition, NAMESPACE, true)?;
+
+        let mut buffer = [0_u8; 2 * 32 + 1];
+        let api_key_sha256 = nvs.get_str(API_KEY_HASH_KEY, &mut buffer)?.map(str::to_owned);
+        let code = match api_key_sha256 {
+            Some(_) => None,
+            None => Some(match nvs.get_str(CODE_KEY, &mut buffer)? {
+                Some(code) => code.to_owned(),
+                None => {
+                    let code = new_code();
+                    nvs.set_str(CODE_KEY, &code)?;
+                    code
+                }
+            }),
+        };
+        let users = Self::load_users(&nvs).unwrap_or_else(|e| {
+            log::error!("Failed to load the API users, dropping them: {:?}", e);
+            Vec::new()
+        });
+
+        Ok(Pairing {
+            nvs: Some(Mutex::new(nvs)),
+            state: Mutex::new(State {
+                code,
+                api_key_sha256,
+                users,
+                ..Default::default()
+            }),
+        })
+    }
+
+    fn load_users(nvs: &EspNvs<NvsDefault>) -> Result<Vec<User>> {
+        let Some(len) = nvs.blob_len(USERS_KEY)? else {
+            return Ok(Vec::new());
+        };
+
+        let mut buffer = vec![0_u8; len];
+        match nvs.get_blob(USERS_KEY, &mut buffer)? {
+            Some(data) => Ok(serde_json::from_slice(data)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Pairing used when NVS is unavailable: a new code on every boot, and the key
+    /// is forgotten on reboot.
+    pub fn in_memory() -> Self {
+        Pairing {
+            nvs: None,
+            state: Mutex::new(State {
+                code: Some(new_code()),
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// The pending pairing code, `None` once paired.
+    pub fn code(&self) -> Option<String> {
+        self.state.lock().unwrap().code.clone()
+    }
+
+    pub fn is_paired(&self) -> bool {
+        self.state.lock().unwrap().api_key_sha256.is_some()
+    }
+
+    /// Exchanges the pairing code for a new API key. The code cannot be used again.
+    pub fn pair(&self, code: &str) -> Result<String, PairError> {
+        let mut state = self.state.lock().unwrap();
+        let now = uptime_secs();
+        if now < state.locked_until {
+            return Err(PairError::LockedOut(state.locked_until - now));
+        }
+        let Some(expected) = &state.code else {
+            return Err(PairError::AlreadyPaired);
+        };
+
+        if !constant_time_eq(expected.as_bytes(), code.trim().as_bytes()) {
+            state.failures += 1;
+            if state.failures >= MAX_FAILURES {
+                state.failures = 0;
+                state.locked_until = now + LOCKOUT_SECS;
+            }
+            return Err(PairError::WrongCode);
+        }
+
+        let (api_key, api_key_sha256) = new_api_key();
+        if let Some(nvs) = &self.nvs {
+            let mut nvs = nvs.lock().unwrap();
+            if let Err(e) = nvs.set_str(API_KEY_HASH_KEY, &api_key_sha256).and_then(|_| nvs.remove(CODE_KEY)) {
+                log::error!("Failed to persist the API key: {:?}", e);
+            }
+        }
+
+        state.code = None;
+        state.failures = 0;
+        state.api_key_sha256 = Some(api_key_sha256);
+        Ok(api_key)
+    }
+
+    /// Forgets the API key and issues a new pairing code.
+    pub fn unpair(&self) -> Result<String> {
+        let code = new_code();
+        if let Some(nvs) = &self.nvs {
+            let mut nvs = nvs.lock().unwrap();
+            nvs.remove(API_KEY_HASH_KEY)?;
+            nvs.remove(USERS_KEY)?;
+            nvs.set_str(CODE_KEY, &code)?;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.api_key_sha256 = None;
+        state.users.clear();
+        state.code = Some(code.clone());
+        Ok(code)
+    }
+
+    /// Hex SHA-256 of the admin key, which signs exports; `None` while unpaired.
+    pub fn admin_key_sha256(&self) -> Option<String> {
+        self.state.lock().unwrap().api_key_sha256.clone()
+    }
+
+    /// The role of the key in an `Authorization` header value. With basic auth
+    /// the user name has to match too: the user's name, or `admin` for the key
+    /// issued by pairing. Always `None` while unpaired.
+    pub fn role(&self, authorization: Option<&str>) -> Option<Role> {
+        let (name, api_key) = credentials(authorization?)?;
+        let name = name.as_deref();
+        let state = self.state.lock().unwrap();
+        let admin = state.api_key_sha256.as_ref()?;
+
+        let digest = codec::hex_string(&Sha256::digest(api_key.trim().as_bytes()));
+        if constant_time_eq(admin.as_bytes(), digest.as_bytes()) {
+            return name.map_or(true, |name| name == ADMIN_NAME).then_some(Role::Admin);
+        }
+        state
+            .users
+            .iter()
+            .find(|user| constant_time_eq(user.key_sha256.as_bytes(), digest.as_bytes()))
+            .filter(|user| name.map_or(true, |name| name == user.name))
+            .map(|user| user.role)
+    }
+
+    pub fn users(&self) -> Vec<UserInfo> {
+        let state = self.state.lock().unwrap();
+        state
+            .users
+            .iter()
+            .map(|user| UserInfo {
+                name: user.name.clone(),
+                role: user.role,
+            })
+            .collect()
+    }
+
+    /// Issues a key for `name`, replacing any key the name had.
+    pub fn add_user(&self, name: &str, role: Role) -> Result<String> {
+        if name.is_empty()
+            || name.len() > MAX_NAME_LEN
+            || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            bail!("Names are 1 to {} letters, digits, '-' or '_'", MAX_NAME_LEN);
+        }
+        if name == ADMIN_NAME {
+            bail!("{} is the basic auth name of the paired key", ADMIN_NAME);
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if state.api_key_sha256.is_none() {
+            bail!("Not paired yet");
+        }
+        let mut users = state.users.clone();
+        users.retain(|user| user.name != name);
+        if users.len() >= MAX_USERS {
+            bail!("At most {} users", MAX_USERS);
+        }
+
+        let (api_key, key_sha256) = new_api_key();
+        users.push(User {
+            name: name.to_string(),
+            role,
+            key_sha256,
+        });
+        self.persist_users(&users)?;
+        state.users = users;
+        Ok(api_key)
+    }
+
+    /// Revokes the key of `name`; false if there is no such user.
+    pub fn remove_user(&self, name: &str) -> Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        let mut users = state.users.clone();
+        users.retain(|user| user.name != name);
+        if users.len() == state.users.len() {
+            return Ok(false);
+        }
+
+        self.persist_users(&users)?;
+        state.users = users;
+        Ok(true)
+    }
+
+    fn persist_users(&self, users: &[User]) -> Result<()> {
+        let Some(nvs) = &self.nvs else {
+            return Ok(());
+        };
+        let data = serde_json::to_vec(users)?;
+        nvs.lock().unwrap().set_blob(USERS_KEY, &data)?;
+        Ok(())
+    }
+}
+
+/// The user name, for basic auth only, and the API key.
+fn credentials(authorization: &str) -> Option<(Option<String>, String)> {
+    if let Some(api_key) = authorization.strip_prefix("Bearer ") {
+        return Some((None, api_key.to_string()));
+    }
+
+    let encoded = authorization.strip_prefix("Basic ")?.trim().as_bytes();
+    let mut decoded = vec![0_u8; codec::base64_decoded_len(encoded.len())];
+    let len = codec::decode_base64(encoded, &mut decoded).ok()?;
+    let decoded = String::from_utf8(decoded[..len].to_vec()).ok()?;
+    let (name, api_key) = decoded.split_once(':')?;
+    Some((Some(name.to_string()), api_key.to_string()))
+}
+
+/// A new API key and its SHA-256, the only part that is stored.
+fn new_api_key() -> (String, String) {
+    let mut key = [0_u8; API_KEY_BYTES];
+    unsafe { sys::esp_fill_random(key.as_mut_ptr() as *mut _, key.len()) };
+    let api_key = codec::hex_string(&key);
+    let api_key_sha256 = codec::hex_string(&Sha256::digest(api_key.as_bytes()));
+    (api_key, api_key_sha256)
+}
+
+/// Eight random digits.
+fn new_code() -> String {
+    let mut random = [0_u8; 4];
+    unsafe { sys::esp_fill_random(random.as_mut_ptr() as *mut _, random.len()) };
+    format!("{:08}", u32::from_le_bytes(random) % 100_000_000)
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
@@ -0,0 +1,29 @@
+//! libpcap file format, as read by Wireshark and tcpdump.
+
+use std::time::Duration;
+
+/// Plain 802.11 frames, without radiotap.
+const LINKTYPE_IEEE802_11: u32 = 105;
+const SNAPLEN: u32 = 65535;
+
+pub fn file_header() -> [u8; 24] {
+    let mut header = [0_u8; 24];
+    header[0..4].copy_from_slice(&0xa1b2c3d4_u32.to_le_bytes());
+    header[4..6].copy_from_slice(&2_u16.to_le_bytes());
+    header[6..8].copy_from_slice(&4_u16.to_le_bytes());
+    // Time zone offset and timestamp accuracy stay zero
+    header[16..20].copy_from_slice(&SNAPLEN.to_le_bytes());
+    header[20..24].copy_from_slice(&LINKTYPE_IEEE802_11.to_le_bytes());
+    header
+}
+
+/// Header of one packet record, to be followed by the `captured_len` bytes kept
+/// of a frame of `original_len`; `timestamp` is counted from the Unix epoch.
+pub fn record_header(captured_len: usize, original_len: usize, timestamp: Duration) -> [u8; 16] {
+    let mut header = [0_u8; 16];
+    header[0..4].copy_from_slice(&(timestamp.as_secs() as u32).to_le_bytes());
+    header[4..8].copy_from_slice(&timestamp.subsec_micros().to_le_bytes());
+    header[8..12].copy_from_slice(&(captured_len as u32).to_le_bytes());
+    header[12..16].copy_from_slice(&(original_len as u32).to_le_bytes());
+    header
+}
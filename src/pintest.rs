@@ -0,0 +1,165 @@
+//! In-field wiring checks: read or drive a single GPIO, and an SPI loopback
+//! with MOSI jumpered to MISO. Pins used by the firmware itself, or wired to the
+//! flash, USB or console UART, are refused.
+//!
+//! The pins are released again when a test ends, so they float afterwards.
+
+use anyhow::{bail, Result};
+use esp_idf_hal::gpio::{AnyIOPin, AnyInputPin, AnyOutputPin, PinDriver, Pull};
+use esp_idf_hal::spi::{config, SpiBusDriver, SpiDriver, SPI2};
+use esp_idf_hal::units::Hertz;
+use serde::{Deserialize, Serialize};
+use std::thread;
+use std::time::Duration;
+
+/// GPIO11-21 belong to the flash, USB and the console UART on the ESP32-C3.
+const LAST_FREE_PIN: u8 = 10;
+const MAX_TOGGLES: u32 = 100;
+/// Longest a single test may hold the HTTP server.
+const MAX_TEST: Duration = Duration::from_secs(10);
+const SPI_BAUDRATE: Hertz = Hertz(1_000_000);
+/// Alternating bits, the extremes and a counter, to tell a short from a stuck line.
+const SPI_PATTERN: [u8; 16] = [
+    0x55, 0xaa, 0x00, 0xff, 0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x12, 0x34, 0x56, 0x78,
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PinPull {
+    #[default]
+    None,
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub enum PinAction {
+    /// Drive the pin for `hold_ms`.
+    High { hold_ms: u32 },
+    Low { hold_ms: u32 },
+    /// Flip the level `count` times, `interval_ms` apart, starting low.
+    Toggle { count: u32, interval_ms: u32 },
+}
+
+#[derive(Debug, Serialize)]
+pub struct PinRead {
+    pub pin: u8,
+    pub high: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SpiLoopback {
+    pub passed: bool,
+    pub sent: Vec<u8>,
+    pub received: Vec<u8>,
+    pub mismatched_bytes: usize,
+    pub hint: Option<&'static str>,
+}
+
+/// Refuses `pin` unless it is free for testing.
+fn check_pin(pin: u8, in_use: &[u8]) -> Result<()> {
+    if pin > LAST_FREE_PIN {
+        bail!("GPIO{} is wired to the flash, USB or console UART", pin);
+    }
+    if in_use.contains(&pin) {
+        bail!("GPIO{} is in use by the firmware", pin);
+    }
+    Ok(())
+}
+
+pub fn read(pin: u8, pull: PinPull, in_use: &[u8]) -> Result<PinRead> {
+    check_pin(pin, in_use)?;
+    // Safety: checked above that no driver owns the pin
+    let mut driver = PinDriver::input(unsafe { AnyIOPin::new(pin as i32) })?;
+    driver.set_pull(match pull {
+        PinPull::None => Pull::Floating,
+        PinPull::Up => Pull::Up,
+        PinPull::Down => Pull::Down,
+    })?;
+    // Let the pull settle
+    thread::sleep(Duration::from_millis(1));
+
+    Ok(PinRead {
+        pin,
+        high: driver.is_high(),
+    })
+}
+
+pub fn drive(pin: u8, action: PinAction, in_use: &[u8]) -> Result<()> {
+    check_pin(pin, in_use)?;
+    let duration = match action {
+        PinAction::High { hold_ms } | PinAction::Low { hold_ms } => Duration::from_millis(hold_ms as u64),
+        PinAction::Toggle { count, interval_ms } => {
+            if count > MAX_TOGGLES {
+                bail!("count must be at most {}", MAX_TOGGLES);
+            }
+            Duration::from_millis(count as u64 * interval_ms as u64)
+        }
+    };
+    if duration > MAX_TEST {
+        bail!("A test may take at most {} s", MAX_TEST.as_secs());
+    }
+
+    // Safety: checked above that no driver owns the pin
+    let mut driver = PinDriver::output(unsafe { AnyOutputPin::new(pin as i32) })?;
+    match action {
+        PinAction::High { hold_ms } => {
+            driver.set_high()?;
+            thread::sleep(Duration::from_millis(hold_ms as u64));
+        }
+        PinAction::Low { hold_ms } => {
+            driver.set_low()?;
+            thread::sleep(Duration::from_millis(hold_ms as u64));
+        }
+        PinAction::Toggle { count, interval_ms } => {
+            driver.set_low()?;
+            for _ in 0..count {
+                thread::sleep(Duration::from_millis(interval_ms as u64));
+                driver.toggle()?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Sends [`SPI_PATTERN`] on SPI2 with MOSI jumpered to MISO, and compares.
+pub fn spi_loopback(sclk: u8, mosi: u8, miso: u8, in_use: &[u8]) -> Result<SpiLoopback> {
+    for pin in [sclk, mosi, miso] {
+        check_pin(pin, in_use)?;
+    }
+    if sclk == mosi || sclk == miso || mosi == miso {
+        bail!("SCLK, MOSI and MISO must be three different pins");
+    }
+
+    // Safety: SPI2 is not used by the firmware, and the pins were checked above
+    let driver = SpiDriver::new(
+        unsafe { SPI2::new() },
+        unsafe { AnyOutputPin::new(sclk as i32) },
+        unsafe { AnyOutputPin::new(mosi as i32) },
+        Some(unsafe { AnyInputPin::new(miso as i32) }),
+        &config::DriverConfig::new(),
+    )?;
+    let mut bus = SpiBusDriver::new(driver, &config::Config::new().baudrate(SPI_BAUDRATE))?;
+    let mut received = [0_u8; SPI_PATTERN.len()];
+    bus.transfer(&mut received, &SPI_PATTERN)?;
+
+    let mismatched_bytes = SPI_PATTERN.iter().zip(&received).filter(|(sent, got)| sent != got).count();
+    let hint = if mismatched_bytes == 0 {
+        None
+    } else if received.iter().all(|&byte| byte == 0x00) {
+        Some("MISO reads low: not jumpered to MOSI, or shorted to ground")
+    } else if received.iter().all(|&byte| byte == 0xff) {
+        Some("MISO reads high: not jumpered to MOSI, or shorted to 3.3 V")
+    } else {
+        Some("Some bits get through: a loose jumper, or a line shared with another device")
+    };
+
+    Ok(SpiLoopback {
+        passed: mismatched_bytes == 0,
+        sent: SPI_PATTERN.to_vec(),
+        received: received.to_vec(),
+        mismatched_bytes,
+        hint,
+    })
+}
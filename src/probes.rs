@@ -0,0 +1,110 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::mpsc::Receiver;
+use std::sync::Mutex;
+
+use crate::alerts::unix_time;
+use crate::honeypot::uptime_secs;
+use crate::page::{Page, PageRequest};
+use crate::scan::{format_mac, MacAddress};
+use crate::sniffer::{ManagementFrame, Subtype};
+
+/// Beyond this many devices the one heard from least recently is forgotten.
+const MAX_DEVICES: usize = 64;
+const MAX_SSIDS: usize = 8;
+/// Queue between the sniffer and the log.
+pub const FRAME_QUEUE: usize = 32;
+/// Tag number of the SSID element in the probe request body.
+const SSID_ELEMENT: u8 = 0;
+
+/// A device that sent probe requests.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProbingDevice {
+    pub mac: String,
+    /// Locally administered address, as phones use while not associated. The same
+    /// phone then shows up under several addresses.
+    pub randomized: bool,
+    /// Networks it asked for by name; wildcard probes are not listed.
+    pub ssids: Vec<String>,
+    pub rssi: i8,
+    pub probes: u32,
+    pub first_seen: u64,
+    pub last_seen: u64,
+    /// `None` until the clock has been set, see [`crate::clock`].
+    pub last_seen_unix: Option<u64>,
+}
+
+/// Who has been probing nearby, from the sniffer's probe requests.
+#[derive(Default)]
+pub struct ProbeLog {
+    devices: Mutex<BTreeMap<[u8; 6], ProbingDevice>>,
+}
+
+impl ProbeLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records probe requests until the sniffer goes away.
+    pub fn run(&self, frames: Receiver<ManagementFrame>) {
+        for frame in frames {
+            if frame.subtype == Subtype::ProbeRequest {
+                self.record(&frame);
+            }
+        }
+    }
+
+    fn record(&self, frame: &ManagementFrame) {
+        let now = uptime_secs();
+        let ssid = requested_ssid(frame.body());
+        let mut devices = self.devices.lock().unwrap();
+
+        if !devices.contains_key(&frame.transmitter) && devices.len() == MAX_DEVICES {
+            let stalest = devices
+                .iter()
+                .min_by_key(|(_, device)| device.last_seen)
+                .map(|(mac, _)| *mac);
+            if let Some(stalest) = stalest {
+                devices.remove(&stalest);
+            }
+        }
+
+        let device = devices.entry(frame.transmitter).or_insert_with(|| ProbingDevice {
+            mac: format_mac(&frame.transmitter),
+            randomized: frame.transmitter[0] & 0x02 != 0,
+            ssids: Vec::new(),
+            rssi: frame.rssi,
+            probes: 0,
+            first_seen: now,
+            last_seen: now,
+            last_seen_unix: None,
+        });
+        device.rssi = frame.rssi;
+        device.probes += 1;
+        device.last_seen = now;
+        device.last_seen_unix = unix_time();
+        if let Some(ssid) = ssid {
+            if !device.ssids.contains(&ssid) && device.ssids.len() < MAX_SSIDS {
+                device.ssids.push(ssid);
+            }
+        }
+    }
+
+    /// By MAC address.
+    pub fn page(&self, request: &PageRequest<MacAddress>) -> Page<ProbingDevice, MacAddress> {
+        let devices = self.devices.lock().unwrap();
+        request.select(devices.iter().map(|(mac, device)| (MacAddress(*mac), device)))
+    }
+}
+
+/// SSID element of a probe request body, `None` for a wildcard probe.
+fn requested_ssid(body: &[u8]) -> Option<String> {
+    let (&tag, rest) = body.split_first()?;
+    let (&len, rest) = rest.split_first()?;
+    if tag != SSID_ELEMENT || len == 0 {
+        return None;
+    }
+
+    let ssid = rest.get(..len as usize)?;
+    Some(String::from_utf8_lossy(ssid).into_owned())
+}
@@ -0,0 +1,92 @@
+use anyhow::{anyhow, Result};
+use qrcodegen::{QrCode, QrCodeEcc};
+use std::fmt::Write;
+use std::net::Ipv4Addr;
+
+use crate::config::{AccessPointSettings, AppConfig, WifiMode};
+
+/// Quiet zone around the code, in modules, as required by the QR spec.
+const BORDER: i32 = 4;
+
+/// What a phone should do after scanning the provisioning QR code: join our soft AP
+/// in access point mode, or open the dashboard once we are on the network.
+///
+/// The honeypot AP is never advertised. With `pairing_code` the dashboard link
+/// carries it, which is only for the console: anyone who reads it can pair. A
+/// `WIFI:` URI has no room for it.
+pub fn payload(config: &AppConfig, ip: Option<Ipv4Addr>, pairing_code: Option<&str>) -> Option<String> {
+    match config.wifi_mode {
+        WifiMode::AccessPoint => Some(wifi_payload(&config.access_point)),
+        WifiMode::Station | WifiMode::Mixed => ip.map(|ip| match pairing_code {
+            Some(code) => format!("http://{}/?pair={}", ip, code),
+            None => format!("http://{}/", ip),
+        }),
+        WifiMode::Honeypot => None,
+    }
+}
+
+/// `WIFI:` URI understood by the Android and iOS camera apps.
+fn wifi_payload(settings: &AccessPointSettings) -> String {
+    if settings.password.is_empty() {
+        format!("WIFI:T:nopass;S:{};;", escape(&settings.ssid))
+    } else {
+        format!(
+            "WIFI:T:WPA;S:{};P:{};;",
+            escape(&settings.ssid),
+            escape(&settings.password)
+        )
+    }
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '\\' | ';' | ',' | ':' | '"') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+pub fn encode(payload: &str) -> Result<QrCode> {
+    QrCode::encode_text(payload, QrCodeEcc::Medium).map_err(|e| anyhow!("QR payload too long: {}", e))
+}
+
+pub fn to_svg(qr: &QrCode) -> String {
+    let dimension = qr.size() + BORDER * 2;
+    let mut svg = String::new();
+
+    let _ = write!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {0} {0}" shape-rendering="crispEdges">"#,
+        dimension
+    );
+    svg.push_str(r##"<rect width="100%" height="100%" fill="#fff"/><path fill="#000" d=""##);
+    for y in 0..qr.size() {
+        for x in 0..qr.size() {
+            if qr.get_module(x, y) {
+                let _ = write!(svg, "M{},{}h1v1h-1z", x + BORDER, y + BORDER);
+            }
+        }
+    }
+    svg.push_str(r#""/></svg>"#);
+
+    svg
+}
+
+/// Renders the code with block characters, two columns per module so it stays square
+/// in a terminal.
+pub fn to_ascii(qr: &QrCode) -> String {
+    let mut ascii = String::new();
+
+    for y in -BORDER..qr.size() + BORDER {
+        for x in -BORDER..qr.size() + BORDER {
+            // Light-on-dark terminals: draw the light modules
+            ascii.push_str(if qr.get_module(x, y) { "  " } else { "██" });
+        }
+        ascii.push('\n');
+    }
+
+    ascii
+}
@@ -0,0 +1,1437 @@
+use anyhow::{bail, Result};
+use embedded_svc::http::Method;
+use esp_idf_svc::http::server::{self, EspHttpConnection, EspHttpServer, Request};
+use esp_idf_svc::tls::X509;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::config::{AppConfig, HttpSettings, WifiMode};
+use crate::errors;
+use crate::form::percent_decode_path;
+use crate::handlers::{self, AppState, HttpRequest};
+use crate::pairing::Role;
+
+pub type Handler = fn(HttpRequest, &AppState) -> Result<()>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Auth {
+    /// Open to anyone on the network: the dashboard page, pairing, the captive
+    /// portal, the provisioning QR code and the firmware version.
+    None,
+    /// The rest need an API key as `Authorization: Bearer <key>` with at least
+    /// this role; `POST /pair` issues the admin key.
+    Viewer,
+    Operator,
+    Admin,
+    /// Changes state but must stay open, like the honeypot portal form.
+    Exempt,
+}
+
+impl Auth {
+    pub fn role(&self) -> Option<Role> {
+        match self {
+            Auth::Viewer => Some(Role::Viewer),
+            Auth::Operator => Some(Role::Operator),
+            Auth::Admin => Some(Role::Admin),
+            Auth::None | Auth::Exempt => None,
+        }
+    }
+}
+
+pub struct Route {
+    pub method: Method,
+    pub path: &'static str,
+    pub auth: Auth,
+    pub description: &'static str,
+    /// Names of the query parameters the route understands.
+    pub query: &'static [&'static str],
+    /// Expected request body, if any.
+    pub body: Option<&'static str>,
+    pub handler: Handler,
+}
+
+pub const ROUTES: &[Route] = &[
+    Route {
+        method: Method::Get,
+        path: "/",
+        auth: Auth::None,
+        description: "Dashboard: LED color and live scan results",
+        query: &[],
+        body: None,
+        handler: handlers::dashboard,
+    },
+    Route {
+        method: Method::Get,
+        path: "/routes",
+        auth: Auth::Viewer,
+        description: "Every route, with its request statistics",
+        query: &["lang"],
+        body: None,
+        handler: handlers::route_index,
+    },
+    Route {
+        method: Method::Get,
+        path: "/status",
+        auth: Auth::Viewer,
+        description: "Safe mode, subsystem startup report, station MAC, link health, energy estimate and CPU usage per task",
+        query: &[],
+        body: None,
+        handler: handlers::status,
+    },
+    Route {
+        method: Method::Post,
+        path: "/api/reboot",
+        auth: Auth::Admin,
+        description: "Restart the device, leaving safe mode, once confirmed: an empty body returns a token, valid 60 s, to post back",
+        query: &[],
+        body: Some("{\"confirm\": \"token\"}, or nothing to get one"),
+        handler: handlers::confirmed_reboot,
+    },
+    Route {
+        method: Method::Post,
+        path: "/api/factory-reset",
+        auth: Auth::Admin,
+        description: "Erase the stored configuration and reboot into the defaults once confirmed; keeps pairing, TLS and files",
+        query: &[],
+        body: Some("{\"confirm\": \"token\"}, or nothing to get one"),
+        handler: handlers::factory_reset,
+    },
+    Route {
+        method: Method::Get,
+        path: "/api/status",
+        auth: Auth::Viewer,
+        description: "Free, minimum and largest free block of heap, uptime, reset reason, task count and firmware version as JSON",
+        query: &[],
+        body: None,
+        handler: handlers::system_info,
+    },
+    Route {
+        method: Method::Get,
+        path: "/api/version",
+        auth: Auth::None,
+        description: "Firmware version, git hash and build time of the running image, and the ESP-IDF version",
+        query: &[],
+        body: None,
+        handler: handlers::build_info,
+    },
+    Route {
+        method: Method::Get,
+        path: "/api/ota",
+        auth: Auth::Viewer,
+        description: "Firmware version, running slot, the slot the next update goes to, any rolled back update and the last pull check",
+        query: &[],
+        body: None,
+        handler: handlers::ota_status,
+    },
+    Route {
+        method: Method::Post,
+        path: "/api/ota",
+        auth: Auth::Admin,
+        description: "Write a firmware image to the update slot and reboot into it; rolled back unless it brings up HTTP",
+        query: &[],
+        body: Some("app image (.bin) with Content-Length"),
+        handler: handlers::ota_upload,
+    },
+    Route {
+        method: Method::Get,
+        path: "/api/ota/pull",
+        auth: Auth::Viewer,
+        description: "Settings for pulling firmware updates from a manifest URL",
+        query: &[],
+        body: None,
+        handler: handlers::get_ota_pull,
+    },
+    Route {
+        method: Method::Post,
+        path: "/api/ota/pull",
+        auth: Auth::Admin,
+        description: "Check a manifest every interval_hours and install and reboot into a newer version; the manifest is {\"version\": \"1.2.0\", \"url\": \"https://...\", \"sha256\": optional hex}; broken downloads resume with Range requests",
+        query: &[],
+        body: Some("{\"enabled\": bool, \"manifest_url\": \"https://...\", \"interval_hours\": 1-720}"),
+        handler: handlers::set_ota_pull,
+    },
+    Route {
+        method: Method::Get,
+        path: "/metrics",
+        auth: Auth::Viewer,
+        description: "Device identity, CPU usage and stack headroom per task, heap usage, per-route HTTP statistics, WiFi RSSI and connection counts, and scan counts, in Prometheus text format",
+        query: &[],
+        body: None,
+        handler: handlers::metrics,
+    },
+    Route {
+        method: Method::Get,
+        path: "/heap",
+        auth: Auth::Viewer,
+        description: "Free heap, largest free block and fragmentation, with the worst seen",
+        query: &[],
+        body: None,
+        handler: handlers::heap,
+    },
+    Route {
+        method: Method::Get,
+        path: "/heap/soak",
+        auth: Auth::Viewer,
+        description: "Soak-test mode settings",
+        query: &[],
+        body: None,
+        handler: handlers::get_soak_test,
+    },
+    Route {
+        method: Method::Post,
+        path: "/heap/soak",
+        auth: Auth::Operator,
+        description: "Start or stop logging heap samples for a soak test",
+        query: &[],
+        body: Some("{\"enabled\": bool, \"interval_secs\": 10-3600}"),
+        handler: handlers::set_soak_test,
+    },
+    Route {
+        method: Method::Get,
+        path: "/support/report",
+        auth: Auth::Admin,
+        description: "Gzipped JSON bundle of firmware info, self-test, redacted config, alerts, stats and recent logs",
+        query: &[],
+        body: None,
+        handler: handlers::support_report,
+    },
+    Route {
+        method: Method::Get,
+        path: "/energy",
+        auth: Auth::Viewer,
+        description: "Estimated charge drawn since boot and time spent in each mode",
+        query: &[],
+        body: None,
+        handler: handlers::energy,
+    },
+    Route {
+        method: Method::Get,
+        path: "/sniffer",
+        auth: Auth::Viewer,
+        description: "Whether management frames are being captured, and how many",
+        query: &[],
+        body: None,
+        handler: handlers::sniffer_status,
+    },
+    Route {
+        method: Method::Get,
+        path: "/sniffer/counters",
+        auth: Auth::Viewer,
+        description: "Management, control and data frames per minute and channel over the last hour, as JSON or CSV",
+        query: &["format"],
+        body: None,
+        handler: handlers::sniffer_counters,
+    },
+    Route {
+        method: Method::Get,
+        path: "/identity",
+        auth: Auth::Viewer,
+        description: "Friendly name, location and tags of this device",
+        query: &[],
+        body: None,
+        handler: handlers::get_identity,
+    },
+    Route {
+        method: Method::Post,
+        path: "/identity",
+        auth: Auth::Operator,
+        description: "Set the name, location and tags shown on the dashboard and in the device_info metric",
+        query: &[],
+        body: Some("{\"name\": \"hall lamp\", \"location\": \"2nd floor\", \"tags\": [\"lamp\"]}"),
+        handler: handlers::set_identity,
+    },
+    Route {
+        method: Method::Get,
+        path: "/time",
+        auth: Auth::Viewer,
+        description: "Current time and where it comes from: SNTP, set by hand, or none",
+        query: &[],
+        body: None,
+        handler: handlers::get_time,
+    },
+    Route {
+        method: Method::Post,
+        path: "/time",
+        auth: Auth::Admin,
+        description: "Set the clock by hand when SNTP is unreachable; the next SNTP sync replaces it",
+        query: &[],
+        body: Some("{\"unix_time\": u64}"),
+        handler: handlers::set_time,
+    },
+    Route {
+        method: Method::Get,
+        path: "/syslog",
+        auth: Auth::Viewer,
+        description: "Syslog forwarding settings",
+        query: &[],
+        body: None,
+        handler: handlers::get_syslog,
+    },
+    Route {
+        method: Method::Post,
+        path: "/syslog",
+        auth: Auth::Admin,
+        description: "Forward log records at or above a level to a syslog server over UDP, applied at once",
+        query: &[],
+        body: Some("{\"enabled\": bool, \"server\": \"192.168.1.10\", \"port\": 514, \"level\": \"error\" | \"warn\" | \"info\" | \"debug\"}"),
+        handler: handlers::set_syslog,
+    },
+    Route {
+        method: Method::Get,
+        path: "/api/logs",
+        auth: Auth::Admin,
+        description: "Most recent log lines, oldest first",
+        query: &["lines"],
+        body: None,
+        handler: handlers::api_logs,
+    },
+    Route {
+        method: Method::Get,
+        path: "/api/logs/stream",
+        auth: Auth::Admin,
+        description: "Server-sent events tailing the device log for a few seconds; the browser reconnects to resume",
+        query: &["seconds"],
+        body: None,
+        handler: handlers::log_stream,
+    },
+    Route {
+        method: Method::Get,
+        path: "/sniffer/pcap",
+        auth: Auth::Operator,
+        description: "Stream management frames as a pcap file for Wireshark, up to 10 s; other requests wait until it ends",
+        query: &["seconds", "count", "subtype", "mac"],
+        body: None,
+        handler: handlers::sniffer_pcap,
+    },
+    Route {
+        method: Method::Get,
+        path: "/espnow",
+        auth: Auth::Viewer,
+        description: "ESP-NOW peers, counters and the most recent messages",
+        query: &[],
+        body: None,
+        handler: handlers::espnow_status,
+    },
+    Route {
+        method: Method::Post,
+        path: "/espnow/peers",
+        auth: Auth::Admin,
+        description: "Register an ESP-NOW peer on the current channel",
+        query: &[],
+        body: Some("{\"mac\": \"aa:bb:cc:dd:ee:ff\"}"),
+        handler: handlers::add_espnow_peer,
+    },
+    Route {
+        method: Method::Delete,
+        path: "/espnow/peers",
+        auth: Auth::Admin,
+        description: "Forget an ESP-NOW peer",
+        query: &["mac"],
+        body: None,
+        handler: handlers::remove_espnow_peer,
+    },
+    Route {
+        method: Method::Post,
+        path: "/espnow/send",
+        auth: Auth::Admin,
+        description: "Send up to 250 bytes to a peer, or broadcast them",
+        query: &[],
+        body: Some("{\"to\": \"aa:bb:cc:dd:ee:ff\" or null, \"data\": \"...\"}"),
+        handler: handlers::espnow_send,
+    },
+    Route {
+        method: Method::Get,
+        path: "/sleep",
+        auth: Auth::Viewer,
+        description: "Whether the device is in idle light sleep, and what keeps it awake",
+        query: &[],
+        body: None,
+        handler: handlers::sleep_status,
+    },
+    Route {
+        method: Method::Get,
+        path: "/sleep/settings",
+        auth: Auth::Viewer,
+        description: "Idle light sleep policy",
+        query: &[],
+        body: None,
+        handler: handlers::get_sleep_settings,
+    },
+    Route {
+        method: Method::Post,
+        path: "/sleep/settings",
+        auth: Auth::Admin,
+        description: "Set the idle light sleep policy; the wake button applies on next boot",
+        query: &[],
+        body: Some("{\"enabled\": bool, \"idle_minutes\": 1-1440, \"wake_gpio\": 0-10 or null}"),
+        handler: handlers::set_sleep_settings,
+    },
+    Route {
+        method: Method::Get,
+        path: "/sniffer/deauth",
+        auth: Auth::Viewer,
+        description: "Deauthentication frame rate that raises the alarm",
+        query: &[],
+        body: None,
+        handler: handlers::get_deauth_settings,
+    },
+    Route {
+        method: Method::Post,
+        path: "/sniffer/deauth",
+        auth: Auth::Operator,
+        description: "Set the deauthentication frame rate that raises the alarm",
+        query: &[],
+        body: Some("{\"frames_per_sec\": 1-1000}"),
+        handler: handlers::set_deauth_settings,
+    },
+    Route {
+        method: Method::Get,
+        path: "/rules",
+        auth: Auth::Viewer,
+        description: "Automation rules run against the event bus, webhook URLs redacted",
+        query: &[],
+        body: None,
+        handler: handlers::get_rules,
+    },
+    Route {
+        method: Method::Post,
+        path: "/rules",
+        auth: Auth::Admin,
+        description: "Replace the automation rules",
+        query: &[],
+        body: Some("[{\"name\", \"when\": {\"event\": ...}, \"conditions\": [{\"if\": \"time-between\", \"from\", \"to\"}], \"then\": [{\"do\": \"set-color\"|\"webhook\"|\"raise-alert\", ...}]}]"),
+        handler: handlers::set_rules,
+    },
+    Route {
+        method: Method::Get,
+        path: "/energy/model",
+        auth: Auth::Viewer,
+        description: "Current drawn in each mode, used by the energy estimate",
+        query: &[],
+        body: None,
+        handler: handlers::get_energy_model,
+    },
+    Route {
+        method: Method::Post,
+        path: "/energy/model",
+        auth: Auth::Operator,
+        description: "Set the per-mode currents and calibration factor",
+        query: &[],
+        body: Some("{\"station_ma\", \"access_point_ma\", \"honeypot_ma\", \"scanning_ma\", \"light_sleep_ma\": 0-1000, \"calibration\": 0.1-10.0}"),
+        handler: handlers::set_energy_model,
+    },
+    Route {
+        method: Method::Get,
+        path: "/api",
+        auth: Auth::Viewer,
+        description: "Machine-readable list of the registered routes",
+        query: &[],
+        body: None,
+        handler: handlers::api_index,
+    },
+    Route {
+        method: Method::Get,
+        path: "/profile",
+        auth: Auth::Viewer,
+        description: "Last applied profile, null if none",
+        query: &[],
+        body: None,
+        handler: handlers::get_profile,
+    },
+    Route {
+        method: Method::Post,
+        path: "/profile",
+        auth: Auth::Admin,
+        description: "Apply a profile's subsystem switches, LED display and WiFi mode",
+        query: &[],
+        body: Some("\"lamp\", \"scanner\", \"radio-gateway\" or \"pentest\""),
+        handler: handlers::set_profile,
+    },
+    Route {
+        method: Method::Get,
+        path: "/api/scan",
+        auth: Auth::Viewer,
+        description: "Networks seen by the last scan, by BSSID, paginated",
+        query: &["cursor", "limit"],
+        body: None,
+        handler: handlers::api_scan,
+    },
+    Route {
+        method: Method::Get,
+        path: "/api/channels",
+        auth: Auth::Viewer,
+        description: "AP count and signal per channel from the last scan, with the least congested channel",
+        query: &[],
+        body: None,
+        handler: handlers::api_channels,
+    },
+    Route {
+        method: Method::Get,
+        path: "/api/network",
+        auth: Auth::Viewer,
+        description: "Current SSID, BSSID, RSSI and addresses, ping statistics and reconnects since boot",
+        query: &[],
+        body: None,
+        handler: handlers::api_network,
+    },
+    Route {
+        method: Method::Get,
+        path: "/api/stations",
+        auth: Auth::Viewer,
+        description: "Stations associated with our access point, with RSSI and association time, paginated",
+        query: &["cursor", "limit"],
+        body: None,
+        handler: handlers::api_stations,
+    },
+    Route {
+        method: Method::Get,
+        path: "/api/probes",
+        auth: Auth::Viewer,
+        description: "Devices sending probe requests, by MAC address, paginated; needs the sniffer on",
+        query: &["cursor", "limit"],
+        body: None,
+        handler: handlers::api_probes,
+    },
+    Route {
+        method: Method::Get,
+        path: "/scan/history",
+        auth: Auth::Viewer,
+        description: "RSSI over time per BSSID, by BSSID and paginated, or one BSSID",
+        query: &["bssid", "cursor", "limit"],
+        body: None,
+        handler: handlers::scan_history,
+    },
+    Route {
+        method: Method::Get,
+        path: "/scan/twins",
+        auth: Auth::Viewer,
+        description: "SSIDs announced by access points with different security or far apart channels, paginated",
+        query: &["cursor", "limit"],
+        body: None,
+        handler: handlers::evil_twins,
+    },
+    Route {
+        method: Method::Get,
+        path: "/scan/settings",
+        auth: Auth::Viewer,
+        description: "Background scan interval and history size",
+        query: &[],
+        body: None,
+        handler: handlers::get_scanner_settings,
+    },
+    Route {
+        method: Method::Post,
+        path: "/scan/settings",
+        auth: Auth::Admin,
+        description: "Set the scan interval; the history size applies after the next reboot",
+        query: &[],
+        body: Some("{\"interval_secs\": 5-3600, \"history_samples\": 1-64}"),
+        handler: handlers::set_scanner_settings,
+    },
+    Route {
+        method: Method::Get,
+        path: "/scan/new-networks",
+        auth: Auth::Viewer,
+        description: "Alerting on access points never seen before, webhook URL redacted",
+        query: &[],
+        body: None,
+        handler: handlers::get_new_network_alerts,
+    },
+    Route {
+        method: Method::Post,
+        path: "/scan/new-networks",
+        auth: Auth::Admin,
+        description: "Enable alerts on new access points, optionally posted to a webhook",
+        query: &[],
+        body: Some("{\"enabled\": bool, \"severity\": \"info\"|\"warning\"|\"critical\", \"webhook_url\": \"https://...\"}"),
+        handler: handlers::set_new_network_alerts,
+    },
+    Route {
+        method: Method::Get,
+        path: "/subsystems",
+        auth: Auth::Viewer,
+        description: "Subsystem on/off switches",
+        query: &[],
+        body: None,
+        handler: handlers::get_subsystems,
+    },
+    Route {
+        method: Method::Post,
+        path: "/subsystems",
+        auth: Auth::Admin,
+        description: "Turn subsystems on or off",
+        query: &[],
+        body: Some("{\"scanner\": bool, \"animations\": bool, \"sniffer\": bool}, fields optional"),
+        handler: handlers::set_subsystems,
+    },
+    Route {
+        method: Method::Get,
+        path: "/scan/display",
+        auth: Auth::Viewer,
+        description: "LED display between scans",
+        query: &[],
+        body: None,
+        handler: handlers::get_scan_display,
+    },
+    Route {
+        method: Method::Post,
+        path: "/scan/display",
+        auth: Auth::Operator,
+        description: "Set the LED display between scans",
+        query: &[],
+        body: Some("\"flash\" or \"channel-heat\""),
+        handler: handlers::set_scan_display,
+    },
+    Route {
+        method: Method::Get,
+        path: "/wifi/mode",
+        auth: Auth::Viewer,
+        description: "WiFi mode",
+        query: &[],
+        body: None,
+        handler: handlers::get_wifi_mode,
+    },
+    Route {
+        method: Method::Post,
+        path: "/wifi/mode",
+        auth: Auth::Admin,
+        description: "Set the WiFi mode used after the next reboot",
+        query: &[],
+        body: Some("\"station\", \"access-point\", \"honeypot\" or \"mixed\""),
+        handler: handlers::set_wifi_mode,
+    },
+    Route {
+        method: Method::Get,
+        path: "/wifi/stats",
+        auth: Auth::Viewer,
+        description: "Per-network connection statistics",
+        query: &[],
+        body: None,
+        handler: handlers::wifi_stats,
+    },
+    Route {
+        method: Method::Get,
+        path: "/wifi/addressing",
+        auth: Auth::Viewer,
+        description: "Station addressing, DHCP or static",
+        query: &[],
+        body: None,
+        handler: handlers::get_sta_addressing,
+    },
+    Route {
+        method: Method::Post,
+        path: "/wifi/addressing",
+        auth: Auth::Admin,
+        description: "Set the station addressing used after the next reboot",
+        query: &[],
+        body: Some("{\"mode\": \"dhcp\"} or {\"mode\": \"static\", \"ip\", \"netmask\", \"gateway\", \"dns\", \"secondary_dns\"}"),
+        handler: handlers::set_sta_addressing,
+    },
+    Route {
+        method: Method::Get,
+        path: "/wifi/hostname",
+        auth: Auth::Viewer,
+        description: "DHCP hostname of the station",
+        query: &[],
+        body: None,
+        handler: handlers::get_hostname,
+    },
+    Route {
+        method: Method::Post,
+        path: "/wifi/hostname",
+        auth: Auth::Admin,
+        description: "Set the DHCP hostname used after the next reboot",
+        query: &[],
+        body: Some("{\"name\": \"living-room-lamp\"}, empty for the default"),
+        handler: handlers::set_hostname,
+    },
+    Route {
+        method: Method::Get,
+        path: "/wifi/power-save",
+        auth: Auth::Viewer,
+        description: "Station modem power save mode",
+        query: &[],
+        body: None,
+        handler: handlers::get_power_save,
+    },
+    Route {
+        method: Method::Post,
+        path: "/wifi/power-save",
+        auth: Auth::Admin,
+        description: "Set the station modem power save mode, applied at once",
+        query: &[],
+        body: Some("\"none\", \"min\" or \"max\""),
+        handler: handlers::set_power_save,
+    },
+    Route {
+        method: Method::Get,
+        path: "/wifi/country",
+        auth: Auth::Viewer,
+        description: "Regulatory domain: allowed channels and transmit power",
+        query: &[],
+        body: None,
+        handler: handlers::get_wifi_country,
+    },
+    Route {
+        method: Method::Post,
+        path: "/wifi/country",
+        auth: Auth::Admin,
+        description: "Set the WiFi country, applied at once",
+        query: &[],
+        body: Some("{\"code\": \"FR\"}, or \"01\" for the world-wide safe domain"),
+        handler: handlers::set_wifi_country,
+    },
+    Route {
+        method: Method::Get,
+        path: "/wifi/mac",
+        auth: Auth::Viewer,
+        description: "Station MAC address setting",
+        query: &[],
+        body: None,
+        handler: handlers::get_sta_mac,
+    },
+    Route {
+        method: Method::Post,
+        path: "/wifi/mac",
+        auth: Auth::Admin,
+        description: "Set the station MAC used after the next reboot",
+        query: &[],
+        body: Some("{\"mode\": \"factory\"}, {\"mode\": \"random\", \"per_reconnect\": bool} or {\"mode\": \"fixed\", \"mac\": \"02:00:00:00:00:01\"}"),
+        handler: handlers::set_sta_mac,
+    },
+    Route {
+        method: Method::Get,
+        path: "/wifi/link",
+        auth: Auth::Viewer,
+        description: "Station link state and reconnect progress",
+        query: &[],
+        body: None,
+        handler: handlers::wifi_link,
+    },
+    Route {
+        method: Method::Get,
+        path: "/wifi/latency",
+        auth: Auth::Viewer,
+        description: "Link health and rolling ping statistics of the gateway and extra hosts",
+        query: &[],
+        body: None,
+        handler: handlers::wifi_latency,
+    },
+    Route {
+        method: Method::Get,
+        path: "/wifi/latency/settings",
+        auth: Auth::Viewer,
+        description: "Latency monitor settings",
+        query: &[],
+        body: None,
+        handler: handlers::get_latency_settings,
+    },
+    Route {
+        method: Method::Post,
+        path: "/wifi/latency/settings",
+        auth: Auth::Admin,
+        description: "Set the latency monitor interval, extra hosts and health thresholds",
+        query: &[],
+        body: Some("{\"enabled\": bool, \"interval_secs\": 5-3600, \"extra_hosts\": [\"1.1.1.1\"], \"degraded_loss_percent\": 20.0, \"degraded_rtt_ms\": 200.0}"),
+        handler: handlers::set_latency_settings,
+    },
+    Route {
+        method: Method::Get,
+        path: "/wifi/networks",
+        auth: Auth::Viewer,
+        description: "Saved networks in priority order, without passwords",
+        query: &[],
+        body: None,
+        handler: handlers::get_known_networks,
+    },
+    Route {
+        method: Method::Post,
+        path: "/wifi/networks",
+        auth: Auth::Admin,
+        description: "Save a network, replacing one with the same SSID",
+        query: &[],
+        body: Some("{\"ssid\": string, \"password\": string, \"priority\": 0-255, \"enterprise\": {\"identity\", \"username\", \"password\", \"ca_cert\", \"ttls_phase2\"}}, enterprise optional"),
+        handler: handlers::add_known_network,
+    },
+    Route {
+        method: Method::Get,
+        path: "/pair",
+        auth: Auth::None,
+        description: "Whether an API key has been issued",
+        query: &[],
+        body: None,
+        handler: handlers::pairing_status,
+    },
+    Route {
+        method: Method::Post,
+        path: "/pair",
+        auth: Auth::Exempt,
+        description: "Exchange the one-time pairing code for the API key",
+        query: &[],
+        body: Some("{\"code\": \"12345678\"}"),
+        handler: handlers::pair,
+    },
+    Route {
+        method: Method::Delete,
+        path: "/pair",
+        auth: Auth::Admin,
+        description: "Revoke every API key and issue a new pairing code",
+        query: &[],
+        body: None,
+        handler: handlers::unpair,
+    },
+    Route {
+        method: Method::Get,
+        path: "/users",
+        auth: Auth::Admin,
+        description: "Names and roles of the API keys issued besides the admin key",
+        query: &[],
+        body: None,
+        handler: handlers::users,
+    },
+    Route {
+        method: Method::Post,
+        path: "/users",
+        auth: Auth::Admin,
+        description: "Issue an API key with the viewer, operator or admin role, replacing the name's previous key",
+        query: &[],
+        body: Some("{\"name\": \"kiosk\", \"role\": \"viewer\"}"),
+        handler: handlers::add_user,
+    },
+    Route {
+        method: Method::Delete,
+        path: "/users",
+        auth: Auth::Admin,
+        description: "Revoke a user's API key",
+        query: &["name"],
+        body: None,
+        handler: handlers::remove_user,
+    },
+    Route {
+        method: Method::Delete,
+        path: "/wifi/networks",
+        auth: Auth::Admin,
+        description: "Forget a saved network",
+        query: &["ssid"],
+        body: None,
+        handler: handlers::remove_known_network,
+    },
+    Route {
+        method: Method::Get,
+        path: "/ap/clients",
+        auth: Auth::Viewer,
+        description: "Access point clients",
+        query: &[],
+        body: None,
+        handler: handlers::ap_clients,
+    },
+    Route {
+        method: Method::Get,
+        path: "/track",
+        auth: Auth::Viewer,
+        description: "Tracked BSSID and its signal strength",
+        query: &[],
+        body: None,
+        handler: handlers::get_tracking,
+    },
+    Route {
+        method: Method::Post,
+        path: "/track",
+        auth: Auth::Operator,
+        description: "Track a BSSID to locate an AP",
+        query: &[],
+        body: Some("BSSID as AA:BB:CC:DD:EE:FF, or \"none\" to stop"),
+        handler: handlers::set_tracking,
+    },
+    Route {
+        method: Method::Get,
+        path: "/provisioning/qr.svg",
+        auth: Auth::None,
+        description: "Provisioning QR code",
+        query: &[],
+        body: None,
+        handler: handlers::provisioning_qr,
+    },
+    Route {
+        method: Method::Get,
+        path: "/diag/ping",
+        auth: Auth::Viewer,
+        description: "Ping a host for up to 4 s and report latency and loss; mode=trace also pings the gateway and DNS server within the same time",
+        query: &["host", "count", "mode"],
+        body: None,
+        handler: handlers::diag_ping,
+    },
+    Route {
+        method: Method::Get,
+        path: "/diag/dns",
+        auth: Auth::Viewer,
+        description: "Resolve a host name and report the A/AAAA records and lookup time",
+        query: &["name"],
+        body: None,
+        handler: handlers::diag_dns,
+    },
+    Route {
+        method: Method::Get,
+        path: "/diag/gpio",
+        auth: Auth::Admin,
+        description: "Read the level of a free GPIO, optionally pulled up or down; pins in use are refused",
+        query: &["pin", "pull"],
+        body: None,
+        handler: handlers::diag_gpio_read,
+    },
+    Route {
+        method: Method::Post,
+        path: "/diag/gpio",
+        auth: Auth::Admin,
+        description: "Drive a free GPIO high or low, or toggle it, for up to 10 s; other requests wait until it ends",
+        query: &[],
+        body: Some("{\"pin\": u8, \"action\": \"high\" | \"low\", \"hold_ms\": u32} or {\"pin\": u8, \"action\": \"toggle\", \"count\": u32, \"interval_ms\": u32}"),
+        handler: handlers::diag_gpio_drive,
+    },
+    Route {
+        method: Method::Post,
+        path: "/diag/spi-loopback",
+        auth: Auth::Admin,
+        description: "Send a test pattern on SPI2 with MOSI jumpered to MISO and compare what comes back",
+        query: &[],
+        body: Some("{\"sclk\": u8, \"mosi\": u8, \"miso\": u8}"),
+        handler: handlers::diag_spi_loopback,
+    },
+    Route {
+        method: Method::Get,
+        path: "/timeline",
+        auth: Auth::Viewer,
+        description: "Scans, AP changes, alerts and setting changes over the last hour, paginated",
+        query: &["since", "cursor", "limit"],
+        body: None,
+        handler: handlers::timeline,
+    },
+    Route {
+        method: Method::Get,
+        path: "/alerts",
+        auth: Auth::Viewer,
+        description: "Alerts, paginated",
+        query: &["cursor", "limit"],
+        body: None,
+        handler: handlers::get_alerts,
+    },
+    Route {
+        method: Method::Post,
+        path: "/alerts",
+        auth: Auth::Operator,
+        description: "Acknowledge an alert",
+        query: &[],
+        body: Some("{\"id\": u32}"),
+        handler: handlers::acknowledge_alert,
+    },
+    Route {
+        method: Method::Get,
+        path: "/led/calibration",
+        auth: Auth::Viewer,
+        description: "LED calibration gains",
+        query: &[],
+        body: None,
+        handler: handlers::get_led_calibration,
+    },
+    Route {
+        method: Method::Post,
+        path: "/led/calibration",
+        auth: Auth::Operator,
+        description: "Adjust the LED white point",
+        query: &[],
+        body: Some("{\"red\": f32, \"green\": f32, \"blue\": f32}, gains 0.0-1.0"),
+        handler: handlers::set_led_calibration,
+    },
+    Route {
+        method: Method::Get,
+        path: "/led/power",
+        auth: Auth::Viewer,
+        description: "LED power limits",
+        query: &[],
+        body: None,
+        handler: handlers::get_led_power,
+    },
+    Route {
+        method: Method::Post,
+        path: "/led/power",
+        auth: Auth::Operator,
+        description: "Limit LED current",
+        query: &[],
+        body: Some("{\"max_combined_percent\": u8, \"max_rise_percent\": u8}, 1-100"),
+        handler: handlers::set_led_power,
+    },
+    Route {
+        method: Method::Get,
+        path: "/led/priority",
+        auth: Auth::Viewer,
+        description: "Whether POST /color preempts scans, and for how long",
+        query: &[],
+        body: None,
+        handler: handlers::get_led_priority,
+    },
+    Route {
+        method: Method::Post,
+        path: "/led/priority",
+        auth: Auth::Admin,
+        description: "Let POST /color abort scans and pause them and the animations, or let scans always complete",
+        query: &[],
+        body: Some("{\"policy\": \"latency\" | \"data-collection\", \"hold_ms\": u16}, 100-30000"),
+        handler: handlers::set_led_priority,
+    },
+    Route {
+        method: Method::Get,
+        path: "/led/{channel}",
+        auth: Auth::Viewer,
+        description: "Last value set on one LED channel: red, green or blue",
+        query: &[],
+        body: None,
+        handler: handlers::get_led_channel,
+    },
+    Route {
+        method: Method::Post,
+        path: "/led/{channel}",
+        auth: Auth::Operator,
+        description: "Set one LED channel, keeping the other two",
+        query: &[],
+        body: Some("0-255"),
+        handler: handlers::set_led_channel,
+    },
+    Route {
+        method: Method::Get,
+        path: "/led/output",
+        auth: Auth::Viewer,
+        description: "GPIOs driving the LED",
+        query: &[],
+        body: None,
+        handler: handlers::get_led_output,
+    },
+    Route {
+        method: Method::Post,
+        path: "/led/output",
+        auth: Auth::Admin,
+        description: "Set the LED GPIOs used after the next reboot, one per channel",
+        query: &[],
+        body: Some("{\"pins\": [u8]}, 1-3 pins out of GPIO0-10"),
+        handler: handlers::set_led_output,
+    },
+    Route {
+        method: Method::Get,
+        path: "/http/sessions",
+        auth: Auth::Viewer,
+        description: "Open HTTP sockets, their client and idle time",
+        query: &[],
+        body: None,
+        handler: handlers::http_sessions,
+    },
+    Route {
+        method: Method::Get,
+        path: "/http/settings",
+        auth: Auth::Viewer,
+        description: "HTTP socket limits",
+        query: &[],
+        body: None,
+        handler: handlers::get_http_settings,
+    },
+    Route {
+        method: Method::Post,
+        path: "/http/settings",
+        auth: Auth::Admin,
+        description: "Set the HTTP socket limits used after the next reboot",
+        query: &[],
+        body: Some("{\"max_open_sockets\": 1-7, \"max_sockets_per_client\": u8, \"idle_timeout_secs\": 5-3600, \"https\": bool, \"redirect_http\": bool, \"access_log\": bool, \"slow_request_ms\": 10-60000}"),
+        handler: handlers::set_http_settings,
+    },
+    Route {
+        method: Method::Get,
+        path: "/api/files",
+        auth: Auth::Viewer,
+        description: "Files in the storage partition, with its used and total bytes",
+        query: &[],
+        body: None,
+        handler: handlers::list_files,
+    },
+    Route {
+        method: Method::Get,
+        path: "/api/files/{name}",
+        auth: Auth::Viewer,
+        description: "Download a file from the storage partition",
+        query: &[],
+        body: None,
+        handler: handlers::get_file,
+    },
+    Route {
+        method: Method::Put,
+        path: "/api/files/{name}",
+        auth: Auth::Admin,
+        description: "Create or replace a file in the storage partition; dashboard.html replaces the dashboard",
+        query: &[],
+        body: Some("file contents"),
+        handler: handlers::put_file,
+    },
+    Route {
+        method: Method::Delete,
+        path: "/api/files/{name}",
+        auth: Auth::Admin,
+        description: "Delete a file from the storage partition",
+        query: &[],
+        body: None,
+        handler: handlers::delete_file,
+    },
+    Route {
+        method: Method::Get,
+        path: "/api/txlog",
+        auth: Auth::Viewer,
+        description: "Estimated transmit airtime, frames and highest PA level per channel, across reboots",
+        query: &[],
+        body: None,
+        handler: handlers::tx_log,
+    },
+    Route {
+        method: Method::Get,
+        path: "/api/txlog.csv",
+        auth: Auth::Viewer,
+        description: "The transmit log as CSV, signed with an HMAC-SHA256 keyed with the hex SHA-256 of the admin API key",
+        query: &[],
+        body: None,
+        handler: handlers::tx_log_csv,
+    },
+    Route {
+        method: Method::Get,
+        path: "/tls",
+        auth: Auth::Viewer,
+        description: "Whether an HTTPS certificate is installed and in use, with its SHA-256 fingerprint",
+        query: &[],
+        body: None,
+        handler: handlers::tls_info,
+    },
+    Route {
+        method: Method::Post,
+        path: "/tls",
+        auth: Auth::Admin,
+        description: "Install the HTTPS certificate and private key used after the next reboot",
+        query: &[],
+        body: Some("{\"certificate\": PEM, \"private_key\": PEM}"),
+        handler: handlers::install_tls,
+    },
+    Route {
+        method: Method::Delete,
+        path: "/tls",
+        auth: Auth::Admin,
+        description: "Remove the HTTPS certificate, back to plain HTTP after the next reboot",
+        query: &[],
+        body: None,
+        handler: handlers::clear_tls,
+    },
+    Route {
+        method: Method::Post,
+        path: "/color",
+        auth: Auth::Operator,
+        description: "Set the LED color, from the body or, without one, the query string",
+        query: &["color", "r", "g", "b"],
+        body: Some("FF0000, #FF0000, #F00 or rgb(255, 0, 0); or as a form, color=%23FF0000 or r=255&g=0&b=0"),
+        handler: handlers::set_color,
+    },
+];
+
+/// Only registered while the device runs as a honeypot.
+pub const HONEYPOT_ROUTES: &[Route] = &[
+    Route {
+        method: Method::Get,
+        path: "/portal",
+        auth: Auth::None,
+        description: "Decoy captive portal",
+        query: &["lang"],
+        body: None,
+        handler: handlers::portal,
+    },
+    Route {
+        method: Method::Post,
+        path: "/portal",
+        auth: Auth::Exempt,
+        description: "Decoy login form",
+        query: &["lang"],
+        body: Some("username=...&password=... form data"),
+        handler: handlers::portal_login,
+    },
+    Route {
+        method: Method::Get,
+        path: "/honeypot/findings",
+        auth: Auth::Admin,
+        description: "Honeypot associations and portal logins, paginated",
+        query: &["cursor", "limit"],
+        body: None,
+        handler: handlers::honeypot_findings,
+    },
+    Route {
+        method: Method::Get,
+        path: "/honeypot/findings/stream",
+        auth: Auth::Admin,
+        description: "Server-sent events with each new honeypot finding for a few seconds; the browser reconnects to resume",
+        query: &["seconds"],
+        body: None,
+        handler: handlers::honeypot_stream,
+    },
+];
+
+// The tables are checked at compile time as well; `validate` repeats the checks on
+// the routes actually registered.
+const _: () = assert!(tables_are_valid(&[ROUTES, HONEYPOT_ROUTES]), "invalid HTTP route table");
+
+const fn tables_are_valid(tables: &[&[Route]]) -> bool {
+    let mut t = 0;
+    while t < tables.len() {
+        let mut i = 0;
+        while i < tables[t].len() {
+            let route = &tables[t][i];
+            if !matches!(route.method, Method::Get) && matches!(route.auth, Auth::None) {
+                return false;
+            }
+
+            // Compare against every route listed before this one, in any table
+            let mut u = 0;
+            while u <= t {
+                let end = if u == t { i } else { tables[u].len() };
+                let mut j = 0;
+                while j < end {
+                    let other = &tables[u][j];
+                    if route.method as u8 == other.method as u8 && same_str(route.path, other.path) {
+                        return false;
+                    }
+                    j += 1;
+                }
+                u += 1;
+            }
+            i += 1;
+        }
+        t += 1;
+    }
+    true
+}
+
+const fn same_str(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Entry of the `GET /api` listing.
+#[derive(Debug, Serialize)]
+pub struct RouteInfo {
+    pub method: &'static str,
+    pub path: &'static str,
+    pub auth: Auth,
+    pub description: &'static str,
+    pub query: &'static [&'static str],
+    pub body: Option<&'static str>,
+}
+
+impl From<&Route> for RouteInfo {
+    fn from(route: &Route) -> Self {
+        RouteInfo {
+            method: method_name(route.method),
+            path: route.path,
+            auth: route.auth,
+            description: route.description,
+            query: route.query,
+            body: route.body,
+        }
+    }
+}
+
+pub fn method_name(method: Method) -> &'static str {
+    match method {
+        Method::Get => "GET",
+        Method::Post => "POST",
+        Method::Put => "PUT",
+        Method::Delete => "DELETE",
+        Method::Patch => "PATCH",
+        Method::Head => "HEAD",
+        Method::Options => "OPTIONS",
+        _ => "OTHER",
+    }
+}
+
+/// Server settings with room for every route in the tables and the WebSocket;
+/// the ESP-IDF default of 32 URI handlers is not enough. With a certificate and
+/// key the server speaks HTTPS only.
+pub fn server_configuration(settings: &HttpSettings, tls: Option<(X509<'static>, X509<'static>)>) -> server::Configuration {
+    server::Configuration {
+        max_uri_handlers: ROUTES.len() + HONEYPOT_ROUTES.len() + 1,
+        max_open_sockets: settings.max_open_sockets as usize,
+        lru_purge_enable: true,
+        uri_match_wildcard: true,
+        server_certificate: tls.map(|(certificate, _)| certificate),
+        private_key: tls.map(|(_, private_key)| private_key),
+        ..Default::default()
+    }
+}
+
+pub fn active_routes(config: &AppConfig) -> Vec<&'static Route> {
+    let mut routes: Vec<&'static Route> = ROUTES.iter().collect();
+    if config.wifi_mode == WifiMode::Honeypot {
+        routes.extend(HONEYPOT_ROUTES);
+    }
+    routes
+}
+
+/// Rejects duplicate method/path pairs and state-changing routes that are open
+/// without being explicitly exempted.
+pub fn validate(routes: &[&Route]) -> Result<()> {
+    for (i, route) in routes.iter().enumerate() {
+        if routes[..i]
+            .iter()
+            .any(|other| other.method == route.method && other.path == route.path)
+        {
+            bail!("Duplicate route {:?} {}", route.method, route.path);
+        }
+
+        if route.method != Method::Get && route.auth == Auth::None {
+            bail!("Route {:?} {} changes state but requires no auth", route.method, route.path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Registers the routes. A route with `{name}` segments is registered as a
+/// wildcard on the path before its first parameter, after all literal paths so
+/// those win; routes sharing a method and wildcard are tried in table order.
+pub fn register(server: &mut EspHttpServer<'static>, state: Arc<AppState>) -> Result<()> {
+    let routes = active_routes(&state.config.get());
+    validate(&routes)?;
+
+    let mut registrations: Vec<(Method, String, Vec<&'static Route>)> = Vec::new();
+    for route in routes.iter().filter(|route| !has_params(route.path)) {
+        registrations.push((route.method, route.path.to_string(), vec![*route]));
+    }
+    for route in routes.iter().filter(|route| has_params(route.path)) {
+        let uri = wildcard_uri(route.path);
+        match registrations.iter_mut().find(|(method, other, _)| *method == route.method && *other == uri) {
+            Some((_, _, candidates)) => candidates.push(route),
+            None => registrations.push((route.method, uri, vec![*route])),
+        }
+    }
+
+    for (method, uri, candidates) in registrations {
+        let state = state.clone();
+        server.fn_handler(&uri, method, move |mut req| -> Result<()> {
+            let started = Instant::now();
+            state.sessions.on_request(&mut req);
+            state.sleep.touch();
+            let connection = req.release();
+            let path = connection.uri().split('?').next().unwrap_or_default().to_string();
+            match candidates.iter().find(|route| match_path(route.path, &path).is_some()) {
+                Some(route) => dispatch(route, connection, &path, &state, started),
+                None => errors::write_error(connection, 404, "No such resource", &[]),
+            }
+        })?;
+    }
+
+    log::info!("HTTP handlers registered");
+    Ok(())
+}
+
+/// Auth, error responses, statistics and the access log around a handler.
+/// Handlers pick their own status, which the server does not report back, so
+/// one that succeeds is logged with 200.
+fn dispatch(
+    route: &Route,
+    connection: &mut EspHttpConnection,
+    uri: &str,
+    state: &AppState,
+    started: Instant,
+) -> Result<()> {
+    let (method, path) = (method_name(route.method), route.path);
+    if let Some(needed) = route.auth.role() {
+        let refusal = match state.pairing.role(connection.header("Authorization")) {
+            Some(role) if role >= needed => None,
+            Some(role) => Some((
+                403,
+                format!("Needs the {} role, this key has {}", needed.as_str(), role.as_str()),
+            )),
+            None if state.pairing.is_paired() => Some((401, "Missing or wrong API key".to_string())),
+            None => Some((401, "Not paired yet, exchange the pairing code at POST /pair first".to_string())),
+        };
+        if let Some((status, message)) = refusal {
+            let headers: &[(&str, &str)] = match status {
+                401 => &[("WWW-Authenticate", "Basic realm=\"esp32\", Bearer")],
+                _ => &[],
+            };
+            errors::write_error(connection, status, &message, headers)?;
+            state.http_stats.record(method, path, uri, status, started.elapsed());
+            return Ok(());
+        }
+    }
+    let result = (route.handler)(Request::wrap(&mut *connection), state);
+    let status = match &result {
+        Ok(()) => 200,
+        Err(e) => errors::status(e),
+    };
+    state.http_stats.record(method, path, uri, status, started.elapsed());
+    match result {
+        // Once the response is on its way the error can only be logged
+        Err(e) if !connection.is_response_initiated() => {
+            let status = errors::status(&e);
+            if status >= 500 {
+                log::error!("{} {} failed: {:?}", method, path, e);
+            } else {
+                log::info!("{} {} refused: {}", method, path, e);
+            }
+            errors::write_error(connection, status, &format!("{:#}", e), &[])
+        }
+        result => result,
+    }
+}
+
+fn has_params(pattern: &str) -> bool {
+    pattern.contains('{')
+}
+
+/// `/led/{channel}` is registered as `/led/*`.
+fn wildcard_uri(pattern: &str) -> String {
+    let prefix = pattern.split('{').next().unwrap_or_default();
+    format!("{}*", prefix)
+}
+
+/// The parameters of `path` when it matches `pattern`, segment by segment.
+pub fn match_path(pattern: &'static str, path: &str) -> Option<Vec<(&'static str, String)>> {
+    let mut params = Vec::new();
+    let mut segments = path.split('/');
+    for expected in pattern.split('/') {
+        let segment = segments.next()?;
+        match expected.strip_prefix('{').and_then(|name| name.strip_suffix('}')) {
+            Some(_) if segment.is_empty() => return None,
+            Some(name) => params.push((name, percent_decode_path(segment))),
+            None if expected == segment => {}
+            None => return None,
+        }
+    }
+    match segments.next() {
+        Some(_) => None,
+        None => Some(params),
+    }
+}
+
+/// The value of the `{name}` segment in the request path.
+pub fn path_param(req: &HttpRequest, name: &str) -> Option<String> {
+    let path = req.uri().split('?').next().unwrap_or_default();
+    ROUTES
+        .iter()
+        .chain(HONEYPOT_ROUTES)
+        .filter(|route| route.method == req.method() && has_params(route.path))
+        .find_map(|route| match_path(route.path, path))?
+        .into_iter()
+        .find(|(param, _)| *param == name)
+        .map(|(_, value)| value)
+}
@@ -0,0 +1,251 @@
+//! "When <event> [if <conditions>] then <actions>" rules, stored with the
+//! configuration and evaluated against the event bus.
+//!
+//! ```json
+//! {"name": "late visitor",
+//!  "when": {"event": "ap-appeared", "bssid": "aa:bb:cc:dd:ee:ff"},
+//!  "conditions": [{"if": "time-between", "from": "22:00", "to": "06:00"}],
+//!  "then": [{"do": "set-color", "color": "#ff0000"},
+//!           {"do": "webhook", "url": "https://example.com/hook"}]}
+//! ```
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+
+use crate::alerts::{unix_time, AlertLog, Severity};
+use crate::color::Color;
+use crate::config::{ConfigStore, Secret};
+use crate::events::{Event, EventBus};
+use crate::latency::LinkHealth;
+use crate::led::RgbLed;
+use crate::scan::parse_mac;
+use crate::webhook;
+
+pub const MAX_RULES: usize = 16;
+/// Queue between the event bus and the rules.
+pub const EVENT_QUEUE: usize = 16;
+/// Alerts raised by rules carry this source and never trigger rules themselves.
+const ALERT_SOURCE: &str = "rules";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rule {
+    pub name: String,
+    pub when: Trigger,
+    /// All must hold.
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    pub then: Vec<Action>,
+}
+
+/// Event that fires a rule. Unset filters match anything.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Trigger {
+    ApAppeared {
+        #[serde(default)]
+        bssid: Option<String>,
+        #[serde(default)]
+        ssid: Option<String>,
+    },
+    ApDisappeared {
+        #[serde(default)]
+        bssid: Option<String>,
+        #[serde(default)]
+        ssid: Option<String>,
+    },
+    ScanCompleted,
+    DeauthFlood,
+    AlertRaised {
+        #[serde(default)]
+        severity: Option<Severity>,
+        #[serde(default)]
+        source: Option<String>,
+    },
+    SettingChanged {
+        #[serde(default)]
+        setting: Option<String>,
+    },
+    LinkHealthChanged {
+        #[serde(default)]
+        health: Option<LinkHealth>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "if", rename_all = "kebab-case")]
+pub enum Condition {
+    /// `HH:MM` in UTC; `from` after `to` wraps over midnight. False until SNTP or
+    /// `POST /time` has set the clock.
+    TimeBetween { from: String, to: String },
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "do", rename_all = "kebab-case")]
+pub enum Action {
+    SetColor { color: String },
+    /// POSTs the triggering event as JSON.
+    Webhook { url: String },
+    RaiseAlert { severity: Severity, message: String },
+}
+
+/// Webhook URLs often carry a token, so they stay out of the logs.
+impl fmt::Debug for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::SetColor { color } => f.debug_struct("SetColor").field("color", color).finish(),
+            Action::Webhook { url } => f.debug_struct("Webhook").field("url", &Secret(url)).finish(),
+            Action::RaiseAlert { severity, message } => f
+                .debug_struct("RaiseAlert")
+                .field("severity", severity)
+                .field("message", message)
+                .finish(),
+        }
+    }
+}
+
+impl Rule {
+    pub fn validate(&self) -> Result<()> {
+        if let Trigger::ApAppeared { bssid: Some(bssid), .. } | Trigger::ApDisappeared { bssid: Some(bssid), .. } =
+            &self.when
+        {
+            if parse_mac(bssid).is_none() {
+                bail!("{}: {} is not a valid BSSID", self.name, bssid);
+            }
+        }
+        for condition in &self.conditions {
+            let Condition::TimeBetween { from, to } = condition;
+            if minutes_of_day(from).is_none() || minutes_of_day(to).is_none() {
+                bail!("{}: times must be HH:MM", self.name);
+            }
+        }
+        if self.then.is_empty() {
+            bail!("{}: a rule needs at least one action", self.name);
+        }
+        for action in &self.then {
+            match action {
+                Action::SetColor { color } => {
+                    color.parse::<Color>()?;
+                }
+                Action::Webhook { url } if !url.starts_with("http://") && !url.starts_with("https://") => {
+                    bail!("{}: webhook url must be http:// or https://", self.name);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn matches(&self, event: &Event) -> bool {
+        let trigger = match (&self.when, event) {
+            (Trigger::ApAppeared { bssid, ssid }, Event::ApAppeared { bssid: seen, ssid: seen_ssid, .. })
+            | (Trigger::ApDisappeared { bssid, ssid }, Event::ApDisappeared { bssid: seen, ssid: seen_ssid }) => {
+                accepts(bssid, |bssid| same_mac(bssid, seen)) && accepts(ssid, |ssid| ssid == seen_ssid)
+            }
+            (Trigger::ScanCompleted, Event::ScanCompleted { .. }) => true,
+            (Trigger::DeauthFlood, Event::DeauthFlood { .. }) => true,
+            (Trigger::AlertRaised { severity, source }, Event::AlertRaised { severity: raised, source: from, .. }) => {
+                from != ALERT_SOURCE
+                    && accepts(severity, |severity| severity == raised)
+                    && accepts(source, |source| source == from)
+            }
+            (Trigger::SettingChanged { setting }, Event::SettingChanged { setting: changed, .. }) => {
+                accepts(setting, |setting| setting == changed)
+            }
+            (Trigger::LinkHealthChanged { health }, Event::LinkHealthChanged { health: changed }) => {
+                accepts(health, |health| health == changed)
+            }
+            _ => false,
+        };
+
+        trigger && self.conditions.iter().all(Condition::holds)
+    }
+}
+
+impl Condition {
+    fn holds(&self) -> bool {
+        let Condition::TimeBetween { from, to } = self;
+        let (Some(from), Some(to), Some(now)) = (minutes_of_day(from), minutes_of_day(to), unix_time()) else {
+            return false;
+        };
+
+        let now = (now % 86_400 / 60) as u16;
+        if from <= to {
+            (from..to).contains(&now)
+        } else {
+            now >= from || now < to
+        }
+    }
+}
+
+/// Runs the configured rules on every event from the bus.
+pub struct RulesEngine {
+    config: Arc<ConfigStore>,
+    led: Arc<RgbLed>,
+    alerts: Arc<AlertLog>,
+    events: Arc<EventBus>,
+}
+
+impl RulesEngine {
+    pub fn new(config: Arc<ConfigStore>, led: Arc<RgbLed>, alerts: Arc<AlertLog>, events: Arc<EventBus>) -> Self {
+        RulesEngine {
+            config,
+            led,
+            alerts,
+            events,
+        }
+    }
+
+    pub fn run(&self, events: Receiver<Event>) {
+        for event in events {
+            for rule in self.config.get().rules.iter().filter(|rule| rule.matches(&event)) {
+                log::info!("Rule {} fired on {:?}", rule.name, event);
+                for action in &rule.then {
+                    if let Err(e) = self.perform(rule, action, &event) {
+                        log::warn!("Rule {} failed: {:?}", rule.name, e);
+                    }
+                }
+            }
+        }
+    }
+
+    fn perform(&self, rule: &Rule, action: &Action, event: &Event) -> Result<()> {
+        match action {
+            Action::SetColor { color } => {
+                let color = color.parse::<Color>()?;
+                self.led.set(color.r, color.g, color.b)?;
+                self.events.publish(Event::LedChanged {
+                    color: color.to_hex(),
+                    source: format!("rule {}", rule.name),
+                });
+                Ok(())
+            }
+            Action::Webhook { url } => webhook::post_json(url, event),
+            Action::RaiseAlert { severity, message } => {
+                self.alerts.raise(*severity, ALERT_SOURCE, message.clone());
+                Ok(())
+            }
+        }
+    }
+}
+
+/// An unset filter accepts anything.
+fn accepts<T>(filter: &Option<T>, matches: impl FnOnce(&T) -> bool) -> bool {
+    match filter {
+        Some(wanted) => matches(wanted),
+        None => true,
+    }
+}
+
+fn minutes_of_day(time: &str) -> Option<u16> {
+    let (hours, minutes) = time.split_once(':')?;
+    let (hours, minutes): (u16, u16) = (hours.parse().ok()?, minutes.parse().ok()?);
+    (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+}
+
+fn same_mac(a: &str, b: &str) -> bool {
+    matches!((parse_mac(a), parse_mac(b)), (Some(a), Some(b)) if a == b)
+}
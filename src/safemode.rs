@@ -0,0 +1,80 @@
+//! Crash loop detection. Boots that end in a panic or a watchdog reset are
+//! counted in NVS; after [`CRASH_LOOP_BOOTS`] of them in a row the device comes
+//! up in safe mode, with WiFi and HTTP only, so it can still be reconfigured or
+//! re-flashed: no scanner or sniffer, no ESP-NOW, no animations or display, and
+//! none of the background threads (detectors, rules, OTA pulls, idle sleep).
+//! Any other reset, such as `POST /api/reboot` or a power cycle, clears the
+//! count and the next boot is a normal one.
+
+use anyhow::Result;
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs};
+use std::time::Duration;
+
+use crate::alerts;
+use crate::version;
+
+const NAMESPACE: &str = "boot";
+const CRASHES_KEY: &str = "crashes";
+/// Crash resets in a row that trigger safe mode.
+pub const CRASH_LOOP_BOOTS: u8 = 3;
+/// Amber, shown on the LED for the whole safe mode boot.
+pub const LED_COLOR: (u8, u8, u8) = (255, 80, 0);
+/// Between answering `POST /api/reboot` and restarting.
+pub const REBOOT_DELAY: Duration = Duration::from_millis(500);
+
+pub struct BootGuard {
+    reset_reason: &'static str,
+    consecutive_crashes: u8,
+}
+
+impl BootGuard {
+    /// Counts the current boot.
+    pub fn new(partition: EspDefaultNvsPartition) -> Result<Self> {
+        let mut nvs = EspNvs::new(partition, NAMESPACE, true)?;
+        let reset_reason = alerts::reset_reason();
+        let consecutive_crashes = next_count(nvs.get_u8(CRASHES_KEY)?.unwrap_or(0), reset_reason);
+        nvs.set_u8(CRASHES_KEY, consecutive_crashes)?;
+
+        Ok(BootGuard {
+            reset_reason,
+            consecutive_crashes,
+        })
+    }
+
+    /// Without NVS only the current reset is known, never enough for safe mode.
+    pub fn in_memory() -> Self {
+        let reset_reason = alerts::reset_reason();
+        BootGuard {
+            reset_reason,
+            consecutive_crashes: next_count(0, reset_reason),
+        }
+    }
+
+    pub fn safe_mode(&self) -> bool {
+        self.consecutive_crashes >= CRASH_LOOP_BOOTS
+    }
+
+    pub fn consecutive_crashes(&self) -> u8 {
+        self.consecutive_crashes
+    }
+
+    pub fn banner(&self) -> String {
+        let mut banner = format!(
+            "ESP32-C3 services {}, reset reason: {}, crash resets in a row: {}",
+            version::summary(),
+            self.reset_reason,
+            self.consecutive_crashes
+        );
+        if self.safe_mode() {
+            banner.push_str("\nSAFE MODE: WiFi and HTTP only, everything else stays off, POST /api/reboot to leave");
+        }
+        banner
+    }
+}
+
+fn next_count(previous: u8, reset_reason: &str) -> u8 {
+    match reset_reason {
+        "panic" | "interrupt-watchdog" | "task-watchdog" | "watchdog" => previous.saturating_add(1),
+        _ => 0,
+    }
+}
@@ -4,17 +4,122 @@ use esp_idf_hal::modem::Modem;
 use esp_idf_hal::peripheral::Peripheral;
 use esp_idf_svc::eventloop::EspSystemEventLoop;
 use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvsPartition, NvsDefault};
-use esp_idf_svc::sys::EspError;
-use esp_idf_svc::wifi::{AuthMethod, BlockingWifi, ClientConfiguration, Configuration, EspWifi};
-use esp_idf_hal::ledc::LedcDriver;
+use esp_idf_svc::sys::{self, esp, EspError};
+use esp_idf_svc::wifi::{AccessPointInfo, AuthMethod, BlockingWifi, ClientConfiguration, Configuration, EspWifi};
+use serde::{Serialize, Serializer};
+use std::fmt::Write as _;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
+use crate::alerts::AlertLog;
+use crate::config::{ConfigStore, ScanDisplay};
+use crate::events::{Event, EventBus};
+use crate::history::ScanHistory;
+use crate::led::RgbLed;
+use crate::tracker::BssidTracker;
+use crate::wifi::SharedWifi;
 
+const CHANNEL_COUNT: usize = 13;
+/// How often a scanner held off by the LED checks again.
+const LED_HOLD_POLL: Duration = Duration::from_millis(100);
 
+/// Results of the most recent scan, shared with the consumers of scan data.
+pub type LatestScan = Arc<Mutex<Vec<ScannedNetwork>>>;
 
-fn auth_method_to_string(auth: Option<AuthMethod>) -> &'static str {
+static SCANS_COMPLETED: AtomicU32 = AtomicU32::new(0);
+/// Preempted scans are not counted as failed.
+static SCANS_FAILED: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Debug, Clone)]
+pub struct ScannedNetwork {
+    pub ssid: String,
+    pub bssid: [u8; 6],
+    pub channel: u8,
+    pub signal_strength: i8,
+    pub auth_method: Option<AuthMethod>,
+}
+
+/// One network as served by `/api/scan`.
+#[derive(Debug, Serialize)]
+pub struct ScanResult {
+    pub ssid: String,
+    pub bssid: String,
+    pub rssi: i8,
+    pub channel: u8,
+    pub auth_method: &'static str,
+}
+
+impl From<&ScannedNetwork> for ScanResult {
+    fn from(network: &ScannedNetwork) -> Self {
+        ScanResult {
+            ssid: network.ssid.clone(),
+            bssid: format_mac(&network.bssid),
+            rssi: network.signal_strength,
+            channel: network.channel,
+            auth_method: auth_method_to_string(network.auth_method),
+        }
+    }
+}
+
+impl From<&AccessPointInfo> for ScannedNetwork {
+    fn from(ap: &AccessPointInfo) -> Self {
+        ScannedNetwork {
+            ssid: ap.ssid.to_string(),
+            bssid: ap.bssid,
+            channel: ap.channel,
+            signal_strength: ap.signal_strength,
+            auth_method: ap.auth_method,
+        }
+    }
+}
+
+
+
+
+pub fn parse_mac(mac: &str) -> Option<[u8; 6]> {
+    let mut bytes = [0_u8; 6];
+    let mut parts = mac.split(|c| c == ':' || c == '-');
+
+    for byte in bytes.iter_mut() {
+        *byte = u8::from_str_radix(parts.next()?, 16).ok()?;
+    }
+
+    match parts.next() {
+        Some(_) => None,
+        None => Some(bytes),
+    }
+}
+
+pub fn format_mac(mac: &[u8; 6]) -> String {
+    format!(
+        "{:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
+        mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+    )
+}
+
+/// A MAC address that sorts by its bytes and reads and writes as `AA:BB:...`,
+/// for use as a page cursor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl FromStr for MacAddress {
+    type Err = &'static str;
+
+    fn from_str(mac: &str) -> Result<Self, Self::Err> {
+        parse_mac(mac).map(MacAddress).ok_or("not a MAC address")
+    }
+}
+
+impl Serialize for MacAddress {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format_mac(&self.0))
+    }
+}
+
+pub fn auth_method_to_string(auth: Option<AuthMethod>) -> &'static str {
     match auth {
         Some(AuthMethod::None) => "Open",
         Some(AuthMethod::WEP) => "WEP",
@@ -81,15 +186,7 @@ pub fn scan_wifi_with_resources(
                         ap.ssid.to_string()
                     };
 
-                    let mac = format!(
-                        "{:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
-                        ap.bssid[0],
-                        ap.bssid[1],
-                        ap.bssid[2],
-                        ap.bssid[3],
-                        ap.bssid[4],
-                        ap.bssid[5]
-                    );
+                    let mac = format_mac(&ap.bssid);
 
                     log::info!(
                         "{:2}. SSID: {:32} | Signal: {:4} dBm | Channel: {:2} | MAC: {:17} | Security: {:?}",
@@ -115,55 +212,228 @@ pub fn scan_wifi_with_resources(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn scan_networks_continuously(
-    sys_loop: EspSystemEventLoop,
-    red_channel: Arc<Mutex<LedcDriver<'static>>>,
-    green_channel: Arc<Mutex<LedcDriver<'static>>>,
+    wifi: SharedWifi,
+    led: Arc<RgbLed>,
+    config: Arc<ConfigStore>,
+    latest_scan: LatestScan,
+    events: Arc<EventBus>,
+    tracker: Arc<BssidTracker>,
+    alerts: Arc<AlertLog>,
+    history: Arc<ScanHistory>,
 ) {
     log::info!("WiFi scanner thread started with LED control");
     
+    let mut previous: Option<Vec<ScannedNetwork>> = None;
+
     loop {
+        let config_snapshot = config.get();
+        let toggles = config_snapshot.subsystems;
+        if !toggles.scanner {
+            if alerts.has_unacknowledged_critical() {
+                flash_red(&led, 1000);
+            } else {
+                thread::sleep(Duration::from_secs(1));
+            }
+            continue;
+        }
+
+        while led.is_held() {
+            thread::sleep(LED_HOLD_POLL);
+        }
+
         log::info!("=== Performing WiFi scan... ===");
         
-        // Use a simpler approach that works with an already initialized WiFi system
-        // We'll scan using the system's WiFi without creating a new instance
-        match perform_wifi_scan() {
+        let mut density = None;
+        match perform_wifi_scan(&wifi) {
             Ok(networks) => {
-                log::info!("Found {} WiFi networks:", networks.len());
+                log::info!("Found {} WiFi networks", networks.len());
+                SCANS_COMPLETED.fetch_add(1, Ordering::Relaxed);
                 for (i, network) in networks.iter().enumerate() {
-                    log::info!("{}. {} (Signal: {} dBm)", i + 1, network.0, network.1);
+                    log::debug!(
+                        "{}. {} (Signal: {} dBm, Channel: {})",
+                        i + 1,
+                        network.ssid,
+                        network.signal_strength,
+                        network.channel
+                    );
                 }
                 
-                flash_green(&red_channel, &green_channel, 500);
+                density = Some(channel_density(&networks));
+                if let Some(previous) = &previous {
+                    for event in diff_scans(previous, &networks) {
+                        events.publish(event);
+                    }
+                }
+                // Consumers of ScanCompleted read the history and the latest scan
+                history.record(&networks);
+                *latest_scan.lock().unwrap() = networks.clone();
+                events.publish(Event::ScanCompleted { networks: networks.len() });
+                tracker.sample(&networks, &events);
+                previous = Some(networks);
+                if toggles.animations && !tracker.is_tracking() {
+                    flash_green(&led, 500);
+                }
             },
+            Err(e) if led.is_held() => {
+                log::info!("WiFi scan preempted by an LED command: {}", e);
+            }
             Err(e) => {
                 log::error!("WiFi scan failed: {}", e);
-                flash_red(&red_channel, &green_channel, 500);
+                SCANS_FAILED.fetch_add(1, Ordering::Relaxed);
+                if toggles.animations {
+                    flash_red(&led, 500);
+                }
             }
         }
         
-        log::info!("Waiting 10 seconds before next scan...");
-        flash_red_waiting(&red_channel, &green_channel, 10000);
+        if alerts.has_unacknowledged_critical() {
+            // Alert state: fast red flashing until the critical alerts are acknowledged
+            flash_red(&led, 10000);
+            continue;
+        }
+
+        if tracker.is_tracking() {
+            // Locator mode: rescan quickly and keep the LED on the hot/cold color
+            show_tracking_color(&led, tracker.smoothed_rssi());
+            thread::sleep(Duration::from_secs(2));
+            continue;
+        }
+
+        let interval_secs = config_snapshot.scanner.interval_secs;
+        let interval_ms = interval_secs as u64 * 1000;
+        log::info!("Waiting {} seconds before next scan...", interval_secs);
+        match (toggles.animations, config_snapshot.scan_display, density) {
+            (true, ScanDisplay::ChannelHeat, Some(density)) => {
+                sweep_channel_heat(&led, &density, interval_ms);
+            }
+            (true, _, _) => flash_red_waiting(&led, interval_ms),
+            (false, _, _) => thread::sleep(Duration::from_secs(interval_secs as u64)),
+        }
+    }
+}
+
+/// Compares two consecutive scans by BSSID.
+fn diff_scans(previous: &[ScannedNetwork], current: &[ScannedNetwork]) -> Vec<Event> {
+    let mut events = Vec::new();
+
+    for network in current {
+        match previous.iter().find(|old| old.bssid == network.bssid) {
+            None => events.push(Event::ApAppeared {
+                ssid: network.ssid.clone(),
+                bssid: format_mac(&network.bssid),
+                channel: network.channel,
+                rssi: network.signal_strength,
+            }),
+            Some(old) if old.channel != network.channel => events.push(Event::ApChannelChanged {
+                ssid: network.ssid.clone(),
+                bssid: format_mac(&network.bssid),
+                from: old.channel,
+                to: network.channel,
+            }),
+            Some(_) => {}
+        }
+    }
+
+    for old in previous {
+        if !current.iter().any(|network| network.bssid == old.bssid) {
+            events.push(Event::ApDisappeared {
+                ssid: old.ssid.clone(),
+                bssid: format_mac(&old.bssid),
+            });
+        }
+    }
+
+    events
+}
+
+/// Number of access points seen on each of the 2.4 GHz channels 1-13.
+fn channel_density(networks: &[ScannedNetwork]) -> [usize; CHANNEL_COUNT] {
+    let mut density = [0; CHANNEL_COUNT];
+
+    for network in networks {
+        if (1..=CHANNEL_COUNT as u8).contains(&network.channel) {
+            density[network.channel as usize - 1] += 1;
+        }
+    }
+
+    density
+}
+
+/// Steps the LED through channels 1-13, giving each channel its own hue and a
+/// brightness proportional to how many access points share it.
+fn sweep_channel_heat(
+    led: &RgbLed,
+    density: &[usize; CHANNEL_COUNT],
+    duration_ms: u64,
+) {
+    let dwell = 400; // Stay 400ms on each channel
+    let busiest = density.iter().copied().max().unwrap_or(0).max(1);
+    let total_steps = duration_ms / dwell;
+
+    for step in 0..total_steps {
+        let index = step as usize % CHANNEL_COUNT;
+        let hue = index as u16 * 300 / (CHANNEL_COUNT as u16 - 1);
+        let value = (density[index] * 255 / busiest) as u8;
+        let (red, green, blue) = hsv_to_rgb(hue, value);
+
+        set_led_color(led, red, green, blue);
+        thread::sleep(Duration::from_millis(dwell));
     }
+
+    set_led_color(led, 0, 0, 0);
+}
+
+/// Blue when the tracked AP is far (or not visible), shifting through green to red
+/// as the smoothed RSSI climbs from -90 to -30 dBm.
+fn show_tracking_color(
+    led: &RgbLed,
+    smoothed_rssi: Option<f32>,
+) {
+    let (red, green, blue) = match smoothed_rssi {
+        Some(rssi) => {
+            let closeness = ((rssi.clamp(-90.0, -30.0) + 90.0) / 60.0 * 240.0) as u16;
+            hsv_to_rgb(240 - closeness, 255)
+        }
+        None => (0, 0, 32),
+    };
+
+    set_led_color(led, red, green, blue);
+}
+
+/// Converts a fully saturated hue (0-359 degrees) and value to RGB.
+fn hsv_to_rgb(hue: u16, value: u8) -> (u8, u8, u8) {
+    let hue = hue % 360;
+    let value = value as u16;
+    let rising = value * (hue % 60) / 60;
+    let falling = value - rising;
+
+    let (red, green, blue) = match hue / 60 {
+        0 => (value, rising, 0),
+        1 => (falling, value, 0),
+        2 => (0, value, rising),
+        3 => (0, falling, value),
+        4 => (rising, 0, value),
+        _ => (value, 0, falling),
+    };
+
+    (red as u8, green as u8, blue as u8)
 }
 
 fn set_led_color(
-    red_channel: &Arc<Mutex<LedcDriver<'static>>>,
-    green_channel: &Arc<Mutex<LedcDriver<'static>>>,
+    led: &RgbLed,
     red: u8,
     green: u8,
+    blue: u8,
 ) {
-    if let Ok(mut red_led) = red_channel.lock() {
-        let _ = red_led.set_duty(red as u32);
-    }
-    if let Ok(mut green_led) = green_channel.lock() {
-        let _ = green_led.set_duty(green as u32);
+    if let Err(e) = led.animate(red, green, blue) {
+        log::warn!("Failed to set LED color: {:?}", e);
     }
 }
 
 fn flash_green(
-    red_channel: &Arc<Mutex<LedcDriver<'static>>>,
-    green_channel: &Arc<Mutex<LedcDriver<'static>>>,
+    led: &RgbLed,
     duration_ms: u64,
 ) {
     let flash_interval = 100; // Flash every 100ms (5 times in 500ms)
@@ -171,19 +441,18 @@ fn flash_green(
     
     for i in 0..total_flashes {
         if i % 2 == 0 {
-            set_led_color(red_channel, green_channel, 0, 255);
+            set_led_color(led, 0, 255, 0);
         } else {
-            set_led_color(red_channel, green_channel, 0, 0);
+            set_led_color(led, 0, 0, 0);
         }
         thread::sleep(Duration::from_millis(flash_interval));
     }
     
-    set_led_color(red_channel, green_channel, 0, 0);
+    set_led_color(led, 0, 0, 0);
 }
 
 fn flash_red(
-    red_channel: &Arc<Mutex<LedcDriver<'static>>>,
-    green_channel: &Arc<Mutex<LedcDriver<'static>>>,
+    led: &RgbLed,
     duration_ms: u64,
 ) {
     let flash_interval = 100; // Flash every 100ms
@@ -191,19 +460,18 @@ fn flash_red(
     
     for i in 0..total_flashes {
         if i % 2 == 0 {
-            set_led_color(red_channel, green_channel, 255, 0);
+            set_led_color(led, 255, 0, 0);
         } else {
-            set_led_color(red_channel, green_channel, 0, 0);
+            set_led_color(led, 0, 0, 0);
         }
         thread::sleep(Duration::from_millis(flash_interval));
     }
     
-    set_led_color(red_channel, green_channel, 0, 0);
+    set_led_color(led, 0, 0, 0);
 }
 
 fn flash_red_waiting(
-    red_channel: &Arc<Mutex<LedcDriver<'static>>>,
-    green_channel: &Arc<Mutex<LedcDriver<'static>>>,
+    led: &RgbLed,
     duration_ms: u64,
 ) {
     let flash_interval = 1000; // Flash every 1 second
@@ -212,25 +480,46 @@ fn flash_red_waiting(
     for i in 0..total_flashes {
         if i % 2 == 0 {
 
-            set_led_color(red_channel, green_channel, 255, 0);
+            set_led_color(led, 255, 0, 0);
         } else {
 
-            set_led_color(red_channel, green_channel, 0, 0);
+            set_led_color(led, 0, 0, 0);
         }
         thread::sleep(Duration::from_millis(flash_interval));
     }
     
 
-    set_led_color(red_channel, green_channel, 0, 0);
+    set_led_color(led, 0, 0, 0);
 }
 
-fn perform_wifi_scan() -> Result<Vec<(String, i8)>> {
-    // This is a simplified scan that works with the existing WiFi system
-    // In a real implementation, you might need to use ESP-IDF APIs directly
-    // For now, let's simulate some networks
-    Ok(vec![
-        ("Network-1".to_string(), -45),
-        ("Network-2".to_string(), -67),
-        ("Wokwi-GUEST".to_string(), -30),
-    ])
-}
\ No newline at end of file
+/// Prometheus text exposition for `/metrics`.
+pub fn metrics(latest_scan: &LatestScan) -> String {
+    let mut metrics = String::new();
+    let _ = writeln!(metrics, "# TYPE wifi_scans_total counter");
+    let _ = writeln!(metrics, "wifi_scans_total {}", SCANS_COMPLETED.load(Ordering::Relaxed));
+    let _ = writeln!(metrics, "# TYPE wifi_scan_failures_total counter");
+    let _ = writeln!(metrics, "wifi_scan_failures_total {}", SCANS_FAILED.load(Ordering::Relaxed));
+    let _ = writeln!(metrics, "# TYPE wifi_networks_seen gauge");
+    let _ = writeln!(metrics, "wifi_networks_seen {}", latest_scan.lock().unwrap().len());
+    metrics
+}
+
+/// Aborts a running scan, which brings the radio back to the station's channel.
+pub fn preempt_scan() {
+    // Also fails when no scan is running, which is fine
+    if let Err(e) = esp!(unsafe { sys::esp_wifi_scan_stop() }) {
+        log::debug!("No scan to preempt: {:?}", e);
+    }
+}
+
+/// Scans on the driver the station uses. The driver is locked for the length of
+/// the scan, so a reconnect attempt waits for it and the other way round.
+fn perform_wifi_scan(wifi: &SharedWifi) -> Result<Vec<ScannedNetwork>> {
+    let mut wifi = wifi.lock().unwrap();
+    if !wifi.is_started()? {
+        anyhow::bail!("WiFi is not started");
+    }
+
+    let access_points = futures::executor::block_on(wifi.scan())?;
+    Ok(access_points.iter().map(ScannedNetwork::from).collect())
+}
@@ -0,0 +1,174 @@
+use esp_idf_svc::handle::RawHandle;
+use esp_idf_svc::sys::{
+    esp, httpd_get_client_list, httpd_handle_t, httpd_req_to_sockfd, httpd_sess_trigger_close, lwip_getpeername,
+    sockaddr_in, socklen_t, AF_INET,
+};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::Ipv4Addr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::HttpSettings;
+use crate::handlers::HttpRequest;
+
+/// Upper bound of `HttpSettings::max_open_sockets`.
+const MAX_SOCKETS: usize = 7;
+
+struct Client {
+    ip: Ipv4Addr,
+    last_seen: Instant,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionInfo {
+    pub socket: i32,
+    pub ip: String,
+    pub idle_ms: u64,
+}
+
+/// Watches the HTTP server's open sockets and closes the ones that sit idle or go
+/// beyond the per-client limit, so stalled clients cannot lock everyone else out.
+///
+/// The server's own LRU purge only kicks in when a new connection finds every
+/// socket taken; this frees them before that happens.
+///
+/// WebSocket frames never pass through a request handler, so their sockets look
+/// idle however busy they are; they are listed but left out of both limits,
+/// [`crate::ws`] caps them itself.
+pub struct HttpSessions {
+    settings: HttpSettings,
+    clients: Mutex<BTreeMap<i32, Client>>,
+    websockets: Mutex<BTreeSet<i32>>,
+}
+
+impl HttpSessions {
+    pub fn new(settings: HttpSettings) -> Self {
+        HttpSessions {
+            settings,
+            clients: Mutex::new(BTreeMap::new()),
+            websockets: Mutex::new(BTreeSet::new()),
+        }
+    }
+
+    /// Exempts `socket` from the limits until [`HttpSessions::websocket_closed`].
+    pub fn websocket_opened(&self, socket: i32) {
+        self.websockets.lock().unwrap().insert(socket);
+    }
+
+    pub fn websocket_closed(&self, socket: i32) {
+        self.websockets.lock().unwrap().remove(&socket);
+    }
+
+    /// Marks the request's socket as active, then applies the limits to the others.
+    pub fn on_request(&self, req: &mut HttpRequest) {
+        let raw = req.connection().handle();
+        // Safety: the request handle is valid for as long as the handler runs
+        let (server, socket) = unsafe { ((*raw).handle, httpd_req_to_sockfd(raw)) };
+        if socket < 0 {
+            return;
+        }
+
+        if let Some(ip) = peer_ipv4(socket) {
+            self.clients.lock().unwrap().insert(
+                socket,
+                Client {
+                    ip,
+                    last_seen: Instant::now(),
+                },
+            );
+        }
+        self.enforce(server, Some(socket));
+    }
+
+    /// Periodic sweep for sockets that went quiet.
+    pub fn evict_idle(&self, server: httpd_handle_t) {
+        self.enforce(server, None);
+    }
+
+    pub fn list(&self) -> Vec<SessionInfo> {
+        self.clients
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(socket, client)| SessionInfo {
+                socket: *socket,
+                ip: client.ip.to_string(),
+                idle_ms: client.last_seen.elapsed().as_millis() as u64,
+            })
+            .collect()
+    }
+
+    fn enforce(&self, server: httpd_handle_t, current: Option<i32>) {
+        let mut sockets = [0; MAX_SOCKETS];
+        let mut count = sockets.len();
+        if esp!(unsafe { httpd_get_client_list(server, &mut count, sockets.as_mut_ptr()) }).is_err() {
+            return;
+        }
+        let open = &sockets[..count];
+
+        let now = Instant::now();
+        let idle_timeout = Duration::from_secs(self.settings.idle_timeout_secs as u64);
+        let mut websockets = self.websockets.lock().unwrap();
+        websockets.retain(|socket| open.contains(socket));
+        let mut clients = self.clients.lock().unwrap();
+
+        // Sockets are reused, so an unknown or re-addressed one counts as new
+        clients.retain(|socket, _| open.contains(socket));
+        for &socket in open {
+            let ip = peer_ipv4(socket).unwrap_or(Ipv4Addr::UNSPECIFIED);
+            if clients.get(&socket).map(|client| client.ip) != Some(ip) {
+                clients.insert(socket, Client { ip, last_seen: now });
+            }
+        }
+
+        let mut close: Vec<i32> = clients
+            .iter()
+            .filter(|(socket, client)| {
+                Some(**socket) != current && !websockets.contains(socket) && now - client.last_seen > idle_timeout
+            })
+            .map(|(socket, _)| *socket)
+            .collect();
+
+        let ips: BTreeSet<Ipv4Addr> = clients.values().map(|client| client.ip).collect();
+        for ip in ips {
+            let mut owned: Vec<(i32, Instant)> = clients
+                .iter()
+                .filter(|(socket, client)| client.ip == ip && !close.contains(socket) && !websockets.contains(socket))
+                .map(|(socket, client)| (*socket, client.last_seen))
+                .collect();
+            // The socket being served first, then the most recently used
+            owned.sort_by_key(|(socket, last_seen)| (Some(*socket) != current, std::cmp::Reverse(*last_seen)));
+            close.extend(
+                owned
+                    .iter()
+                    .skip(self.settings.max_sockets_per_client as usize)
+                    .map(|(socket, _)| *socket),
+            );
+        }
+
+        for socket in close {
+            if let Some(client) = clients.remove(&socket) {
+                log::info!(
+                    "Closing HTTP socket {} from {}, idle for {:?}",
+                    socket,
+                    client.ip,
+                    now - client.last_seen
+                );
+            }
+            unsafe { httpd_sess_trigger_close(server, socket) };
+        }
+    }
+}
+
+fn peer_ipv4(socket: i32) -> Option<Ipv4Addr> {
+    let mut addr = sockaddr_in {
+        sin_len: core::mem::size_of::<sockaddr_in>() as _,
+        sin_family: AF_INET as _,
+        ..Default::default()
+    };
+    let mut len = core::mem::size_of::<sockaddr_in>() as socklen_t;
+
+    esp!(unsafe { lwip_getpeername(socket, &mut addr as *mut _ as *mut _, &mut len) }).ok()?;
+    Some(Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)))
+}
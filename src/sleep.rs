@@ -0,0 +1,191 @@
+//! Automatic light sleep for an idle station.
+//!
+//! Once nothing has needed the device for `idle_minutes`, power management may
+//! light-sleep between beacons with the modem in its deepest power save mode.
+//! Traffic addressed to the station and the wake button still wake the chip, so
+//! an HTTP request is answered, a little late, and makes the device active again.
+//! Waking restores the configured power save mode.
+//!
+//! Needs `CONFIG_PM_ENABLE` and `CONFIG_FREERTOS_USE_TICKLESS_IDLE`, see
+//! `sdkconfig.defaults`.
+
+use anyhow::Result;
+use esp_idf_svc::sys::{self, esp};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::{AppConfig, ConfigStore, WifiMode, WifiPowerSave};
+use crate::sessions::HttpSessions;
+use crate::wifi;
+
+/// How often the wake button is polled; the chip is woken while it is held.
+const BUTTON_POLL: Duration = Duration::from_millis(250);
+/// Button polls between two checks of the idle conditions.
+const POLLS_PER_CHECK: u32 = 20;
+/// The ESP32-C3 crystal, the lowest CPU clock power management may pick.
+const MIN_FREQ_MHZ: i32 = 40;
+
+#[derive(Debug, Serialize)]
+pub struct SleepStatus {
+    pub asleep: bool,
+    pub idle_secs: u64,
+    /// What keeps the device awake; empty when it sleeps or is about to.
+    pub blockers: Vec<&'static str>,
+}
+
+struct State {
+    last_activity: Instant,
+    asleep: bool,
+}
+
+pub struct IdleSleep {
+    config: Arc<ConfigStore>,
+    sessions: Arc<HttpSessions>,
+    /// Read once at boot.
+    wake_gpio: Option<u8>,
+    state: Mutex<State>,
+}
+
+impl IdleSleep {
+    pub fn new(config: Arc<ConfigStore>, sessions: Arc<HttpSessions>) -> Self {
+        let current = config.get();
+        let wake_gpio = match current.sleep.validate(&current.led_output.pins) {
+            Ok(()) => current.sleep.wake_gpio,
+            Err(e) => {
+                log::error!("Invalid sleep settings, no wake button: {}", e);
+                None
+            }
+        };
+        if let Some(gpio) = wake_gpio {
+            if let Err(e) = setup_wake_button(gpio) {
+                log::error!("Failed to set up GPIO{} as wake button: {:?}", gpio, e);
+            }
+        }
+
+        IdleSleep {
+            config,
+            sessions,
+            wake_gpio,
+            state: Mutex::new(State {
+                last_activity: Instant::now(),
+                asleep: false,
+            }),
+        }
+    }
+
+    /// Polls the button and checks the idle conditions, forever.
+    pub fn run(&self) {
+        let mut polls = 0;
+        loop {
+            thread::sleep(BUTTON_POLL);
+            let pressed = self
+                .wake_gpio
+                .is_some_and(|gpio| unsafe { sys::gpio_get_level(gpio as i32) } == 0);
+            if pressed {
+                self.touch();
+            }
+
+            polls += 1;
+            if polls == POLLS_PER_CHECK {
+                polls = 0;
+                self.check(&self.config.get());
+            }
+        }
+    }
+
+    /// Records activity, waking the device if it was asleep.
+    pub fn touch(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.last_activity = Instant::now();
+        if state.asleep {
+            log::info!("Activity, leaving light sleep");
+            state.asleep = false;
+            if let Err(e) = set_light_sleep(false, self.config.get().wifi_power_save) {
+                log::error!("Failed to leave light sleep: {:?}", e);
+            }
+        }
+    }
+
+    pub fn is_asleep(&self) -> bool {
+        self.state.lock().unwrap().asleep
+    }
+
+    pub fn status(&self, config: &AppConfig) -> SleepStatus {
+        let state = self.state.lock().unwrap();
+        SleepStatus {
+            asleep: state.asleep,
+            idle_secs: state.last_activity.elapsed().as_secs(),
+            blockers: self.blockers(config),
+        }
+    }
+
+    fn check(&self, config: &AppConfig) {
+        let blockers = self.blockers(config);
+        let mut state = self.state.lock().unwrap();
+        let idle = state.last_activity.elapsed() >= Duration::from_secs(config.sleep.idle_minutes as u64 * 60);
+
+        if !state.asleep && blockers.is_empty() && idle {
+            log::info!("Idle for {} minutes, entering light sleep", config.sleep.idle_minutes);
+            match set_light_sleep(true, config.wifi_power_save) {
+                Ok(()) => state.asleep = true,
+                Err(e) => log::error!("Failed to enter light sleep: {:?}", e),
+            }
+        } else if state.asleep && !blockers.is_empty() {
+            log::info!("Leaving light sleep for {}", blockers.join(", "));
+            state.asleep = false;
+            state.last_activity = Instant::now();
+            if let Err(e) = set_light_sleep(false, config.wifi_power_save) {
+                log::error!("Failed to leave light sleep: {:?}", e);
+            }
+        }
+    }
+
+    fn blockers(&self, config: &AppConfig) -> Vec<&'static str> {
+        let mut blockers = Vec::new();
+        if !config.sleep.enabled {
+            blockers.push("disabled");
+        }
+        if config.wifi_mode != WifiMode::Station {
+            blockers.push("access-point");
+        }
+        if config.subsystems.scanner {
+            blockers.push("scanner");
+        }
+        if config.subsystems.sniffer {
+            blockers.push("sniffer");
+        }
+        if config.subsystems.animations {
+            blockers.push("animations");
+        }
+        if !self.sessions.list().is_empty() {
+            blockers.push("http-clients");
+        }
+        blockers
+    }
+}
+
+fn setup_wake_button(gpio: u8) -> Result<()> {
+    let gpio = gpio as i32;
+    unsafe {
+        esp!(sys::gpio_set_direction(gpio, sys::gpio_mode_t_GPIO_MODE_INPUT))?;
+        esp!(sys::gpio_set_pull_mode(gpio, sys::gpio_pull_mode_t_GPIO_PULLUP_ONLY))?;
+        esp!(sys::gpio_wakeup_enable(gpio, sys::gpio_int_type_t_GPIO_INTR_LOW_LEVEL))?;
+        esp!(sys::esp_sleep_enable_gpio_wakeup())?;
+    }
+    Ok(())
+}
+
+/// `power_save` is the mode to return to when awake.
+fn set_light_sleep(enabled: bool, power_save: WifiPowerSave) -> Result<()> {
+    let max_freq_mhz = sys::CONFIG_ESP_DEFAULT_CPU_FREQ_MHZ as i32;
+    let config = sys::esp_pm_config_t {
+        max_freq_mhz,
+        min_freq_mhz: if enabled { MIN_FREQ_MHZ } else { max_freq_mhz },
+        light_sleep_enable: enabled,
+    };
+
+    esp!(unsafe { sys::esp_pm_configure(&config as *const _ as *const _) })?;
+    wifi::set_power_save(if enabled { WifiPowerSave::Max } else { power_save })
+}
@@ -0,0 +1,352 @@
+//! 802.11 management frames captured in promiscuous mode.
+//!
+//! The radio keeps serving the station or access point while sniffing, so frames
+//! are only seen on the channel it is already on.
+//!
+//! Captured frames are copied into a fixed pool rather than a fresh allocation
+//! each, and every subscriber shares that one copy, so hours of sniffing do not
+//! fragment the heap. When the pool is exhausted, frames are dropped.
+//!
+//! Control and data frames are received as well, but only counted per channel,
+//! see [`take_frame_counts`].
+
+use anyhow::Result;
+use esp_idf_svc::sys::{
+    esp, esp_wifi_set_promiscuous, esp_wifi_set_promiscuous_ctrl_filter, esp_wifi_set_promiscuous_filter,
+    esp_wifi_set_promiscuous_rx_cb, wifi_promiscuous_filter_t, wifi_promiscuous_pkt_t, wifi_promiscuous_pkt_type_t,
+    wifi_promiscuous_pkt_type_t_WIFI_PKT_CTRL, wifi_promiscuous_pkt_type_t_WIFI_PKT_DATA,
+    wifi_promiscuous_pkt_type_t_WIFI_PKT_MGMT, WIFI_PROMIS_CTRL_FILTER_MASK_ALL, WIFI_PROMIS_FILTER_MASK_CTRL,
+    WIFI_PROMIS_FILTER_MASK_DATA, WIFI_PROMIS_FILTER_MASK_MGMT,
+};
+use serde::{Deserialize, Serialize};
+use std::cell::UnsafeCell;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Mutex;
+
+use crate::scan::format_mac;
+
+/// Frame control, duration and three addresses.
+const HEADER_LEN: usize = 24;
+/// Frame check sequence the driver leaves at the end of `sig_len`.
+const FCS_LEN: usize = 4;
+/// Longer frames are cut; beacons rarely get there.
+pub const MAX_FRAME_LEN: usize = 512;
+/// Frames alive at the same time, queued or being looked at by a consumer.
+const POOL_SLOTS: usize = 24;
+/// Highest 2.4 GHz channel.
+pub const MAX_CHANNEL: usize = 14;
+
+// The receive callback takes no context, so the sniffer state is global
+static SUBSCRIBERS: Mutex<Vec<SyncSender<ManagementFrame>>> = Mutex::new(Vec::new());
+static RUNNING: AtomicBool = AtomicBool::new(false);
+static CAPTURED: AtomicU32 = AtomicU32::new(0);
+static DROPPED: AtomicU32 = AtomicU32::new(0);
+static TRUNCATED: AtomicU32 = AtomicU32::new(0);
+static POOL: [Slot; POOL_SLOTS] = [Slot::EMPTY; POOL_SLOTS];
+/// Indexed by channel - 1, since the last [`take_frame_counts`].
+static FRAME_COUNTS: [FrameCounter; MAX_CHANNEL] = [FrameCounter::EMPTY; MAX_CHANNEL];
+
+struct FrameCounter {
+    management: AtomicU32,
+    control: AtomicU32,
+    data: AtomicU32,
+}
+
+impl FrameCounter {
+    // Only ever used to initialise `FRAME_COUNTS`
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: FrameCounter = FrameCounter {
+        management: AtomicU32::new(0),
+        control: AtomicU32::new(0),
+        data: AtomicU32::new(0),
+    };
+}
+
+/// Frames of each type heard on one channel.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChannelFrames {
+    pub channel: u8,
+    pub management: u32,
+    pub control: u32,
+    pub data: u32,
+}
+
+/// A pool buffer; its owners count in `refs`, and whoever takes it from 0 to 1
+/// has it to themselves until it is handed over.
+struct Slot {
+    refs: AtomicU8,
+    data: UnsafeCell<[u8; MAX_FRAME_LEN]>,
+}
+
+// Written only by the owner that took a free slot, before anyone else sees it
+unsafe impl Sync for Slot {}
+
+impl Slot {
+    // Only ever used to initialise `POOL`
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Slot = Slot {
+        refs: AtomicU8::new(0),
+        data: UnsafeCell::new([0; MAX_FRAME_LEN]),
+    };
+}
+
+/// Bytes of a captured frame in the pool, shared by the clones of a frame.
+pub struct FrameData {
+    slot: &'static Slot,
+    len: usize,
+}
+
+impl FrameData {
+    /// `None` when every slot is taken.
+    fn new(bytes: &[u8]) -> Option<Self> {
+        let len = bytes.len().min(MAX_FRAME_LEN);
+        let slot = POOL
+            .iter()
+            .find(|slot| slot.refs.compare_exchange(0, 1, Ordering::Acquire, Ordering::Relaxed).is_ok())?;
+        unsafe { (*slot.data.get())[..len].copy_from_slice(&bytes[..len]) };
+        Some(FrameData { slot, len })
+    }
+}
+
+impl Deref for FrameData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { &(*self.slot.data.get())[..self.len] }
+    }
+}
+
+impl Clone for FrameData {
+    fn clone(&self) -> Self {
+        self.slot.refs.fetch_add(1, Ordering::Relaxed);
+        FrameData {
+            slot: self.slot,
+            len: self.len,
+        }
+    }
+}
+
+impl Drop for FrameData {
+    fn drop(&mut self) {
+        self.slot.refs.fetch_sub(1, Ordering::Release);
+    }
+}
+
+impl std::fmt::Debug for FrameData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "FrameData({} bytes)", self.len)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Subtype {
+    AssociationRequest,
+    AssociationResponse,
+    ReassociationRequest,
+    ReassociationResponse,
+    ProbeRequest,
+    ProbeResponse,
+    Beacon,
+    Disassociation,
+    Authentication,
+    Deauthentication,
+    Action,
+    Other(u8),
+}
+
+impl From<u8> for Subtype {
+    fn from(subtype: u8) -> Self {
+        match subtype {
+            0 => Subtype::AssociationRequest,
+            1 => Subtype::AssociationResponse,
+            2 => Subtype::ReassociationRequest,
+            3 => Subtype::ReassociationResponse,
+            4 => Subtype::ProbeRequest,
+            5 => Subtype::ProbeResponse,
+            8 => Subtype::Beacon,
+            10 => Subtype::Disassociation,
+            11 => Subtype::Authentication,
+            12 => Subtype::Deauthentication,
+            13 => Subtype::Action,
+            other => Subtype::Other(other),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ManagementFrame {
+    pub subtype: Subtype,
+    pub receiver: [u8; 6],
+    pub transmitter: [u8; 6],
+    pub bssid: [u8; 6],
+    pub rssi: i8,
+    pub channel: u8,
+    /// The frame as received without the FCS, cut at [`MAX_FRAME_LEN`].
+    pub data: FrameData,
+    /// Length before cutting.
+    pub original_len: usize,
+}
+
+impl ManagementFrame {
+    /// Everything after the MAC header.
+    pub fn body(&self) -> &[u8] {
+        &self.data[HEADER_LEN..]
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SnifferStatus {
+    pub running: bool,
+    pub captured: u32,
+    /// Frames no subscriber had room for, or that found the pool full.
+    pub dropped: u32,
+    /// Frames longer than [`MAX_FRAME_LEN`], kept cut.
+    pub truncated: u32,
+    /// Pool buffers in use.
+    pub pool_used: usize,
+    pub pool_size: usize,
+}
+
+/// Frames for one consumer. Like the event bus, a consumer that falls behind
+/// loses frames rather than stalling the WiFi task.
+pub fn subscribe(capacity: usize) -> Receiver<ManagementFrame> {
+    let (sender, receiver) = mpsc::sync_channel(capacity);
+    SUBSCRIBERS.lock().unwrap().push(sender);
+    receiver
+}
+
+/// Needs the WiFi driver to be started.
+pub fn start() -> Result<()> {
+    let filter = wifi_promiscuous_filter_t {
+        filter_mask: WIFI_PROMIS_FILTER_MASK_MGMT | WIFI_PROMIS_FILTER_MASK_CTRL | WIFI_PROMIS_FILTER_MASK_DATA,
+    };
+    let ctrl_filter = wifi_promiscuous_filter_t {
+        filter_mask: WIFI_PROMIS_CTRL_FILTER_MASK_ALL,
+    };
+
+    esp!(unsafe { esp_wifi_set_promiscuous_filter(&filter) })?;
+    esp!(unsafe { esp_wifi_set_promiscuous_ctrl_filter(&ctrl_filter) })?;
+    esp!(unsafe { esp_wifi_set_promiscuous_rx_cb(Some(on_frame)) })?;
+    esp!(unsafe { esp_wifi_set_promiscuous(true) })?;
+    RUNNING.store(true, Ordering::Relaxed);
+    log::info!("Sniffer started");
+
+    Ok(())
+}
+
+pub fn stop() -> Result<()> {
+    esp!(unsafe { esp_wifi_set_promiscuous(false) })?;
+    RUNNING.store(false, Ordering::Relaxed);
+    log::info!("Sniffer stopped");
+
+    Ok(())
+}
+
+pub fn status() -> SnifferStatus {
+    SnifferStatus {
+        running: RUNNING.load(Ordering::Relaxed),
+        captured: CAPTURED.load(Ordering::Relaxed),
+        dropped: DROPPED.load(Ordering::Relaxed),
+        truncated: TRUNCATED.load(Ordering::Relaxed),
+        pool_used: POOL.iter().filter(|slot| slot.refs.load(Ordering::Relaxed) > 0).count(),
+        pool_size: POOL_SLOTS,
+    }
+}
+
+/// The frames counted on each channel since the last call, which starts the
+/// count over; channels without any are left out.
+pub fn take_frame_counts() -> Vec<ChannelFrames> {
+    (1..)
+        .zip(&FRAME_COUNTS)
+        .map(|(channel, counter)| ChannelFrames {
+            channel,
+            management: counter.management.swap(0, Ordering::Relaxed),
+            control: counter.control.swap(0, Ordering::Relaxed),
+            data: counter.data.swap(0, Ordering::Relaxed),
+        })
+        .filter(|frames| (frames.management, frames.control, frames.data) != (0, 0, 0))
+        .collect()
+}
+
+/// Runs on the WiFi task: count, parse, hand over and get out.
+unsafe extern "C" fn on_frame(buf: *mut core::ffi::c_void, kind: wifi_promiscuous_pkt_type_t) {
+    if buf.is_null() {
+        return;
+    }
+
+    let packet = &*(buf as *const wifi_promiscuous_pkt_t);
+    if let Some(counter) = FRAME_COUNTS.get((packet.rx_ctrl.channel() as usize).wrapping_sub(1)) {
+        #[allow(non_upper_case_globals)]
+        let count = match kind {
+            wifi_promiscuous_pkt_type_t_WIFI_PKT_MGMT => &counter.management,
+            wifi_promiscuous_pkt_type_t_WIFI_PKT_CTRL => &counter.control,
+            wifi_promiscuous_pkt_type_t_WIFI_PKT_DATA => &counter.data,
+            _ => return,
+        };
+        count.fetch_add(1, Ordering::Relaxed);
+    }
+    if kind != wifi_promiscuous_pkt_type_t_WIFI_PKT_MGMT {
+        return;
+    }
+
+    let len = (packet.rx_ctrl.sig_len() as usize).saturating_sub(FCS_LEN);
+    let payload = core::slice::from_raw_parts(packet.payload.as_ptr(), len);
+
+    if !is_management(payload) {
+        return;
+    }
+    CAPTURED.fetch_add(1, Ordering::Relaxed);
+    let Some(frame) = parse(payload, packet.rx_ctrl.rssi() as i8, packet.rx_ctrl.channel() as u8) else {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+        return;
+    };
+    if frame.original_len > MAX_FRAME_LEN {
+        TRUNCATED.fetch_add(1, Ordering::Relaxed);
+    }
+
+    let Ok(mut subscribers) = SUBSCRIBERS.try_lock() else {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+        return;
+    };
+    subscribers.retain(|subscriber| match subscriber.try_send(frame.clone()) {
+        Ok(()) => true,
+        Err(TrySendError::Full(_)) => {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+            true
+        }
+        Err(TrySendError::Disconnected(_)) => false,
+    });
+}
+
+fn is_management(payload: &[u8]) -> bool {
+    // Type 0 is management
+    payload.len() >= HEADER_LEN && (payload[0] >> 2) & 0x03 == 0
+}
+
+/// `None` when the pool is full.
+fn parse(payload: &[u8], rssi: i8, channel: u8) -> Option<ManagementFrame> {
+    let frame_control = payload[0];
+    let address = |offset: usize| -> [u8; 6] { payload[offset..offset + 6].try_into().unwrap() };
+    let frame = ManagementFrame {
+        subtype: Subtype::from(frame_control >> 4),
+        receiver: address(4),
+        transmitter: address(10),
+        bssid: address(16),
+        rssi,
+        channel,
+        data: FrameData::new(payload)?,
+        original_len: payload.len(),
+    };
+    log::trace!(
+        "{:?} from {} to {} on channel {}",
+        frame.subtype,
+        format_mac(&frame.transmitter),
+        format_mac(&frame.receiver),
+        frame.channel
+    );
+
+    Some(frame)
+}
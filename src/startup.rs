@@ -0,0 +1,139 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Health {
+    Up,
+    Failed,
+    Skipped,
+}
+
+impl Health {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Health::Up => "up",
+            Health::Failed => "failed",
+            Health::Skipped => "skipped",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SubsystemHealth {
+    pub name: &'static str,
+    pub health: Health,
+    pub attempts: u32,
+    pub error: Option<String>,
+}
+
+/// Starts subsystems in the order `main` calls them and records their health.
+///
+/// A subsystem is only started once all the subsystems it requires are up; a failed
+/// start is retried with a growing delay before it is reported as failed.
+#[derive(Default)]
+pub struct Startup {
+    subsystems: Mutex<Vec<SubsystemHealth>>,
+}
+
+impl Startup {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn run<T, F>(
+        &self,
+        name: &'static str,
+        requires: &[&'static str],
+        attempts: u32,
+        mut init: F,
+    ) -> Option<T>
+    where
+        F: FnMut() -> Result<T>,
+    {
+        if let Some(missing) = requires.iter().find(|dependency| !self.is_up(dependency)) {
+            log::error!("Not starting {}: dependency {} is not up", name, missing);
+            self.record(name, Health::Skipped, 0, Some(format!("dependency {} is not up", missing)));
+            return None;
+        }
+
+        let attempts = attempts.max(1);
+        for attempt in 1..=attempts {
+            log::info!("Starting {} (attempt {}/{})...", name, attempt, attempts);
+
+            match init() {
+                Ok(value) => {
+                    log::info!("{} is up", name);
+                    self.record(name, Health::Up, attempt, None);
+                    return Some(value);
+                }
+                Err(e) => {
+                    log::error!("Failed to start {}: {:?}", name, e);
+
+                    if attempt == attempts {
+                        self.record(name, Health::Failed, attempt, Some(e.to_string()));
+                    } else {
+                        thread::sleep(RETRY_DELAY * attempt);
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    pub fn is_up(&self, name: &str) -> bool {
+        self.subsystems
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|subsystem| subsystem.name == name && subsystem.health == Health::Up)
+    }
+
+    /// In start order.
+    pub fn subsystems(&self) -> Vec<SubsystemHealth> {
+        self.subsystems.lock().unwrap().clone()
+    }
+
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+
+        for subsystem in self.subsystems.lock().unwrap().iter() {
+            let _ = write!(
+                report,
+                "{}: {} (attempts: {})",
+                subsystem.name,
+                subsystem.health.as_str(),
+                subsystem.attempts
+            );
+            if let Some(error) = &subsystem.error {
+                let _ = write!(report, " - {}", error);
+            }
+            report.push('\n');
+        }
+
+        report
+    }
+
+    fn record(&self, name: &'static str, health: Health, attempts: u32, error: Option<String>) {
+        let mut subsystems = self.subsystems.lock().unwrap();
+
+        let entry = SubsystemHealth {
+            name,
+            health,
+            attempts,
+            error,
+        };
+
+        match subsystems.iter_mut().find(|subsystem| subsystem.name == name) {
+            Some(existing) => *existing = entry,
+            None => subsystems.push(entry),
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! Values larger than a single NVS entry, split across several keys.
+//!
+//! The key itself holds a small header: magic, generation, chunk count, length
+//! and CRC32. Chunks live under `<key>:<generation><index>`. A write goes to the
+//! generation the header does not point at, then the header is switched and the
+//! old chunks are erased, so losing power midway leaves the previous value intact.
+//!
+//! A plain blob stored under the key before it went through here is still read.
+
+use anyhow::{bail, Result};
+use esp_idf_svc::nvs::{EspNvs, NvsDefault};
+
+use crate::gzip;
+
+/// Bytes per chunk: well under a 4 KiB flash page, so a chunk never has to span pages.
+const CHUNK: usize = 1984;
+const MAGIC: &[u8; 4] = b"CHK1";
+const HEADER_LEN: usize = 14;
+/// Keys are at most 15 characters and chunk keys add up to four.
+const MAX_KEY_LEN: usize = 11;
+const MAX_CHUNKS: usize = 99;
+
+struct Header {
+    generation: u8,
+    chunks: u8,
+    len: u32,
+    crc: u32,
+}
+
+impl Header {
+    fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() != HEADER_LEN || !data.starts_with(MAGIC) {
+            return None;
+        }
+        Some(Header {
+            generation: data[4],
+            chunks: data[5],
+            len: u32::from_le_bytes(data[6..10].try_into().unwrap()),
+            crc: u32::from_le_bytes(data[10..14].try_into().unwrap()),
+        })
+    }
+
+    fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut header = [0_u8; HEADER_LEN];
+        header[..4].copy_from_slice(MAGIC);
+        header[4] = self.generation;
+        header[5] = self.chunks;
+        header[6..10].copy_from_slice(&self.len.to_le_bytes());
+        header[10..14].copy_from_slice(&self.crc.to_le_bytes());
+        header
+    }
+}
+
+/// `None` when nothing is stored under `key`.
+pub fn read(nvs: &EspNvs<NvsDefault>, key: &str) -> Result<Option<Vec<u8>>> {
+    let Some(len) = nvs.blob_len(key)? else {
+        return Ok(None);
+    };
+    let mut buffer = vec![0_u8; len];
+    let Some(stored) = nvs.get_blob(key, &mut buffer)? else {
+        return Ok(None);
+    };
+    let Some(header) = Header::parse(stored) else {
+        // Written before the value was chunked
+        return Ok(Some(stored.to_vec()));
+    };
+
+    let mut data = Vec::with_capacity(header.len as usize);
+    for index in 0..header.chunks {
+        let chunk_key = chunk_key(key, header.generation, index);
+        let Some(len) = nvs.blob_len(&chunk_key)? else {
+            bail!("Chunk {} of {} is missing", index, key);
+        };
+        let start = data.len();
+        data.resize(start + len, 0);
+        if nvs.get_blob(&chunk_key, &mut data[start..])?.is_none() {
+            bail!("Chunk {} of {} is missing", index, key);
+        }
+    }
+
+    if data.len() != header.len as usize || checksum(&data) != header.crc {
+        bail!("{} is corrupted", key);
+    }
+    Ok(Some(data))
+}
+
+pub fn write(nvs: &mut EspNvs<NvsDefault>, key: &str, data: &[u8]) -> Result<()> {
+    if key.len() > MAX_KEY_LEN {
+        bail!("Key {} is longer than {} characters", key, MAX_KEY_LEN);
+    }
+    let chunks = data.chunks(CHUNK).count();
+    if chunks > MAX_CHUNKS {
+        bail!("{} bytes do not fit in {} chunks", data.len(), MAX_CHUNKS);
+    }
+
+    let previous = previous_header(nvs, key)?;
+    let generation = match &previous {
+        Some(previous) => previous.generation ^ 1,
+        None => 0,
+    };
+
+    for (index, chunk) in data.chunks(CHUNK).enumerate() {
+        nvs.set_blob(&chunk_key(key, generation, index as u8), chunk)?;
+    }
+    let header = Header {
+        generation,
+        chunks: chunks as u8,
+        len: data.len() as u32,
+        crc: checksum(data),
+    };
+    nvs.set_blob(key, &header.to_bytes())?;
+
+    // Only wasted space from here on if it fails
+    if let Some(previous) = previous {
+        for index in 0..previous.chunks {
+            if let Err(e) = nvs.remove(&chunk_key(key, previous.generation, index)) {
+                log::warn!("Failed to erase an old chunk of {}: {:?}", key, e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Removes `key` and its chunks; nothing stored is fine.
+pub fn remove(nvs: &mut EspNvs<NvsDefault>, key: &str) -> Result<()> {
+    let previous = previous_header(nvs, key)?;
+    nvs.remove(key)?;
+    if let Some(previous) = previous {
+        for index in 0..previous.chunks {
+            nvs.remove(&chunk_key(key, previous.generation, index))?;
+        }
+    }
+    Ok(())
+}
+
+/// The header currently stored under `key`, `None` for nothing or a plain blob.
+fn previous_header(nvs: &EspNvs<NvsDefault>, key: &str) -> Result<Option<Header>> {
+    match nvs.blob_len(key)? {
+        Some(HEADER_LEN) => {
+            let mut buffer = [0_u8; HEADER_LEN];
+            Ok(nvs.get_blob(key, &mut buffer)?.and_then(Header::parse))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn chunk_key(key: &str, generation: u8, index: u8) -> String {
+    format!("{}:{}{}", key, generation, index)
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    !gzip::crc32(!0, data)
+}
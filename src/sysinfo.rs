@@ -0,0 +1,37 @@
+//! A one-shot snapshot of the system for `/api/status`: heap, uptime, the
+//! reason for the last reset, FreeRTOS tasks and the firmware version.
+
+use esp_idf_svc::sys::uxTaskGetNumberOfTasks;
+use serde::Serialize;
+
+use crate::alerts::reset_reason;
+use crate::heap;
+use crate::honeypot::uptime_secs;
+use crate::version;
+
+#[derive(Debug, Serialize)]
+pub struct SystemInfo {
+    pub free_heap: u32,
+    /// Low-water mark since boot.
+    pub min_free_heap: u32,
+    pub largest_free_block: u32,
+    pub uptime_secs: u64,
+    pub reset_reason: &'static str,
+    pub task_count: u32,
+    pub version: &'static str,
+    pub git_hash: &'static str,
+}
+
+pub fn snapshot() -> SystemInfo {
+    let heap = heap::sample();
+    SystemInfo {
+        free_heap: heap.free,
+        min_free_heap: heap.min_free,
+        largest_free_block: heap.largest_free_block,
+        uptime_secs: uptime_secs(),
+        reset_reason: reset_reason(),
+        task_count: unsafe { uxTaskGetNumberOfTasks() } as u32,
+        version: version::VERSION,
+        git_hash: version::GIT_HASH,
+    }
+}
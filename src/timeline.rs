@@ -0,0 +1,116 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::Mutex;
+
+use crate::alerts::unix_time;
+use crate::events::Event;
+use crate::honeypot::uptime_secs;
+use crate::page::{Page, PageRequest};
+
+const MAX_ENTRIES: usize = 256;
+/// Entries older than this are dropped even when there is room.
+const WINDOW_SECS: u64 = 60 * 60;
+/// Queue between the event bus and the timeline.
+pub const EVENT_QUEUE: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EntryKind {
+    Scan,
+    Ap,
+    Channel,
+    Alert,
+    Mode,
+    Link,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TimelineEntry {
+    /// Increasing over the boot, since several entries share a second.
+    pub id: u64,
+    pub uptime_secs: u64,
+    /// `None` until the clock has been set, see [`crate::clock`].
+    pub unix_time: Option<u64>,
+    pub kind: EntryKind,
+    pub detail: String,
+}
+
+/// What the device has been doing over the last hour, built from the event bus.
+#[derive(Default)]
+pub struct Timeline {
+    entries: Mutex<VecDeque<TimelineEntry>>,
+    next_id: AtomicU64,
+}
+
+impl Timeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records events from the bus until it goes away.
+    pub fn run(&self, events: Receiver<Event>) {
+        for event in events {
+            if let Some((kind, detail)) = summarize(&event) {
+                self.record(kind, detail);
+            }
+        }
+    }
+
+    pub fn record(&self, kind: EntryKind, detail: String) {
+        let now = uptime_secs();
+        let mut entries = self.entries.lock().unwrap();
+
+        while entries
+            .front()
+            .is_some_and(|entry| entries.len() == MAX_ENTRIES || now - entry.uptime_secs > WINDOW_SECS)
+        {
+            entries.pop_front();
+        }
+        entries.push_back(TimelineEntry {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            uptime_secs: now,
+            unix_time: unix_time(),
+            kind,
+            detail,
+        });
+    }
+
+    /// Entries recorded at or after `since` seconds of uptime, oldest first.
+    pub fn page(&self, since: u64, request: &PageRequest<u64>) -> Page<TimelineEntry, u64> {
+        let entries = self.entries.lock().unwrap();
+        request.select(
+            entries
+                .iter()
+                .filter(|entry| entry.uptime_secs >= since)
+                .map(|entry| (entry.id, entry)),
+        )
+    }
+}
+
+fn summarize(event: &Event) -> Option<(EntryKind, String)> {
+    match event {
+        Event::ScanCompleted { networks } => Some((EntryKind::Scan, format!("{} networks", networks))),
+        Event::ApAppeared { ssid, channel, .. } => {
+            Some((EntryKind::Ap, format!("{} appeared on channel {}", ssid, channel)))
+        }
+        Event::ApDisappeared { ssid, .. } => Some((EntryKind::Ap, format!("{} disappeared", ssid))),
+        Event::ApChannelChanged { ssid, from, to, .. } => Some((
+            EntryKind::Channel,
+            format!("{} moved from channel {} to {}", ssid, from, to),
+        )),
+        Event::DeauthFlood { bssid, frames } => Some((
+            EntryKind::Alert,
+            format!("{} deauthentication frames/s for {}", frames, bssid),
+        )),
+        Event::AlertRaised { id, severity, source } => Some((
+            EntryKind::Alert,
+            format!("{:?} alert {} from {}", severity, id, source),
+        )),
+        Event::LinkHealthChanged { health } => Some((EntryKind::Link, format!("link {}", health.as_str()))),
+        Event::SettingChanged { setting, value } => Some((EntryKind::Mode, format!("{} set to {}", setting, value))),
+        // Too frequent to be useful here
+        Event::TrackedRssi { .. } | Event::EspNowReceived { .. } | Event::LedChanged { .. } => None,
+    }
+}
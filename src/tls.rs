@@ -0,0 +1,296 @@
+//! The HTTPS certificate and private key, stored together in the `tls` NVS
+//! namespace so an install replaces both or neither.
+//!
+//! They are read once at boot: with `https` set in the HTTP settings and both
+//! present, the server listens on 443 instead of 80, and with `redirect_http` a
+//! second, minimal server on 80 sends every request there. Without them, or
+//! when the HTTPS server fails to start, the server stays on plain HTTP.
+//!
+//! NVS is not encrypted, so the key can be read back by anyone with the flash.
+
+use anyhow::{bail, Context, Result};
+use esp_idf_svc::http::server::{self, EspHttpServer};
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use esp_idf_svc::sys;
+use esp_idf_svc::tls::X509;
+use embedded_svc::http::Method;
+use embedded_svc::io::Write;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::ffi::{c_int, c_void};
+use std::ptr;
+use std::sync::Mutex;
+
+use crate::codec;
+use crate::storage;
+
+const NAMESPACE: &str = "tls";
+const PEMS_KEY: &str = "pems";
+/// Where certificate and key went, as separate blobs, before they were stored together.
+const LEGACY_CERTIFICATE_KEY: &str = "cert";
+const LEGACY_PRIVATE_KEY_KEY: &str = "key";
+/// Enough for an RSA-2048 or EC certificate and key.
+pub const MAX_PEM_LEN: usize = 4096;
+const CERTIFICATE_BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+const CERTIFICATE_END: &str = "-----END CERTIFICATE-----";
+/// The redirect server only ever holds a connection or two.
+const REDIRECT_SOCKETS: usize = 1;
+/// Next to the main server's default control port.
+const REDIRECT_CTRL_PORT: u16 = 32769;
+
+/// Both NUL-terminated, as the server and mbedtls need them, and stored back
+/// to back.
+struct StoredPems {
+    certificate: Vec<u8>,
+    private_key: Vec<u8>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TlsInfo {
+    pub installed: bool,
+    /// SHA-256 of the DER certificate, as browsers show it.
+    pub certificate_sha256: Option<String>,
+    /// Whether this boot serves HTTPS.
+    pub active: bool,
+}
+
+pub struct TlsStore {
+    nvs: Option<Mutex<EspNvs<NvsDefault>>>,
+    active: Mutex<bool>,
+}
+
+impl TlsStore {
+    pub fn new(partition: EspDefaultNvsPartition) -> Result<Self> {
+        Ok(TlsStore {
+            nvs: Some(Mutex::new(EspNvs::new(partition, NAMESPACE, true)?)),
+            active: Mutex::new(false),
+        })
+    }
+
+    /// Without NVS nothing can be installed, so the server stays on HTTP.
+    pub fn in_memory() -> Self {
+        TlsStore {
+            nvs: None,
+            active: Mutex::new(false),
+        }
+    }
+
+    /// The certificate and key for the server, when both are installed. Their
+    /// buffers are leaked: the server keeps pointers to them for as long as it runs.
+    pub fn load(&self) -> Result<Option<(X509<'static>, X509<'static>)>> {
+        let Some(nvs) = &self.nvs else {
+            return Ok(None);
+        };
+        let Some(pems) = read_pems(&nvs.lock().unwrap())? else {
+            return Ok(None);
+        };
+
+        let certificate: &'static [u8] = Box::leak(pems.certificate.into_boxed_slice());
+        let private_key: &'static [u8] = Box::leak(pems.private_key.into_boxed_slice());
+        Ok(Some((X509::pem_until_nul(certificate), X509::pem_until_nul(private_key))))
+    }
+
+    /// Marks this boot as serving HTTPS, for [`TlsStore::info`].
+    pub fn set_active(&self) {
+        *self.active.lock().unwrap() = true;
+    }
+
+    pub fn info(&self) -> Result<TlsInfo> {
+        let certificate = match &self.nvs {
+            Some(nvs) => read_pems(&nvs.lock().unwrap())?.map(|pems| pems.certificate),
+            None => None,
+        };
+        let certificate_sha256 = match &certificate {
+            Some(pem) => Some(codec::hex_string(&Sha256::digest(certificate_der(pem)?))),
+            None => None,
+        };
+
+        Ok(TlsInfo {
+            installed: certificate.is_some(),
+            certificate_sha256,
+            active: *self.active.lock().unwrap(),
+        })
+    }
+
+    /// Stores a PEM certificate and the private key that belongs to it, used
+    /// from the next boot.
+    pub fn install(&self, certificate: &str, private_key: &str) -> Result<()> {
+        let Some(nvs) = &self.nvs else {
+            bail!("No NVS to store the certificate in");
+        };
+        for (name, pem) in [("certificate", certificate), ("private_key", private_key)] {
+            if pem.len() >= MAX_PEM_LEN {
+                bail!("{} must be shorter than {} bytes", name, MAX_PEM_LEN);
+            }
+        }
+        certificate_der(certificate.as_bytes()).context("certificate")?;
+        if !private_key.contains("-----BEGIN") || !private_key.contains("PRIVATE KEY-----") {
+            bail!("private_key is not a PEM private key");
+        }
+
+        let pems = StoredPems {
+            certificate: nul_terminated(certificate),
+            private_key: nul_terminated(private_key),
+        };
+        check_pair(&pems.certificate, &pems.private_key)?;
+
+        let mut nvs = nvs.lock().unwrap();
+        storage::write(&mut nvs, PEMS_KEY, &[pems.certificate, pems.private_key].concat())?;
+        remove_legacy(&mut nvs);
+        Ok(())
+    }
+
+    /// Removes the certificate and key; the server is back on HTTP after a reboot.
+    pub fn clear(&self) -> Result<()> {
+        if let Some(nvs) = &self.nvs {
+            let mut nvs = nvs.lock().unwrap();
+            storage::remove(&mut nvs, PEMS_KEY)?;
+            remove_legacy(&mut nvs);
+        }
+        Ok(())
+    }
+}
+
+/// A plain HTTP server on port 80 answering every GET with a redirect to the
+/// same path over HTTPS.
+pub fn redirect_server() -> Result<EspHttpServer<'static>> {
+    let mut server = EspHttpServer::new(&server::Configuration {
+        http_port: 80,
+        ctrl_port: REDIRECT_CTRL_PORT,
+        max_open_sockets: REDIRECT_SOCKETS,
+        max_uri_handlers: 1,
+        uri_match_wildcard: true,
+        ..Default::default()
+    })?;
+    server.fn_handler("/*", Method::Get, |req| -> Result<()> {
+        let Some(host) = req.header("Host") else {
+            let mut response = req.into_status_response(400)?;
+            response.write_all("Missing Host header".as_bytes())?;
+            return Ok(());
+        };
+        // Drop a :80 port, the HTTPS server is on the default port
+        let host = host.rsplit_once(':').map_or(host, |(name, _)| name);
+        let location = format!("https://{}{}", host, req.uri());
+        req.into_response(301, None, &[("Location", &location)])?;
+        Ok(())
+    })?;
+    Ok(server)
+}
+
+fn read_pems(nvs: &EspNvs<NvsDefault>) -> Result<Option<StoredPems>> {
+    if let Some(mut data) = storage::read(nvs, PEMS_KEY)? {
+        let Some(end) = data.iter().position(|&byte| byte == 0) else {
+            bail!("Stored TLS certificate and key are corrupted");
+        };
+        let private_key = data.split_off(end + 1);
+        return Ok(Some(StoredPems {
+            certificate: data,
+            private_key,
+        }));
+    }
+    match (read_pem(nvs, LEGACY_CERTIFICATE_KEY)?, read_pem(nvs, LEGACY_PRIVATE_KEY_KEY)?) {
+        (Some(certificate), Some(private_key)) => Ok(Some(StoredPems {
+            certificate,
+            private_key,
+        })),
+        _ => Ok(None),
+    }
+}
+
+fn remove_legacy(nvs: &mut EspNvs<NvsDefault>) {
+    for key in [LEGACY_CERTIFICATE_KEY, LEGACY_PRIVATE_KEY_KEY] {
+        if let Err(e) = nvs.remove(key) {
+            log::warn!("Failed to remove the old TLS entry {}: {:?}", key, e);
+        }
+    }
+}
+
+/// The stored PEM with its NUL terminator, which the server needs.
+fn read_pem(nvs: &EspNvs<NvsDefault>, key: &str) -> Result<Option<Vec<u8>>> {
+    let Some(len) = nvs.blob_len(key)? else {
+        return Ok(None);
+    };
+    let mut buffer = vec![0_u8; len];
+    if nvs.get_blob(key, &mut buffer)?.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(buffer))
+}
+
+fn nul_terminated(pem: &str) -> Vec<u8> {
+    let mut data = Vec::with_capacity(pem.len() + 1);
+    data.extend_from_slice(pem.trim().as_bytes());
+    data.extend_from_slice(b"\n\0");
+    data
+}
+
+/// Decodes the first certificate of a PEM chain.
+fn certificate_der(pem: &[u8]) -> Result<Vec<u8>> {
+    let pem = std::str::from_utf8(pem)?.trim_end_matches('\0');
+    let Some((_, rest)) = pem.split_once(CERTIFICATE_BEGIN) else {
+        bail!("Not a PEM certificate");
+    };
+    let Some((body, _)) = rest.split_once(CERTIFICATE_END) else {
+        bail!("Truncated PEM certificate");
+    };
+
+    let text: Vec<u8> = body.bytes().filter(|byte| !byte.is_ascii_whitespace()).collect();
+    let mut der = vec![0_u8; codec::base64_decoded_len(text.len())];
+    let len = codec::decode_base64(&text, &mut der)?;
+    der.truncate(len);
+    Ok(der)
+}
+
+/// Parses both NUL-terminated PEMs with mbedtls, as the server will, and checks
+/// that the key is the private half of the certificate's public key.
+fn check_pair(certificate: &[u8], private_key: &[u8]) -> Result<()> {
+    // Safety: both contexts are initialised before use and freed on every path
+    unsafe {
+        let mut crt: sys::mbedtls_x509_crt = std::mem::zeroed();
+        let mut key: sys::mbedtls_pk_context = std::mem::zeroed();
+        sys::mbedtls_x509_crt_init(&mut crt);
+        sys::mbedtls_pk_init(&mut key);
+
+        let result = mbedtls_result(
+            sys::mbedtls_x509_crt_parse(&mut crt, certificate.as_ptr(), certificate.len()),
+            "certificate is not usable",
+        )
+        .and_then(|()| {
+            mbedtls_result(
+                sys::mbedtls_pk_parse_key(
+                    &mut key,
+                    private_key.as_ptr(),
+                    private_key.len(),
+                    ptr::null(),
+                    0,
+                    Some(fill_random),
+                    ptr::null_mut(),
+                ),
+                "private_key is not usable",
+            )
+        })
+        .and_then(|()| {
+            mbedtls_result(
+                sys::mbedtls_pk_check_pair(&crt.private_pk, &key, Some(fill_random), ptr::null_mut()),
+                "private_key does not belong to the certificate",
+            )
+        });
+
+        sys::mbedtls_pk_free(&mut key);
+        sys::mbedtls_x509_crt_free(&mut crt);
+        result
+    }
+}
+
+fn mbedtls_result(code: c_int, message: &str) -> Result<()> {
+    if code != 0 {
+        bail!("{} (mbedtls error -0x{:04x})", message, code.unsigned_abs());
+    }
+    Ok(())
+}
+
+/// The RNG callback mbedtls wants for blinding, from the hardware RNG.
+unsafe extern "C" fn fill_random(_: *mut c_void, output: *mut u8, len: usize) -> c_int {
+    sys::esp_fill_random(output as *mut c_void, len);
+    0
+}
@@ -0,0 +1,97 @@
+use serde::Serialize;
+use std::sync::Mutex;
+
+use crate::events::{Event, EventBus};
+use crate::scan::{format_mac, ScannedNetwork};
+
+/// Weight of the newest sample in the exponential moving average.
+const SMOOTHING: f32 = 0.3;
+
+#[derive(Default)]
+struct TrackerState {
+    target: Option<[u8; 6]>,
+    rssi: Option<i8>,
+    smoothed_rssi: Option<f32>,
+    samples: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TrackingStatus {
+    pub bssid: Option<String>,
+    pub visible: bool,
+    pub rssi: Option<i8>,
+    pub smoothed_rssi: Option<f32>,
+    pub samples: u32,
+}
+
+/// Follows the signal strength of one BSSID across scans, for "hot/cold" AP hunting.
+#[derive(Default)]
+pub struct BssidTracker {
+    state: Mutex<TrackerState>,
+}
+
+impl BssidTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn track(&self, bssid: Option<[u8; 6]>) {
+        match bssid {
+            Some(bssid) => log::info!("Tracking BSSID {}", format_mac(&bssid)),
+            None => log::info!("BSSID tracking stopped"),
+        }
+
+        *self.state.lock().unwrap() = TrackerState {
+            target: bssid,
+            ..Default::default()
+        };
+    }
+
+    pub fn is_tracking(&self) -> bool {
+        self.state.lock().unwrap().target.is_some()
+    }
+
+    /// Feeds one scan into the tracker and publishes the updated reading.
+    pub fn sample(&self, networks: &[ScannedNetwork], events: &EventBus) {
+        let mut state = self.state.lock().unwrap();
+        let Some(target) = state.target else {
+            return;
+        };
+
+        state.rssi = networks
+            .iter()
+            .find(|network| network.bssid == target)
+            .map(|network| network.signal_strength);
+
+        if let Some(rssi) = state.rssi {
+            let smoothed = match state.smoothed_rssi {
+                Some(smoothed) => smoothed + SMOOTHING * (rssi as f32 - smoothed),
+                None => rssi as f32,
+            };
+            state.smoothed_rssi = Some(smoothed);
+            state.samples += 1;
+        }
+
+        events.publish(Event::TrackedRssi {
+            bssid: format_mac(&target),
+            rssi: state.rssi,
+            smoothed_rssi: state.smoothed_rssi,
+        });
+    }
+
+    pub fn smoothed_rssi(&self) -> Option<f32> {
+        self.state.lock().unwrap().smoothed_rssi
+    }
+
+    pub fn status(&self) -> TrackingStatus {
+        let state = self.state.lock().unwrap();
+
+        TrackingStatus {
+            bssid: state.target.as_ref().map(format_mac),
+            visible: state.rssi.is_some(),
+            rssi: state.rssi,
+            smoothed_rssi: state.smoothed_rssi,
+            samples: state.samples,
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+
+use crate::alerts::{AlertLog, Severity};
+use crate::events::Event;
+use crate::honeypot::uptime_secs;
+use crate::page::{Page, PageRequest};
+use crate::scan::{auth_method_to_string, format_mac, LatestScan, ScannedNetwork};
+
+/// Same-SSID access points further apart than this are flagged. A mesh spread over
+/// channels 1 and 11 trips it too, so channel findings are weaker than auth ones.
+const CHANNEL_SPREAD: u8 = 8;
+const MAX_FINDINGS: usize = 32;
+/// Queue between the event bus and the detector.
+pub const EVENT_QUEUE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TwinReason {
+    /// Same SSID, different security: the classic open or WEP copy of a WPA2 network.
+    AuthMismatch,
+    ChannelSpread,
+}
+
+/// Two access points announcing the same SSID in a way a single network would not.
+#[derive(Debug, Clone, Serialize)]
+pub struct TwinFinding {
+    /// In the order the pairs were first seen.
+    pub id: u32,
+    pub ssid: String,
+    pub bssids: [String; 2],
+    pub auth_methods: [&'static str; 2],
+    pub channels: [u8; 2],
+    pub reason: TwinReason,
+    pub first_seen: u64,
+    pub last_seen: u64,
+}
+
+/// Looks for evil twins in every scan and keeps what it found.
+pub struct TwinDetector {
+    latest_scan: LatestScan,
+    alerts: Arc<AlertLog>,
+    findings: Mutex<Vec<TwinFinding>>,
+    next_id: AtomicU32,
+}
+
+impl TwinDetector {
+    pub fn new(latest_scan: LatestScan, alerts: Arc<AlertLog>) -> Self {
+        TwinDetector {
+            latest_scan,
+            alerts,
+            findings: Mutex::new(Vec::new()),
+            next_id: AtomicU32::new(0),
+        }
+    }
+
+    /// Checks each scan until the bus goes away.
+    pub fn run(&self, events: Receiver<Event>) {
+        for event in events {
+            if let Event::ScanCompleted { .. } = event {
+                let networks = self.latest_scan.lock().unwrap().clone();
+                self.check(&networks);
+            }
+        }
+    }
+
+    pub fn page(&self, request: &PageRequest<u32>) -> Page<TwinFinding, u32> {
+        request.select(self.findings.lock().unwrap().iter().map(|finding| (finding.id, finding)))
+    }
+
+    fn check(&self, networks: &[ScannedNetwork]) {
+        let now = uptime_secs();
+        let mut findings = self.findings.lock().unwrap();
+
+        for (i, a) in networks.iter().enumerate() {
+            for b in &networks[i + 1..] {
+                if a.ssid.is_empty() || a.ssid != b.ssid {
+                    continue;
+                }
+                let Some(reason) = twin_reason(a, b) else {
+                    continue;
+                };
+
+                // Same pair in either order
+                let (first, second) = if a.bssid < b.bssid { (a, b) } else { (b, a) };
+                let bssids = [format_mac(&first.bssid), format_mac(&second.bssid)];
+                if let Some(finding) = findings.iter_mut().find(|finding| finding.bssids == bssids) {
+                    finding.last_seen = now;
+                    continue;
+                }
+
+                self.alerts.raise(
+                    Severity::Warning,
+                    "evil-twin",
+                    format!("{} is announced by {} and {} ({:?})", first.ssid, bssids[0], bssids[1], reason),
+                );
+                if findings.len() == MAX_FINDINGS {
+                    if let Some(stalest) = (0..findings.len()).min_by_key(|&index| findings[index].last_seen) {
+                        findings.remove(stalest);
+                    }
+                }
+                findings.push(TwinFinding {
+                    id: self.next_id.fetch_add(1, Ordering::Relaxed),
+                    ssid: first.ssid.clone(),
+                    bssids,
+                    auth_methods: [
+                        auth_method_to_string(first.auth_method),
+                        auth_method_to_string(second.auth_method),
+                    ],
+                    channels: [first.channel, second.channel],
+                    reason,
+                    first_seen: now,
+                    last_seen: now,
+                });
+            }
+        }
+    }
+}
+
+fn twin_reason(a: &ScannedNetwork, b: &ScannedNetwork) -> Option<TwinReason> {
+    if a.auth_method != b.auth_method {
+        Some(TwinReason::AuthMismatch)
+    } else if a.channel.abs_diff(b.channel) > CHANNEL_SPREAD {
+        Some(TwinReason::ChannelSpread)
+    } else {
+        None
+    }
+}
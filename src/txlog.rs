@@ -0,0 +1,254 @@
+//! Per-channel record of what the firmware transmits on its own, kept across
+//! reboots, so an experiment can be shown to have stayed within what was
+//! authorized: estimated airtime, frame count and the highest PA setting seen.
+//!
+//! Counted are ESP-NOW sends and the probe requests of active scans. Airtime is
+//! estimated at 1 Mbit/s, the rate both go out at; station and access point
+//! traffic, beacons included, is not counted.
+//!
+//! The CSV export ends with an HMAC-SHA256 over everything before it, keyed with
+//! the hex SHA-256 of the admin API key, so whoever holds the key can check it
+//! came from the device unaltered.
+
+use anyhow::{bail, Result};
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use esp_idf_svc::sys::{self, esp};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::alerts::unix_time;
+use crate::codec;
+use crate::config::ConfigStore;
+use crate::events::Event;
+
+const NAMESPACE: &str = "txlog";
+const CHANNELS_KEY: &str = "channels";
+pub const EVENT_QUEUE: usize = 8;
+/// 2.4 GHz channels 1-14.
+const CHANNEL_COUNT: usize = 14;
+/// Counts are folded in memory and written out at most this often.
+const PERSIST_INTERVAL: Duration = Duration::from_secs(300);
+/// Long preamble and PLCP header at 1 Mbit/s.
+const PREAMBLE_US: u64 = 192;
+/// MAC header, vendor action frame and vendor element around an ESP-NOW payload, with FCS.
+const ESPNOW_OVERHEAD: usize = 43;
+/// A probe request with the SSID, rate and HT capability elements, with FCS.
+const PROBE_REQUEST_LEN: usize = 80;
+const HMAC_BLOCK: usize = 64;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChannelUsage {
+    pub tx_us: u64,
+    pub frames: u64,
+    /// Highest PA setting while transmitting on the channel, in dBm.
+    pub max_power_dbm: Option<f32>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct Stored {
+    /// Time of the first transmission recorded, when the clock was set.
+    since: Option<u64>,
+    channels: [ChannelUsage; CHANNEL_COUNT],
+}
+
+#[derive(Debug, Serialize)]
+pub struct TxLogInfo {
+    pub since: Option<u64>,
+    /// Index 0 is channel 1.
+    pub channels: Vec<ChannelUsage>,
+}
+
+#[derive(Default)]
+struct State {
+    stored: Stored,
+    persisted_at: Option<Instant>,
+    dirty: bool,
+}
+
+pub struct TxLog {
+    config: Arc<ConfigStore>,
+    nvs: Option<Mutex<EspNvs<NvsDefault>>>,
+    state: Mutex<State>,
+}
+
+impl TxLog {
+    pub fn new(partition: EspDefaultNvsPartition, config: Arc<ConfigStore>) -> Result<Self> {
+        let nvs = EspNvs::new(partition, NAMESPACE, true)?;
+        let stored = Self::load(&nvs).unwrap_or_else(|e| {
+            log::error!("Failed to load the transmit log, starting empty: {:?}", e);
+            Stored::default()
+        });
+
+        Ok(TxLog {
+            config,
+            nvs: Some(Mutex::new(nvs)),
+            state: Mutex::new(State {
+                stored,
+                ..Default::default()
+            }),
+        })
+    }
+
+    /// Log used when NVS is unavailable; it only covers the current boot.
+    pub fn in_memory(config: Arc<ConfigStore>) -> Self {
+        TxLog {
+            config,
+            nvs: None,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn load(nvs: &EspNvs<NvsDefault>) -> Result<Stored> {
+        let Some(len) = nvs.blob_len(CHANNELS_KEY)? else {
+            return Ok(Stored::default());
+        };
+        let mut buffer = vec![0_u8; len];
+        match nvs.get_blob(CHANNELS_KEY, &mut buffer)? {
+            Some(data) => Ok(serde_json::from_slice(data)?),
+            None => Ok(Stored::default()),
+        }
+    }
+
+    /// Records the probe requests of every finished scan, until the bus goes away.
+    pub fn run(&self, events: Receiver<Event>) {
+        for event in events {
+            if let Event::ScanCompleted { .. } = event {
+                self.record_scan();
+            }
+        }
+    }
+
+    /// One probe request on each channel the regulatory domain allows active scans on.
+    fn record_scan(&self) {
+        let max_channel = self.config.get().wifi_country.max_channel();
+        for channel in 1..=max_channel {
+            self.record(channel, PROBE_REQUEST_LEN);
+        }
+    }
+
+    /// An ESP-NOW frame with `payload_len` bytes, on the channel the radio is on.
+    pub fn record_espnow(&self, payload_len: usize) {
+        let mut channel = 0_u8;
+        let mut second = sys::wifi_second_chan_t_WIFI_SECOND_CHAN_NONE;
+        if let Err(e) = esp!(unsafe { sys::esp_wifi_get_channel(&mut channel, &mut second) }) {
+            log::warn!("Failed to read the channel of an ESP-NOW send: {:?}", e);
+            return;
+        }
+        self.record(channel, ESPNOW_OVERHEAD + payload_len);
+    }
+
+    fn record(&self, channel: u8, frame_len: usize) {
+        if !(1..=CHANNEL_COUNT as u8).contains(&channel) {
+            return;
+        }
+        let power = max_tx_power_dbm();
+
+        let mut state = self.state.lock().unwrap();
+        if state.stored.since.is_none() {
+            state.stored.since = unix_time();
+        }
+        let usage = &mut state.stored.channels[channel as usize - 1];
+        usage.tx_us += PREAMBLE_US + 8 * frame_len as u64;
+        usage.frames += 1;
+        if let Some(power) = power {
+            usage.max_power_dbm = Some(usage.max_power_dbm.map_or(power, |max| max.max(power)));
+        }
+        state.dirty = true;
+
+        if state.persisted_at.map_or(true, |at| at.elapsed() >= PERSIST_INTERVAL) {
+            self.persist(&mut state);
+        }
+    }
+
+    fn persist(&self, state: &mut State) {
+        state.persisted_at = Some(Instant::now());
+        state.dirty = false;
+
+        let Some(nvs) = &self.nvs else {
+            return;
+        };
+        let result = serde_json::to_vec(&state.stored)
+            .map_err(anyhow::Error::from)
+            .and_then(|data| Ok(nvs.lock().unwrap().set_blob(CHANNELS_KEY, &data)?));
+        if let Err(e) = result {
+            log::error!("Failed to persist the transmit log: {:?}", e);
+        }
+    }
+
+    /// Writes out counts not persisted yet, so an export matches what survives a reboot.
+    fn flush(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.dirty {
+            self.persist(&mut state);
+        }
+    }
+
+    pub fn info(&self) -> TxLogInfo {
+        let state = self.state.lock().unwrap();
+        TxLogInfo {
+            since: state.stored.since,
+            channels: state.stored.channels.to_vec(),
+        }
+    }
+
+    /// The log as CSV, one line per channel with any transmission, signed with
+    /// `signing_key`. Lines starting with `#` are comments.
+    pub fn csv(&self, device: &str, signing_key: Option<&str>) -> Result<String> {
+        let Some(signing_key) = signing_key else {
+            bail!("The export is signed with the admin API key, pair the device first");
+        };
+        self.flush();
+        let info = self.info();
+
+        let mut csv = String::new();
+        writeln!(csv, "# device: {}", device)?;
+        if let Some(since) = info.since {
+            writeln!(csv, "# since: {}", since)?;
+        }
+        if let Some(now) = unix_time() {
+            writeln!(csv, "# exported: {}", now)?;
+        }
+        writeln!(csv, "channel,tx_seconds,frames,max_power_dbm")?;
+        for (index, usage) in info.channels.iter().enumerate().filter(|(_, usage)| usage.frames > 0) {
+            write!(csv, "{},{:.6},{},", index + 1, usage.tx_us as f64 / 1e6, usage.frames)?;
+            match usage.max_power_dbm {
+                Some(power) => writeln!(csv, "{:.2}", power)?,
+                None => writeln!(csv)?,
+            }
+        }
+
+        let signature = hmac_sha256(signing_key.as_bytes(), csv.as_bytes());
+        writeln!(csv, "# hmac-sha256: {}", codec::hex_string(&signature))?;
+        Ok(csv)
+    }
+}
+
+/// The PA limit the driver is set to, which it reports in steps of 0.25 dBm.
+fn max_tx_power_dbm() -> Option<f32> {
+    let mut power: i8 = 0;
+    esp!(unsafe { sys::esp_wifi_get_max_tx_power(&mut power) }).ok()?;
+    Some(power as f32 / 4.0)
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut block = [0_u8; HMAC_BLOCK];
+    if key.len() > HMAC_BLOCK {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.update(block.map(|byte| byte ^ 0x36));
+    inner.update(data);
+    let mut outer = Sha256::new();
+    outer.update(block.map(|byte| byte ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().into()
+}
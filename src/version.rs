@@ -0,0 +1,39 @@
+//! What image this is, embedded by `build.rs`. A `-dirty` hash means the tree
+//! had uncommitted changes when it was built.
+
+use serde::Serialize;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const GIT_HASH: &str = env!("BUILD_GIT_HASH");
+/// UTC, ISO 8601.
+pub const BUILD_TIME: &str = env!("BUILD_TIME");
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_hash: &'static str,
+    pub build_time: &'static str,
+    pub build_unix_time: u64,
+    pub idf_version: String,
+}
+
+pub fn info() -> BuildInfo {
+    BuildInfo {
+        version: VERSION,
+        git_hash: GIT_HASH,
+        build_time: BUILD_TIME,
+        build_unix_time: env!("BUILD_UNIX_TIME").parse().unwrap_or(0),
+        idf_version: idf_version(),
+    }
+}
+
+/// One line for the boot log.
+pub fn summary() -> String {
+    format!("{} ({}, built {}, ESP-IDF {})", VERSION, GIT_HASH, BUILD_TIME, idf_version())
+}
+
+fn idf_version() -> String {
+    unsafe { std::ffi::CStr::from_ptr(esp_idf_svc::sys::esp_get_idf_version()) }
+        .to_string_lossy()
+        .into_owned()
+}
@@ -0,0 +1,169 @@
+use anyhow::Result;
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+
+use crate::alerts::AlertLog;
+use crate::config::ConfigStore;
+use crate::events::Event;
+use crate::history::{BssidHistory, ScanHistory};
+use crate::webhook;
+
+const NAMESPACE: &str = "watch";
+const SEEN_KEY: &str = "seen";
+/// Beyond this many BSSIDs the ones learned first are forgotten.
+const MAX_SEEN: usize = 256;
+/// Queue between the event bus and the watch.
+pub const EVENT_QUEUE: usize = 8;
+
+/// Body of the webhook POST.
+#[derive(Debug, Serialize)]
+struct NewNetwork<'a> {
+    alert_id: u32,
+    ssid: &'a str,
+    bssid: &'a str,
+    channel: u8,
+    rssi: Option<i8>,
+}
+
+/// Flags access points the device has never seen before, checking the scan
+/// history after every scan. Known BSSIDs are persisted in the `watch` NVS
+/// namespace so a reboot does not make the whole neighbourhood new again.
+pub struct NetworkWatch {
+    nvs: Option<Mutex<EspNvs<NvsDefault>>>,
+    seen: Mutex<VecDeque<[u8; 6]>>,
+    history: Arc<ScanHistory>,
+    config: Arc<ConfigStore>,
+    alerts: Arc<AlertLog>,
+}
+
+impl NetworkWatch {
+    pub fn new(
+        partition: EspDefaultNvsPartition,
+        history: Arc<ScanHistory>,
+        config: Arc<ConfigStore>,
+        alerts: Arc<AlertLog>,
+    ) -> Result<Self> {
+        let nvs = EspNvs::new(partition, NAMESPACE, true)?;
+
+        let seen = match Self::load(&nvs) {
+            Ok(seen) => seen,
+            Err(e) => {
+                log::warn!("Failed to load known BSSIDs, starting empty: {}", e);
+                VecDeque::new()
+            }
+        };
+
+        Ok(NetworkWatch {
+            nvs: Some(Mutex::new(nvs)),
+            seen: Mutex::new(seen),
+            history,
+            config,
+            alerts,
+        })
+    }
+
+    /// Watch used when NVS is unavailable; known BSSIDs are relearned on every boot.
+    pub fn in_memory(history: Arc<ScanHistory>, config: Arc<ConfigStore>, alerts: Arc<AlertLog>) -> Self {
+        NetworkWatch {
+            nvs: None,
+            seen: Mutex::new(VecDeque::new()),
+            history,
+            config,
+            alerts,
+        }
+    }
+
+    fn load(nvs: &EspNvs<NvsDefault>) -> Result<VecDeque<[u8; 6]>> {
+        let Some(len) = nvs.blob_len(SEEN_KEY)? else {
+            return Ok(VecDeque::new());
+        };
+
+        let mut buffer = vec![0_u8; len];
+        let Some(data) = nvs.get_blob(SEEN_KEY, &mut buffer)? else {
+            return Ok(VecDeque::new());
+        };
+        Ok(data
+            .chunks_exact(6)
+            .map(|chunk| chunk.try_into().unwrap())
+            .collect())
+    }
+
+    /// Checks the history after each scan until the bus goes away.
+    pub fn run(&self, events: Receiver<Event>) {
+        for event in events {
+            if let Event::ScanCompleted { .. } = event {
+                self.check();
+            }
+        }
+    }
+
+    fn check(&self) {
+        let mut seen = self.seen.lock().unwrap();
+        // Everything around on the very first scan is the baseline, not news
+        let learning = seen.is_empty();
+
+        let new: Vec<BssidHistory> = self
+            .history
+            .all()
+            .into_iter()
+            .filter(|network| !seen.contains(&network.mac))
+            .collect();
+        if new.is_empty() {
+            return;
+        }
+
+        for network in &new {
+            if seen.len() == MAX_SEEN {
+                seen.pop_front();
+            }
+            seen.push_back(network.mac);
+        }
+        self.persist(&seen);
+        drop(seen);
+
+        let settings = self.config.get().new_network_alerts;
+        if learning || !settings.enabled {
+            log::info!("Learned {} new BSSIDs", new.len());
+            return;
+        }
+
+        for network in &new {
+            let id = self.alerts.raise(
+                settings.severity,
+                "new-network",
+                format!(
+                    "New network {} ({}) on channel {}",
+                    network.ssid, network.bssid, network.channel
+                ),
+            );
+
+            if settings.webhook_url.is_empty() {
+                continue;
+            }
+            let body = NewNetwork {
+                alert_id: id,
+                ssid: &network.ssid,
+                bssid: &network.bssid,
+                channel: network.channel,
+                rssi: network.samples.back().map(|sample| sample.rssi),
+            };
+            if let Err(e) = webhook::post_json(&settings.webhook_url, &body) {
+                log::warn!("Failed to notify new network {}: {:?}", network.bssid, e);
+            }
+        }
+    }
+
+    fn persist(&self, seen: &VecDeque<[u8; 6]>) {
+        let Some(nvs) = &self.nvs else {
+            return;
+        };
+
+        let data: Vec<u8> = seen.iter().flatten().copied().collect();
+        if let Err(e) = nvs.lock().unwrap().set_blob(SEEN_KEY, &data) {
+            log::warn!("Failed to persist known BSSIDs: {:?}", e);
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use anyhow::{bail, Result};
+use embedded_svc::http::client::Client;
+use embedded_svc::io::Write;
+use esp_idf_svc::http::client::{Configuration, EspHttpConnection};
+use serde::Serialize;
+use std::time::Duration;
+
+/// Stack for threads that post webhooks: the TLS handshake needs more than the default.
+pub const THREAD_STACK_SIZE: usize = 10 * 1024;
+
+/// POSTs `body` as JSON to `url`. HTTPS servers are checked against the
+/// certificate bundle built into ESP-IDF.
+pub fn post_json<T: Serialize>(url: &str, body: &T) -> Result<()> {
+    let payload = serde_json::to_vec(body)?;
+    let length = payload.len().to_string();
+
+    let connection = EspHttpConnection::new(&Configuration {
+        timeout: Some(Duration::from_secs(10)),
+        crt_bundle_attach: Some(esp_idf_svc::sys::esp_crt_bundle_attach),
+        ..Default::default()
+    })?;
+    let mut client = Client::wrap(connection);
+
+    let headers = [("Content-Type", "application/json"), ("Content-Length", length.as_str())];
+    let mut request = client.post(url, &headers)?;
+    request.write_all(&payload)?;
+    request.flush()?;
+
+    let status = request.submit()?.status();
+    if !(200..300).contains(&status) {
+        bail!("webhook answered with status {}", status);
+    }
+
+    Ok(())
+}
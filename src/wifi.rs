@@ -0,0 +1,625 @@
+use anyhow::{anyhow, Result};
+use embedded_svc::wifi::{AccessPointConfiguration, AccessPointInfo, AuthMethod, ClientConfiguration, Configuration};
+use esp_idf_hal::peripheral::Peripheral;
+use esp_idf_svc::eventloop::{EspSubscription, EspSystemEventLoop, System};
+use esp_idf_svc::handle::RawHandle;
+use esp_idf_svc::ipv4::{self, ClientSettings, Mask, Subnet};
+use esp_idf_svc::netif::{EspNetif, NetifConfiguration, NetifStack};
+use esp_idf_svc::nvs::{EspNvsPartition, NvsDefault};
+use esp_idf_svc::sys::{self, esp};
+use esp_idf_svc::timer::{EspTimerService, Task};
+use esp_idf_svc::wifi::{AsyncWifi, EspWifi, WifiDeviceId, WifiDriver, WifiEvent};
+use log::info;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::Ipv4Addr;
+use std::ffi::CString;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::channels;
+use crate::config::{
+    AccessPointSettings, AppConfig, ConfigStore, EnterpriseCredentials, Hostname, KnownNetwork, StaAddressing, StaMac,
+    TtlsPhase2, WifiCountry, WifiMode, WifiPowerSave,
+};
+use crate::netstats::{NetworkStats, SsidStats};
+use crate::scan::{format_mac, parse_mac, ScannedNetwork};
+
+/// Network tried when no known network has been configured yet.
+const FALLBACK_SSID: &str = "Wokwi-GUEST";
+/// The one WiFi driver, shared by the reconnect task and the scanner.
+pub type SharedWifi = Arc<Mutex<AsyncWifi<EspWifi<'static>>>>;
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Station link state as seen by the reconnect task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case", tag = "state")]
+pub enum LinkState {
+    Connected,
+    /// Lost, and the reconnect task has not picked it up yet.
+    Disconnected,
+    /// Waiting `retry_in_ms` before reconnect attempt number `attempt`.
+    Reconnecting { attempt: u32, retry_in_ms: u64 },
+}
+
+/// Keeps the station connected: a disconnect event wakes the reconnect task, which
+/// retries with exponential backoff until one of the known networks is joined again.
+pub struct WifiLink {
+    state: Mutex<LinkState>,
+    changed: Condvar,
+    /// Links restored by the reconnect task since boot.
+    reconnects: AtomicU32,
+}
+
+impl WifiLink {
+    pub fn new(connected: bool) -> Self {
+        WifiLink {
+            state: Mutex::new(if connected { LinkState::Connected } else { LinkState::Disconnected }),
+            changed: Condvar::new(),
+            reconnects: AtomicU32::new(0),
+        }
+    }
+
+    pub fn state(&self) -> LinkState {
+        *self.state.lock().unwrap()
+    }
+
+    pub fn reconnects(&self) -> u32 {
+        self.reconnects.load(Ordering::Relaxed)
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.state() == LinkState::Connected
+    }
+
+    /// Marks the link as lost. Disconnects raised by our own failed attempts are
+    /// ignored, since the link is not connected at that point.
+    pub fn subscribe(self: &Arc<Self>, sys_loop: &EspSystemEventLoop) -> Result<EspSubscription<'static, System>> {
+        let link = self.clone();
+
+        let subscription = sys_loop.subscribe::<WifiEvent, _>(move |event| {
+            if let WifiEvent::StaDisconnected(_) = event {
+                let mut state = link.state.lock().unwrap();
+                if *state == LinkState::Connected {
+                    log::warn!("WiFi link lost");
+                    *state = LinkState::Disconnected;
+                    link.changed.notify_all();
+                }
+            }
+        })?;
+
+        Ok(subscription)
+    }
+
+    /// Reconnect task body; never returns.
+    pub fn run(
+        &self,
+        wifi: SharedWifi,
+        config: Arc<ConfigStore>,
+        stats: Arc<NetworkStats>,
+    ) -> ! {
+        use futures::executor::block_on;
+
+        loop {
+            let mut state = self.state.lock().unwrap();
+            while *state == LinkState::Connected {
+                state = self.changed.wait(state).unwrap();
+            }
+            drop(state);
+
+            let mut backoff = INITIAL_BACKOFF;
+            for attempt in 1.. {
+                self.set(LinkState::Reconnecting {
+                    attempt,
+                    retry_in_ms: backoff.as_millis() as u64,
+                });
+                thread::sleep(backoff);
+
+                info!("Reconnect attempt {}", attempt);
+                let config = config.get();
+                let mac = next_sta_mac(&config.sta_mac, true);
+                let result = mixed_access_point(&config).and_then(|access_point| {
+                    block_on(connect_wifi(
+                        &mut wifi.lock().unwrap(),
+                        &config.known_networks,
+                        access_point.as_ref(),
+                        mac,
+                        &stats,
+                    ))
+                });
+                match result {
+                    Ok(()) => {
+                        info!("WiFi link restored after {} attempts", attempt);
+                        self.reconnects.fetch_add(1, Ordering::Relaxed);
+                        self.set(LinkState::Connected);
+                        break;
+                    }
+                    Err(e) => {
+                        log::warn!("Reconnect attempt {} failed: {:?}", attempt, e);
+                        backoff = (backoff * 2).min(MAX_BACKOFF);
+                    }
+                }
+            }
+        }
+    }
+
+    fn set(&self, state: LinkState) {
+        *self.state.lock().unwrap() = state;
+        self.changed.notify_all();
+    }
+}
+
+pub fn wifi(
+    modem: impl Peripheral<P = esp_idf_hal::modem::Modem> + 'static,
+    sysloop: EspSystemEventLoop,
+    nvs: Option<EspNvsPartition<NvsDefault>>,
+    timer_service: EspTimerService<Task>,
+    addressing: &StaAddressing,
+    hostname: &Hostname,
+    country: &WifiCountry,
+) -> Result<AsyncWifi<EspWifi<'static>>> {
+    let driver = WifiDriver::new(modem, sysloop.clone(), nvs)?;
+    set_country(country)?;
+    let sta_netif = EspNetif::new_with_conf(&NetifConfiguration {
+        ip_configuration: Some(ipv4::Configuration::Client(sta_ip_configuration(addressing))),
+        ..NetifConfiguration::wifi_default_client()
+    })?;
+    // Before DHCP starts, which sends it
+    if !hostname.name.is_empty() {
+        let name = CString::new(hostname.name.as_str())?;
+        esp!(unsafe { sys::esp_netif_set_hostname(sta_netif.handle(), name.as_ptr()) })?;
+        info!("Hostname set to {}", hostname.name);
+    }
+
+    let wifi = AsyncWifi::wrap(
+        EspWifi::wrap_all(driver, sta_netif, EspNetif::new(NetifStack::Ap)?)?,
+        sysloop,
+        timer_service,
+    )?;
+
+    Ok(wifi)
+}
+
+/// Needs the driver initialised. The country access points advertise is ignored:
+/// what is legal is where the device is, as configured.
+pub fn set_country(country: &WifiCountry) -> Result<()> {
+    let code = CString::new(country.code.as_str())?;
+    esp!(unsafe { sys::esp_wifi_set_country_code(code.as_ptr(), false) })?;
+    info!("WiFi country set to {}", country.code);
+    Ok(())
+}
+
+/// Only affects the station; an access point keeps its radio on.
+pub fn set_power_save(mode: WifiPowerSave) -> Result<()> {
+    let power_save = match mode {
+        WifiPowerSave::None => sys::wifi_ps_type_t_WIFI_PS_NONE,
+        WifiPowerSave::Min => sys::wifi_ps_type_t_WIFI_PS_MIN_MODEM,
+        WifiPowerSave::Max => sys::wifi_ps_type_t_WIFI_PS_MAX_MODEM,
+    };
+    esp!(unsafe { sys::esp_wifi_set_ps(power_save) })?;
+    Ok(())
+}
+
+fn sta_ip_configuration(addressing: &StaAddressing) -> ipv4::ClientConfiguration {
+    match *addressing {
+        StaAddressing::Dhcp => ipv4::ClientConfiguration::default(),
+        StaAddressing::Static {
+            ip,
+            netmask,
+            gateway,
+            dns,
+            secondary_dns,
+        } => {
+            info!("Using static address {} via {}", ip, gateway);
+            ipv4::ClientConfiguration::Fixed(ClientSettings {
+                ip,
+                subnet: Subnet {
+                    gateway,
+                    mask: Mask(u32::from(netmask).count_ones() as u8),
+                },
+                dns,
+                secondary_dns,
+            })
+        }
+    }
+}
+
+pub fn connect(
+    wifi: &mut AsyncWifi<EspWifi<'static>>,
+    config: &AppConfig,
+    stats: &NetworkStats,
+) -> Result<()> {
+    use futures::executor::block_on;
+
+    match config.wifi_mode {
+        WifiMode::Station | WifiMode::Mixed => {
+            let mac = next_sta_mac(&config.sta_mac, false);
+            let access_point = mixed_access_point(config)?;
+            block_on(connect_wifi(wifi, &config.known_networks, access_point.as_ref(), mac, stats))?;
+
+            let ip_info = wifi.wifi().sta_netif().get_ip_info()?;
+
+            log::info!("Wifi DHCP info: {:?}", ip_info);
+            if access_point.is_some() {
+                log::info!("Access point IP info: {:?}", wifi.wifi().ap_netif().get_ip_info()?);
+            }
+        }
+        WifiMode::AccessPoint | WifiMode::Honeypot => {
+            let settings = match config.wifi_mode {
+                WifiMode::Honeypot => &config.honeypot.access_point,
+                _ => &config.access_point,
+            };
+            block_on(start_access_point(wifi, settings, config.wifi_country.max_channel()))?;
+
+            let ip_info = wifi.wifi().ap_netif().get_ip_info()?;
+
+            log::info!("Access point IP info: {:?}", ip_info);
+        }
+    }
+
+    Ok(())
+}
+
+/// Our own access point next to the station in mixed mode, `None` otherwise.
+fn mixed_access_point(config: &AppConfig) -> Result<Option<AccessPointConfiguration>> {
+    match config.wifi_mode {
+        WifiMode::Mixed => Ok(Some(access_point_configuration(&config.access_point, 1)?)),
+        _ => Ok(None),
+    }
+}
+
+/// Station configuration, with the access point alongside in mixed mode.
+fn with_access_point(client: ClientConfiguration, access_point: Option<&AccessPointConfiguration>) -> Configuration {
+    match access_point {
+        Some(access_point) => Configuration::Mixed(client, access_point.clone()),
+        None => Configuration::Client(client),
+    }
+}
+
+/// Address to give the station before connecting, `None` to keep the current one.
+fn next_sta_mac(setting: &StaMac, reconnecting: bool) -> Option<[u8; 6]> {
+    match setting {
+        StaMac::Random { per_reconnect } if !reconnecting || *per_reconnect => {
+            let mut mac = [0_u8; 6];
+            unsafe { sys::esp_fill_random(mac.as_mut_ptr() as *mut _, mac.len()) };
+            // Locally administered, unicast
+            mac[0] = (mac[0] & 0xfc) | 0x02;
+            Some(mac)
+        }
+        StaMac::Fixed { mac } if !reconnecting => parse_mac(mac),
+        _ => None,
+    }
+}
+
+/// Gateway of the station interface, `None` without an address.
+pub fn sta_gateway() -> Option<Ipv4Addr> {
+    sta_address().map(|address| address.gateway).filter(|gateway| !gateway.is_unspecified())
+}
+
+/// Addresses of the station interface right now, all unspecified until DHCP is done.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct StaAddress {
+    pub ip: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub gateway: Ipv4Addr,
+    pub dns: Option<Ipv4Addr>,
+}
+
+pub fn sta_address() -> Option<StaAddress> {
+    let netif = unsafe { sys::esp_netif_get_handle_from_ifkey(c"WIFI_STA_DEF".as_ptr()) };
+    if netif.is_null() {
+        return None;
+    }
+
+    let mut ip_info = sys::esp_netif_ip_info_t::default();
+    esp!(unsafe { sys::esp_netif_get_ip_info(netif, &mut ip_info) }).ok()?;
+    let mut dns_info = sys::esp_netif_dns_info_t::default();
+    let dns = esp!(unsafe { sys::esp_netif_get_dns_info(netif, sys::esp_netif_dns_type_t_ESP_NETIF_DNS_MAIN, &mut dns_info) })
+        .ok()
+        .map(|_| from_network_order(unsafe { dns_info.ip.u_addr.ip4.addr }))
+        .filter(|dns| !dns.is_unspecified());
+
+    Some(StaAddress {
+        ip: from_network_order(ip_info.ip.addr),
+        netmask: from_network_order(ip_info.netmask.addr),
+        gateway: from_network_order(ip_info.gw.addr),
+        dns,
+    })
+}
+
+fn from_network_order(addr: u32) -> Ipv4Addr {
+    Ipv4Addr::from(addr.to_le_bytes())
+}
+
+/// The access point the station is associated with.
+#[derive(Debug, Clone, Serialize)]
+pub struct StaApInfo {
+    pub ssid: String,
+    pub bssid: String,
+    pub channel: u8,
+    pub rssi: i8,
+}
+
+/// `None` while not associated.
+pub fn sta_ap_info() -> Option<StaApInfo> {
+    let mut record = sys::wifi_ap_record_t::default();
+    esp!(unsafe { sys::esp_wifi_sta_get_ap_info(&mut record) }).ok()?;
+    let ssid_len = record.ssid.iter().position(|&b| b == 0).unwrap_or(record.ssid.len());
+
+    Some(StaApInfo {
+        ssid: String::from_utf8_lossy(&record.ssid[..ssid_len]).into_owned(),
+        bssid: format_mac(&record.bssid),
+        channel: record.primary,
+        rssi: record.rssi,
+    })
+}
+
+/// Address the station currently uses.
+pub fn sta_mac_address() -> Option<[u8; 6]> {
+    let mut mac = [0_u8; 6];
+    esp!(unsafe { sys::esp_wifi_get_mac(sys::wifi_interface_t_WIFI_IF_STA, mac.as_mut_ptr()) }).ok()?;
+    Some(mac)
+}
+
+/// Tries the known networks by descending priority until one connects. Networks with
+/// the same priority are ordered by their recorded failure rate, and networks that a
+/// scan does not find are skipped.
+async fn connect_wifi(
+    wifi: &mut AsyncWifi<EspWifi<'static>>,
+    networks: &[KnownNetwork],
+    own_access_point: Option<&AccessPointConfiguration>,
+    mac: Option<[u8; 6]>,
+    stats: &NetworkStats,
+) -> anyhow::Result<()> {
+    let mut candidates = networks.to_vec();
+    if candidates.is_empty() {
+        candidates.push(KnownNetwork {
+            ssid: FALLBACK_SSID.to_string(),
+            password: String::new(),
+            priority: 0,
+            enterprise: None,
+        });
+    }
+
+    let history = stats.all();
+    candidates.sort_by(|a, b| {
+        b.priority
+            .cmp(&a.priority)
+            .then_with(|| failure_rate(&history, &a.ssid).total_cmp(&failure_rate(&history, &b.ssid)))
+    });
+
+    if let Some(mac) = mac {
+        // The address can only change while the interface is down
+        if wifi.is_started()? {
+            wifi.stop().await?;
+        }
+        wifi.wifi_mut().driver_mut().set_mac(WifiDeviceId::Sta, mac)?;
+        info!("Station MAC set to {}", format_mac(&mac));
+    }
+
+    wifi.set_configuration(&with_access_point(ClientConfiguration::default(), own_access_point))?;
+
+    wifi.start().await?;
+    info!("Wifi started");
+
+    let in_range = match wifi.scan().await {
+        Ok(access_points) => Some(access_points),
+        Err(e) => {
+            log::warn!("Scan before connecting failed, trying every known network: {:?}", e);
+            None
+        }
+    };
+
+    let mut last_error = None;
+    for network in &candidates {
+        let access_point = in_range
+            .as_ref()
+            .and_then(|access_points| access_points.iter().find(|ap| ap.ssid.as_str() == network.ssid));
+        if in_range.is_some() && access_point.is_none() {
+            info!("Known network {} is not in range", network.ssid);
+            continue;
+        }
+
+        match join_network(wifi, network, access_point, own_access_point, stats).await {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                log::warn!("Could not join {}: {:?}", network.ssid, e);
+                let _ = wifi.disconnect().await;
+                last_error = Some(e);
+            }
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| anyhow!("None of the {} known networks is in range", candidates.len())))
+}
+
+async fn join_network(
+    wifi: &mut AsyncWifi<EspWifi<'static>>,
+    network: &KnownNetwork,
+    access_point: Option<&AccessPointInfo>,
+    own_access_point: Option<&AccessPointConfiguration>,
+    stats: &NetworkStats,
+) -> anyhow::Result<()> {
+    let auth_method = match (access_point.and_then(|ap| ap.auth_method), &network.enterprise) {
+        (_, Some(_)) => AuthMethod::WPA2Enterprise,
+        (Some(auth_method), None) => auth_method,
+        (None, None) if network.password.is_empty() => AuthMethod::None,
+        (None, None) => AuthMethod::WPA2Personal,
+    };
+    let password = match network.enterprise {
+        Some(_) => "",
+        None => network.password.as_str(),
+    };
+
+    let client = ClientConfiguration {
+        ssid: network.ssid.as_str().try_into().map_err(|_| anyhow!("SSID is too long"))?,
+        bssid: None,
+        auth_method,
+        password: password.try_into().map_err(|_| anyhow!("Password is too long"))?,
+        channel: access_point.map(|ap| ap.channel),
+        ..Default::default()
+    };
+    // The radio has a single channel, so our access point moves to the network's
+    let own_access_point = own_access_point.map(|own| AccessPointConfiguration {
+        channel: access_point.map_or(own.channel, |ap| ap.channel),
+        ..own.clone()
+    });
+    let wifi_configuration = with_access_point(client, own_access_point.as_ref());
+
+    info!("Joining {} ({:?})", network.ssid, auth_method);
+
+    wifi.set_configuration(&wifi_configuration)?;
+    match &network.enterprise {
+        Some(credentials) => configure_enterprise(credentials)?,
+        None => esp!(unsafe { sys::esp_wifi_sta_enterprise_disable() })?,
+    }
+
+    let started = Instant::now();
+    if let Err(e) = wifi.connect().await {
+        stats.record_failure(&network.ssid);
+        return Err(e.into());
+    }
+    let connect_time = started.elapsed();
+    info!("Wifi connected");
+
+    let started = Instant::now();
+    if let Err(e) = wifi.wait_netif_up().await {
+        stats.record_failure(&network.ssid);
+        return Err(e.into());
+    }
+    info!("Wifi netif up");
+
+    stats.record_connect(&network.ssid, connect_time, started.elapsed());
+
+    Ok(())
+}
+
+/// The supplicant keeps a pointer to the CA certificate instead of copying it, so
+/// the PEM has to outlive the connection.
+static CA_CERT: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+
+/// Hands the EAP credentials to the supplicant and turns WPA2-Enterprise on.
+fn configure_enterprise(credentials: &EnterpriseCredentials) -> Result<()> {
+    let identity = match credentials.identity.as_str() {
+        "" => credentials.username.as_bytes(),
+        identity => identity.as_bytes(),
+    };
+    let phase2 = match credentials.ttls_phase2 {
+        TtlsPhase2::Mschapv2 => sys::esp_eap_ttls_phase2_types_ESP_EAP_TTLS_PHASE2_MSCHAPV2,
+        TtlsPhase2::Mschap => sys::esp_eap_ttls_phase2_types_ESP_EAP_TTLS_PHASE2_MSCHAP,
+        TtlsPhase2::Pap => sys::esp_eap_ttls_phase2_types_ESP_EAP_TTLS_PHASE2_PAP,
+        TtlsPhase2::Chap => sys::esp_eap_ttls_phase2_types_ESP_EAP_TTLS_PHASE2_CHAP,
+    };
+
+    unsafe {
+        esp!(sys::esp_eap_client_set_identity(identity.as_ptr(), identity.len() as _))?;
+        esp!(sys::esp_eap_client_set_username(
+            credentials.username.as_ptr(),
+            credentials.username.len() as _
+        ))?;
+        esp!(sys::esp_eap_client_set_password(
+            credentials.password.as_ptr(),
+            credentials.password.len() as _
+        ))?;
+        esp!(sys::esp_eap_client_set_ttls_phase2_method(phase2))?;
+    }
+
+    let mut ca_cert = CA_CERT.lock().unwrap();
+    unsafe { sys::esp_eap_client_clear_ca_cert() };
+    ca_cert.clear();
+    match &credentials.ca_cert {
+        Some(pem) => {
+            // mbedTLS only parses PEM with the terminating NUL included in the length
+            ca_cert.extend_from_slice(pem.as_bytes());
+            ca_cert.push(0);
+            esp!(unsafe { sys::esp_eap_client_set_ca_cert(ca_cert.as_ptr(), ca_cert.len() as _) })?;
+        }
+        None => log::warn!("No CA certificate configured, the RADIUS server is not verified"),
+    }
+
+    esp!(unsafe { sys::esp_wifi_sta_enterprise_enable() })?;
+    Ok(())
+}
+
+fn failure_rate(history: &BTreeMap<String, SsidStats>, ssid: &str) -> f32 {
+    match history.get(ssid) {
+        Some(stats) if stats.connects + stats.failures > 0 => {
+            stats.failures as f32 / (stats.connects + stats.failures) as f32
+        }
+        _ => 0.0,
+    }
+}
+
+fn access_point_configuration(settings: &AccessPointSettings, channel: u8) -> Result<AccessPointConfiguration> {
+    let auth_method = if settings.password.is_empty() {
+        AuthMethod::None
+    } else {
+        AuthMethod::WPA2Personal
+    };
+
+    Ok(AccessPointConfiguration {
+        ssid: settings.ssid.as_str().try_into().map_err(|_| anyhow!("AP SSID is too long"))?,
+        password: settings.password.as_str().try_into().map_err(|_| anyhow!("AP password is too long"))?,
+        channel,
+        auth_method,
+        max_connections: settings.max_connections,
+        ..Default::default()
+    })
+}
+
+async fn start_access_point(
+    wifi: &mut AsyncWifi<EspWifi<'static>>,
+    settings: &AccessPointSettings,
+    max_channel: u8,
+) -> anyhow::Result<()> {
+    let channel = match settings.channel {
+        0 => pick_ap_channel(wifi, max_channel).await,
+        channel => channel,
+    };
+
+    let ap_configuration = access_point_configuration(settings, channel)?;
+    info!(
+        "Access point {} on channel {} ({:?}, up to {} clients)",
+        ap_configuration.ssid, ap_configuration.channel, ap_configuration.auth_method, ap_configuration.max_connections
+    );
+    let wifi_configuration = Configuration::AccessPoint(ap_configuration);
+
+    wifi.set_configuration(&wifi_configuration)?;
+
+    wifi.start().await?;
+    info!("Access point started");
+
+    wifi.wait_netif_up().await?;
+    info!("Access point netif up");
+
+    Ok(())
+}
+
+/// Scans as a station before the access point comes up and returns the least
+/// crowded channel, or channel 1 when the scan fails.
+async fn pick_ap_channel(wifi: &mut AsyncWifi<EspWifi<'static>>, max_channel: u8) -> u8 {
+    let scan = async {
+        wifi.set_configuration(&Configuration::Client(ClientConfiguration::default()))?;
+        wifi.start().await?;
+        let found = wifi.scan().await;
+        wifi.stop().await?;
+        anyhow::Ok(found?)
+    };
+
+    match scan.await {
+        Ok(found) => {
+            let networks: Vec<ScannedNetwork> = found.iter().map(ScannedNetwork::from).collect();
+            let channel = channels::survey(&networks, max_channel).recommended;
+            info!("Picked channel {} for the access point out of {} networks", channel, networks.len());
+            channel
+        }
+        Err(e) => {
+            log::warn!("Channel scan failed, using channel 1: {:?}", e);
+            1
+        }
+    }
+}
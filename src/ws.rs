@@ -0,0 +1,159 @@
+//! WebSocket push on [`PATH`]: every event on the bus goes out to each connected
+//! client as the same JSON the webhooks get, and a finished scan is followed by
+//! its results, so a dashboard does not have to poll `/api/scan`.
+//!
+//! Like the read routes, the socket needs at least a viewer key, as an
+//! `Authorization` header or, since browsers cannot set headers on a
+//! WebSocket, as `?key=`.
+
+use anyhow::{bail, Result};
+use embedded_svc::ws::FrameType;
+use esp_idf_svc::http::server::ws::{EspHttpWsConnection, EspHttpWsDetachedSender};
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::sys::{self, esp};
+use serde::Serialize;
+use std::ffi::c_char;
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+
+use crate::events::Event;
+use crate::form::Form;
+use crate::pairing::Pairing;
+use crate::scan::{LatestScan, ScanResult};
+use crate::sessions::HttpSessions;
+
+pub const PATH: &str = "/ws";
+/// Queue between the event bus and the clients.
+pub const EVENT_QUEUE: usize = 16;
+/// Each client holds a socket for as long as it stays connected.
+const MAX_CLIENTS: usize = 4;
+/// Clients are not expected to send anything; longer frames close the socket.
+const MAX_INCOMING_FRAME: usize = 128;
+
+/// Sent after [`Event::ScanCompleted`], strongest first.
+#[derive(Serialize)]
+#[serde(tag = "type")]
+enum Push {
+    ScanResults { networks: Vec<ScanResult> },
+}
+
+pub struct WsClients {
+    latest_scan: LatestScan,
+    pairing: Arc<Pairing>,
+    /// Told about each socket, so its idle sweep leaves them open.
+    sessions: Arc<HttpSessions>,
+    clients: Mutex<Vec<EspHttpWsDetachedSender>>,
+}
+
+impl WsClients {
+    pub fn new(latest_scan: LatestScan, pairing: Arc<Pairing>, sessions: Arc<HttpSessions>) -> Self {
+        WsClients {
+            latest_scan,
+            pairing,
+            sessions,
+            clients: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn register(self: &Arc<Self>, server: &mut EspHttpServer<'static>) -> Result<()> {
+        let clients = self.clone();
+        server.ws_handler(PATH, move |connection| clients.handle(connection))?;
+        Ok(())
+    }
+
+    /// Pushes events until the bus goes away.
+    pub fn run(&self, events: Receiver<Event>) {
+        for event in events {
+            self.push(&event);
+            if let Event::ScanCompleted { .. } = event {
+                let mut networks: Vec<ScanResult> =
+                    self.latest_scan.lock().unwrap().iter().map(ScanResult::from).collect();
+                networks.sort_by(|a, b| b.rssi.cmp(&a.rssi));
+                self.push(&Push::ScanResults { networks });
+            }
+        }
+    }
+
+    fn handle(&self, connection: &mut EspHttpWsConnection) -> Result<()> {
+        if let EspHttpWsConnection::New(_, raw_req) = connection {
+            let authorization = unsafe { authorization(*raw_req) };
+            if self.pairing.role(authorization.as_deref()).is_none() {
+                bail!("WebSocket client {} has no valid API key", connection.session());
+            }
+            let mut clients = self.clients.lock().unwrap();
+            clients.retain(|client| !client.is_closed());
+            if clients.len() >= MAX_CLIENTS {
+                bail!("Already {} WebSocket clients", MAX_CLIENTS);
+            }
+            clients.push(connection.create_detached_sender()?);
+            self.sessions.websocket_opened(connection.session());
+            log::info!("WebSocket client {} connected", connection.session());
+        } else if connection.is_closed() {
+            let session = connection.session();
+            self.clients.lock().unwrap().retain(|client| client.session() != session);
+            self.sessions.websocket_closed(session);
+            log::info!("WebSocket client {} disconnected", session);
+        } else {
+            let mut buffer = [0_u8; MAX_INCOMING_FRAME];
+            let (_, len) = connection.recv(&mut buffer)?;
+            if len > buffer.len() {
+                bail!("WebSocket frame of {} bytes", len);
+            }
+        }
+        Ok(())
+    }
+
+    /// Clients that cannot be sent to are dropped.
+    fn push<T: Serialize>(&self, message: &T) {
+        let mut clients = self.clients.lock().unwrap();
+        if clients.is_empty() {
+            return;
+        }
+        let data = match serde_json::to_vec(message) {
+            Ok(data) => data,
+            Err(e) => {
+                log::error!("Failed to serialize a WebSocket message: {:?}", e);
+                return;
+            }
+        };
+
+        clients.retain_mut(|client| match client.send(FrameType::Text(false), &data) {
+            Ok(()) => true,
+            Err(e) => {
+                log::info!("Dropping WebSocket client {}: {:?}", client.session(), e);
+                self.sessions.websocket_closed(client.session());
+                false
+            }
+        });
+    }
+}
+
+/// The handshake's `Authorization` header, or else its `key` query parameter
+/// as a bearer key.
+///
+/// Safety: `raw_req` is the handshake request of a new connection.
+unsafe fn authorization(raw_req: *mut sys::httpd_req_t) -> Option<String> {
+    let field = c"Authorization";
+    let header = read_str(sys::httpd_req_get_hdr_value_len(raw_req, field.as_ptr()), |buffer, len| {
+        sys::httpd_req_get_hdr_value_str(raw_req, field.as_ptr(), buffer, len)
+    });
+    if header.is_some() {
+        return header;
+    }
+
+    let query = read_str(sys::httpd_req_get_url_query_len(raw_req), |buffer, len| {
+        sys::httpd_req_get_url_query_str(raw_req, buffer, len)
+    })?;
+    Form::parse(&query).get("key").map(|key| format!("Bearer {}", key))
+}
+
+/// A string of `len` bytes that `read` copies out with its NUL.
+fn read_str(len: usize, read: impl FnOnce(*mut c_char, usize) -> sys::esp_err_t) -> Option<String> {
+    if len == 0 {
+        return None;
+    }
+    let mut buffer = vec![0_u8; len + 1];
+    esp!(read(buffer.as_mut_ptr().cast(), buffer.len())).ok()?;
+    buffer.truncate(len);
+    String::from_utf8(buffer).ok()
+}